@@ -0,0 +1,886 @@
+//! ZIP and tar archives of files below a directory, built while they are
+//! being sent so nothing is written to disk first.
+//!
+//! ZIP entries carry their checksum and sizes in a data descriptor after the
+//! data, since a stream cannot go back to fill them in, and switch to ZIP64
+//! fields past 4 GB. Tar uses ustar headers, with pax records for names and
+//! sizes that do not fit.
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compression::{Crc32, DeflateEncoder, GzipEncoder};
+use crate::fileinfo;
+use crate::http::date::DateTime;
+use crate::mime;
+
+/// Bytes read from a file at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The largest size or offset a plain ZIP field holds; at it, the field
+/// says to look in the ZIP64 extra field instead.
+const ZIP32_MAX: u64 = 0xFFFF_FFFF;
+/// The largest size an 11-digit octal tar field holds.
+const TAR_SIZE_MAX: u64 = 0o777_7777_7777;
+const BLOCK: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Zip, Format::Tar, Format::TarGz];
+
+    /// Reads `zip`, `tar`, or `tar.gz` (also `tgz`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zip" => Some(Format::Zip),
+            "tar" => Some(Format::Tar),
+            "tar.gz" | "tgz" => Some(Format::TarGz),
+            _ => None,
+        }
+    }
+
+    /// The name [`Format::from_name`] reads, which is also the extension.
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::Tar => "tar",
+            Format::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Zip => "application/zip",
+            Format::Tar => "application/x-tar",
+            Format::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// An archive of a file or directory below a root, or of some entries of a
+/// directory.
+pub struct Archive {
+    root: PathBuf,
+    format: Format,
+    /// Paths below the root, and the names they get in the archive.
+    items: Vec<(String, String)>,
+    visible: Box<dyn Fn(&str) -> bool + Send>,
+    deflate: bool,
+}
+
+impl Archive {
+    /// An archive of `path`, a decoded path below `root`, under its own
+    /// name. The root itself is archived as its entries.
+    pub fn new(root: impl Into<PathBuf>, path: &str, format: Format) -> Self {
+        let path = path.trim_matches('/').to_string();
+        let name = path.rsplit('/').next().unwrap_or_default().to_string();
+        Archive {
+            root: root.into(),
+            format,
+            items: vec![(path, name)],
+            visible: Box::new(|_| true),
+            deflate: true,
+        }
+    }
+
+    /// Archives only the entries `names` of the directory given to
+    /// [`Archive::new`], each under its own name. Names of more than one
+    /// path segment are ignored.
+    pub fn select(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        let directory = self.items[0].0.clone();
+        let mut names: Vec<String> = names
+            .into_iter()
+            .filter(|name| {
+                !matches!(name.as_str(), "" | "." | "..") && !name.contains(['/', '\\', '\0'])
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        self.items = names
+            .into_iter()
+            .map(|name| match directory.is_empty() {
+                true => (name.clone(), name),
+                false => (format!("{}/{}", directory, name), name),
+            })
+            .collect();
+        self
+    }
+
+    /// Leaves out entries whose path relative to the root fails `visible`,
+    /// and everything below such directories.
+    pub fn visible(mut self, visible: impl Fn(&str) -> bool + Send + 'static) -> Self {
+        self.visible = Box::new(visible);
+        self
+    }
+
+    /// Whether ZIP entries are compressed, which is the default. Files of
+    /// types that are compressed already are stored either way.
+    pub fn deflate(mut self, enabled: bool) -> Self {
+        self.deflate = enabled;
+        self
+    }
+
+    /// The archive's bytes, produced as they are read. Entries that cannot
+    /// be read when their turn comes, such as dangling links, are left out;
+    /// links to directories are too, so a loop cannot make it endless.
+    pub fn reader(mut self) -> ArchiveReader {
+        let pending = self.items.drain(..).rev().collect();
+        let gzip = (self.format == Format::TarGz).then(|| GzipEncoder::new(Vec::new()));
+        ArchiveReader {
+            archive: self,
+            pending,
+            current: None,
+            out: Vec::new(),
+            position: 0,
+            offset: 0,
+            central: Vec::new(),
+            entries: 0,
+            gzip,
+            finished: false,
+        }
+    }
+}
+
+/// The file being copied into the archive.
+struct Current {
+    file: io::Take<File>,
+    /// The size given in the header; tar pads a file that shrank up to it.
+    size: u64,
+    read: u64,
+    written: u64,
+    crc: Crc32,
+    deflate: Option<DeflateEncoder<Vec<u8>>>,
+    /// What the ZIP central directory needs once the data is written.
+    zip: ZipEntry,
+}
+
+struct ZipEntry {
+    name: String,
+    /// 0 for stored, 8 for deflated.
+    method: u16,
+    time: (u16, u16),
+    mode: u32,
+    offset: u64,
+    zip64: bool,
+}
+
+/// Reads an [`Archive`]; see [`Archive::reader`].
+pub struct ArchiveReader {
+    archive: Archive,
+    /// Entries still to be written, the next one last.
+    pending: Vec<(String, String)>,
+    current: Option<Current>,
+    out: Vec<u8>,
+    position: usize,
+    /// Bytes of the archive produced so far, before gzip.
+    offset: u64,
+    /// The ZIP central directory, collected as entries are written.
+    central: Vec<u8>,
+    entries: u64,
+    gzip: Option<GzipEncoder<Vec<u8>>>,
+    finished: bool,
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.out.len() {
+            self.out.clear();
+            self.position = 0;
+            if !self.step()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.out.len() - self.position);
+        buf[..n].copy_from_slice(&self.out[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+impl ArchiveReader {
+    /// Produces the next part of the archive, which may be nothing while a
+    /// compressor fills its buffer. Returns `false` at the end.
+    fn step(&mut self) -> io::Result<bool> {
+        if let Some(mut current) = self.current.take() {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let n = current.file.read(&mut chunk)?;
+            if n == 0 {
+                self.finish_file(current)?;
+            } else {
+                self.file_data(&mut current, &chunk[..n])?;
+                self.current = Some(current);
+            }
+        } else if let Some((path, name)) = self.pending.pop() {
+            self.start_entry(path, name)?;
+        } else if !self.finished {
+            self.finished = true;
+            self.finish_archive()?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn start_entry(&mut self, path: String, name: String) -> io::Result<()> {
+        if !path.is_empty() && !(self.archive.visible)(&path) {
+            return Ok(());
+        }
+        let full_path = self.archive.root.join(&path);
+        let (Ok(link), Ok(metadata)) = (fs::symlink_metadata(&full_path), fs::metadata(&full_path))
+        else {
+            return Ok(());
+        };
+
+        if metadata.is_dir() {
+            if link.file_type().is_symlink() {
+                return Ok(());
+            }
+            let Ok(entries) = fs::read_dir(&full_path) else {
+                return Ok(());
+            };
+            let mut children: Vec<String> = entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            children.sort();
+            for child in children.into_iter().rev() {
+                let child_path = match path.is_empty() {
+                    true => child.clone(),
+                    false => format!("{}/{}", path, child),
+                };
+                let child_name = match name.is_empty() {
+                    true => child,
+                    false => format!("{}/{}", name, child),
+                };
+                self.pending.push((child_path, child_name));
+            }
+            if !name.is_empty() {
+                self.directory_header(&format!("{}/", name), &metadata)?;
+            }
+        } else if metadata.is_file() {
+            let Ok(file) = File::open(&full_path) else {
+                return Ok(());
+            };
+            self.file_header(file, name, &metadata)?;
+        }
+        Ok(())
+    }
+
+    fn directory_header(&mut self, name: &str, metadata: &Metadata) -> io::Result<()> {
+        let mode = fileinfo::mode(metadata).unwrap_or(0o755);
+        match self.archive.format {
+            Format::Zip => {
+                let zip = ZipEntry {
+                    name: name.to_string(),
+                    method: 0,
+                    time: dos_time(metadata.modified().ok()),
+                    mode: 0o040000 | mode,
+                    offset: self.offset,
+                    zip64: false,
+                };
+                let header = zip_local_header(&zip);
+                self.emit(&header)?;
+                self.central_entry(&zip, 0, 0, 0);
+                Ok(())
+            }
+            Format::Tar | Format::TarGz => {
+                let header = tar_header(name, 0, mode, mtime(metadata), b'5');
+                self.emit(&header)
+            }
+        }
+    }
+
+    fn file_header(&mut self, file: File, name: String, metadata: &Metadata) -> io::Result<()> {
+        let size = metadata.len();
+        let mode = fileinfo::mode(metadata).unwrap_or(0o644);
+        let compress = self.archive.deflate && !already_compressed(mime::from_path(&name));
+        let zip = ZipEntry {
+            method: if compress { 8 } else { 0 },
+            time: dos_time(metadata.modified().ok()),
+            mode: 0o100000 | mode,
+            offset: self.offset,
+            zip64: size >= ZIP32_MAX,
+            name,
+        };
+        let deflate = match self.archive.format {
+            Format::Zip => {
+                let header = zip_local_header(&zip);
+                self.emit(&header)?;
+                compress.then(|| DeflateEncoder::new(Vec::new()))
+            }
+            Format::Tar | Format::TarGz => {
+                let header = tar_header(&zip.name, size, mode, mtime(metadata), b'0');
+                self.emit(&header)?;
+                None
+            }
+        };
+        self.current = Some(Current {
+            // A file that grew since is cut at the size in the header.
+            file: file.take(size),
+            size,
+            read: 0,
+            written: 0,
+            crc: Crc32::new(),
+            deflate,
+            zip,
+        });
+        Ok(())
+    }
+
+    fn file_data(&mut self, current: &mut Current, data: &[u8]) -> io::Result<()> {
+        current.read += data.len() as u64;
+        current.crc.update(data);
+        match &mut current.deflate {
+            Some(deflate) => {
+                deflate.write_all(data)?;
+                let compressed = std::mem::take(deflate.get_mut());
+                current.written += compressed.len() as u64;
+                self.emit(&compressed)
+            }
+            None => {
+                current.written += data.len() as u64;
+                self.emit(data)
+            }
+        }
+    }
+
+    fn finish_file(&mut self, current: Current) -> io::Result<()> {
+        let Current {
+            size,
+            read,
+            mut written,
+            crc,
+            deflate,
+            zip,
+            ..
+        } = current;
+        if let Some(deflate) = deflate {
+            let rest = deflate.finish()?;
+            written += rest.len() as u64;
+            self.emit(&rest)?;
+        }
+
+        match self.archive.format {
+            Format::Zip => {
+                let crc = crc.value();
+                let mut descriptor = Vec::with_capacity(24);
+                descriptor.extend_from_slice(&0x0807_4B50u32.to_le_bytes());
+                descriptor.extend_from_slice(&crc.to_le_bytes());
+                if zip.zip64 {
+                    descriptor.extend_from_slice(&written.to_le_bytes());
+                    descriptor.extend_from_slice(&read.to_le_bytes());
+                } else {
+                    descriptor.extend_from_slice(&(written as u32).to_le_bytes());
+                    descriptor.extend_from_slice(&(read as u32).to_le_bytes());
+                }
+                self.emit(&descriptor)?;
+                self.central_entry(&zip, crc, written, read);
+            }
+            Format::Tar | Format::TarGz => {
+                // A file that shrank is padded to the size in its header.
+                let padding = (size - read) as usize + padding(size);
+                self.emit(&vec![0; padding])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn central_entry(&mut self, zip: &ZipEntry, crc: u32, compressed: u64, uncompressed: u64) {
+        let zip64 = compressed >= ZIP32_MAX || uncompressed >= ZIP32_MAX || zip.offset >= ZIP32_MAX;
+        let mut extra = Vec::new();
+        if zip64 {
+            extra.extend_from_slice(&1u16.to_le_bytes());
+            extra.extend_from_slice(&24u16.to_le_bytes());
+            extra.extend_from_slice(&uncompressed.to_le_bytes());
+            extra.extend_from_slice(&compressed.to_le_bytes());
+            extra.extend_from_slice(&zip.offset.to_le_bytes());
+        }
+        let field = |value: u64| if zip64 { u32::MAX } else { value as u32 };
+        let is_dir = zip.name.ends_with('/');
+
+        let header = &mut self.central;
+        header.extend_from_slice(&0x0201_4B50u32.to_le_bytes());
+        // Made by Unix, so the mode below is read; ZIP 4.5 for ZIP64.
+        header.extend_from_slice(&(0x0300u16 | 45).to_le_bytes());
+        header.extend_from_slice(&version_needed(zip64).to_le_bytes());
+        header.extend_from_slice(&flags(!is_dir).to_le_bytes());
+        header.extend_from_slice(&zip.method.to_le_bytes());
+        header.extend_from_slice(&zip.time.0.to_le_bytes());
+        header.extend_from_slice(&zip.time.1.to_le_bytes());
+        header.extend_from_slice(&crc.to_le_bytes());
+        header.extend_from_slice(&field(compressed).to_le_bytes());
+        header.extend_from_slice(&field(uncompressed).to_le_bytes());
+        header.extend_from_slice(&(zip.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        // No comment, disk 0, no internal attributes.
+        header.extend_from_slice(&[0; 6]);
+        let dos_directory = if is_dir { 0x10 } else { 0 };
+        header.extend_from_slice(&(zip.mode << 16 | dos_directory).to_le_bytes());
+        header.extend_from_slice(&field(zip.offset).to_le_bytes());
+        header.extend_from_slice(zip.name.as_bytes());
+        header.extend_from_slice(&extra);
+        self.entries += 1;
+    }
+
+    fn finish_archive(&mut self) -> io::Result<()> {
+        match self.archive.format {
+            Format::Zip => {
+                let central_offset = self.offset;
+                let central = std::mem::take(&mut self.central);
+                self.emit(&central)?;
+                let central_size = central.len() as u64;
+
+                let zip64 = self.entries >= 0xFFFF
+                    || central_offset >= ZIP32_MAX
+                    || central_size >= ZIP32_MAX;
+                let mut end = Vec::new();
+                if zip64 {
+                    let record_offset = self.offset;
+                    end.extend_from_slice(&0x0606_4B50u32.to_le_bytes());
+                    end.extend_from_slice(&44u64.to_le_bytes());
+                    end.extend_from_slice(&(0x0300u16 | 45).to_le_bytes());
+                    end.extend_from_slice(&45u16.to_le_bytes());
+                    end.extend_from_slice(&[0; 8]);
+                    end.extend_from_slice(&self.entries.to_le_bytes());
+                    end.extend_from_slice(&self.entries.to_le_bytes());
+                    end.extend_from_slice(&central_size.to_le_bytes());
+                    end.extend_from_slice(&central_offset.to_le_bytes());
+                    // The locator of the record above, on the only disk.
+                    end.extend_from_slice(&0x0706_4B50u32.to_le_bytes());
+                    end.extend_from_slice(&0u32.to_le_bytes());
+                    end.extend_from_slice(&record_offset.to_le_bytes());
+                    end.extend_from_slice(&1u32.to_le_bytes());
+                }
+                let entries = self.entries.min(0xFFFF) as u16;
+                end.extend_from_slice(&0x0605_4B50u32.to_le_bytes());
+                end.extend_from_slice(&[0; 4]);
+                end.extend_from_slice(&entries.to_le_bytes());
+                end.extend_from_slice(&entries.to_le_bytes());
+                end.extend_from_slice(&(central_size.min(ZIP32_MAX) as u32).to_le_bytes());
+                end.extend_from_slice(&(central_offset.min(ZIP32_MAX) as u32).to_le_bytes());
+                end.extend_from_slice(&0u16.to_le_bytes());
+                self.emit(&end)
+            }
+            Format::Tar | Format::TarGz => {
+                self.emit(&[0; 2 * BLOCK])?;
+                if let Some(gzip) = self.gzip.take() {
+                    let rest = gzip.finish()?;
+                    self.out.extend_from_slice(&rest);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds `bytes` of the archive to the output, through gzip if it has it.
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.offset += bytes.len() as u64;
+        match &mut self.gzip {
+            Some(gzip) => {
+                gzip.write_all(bytes)?;
+                self.out.append(gzip.get_mut());
+            }
+            None => self.out.extend_from_slice(bytes),
+        }
+        Ok(())
+    }
+}
+
+/// A local file header. File sizes and checksums follow the data in a
+/// descriptor; a ZIP64 entry says so with an extra field of zero sizes.
+fn zip_local_header(zip: &ZipEntry) -> Vec<u8> {
+    let is_dir = zip.name.ends_with('/');
+    let sizes = if zip.zip64 { u32::MAX } else { 0 };
+    let mut header = Vec::with_capacity(30 + zip.name.len() + 20);
+    header.extend_from_slice(&0x0403_4B50u32.to_le_bytes());
+    header.extend_from_slice(&version_needed(zip.zip64).to_le_bytes());
+    header.extend_from_slice(&flags(!is_dir).to_le_bytes());
+    header.extend_from_slice(&zip.method.to_le_bytes());
+    header.extend_from_slice(&zip.time.0.to_le_bytes());
+    header.extend_from_slice(&zip.time.1.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&sizes.to_le_bytes());
+    header.extend_from_slice(&sizes.to_le_bytes());
+    header.extend_from_slice(&(zip.name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&(if zip.zip64 { 20u16 } else { 0 }).to_le_bytes());
+    header.extend_from_slice(zip.name.as_bytes());
+    if zip.zip64 {
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(&[0; 16]);
+    }
+    header
+}
+
+fn version_needed(zip64: bool) -> u16 {
+    if zip64 { 45 } else { 20 }
+}
+
+/// UTF-8 names, and for files a data descriptor.
+fn flags(descriptor: bool) -> u16 {
+    0x0800 | if descriptor { 0x0008 } else { 0 }
+}
+
+/// A time as the MS-DOS `(time, date)` ZIP uses, in UTC; times before it
+/// begins in 1980 are rounded up to it.
+fn dos_time(time: Option<SystemTime>) -> (u16, u16) {
+    let date = DateTime::from_system_time(time.unwrap_or(UNIX_EPOCH));
+    if date.year < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let year = (date.year - 1980).min(127) as u32;
+    (
+        ((date.hour << 11) | (date.minute << 5) | (date.second / 2)) as u16,
+        ((year << 9) | (date.month << 5) | date.day) as u16,
+    )
+}
+
+fn mtime(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// A ustar header, after a pax header with the name or size if they do not
+/// fit in its fields.
+fn tar_header(name: &str, size: u64, mode: u32, mtime: u64, kind: u8) -> Vec<u8> {
+    let mut pax = String::new();
+    if name.len() > 100 {
+        pax.push_str(&pax_record("path", name));
+    }
+    if size > TAR_SIZE_MAX {
+        pax.push_str(&pax_record("size", &size.to_string()));
+    }
+
+    let mut out = Vec::new();
+    if !pax.is_empty() {
+        out.extend(ustar_block(
+            "././@PaxHeader",
+            pax.len() as u64,
+            0o644,
+            mtime,
+            b'x',
+        ));
+        out.extend_from_slice(pax.as_bytes());
+        out.resize(out.len() + padding(pax.len() as u64), 0);
+    }
+    out.extend(ustar_block(name, size, mode, mtime, kind));
+    out
+}
+
+fn ustar_block(name: &str, size: u64, mode: u32, mtime: u64, kind: u8) -> [u8; BLOCK] {
+    let mut block = [0u8; BLOCK];
+    // Cut on a character boundary; the pax header has the whole name.
+    let mut end = name.len().min(100);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    block[..end].copy_from_slice(&name.as_bytes()[..end]);
+    octal(&mut block[100..108], mode as u64);
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    octal(&mut block[124..136], size.min(TAR_SIZE_MAX));
+    octal(&mut block[136..148], mtime);
+    block[156] = kind;
+    block[257..265].copy_from_slice(b"ustar\x0000");
+
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&byte| byte as u32).sum();
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    block
+}
+
+/// Writes `value` as zero-padded octal digits and a NUL, filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(&digits.as_bytes()[digits.len() - field.len()..]);
+}
+
+/// `"<length> <key>=<value>\n"`, where the length counts itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + 1;
+    while (rest + length.to_string().len()) != length {
+        length = rest + length.to_string().len();
+    }
+    format!("{} {}={}\n", length, key, value)
+}
+
+/// Zero bytes that bring `size` up to a whole number of tar blocks.
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+/// Whether a type is compressed already, so deflating it again would only
+/// cost time.
+fn already_compressed(mime: &str) -> bool {
+    const COMPRESSED: [&str; 8] = [
+        "image/",
+        "audio/",
+        "video/",
+        "font/woff",
+        "application/zip",
+        "application/gzip",
+        "application/x-7z-compressed",
+        "application/vnd.openxmlformats",
+    ];
+    !matches!(mime, "image/svg+xml" | "image/bmp" | "audio/wav")
+        && COMPRESSED.iter().any(|prefix| mime.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::crc32;
+
+    fn u16_at(bytes: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    /// The names and contents in a ZIP archive, by its central directory.
+    fn unzip(zip: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(zip, end), 0x0605_4B50);
+        let mut at = u32_at(zip, end + 16);
+        let mut entries = Vec::new();
+        for _ in 0..u16_at(zip, end + 10) {
+            assert_eq!(u32_at(zip, at), 0x0201_4B50);
+            let (method, crc) = (u16_at(zip, at + 10), u32_at(zip, at + 16));
+            let (compressed, size) = (u32_at(zip, at + 20), u32_at(zip, at + 24));
+            let name_length = u16_at(zip, at + 28);
+            let name = String::from_utf8(zip[at + 46..at + 46 + name_length].to_vec()).unwrap();
+            let offset = u32_at(zip, at + 42);
+
+            assert_eq!(u32_at(zip, offset), 0x0403_4B50);
+            let start = offset + 30 + u16_at(zip, offset + 26) + u16_at(zip, offset + 28);
+            let data = &zip[start..start + compressed];
+            let data = match method {
+                0 => data.to_vec(),
+                8 => {
+                    let mut inflated = Vec::new();
+                    flate2::read::DeflateDecoder::new(data)
+                        .read_to_end(&mut inflated)
+                        .unwrap();
+                    inflated
+                }
+                _ => panic!("method {}", method),
+            };
+            assert_eq!((data.len(), crc32(&data) as usize), (size, crc), "{}", name);
+            entries.push((name, data));
+            at += 46 + name_length + u16_at(zip, at + 30) + u16_at(zip, at + 32);
+        }
+        entries
+    }
+
+    /// The names and contents in a tar archive, with pax paths applied.
+    fn untar(tar: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut entries = Vec::new();
+        let mut long_name = None;
+        let mut at = 0;
+        while tar[at..at + BLOCK].iter().any(|&byte| byte != 0) {
+            let header = &tar[at..at + BLOCK];
+            let field = |range: std::ops::Range<usize>| {
+                let text = std::str::from_utf8(&header[range]).unwrap();
+                text.trim_end_matches(['\0', ' ']).to_string()
+            };
+            let checksum = u32::from_str_radix(&field(148..156), 8).unwrap();
+            let sum: u32 = header
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| {
+                    if (148..156).contains(&i) {
+                        32
+                    } else {
+                        b as u32
+                    }
+                })
+                .sum();
+            assert_eq!(checksum, sum);
+            let size = usize::from_str_radix(&field(124..136), 8).unwrap();
+            let data = tar[at + BLOCK..at + BLOCK + size].to_vec();
+            at += BLOCK + size + padding(size as u64);
+
+            if header[156] == b'x' {
+                let records = String::from_utf8(data).unwrap();
+                long_name = records
+                    .lines()
+                    .find_map(|record| record.split_once(" path=").map(|(_, p)| p.to_string()));
+                continue;
+            }
+            entries.push((long_name.take().unwrap_or_else(|| field(0..100)), data));
+        }
+        assert!(tar[at..].iter().all(|&byte| byte == 0) && tar.len() - at >= 2 * BLOCK);
+        entries
+    }
+
+    fn read(archive: Archive) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        archive.reader().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_archives() {
+        let root = std::env::temp_dir().join(format!("rustserve-archive-{}", std::process::id()));
+        let long = "a-rather-long-directory-name-".repeat(3);
+        fs::create_dir_all(root.join("docs/sub").join(&long)).unwrap();
+        let text = "hello archive ".repeat(10_000);
+        fs::write(root.join("docs/notes.txt"), &text).unwrap();
+        fs::write(root.join("docs/photo.jpg"), [0xFF, 0xD8, 0xFF]).unwrap();
+        fs::write(root.join("docs/secret.txt"), "hidden").unwrap();
+        fs::write(root.join("docs/sub").join(&long).join("deep.txt"), "deep").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("..", root.join("docs/sub/loop")).unwrap();
+
+        let archive = |format| {
+            Archive::new(&root, "docs", format).visible(|path| !path.ends_with("secret.txt"))
+        };
+        let long_path = format!("docs/sub/{}/deep.txt", long);
+        let expected = vec![
+            ("docs/".to_string(), Vec::new()),
+            ("docs/notes.txt".to_string(), text.clone().into_bytes()),
+            ("docs/photo.jpg".to_string(), vec![0xFF, 0xD8, 0xFF]),
+            ("docs/sub/".to_string(), Vec::new()),
+            (format!("docs/sub/{}/", long), Vec::new()),
+            (long_path.clone(), b"deep".to_vec()),
+        ];
+
+        let zip = read(archive(Format::Zip));
+        assert!(zip.len() < text.len() / 4);
+        assert_eq!(unzip(&zip), expected);
+        let stored = read(archive(Format::Zip).deflate(false));
+        assert!(stored.len() > text.len());
+        assert_eq!(unzip(&stored), expected);
+
+        let tar = read(archive(Format::Tar));
+        assert_eq!(tar.len() % BLOCK, 0);
+        assert_eq!(untar(&tar), expected);
+        let mut tar_gz = Vec::new();
+        flate2::read::GzDecoder::new(&read(archive(Format::TarGz))[..])
+            .read_to_end(&mut tar_gz)
+            .unwrap();
+        assert_eq!(tar_gz, tar);
+
+        let selected = Archive::new(&root, "docs/", Format::Zip).select([
+            "photo.jpg".to_string(),
+            "../escape".to_string(),
+            "missing".to_string(),
+        ]);
+        let photo = ("photo.jpg".to_string(), expected[2].1.clone());
+        assert_eq!(unzip(&read(selected)), [photo]);
+        let whole_root = unzip(&read(Archive::new(&root, "", Format::Zip)));
+        assert_eq!(whole_root[0].0, "docs/");
+        assert_eq!(whole_root.len(), 7);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tar_gz_round_trip() {
+        let root = std::env::temp_dir().join(format!("rustserve-targz-{}", std::process::id()));
+        let long_name = format!("{}.txt", "n".repeat(150));
+        let long_dir = "d".repeat(120);
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join(&long_dir).join("inner")).unwrap();
+        let binary: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        fs::write(root.join("binary.bin"), &binary).unwrap();
+        fs::write(root.join(&long_name), "long").unwrap();
+        fs::write(
+            root.join(&long_dir).join("inner").join(&long_name),
+            "deeper",
+        )
+        .unwrap();
+
+        let expected = vec![
+            ("binary.bin".to_string(), binary),
+            (format!("{}/", long_dir), Vec::new()),
+            (format!("{}/inner/", long_dir), Vec::new()),
+            (
+                format!("{}/inner/{}", long_dir, long_name),
+                b"deeper".to_vec(),
+            ),
+            ("empty/".to_string(), Vec::new()),
+            (long_name.clone(), b"long".to_vec()),
+        ];
+        let tar_gz = read(Archive::new(&root, "", Format::TarGz));
+        assert_eq!(tar_gz[..2], [0x1F, 0x8B]);
+        let mut tar = Vec::new();
+        flate2::read::GzDecoder::new(&tar_gz[..])
+            .read_to_end(&mut tar)
+            .unwrap();
+        // Names past the header's 100 bytes only survive in pax records.
+        assert_eq!(untar(&tar), expected);
+        assert_eq!(unzip(&read(Archive::new(&root, "", Format::Zip))), expected);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_skipped_entries() {
+        let root = std::env::temp_dir().join(format!("rustserve-skipped-{}", std::process::id()));
+        fs::create_dir_all(root.join("private/deep")).unwrap();
+        fs::create_dir_all(root.join("public")).unwrap();
+        fs::write(root.join("private/deep/s.txt"), "secret").unwrap();
+        fs::write(root.join("public/a.txt"), "a").unwrap();
+        fs::write(root.join("public/.hidden"), "h").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::os::unix::fs::symlink("missing", root.join("public/dangling")).unwrap();
+            fs::write(root.join("public/locked.txt"), "locked").unwrap();
+            let locked = fs::Permissions::from_mode(0o000);
+            fs::set_permissions(root.join("public/locked.txt"), locked).unwrap();
+        }
+
+        let mut expected = vec![
+            ("public/".to_string(), Vec::new()),
+            ("public/a.txt".to_string(), b"a".to_vec()),
+        ];
+        // Unless running as root, which can read it anyway.
+        if File::open(root.join("public/locked.txt")).is_ok() {
+            expected.push(("public/locked.txt".to_string(), b"locked".to_vec()));
+        }
+        let archive = |format| {
+            Archive::new(&root, "", format)
+                .visible(|path| !path.starts_with("private") && !path.ends_with("/.hidden"))
+        };
+        assert_eq!(unzip(&read(archive(Format::Zip))), expected);
+        assert_eq!(untar(&read(archive(Format::Tar))), expected);
+        let hidden_root =
+            Archive::new(&root, "private", Format::Zip).visible(|path| path != "private");
+        assert!(unzip(&read(hidden_root)).is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fields() {
+        assert_eq!(pax_record("path", "abc"), "12 path=abc\n");
+        assert_eq!(pax_record("path", &"x".repeat(93)).len(), 103);
+        let mut field = [0u8; 8];
+        octal(&mut field, 0o644);
+        assert_eq!(&field, b"0000644\0");
+        let date = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        // 2023-11-14 22:13:20
+        assert_eq!(
+            dos_time(Some(date)),
+            (22 << 11 | 13 << 5 | 10, 43 << 9 | 11 << 5 | 14)
+        );
+        assert_eq!(dos_time(None), (0, 33));
+        assert!(already_compressed("image/jpeg") && !already_compressed("image/svg+xml"));
+        assert_eq!(Format::from_name("tgz"), Some(Format::TarGz));
+    }
+}
@@ -0,0 +1,171 @@
+//! The `/_admin` statistics: a JSON snapshot of the server's [`Stats`] and a
+//! stream of them as server-sent events.
+
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use rustserve::stats::{Percentiles, Stats};
+
+use crate::json_escape;
+
+/// How many of the most downloaded files a snapshot lists.
+const TOP_FILES: usize = 10;
+/// Time between two events on the stream.
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long one stream lasts before the browser is left to reconnect, so an
+/// abandoned page does not hold a connection forever.
+const STREAM_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// The statistics shown on the admin page, as a JSON object.
+pub fn snapshot(stats: &Stats) -> String {
+    let responses: Vec<String> = (1..=5)
+        .map(|class| format!(r#""{}xx":{}"#, class, stats.get_responses(class)))
+        .collect();
+    let rates = |rate: &dyn Fn(Duration) -> f64| {
+        [1, 5, 15]
+            .map(|minutes| {
+                let rate = rate(Duration::from_secs(minutes * 60));
+                format!(r#""{}m":{:.2}"#, minutes, rate)
+            })
+            .join(",")
+    };
+
+    let connections: Vec<String> = stats
+        .connections()
+        .iter()
+        .map(|connection| {
+            format!(
+                r#"{{"remote_addr":{},"path":{},"seconds":{}}}"#,
+                json_string(connection.remote_addr.map(|addr| addr.to_string())),
+                json_string(connection.path.as_deref()),
+                connection.opened.elapsed().as_secs()
+            )
+        })
+        .collect();
+    let top_files: Vec<String> = stats
+        .top_files(TOP_FILES)
+        .iter()
+        .map(|(path, downloads)| {
+            format!(
+                r#"{{"path":"{}","downloads":{}}}"#,
+                json_escape(path),
+                downloads
+            )
+        })
+        .collect();
+    let errors: Vec<String> = stats
+        .recent_errors()
+        .iter()
+        .map(|error| {
+            let time = error
+                .time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            format!(
+                r#"{{"time":{},"remote_addr":{},"method":"{}","path":"{}","status":{}}}"#,
+                time,
+                json_string(error.remote_addr.map(|addr| addr.to_string())),
+                json_escape(&error.method),
+                json_escape(&error.path),
+                error.status
+            )
+        })
+        .collect();
+    let routes: Vec<String> = stats
+        .routes()
+        .iter()
+        .map(|(label, route)| {
+            format!(
+                r#"{{"route":"{}","requests":{},"errors":{},"latency_ms":{}}}"#,
+                json_escape(label),
+                route.requests,
+                route.responses[3] + route.responses[4],
+                milliseconds(route.latency)
+            )
+        })
+        .collect();
+
+    let latency = stats.latency.percentiles();
+    format!(
+        concat!(
+            r#"{{"uptime":{},"active_connections":{},"total_requests":{},"#,
+            r#""files_downloaded":{},"bytes_sent":{},"responses":{{{}}},"#,
+            r#""latency_ms":{},"request_rate":{{{}}},"byte_rate":{{{}}},"#,
+            r#""connections":[{}],"top_files":[{}],"recent_errors":[{}],"routes":[{}]}}"#
+        ),
+        stats.uptime().as_secs(),
+        stats.get_active_connections(),
+        stats.get_total_requests(),
+        stats.get_files_downloaded(),
+        stats.get_total_bytes_sent(),
+        responses.join(","),
+        milliseconds(latency),
+        rates(&|window| stats.request_rate(window)),
+        rates(&|window| stats.byte_rate(window)),
+        connections.join(","),
+        top_files.join(","),
+        errors.join(","),
+        routes.join(",")
+    )
+}
+
+/// Percentiles of a histogram in microseconds, as milliseconds.
+fn milliseconds(percentiles: Percentiles) -> String {
+    let Percentiles { p50, p90, p99 } = percentiles;
+    format!(
+        r#"{{"p50":{:.1},"p90":{:.1},"p99":{:.1}}}"#,
+        p50 as f64 / 1000.0,
+        p90 as f64 / 1000.0,
+        p99 as f64 / 1000.0
+    )
+}
+
+fn json_string<S: AsRef<str>>(value: Option<S>) -> String {
+    match value {
+        Some(value) => format!(r#""{}""#, json_escape(value.as_ref())),
+        None => "null".to_string(),
+    }
+}
+
+/// A `text/event-stream` body sending a [`snapshot`] every second.
+pub struct Events {
+    stats: Arc<Stats>,
+    pending: Vec<u8>,
+    next: Instant,
+    ends: Instant,
+}
+
+impl Events {
+    pub fn new(stats: Arc<Stats>) -> Self {
+        let now = Instant::now();
+        Events {
+            stats,
+            // Ask the browser to come back soon after the stream ends.
+            pending: b"retry: 1000\n\n".to_vec(),
+            next: now,
+            ends: now + STREAM_LIFETIME,
+        }
+    }
+}
+
+impl Read for Events {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let now = Instant::now();
+            if now >= self.ends {
+                return Ok(0);
+            }
+            if let Some(wait) = self.next.checked_duration_since(now) {
+                thread::sleep(wait);
+            }
+            self.next += EVENT_INTERVAL;
+            self.pending = format!("data: {}\n\n", snapshot(&self.stats)).into_bytes();
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
@@ -0,0 +1,815 @@
+//! The versioned file API under `/api/v1`. Each endpoint is followed by a
+//! path below the shared directory, e.g. `GET /api/v1/stat/docs/notes.txt`:
+//!
+//! - `GET list/<dir>?depth=N` or `?recursive=true`: the entries, as
+//!   [`FileInfo`] objects, with those of subdirectories down to `depth`
+//! - `GET stat/<path>`: one [`FileInfo`]
+//! - `GET download/<file>`: the file itself
+//! - `PUT upload/<file>`: writes the body, answering `201`, or `204` with
+//!   no body if it replaced a file
+//! - `POST mkdir/<dir>`
+//! - `POST rename/<path>?to=<name>`: renames within the same directory
+//! - `POST move/<path>?to=<path>` and `POST copy/<path>?to=<path>`
+//! - `DELETE delete/<path>`, with `?recursive=true` for a non-empty
+//!   directory
+//!
+//! Changes answer with the [`FileInfo`] of the result. Every error is
+//! `{"error":{"status":409,"message":"..."}}` with the same status.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustserve::fileinfo::FileInfo;
+use rustserve::http::{
+    Filter, IntoResponse, Request, Response, delete, get, normalize_path, path,
+    percent_encode_path, post, put, request, serve_file,
+};
+
+use crate::auth::{Access, Permission};
+use crate::{authorize, json_escape, readable, visible};
+
+/// How deep a `recursive` listing goes, and the most `depth` may ask for.
+const MAX_DEPTH: usize = 16;
+
+type Answer = Result<Response, Response>;
+
+/// An endpoint, given the normalised path below the shared directory.
+type Handler = fn(&Api, &str, &Request) -> Answer;
+
+/// The API for one shared directory.
+pub struct Api {
+    pub root: PathBuf,
+    pub access: Option<Arc<Access>>,
+    /// Whether the endpoints that change files are enabled, as uploads are.
+    pub writable: bool,
+}
+
+impl Api {
+    /// The `/api/v1` routes, and `/api/files` as an alias of `list`.
+    pub fn routes(self) -> impl Filter<Extract: IntoResponse> {
+        let api = Arc::new(self);
+        let endpoint = |handler: Handler| {
+            let api = Arc::clone(&api);
+            move |(path, request): (String, Request)| api.handle(handler, &path, &request)
+        };
+
+        let list = get("/api/v1/list")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::list));
+        let legacy_list = get("/api/files")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::list));
+        let stat = get("/api/v1/stat")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::stat));
+        let download = get("/api/v1/download")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::download));
+        let upload = put("/api/v1/upload")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::upload));
+        let mkdir = post("/api/v1/mkdir")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::mkdir));
+        let rename = post("/api/v1/rename")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::rename));
+        let move_route = post("/api/v1/move")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::move_entry));
+        let copy = post("/api/v1/copy")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::copy));
+        let delete_route = delete("/api/v1/delete")
+            .param_slashes::<String>()
+            .and(request())
+            .map(endpoint(Api::delete));
+        let unknown = path("/api/v1")
+            .param_slashes::<String>()
+            .map(|_| error(404, "No such endpoint"));
+
+        list.or(legacy_list)
+            .or(stat)
+            .or(download)
+            .or(upload)
+            .or(mkdir)
+            .or(rename)
+            .or(move_route)
+            .or(copy)
+            .or(delete_route)
+            .or(unknown)
+    }
+
+    /// Answers a request for `url_path` with `handler`, which is given the
+    /// path both access rules and the file system then see.
+    fn handle(&self, handler: Handler, url_path: &str, request: &Request) -> Response {
+        let answer = match normalize_path(url_path) {
+            Some(tree_path) => handler(self, &tree_path, request),
+            None => Err(error(400, "Invalid path")),
+        };
+        match answer {
+            Ok(response) | Err(response) => response,
+        }
+    }
+
+    fn list(&self, tree_path: &str, request: &Request) -> Answer {
+        let user = self.authorize(request, tree_path, Permission::List)?;
+        let directory = self.root.join(tree_path);
+        if !directory.is_dir() {
+            return Err(not_a(&directory, "directory"));
+        }
+        let depth = match request.query_param("recursive").as_deref() {
+            Some("" | "1" | "true") => MAX_DEPTH,
+            _ => match request.query_param("depth").map(|depth| depth.parse()) {
+                None => 1,
+                Some(Ok(depth)) if depth > 0 => depth,
+                Some(_) => return Err(error(400, "Invalid depth")),
+            },
+        };
+        let access = self.access.as_deref();
+        let visible = |path: &str| visible(access, user.as_deref(), path);
+        let json = list_directory_json(&self.root, tree_path, depth.min(MAX_DEPTH), &visible);
+        json.map(Response::json).map_err(io_error)
+    }
+
+    fn stat(&self, tree_path: &str, request: &Request) -> Answer {
+        self.authorize(request, tree_path, Permission::List)?;
+        self.info(tree_path)
+            .map(|info| Response::json(info.to_json()))
+    }
+
+    fn download(&self, tree_path: &str, request: &Request) -> Answer {
+        self.authorize(request, tree_path, Permission::Read)?;
+        let file = self.root.join(tree_path);
+        if !file.is_file() {
+            return Err(not_a(&file, "file"));
+        }
+        let name = tree_path
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .replace('"', "'");
+        Ok(serve_file(request, &file).header(
+            "Content-Disposition",
+            &format!("attachment; filename=\"{}\"", name),
+        ))
+    }
+
+    fn upload(&self, tree_path: &str, request: &Request) -> Answer {
+        self.authorize_change(request, tree_path, Permission::Upload)?;
+        let file = self.resolve_below_root(tree_path)?;
+        if file.is_dir() {
+            return Err(error(409, "A directory exists at this path"));
+        }
+        parent_exists(&file)?;
+
+        let existed = file.exists();
+        fs::write(&file, request.body().unwrap_or_default()).map_err(io_error)?;
+        if existed {
+            return Ok(Response::no_content());
+        }
+        let info = self.info(tree_path)?;
+        Ok(Response::json(info.to_json()).status(201))
+    }
+
+    fn mkdir(&self, tree_path: &str, request: &Request) -> Answer {
+        self.authorize_change(request, tree_path, Permission::Upload)?;
+        let directory = self.resolve_below_root(tree_path)?;
+        parent_exists(&directory)?;
+        fs::create_dir(&directory).map_err(io_error)?;
+        let info = self.info(tree_path)?;
+        Ok(Response::json(info.to_json()).status(201))
+    }
+
+    fn rename(&self, tree_path: &str, request: &Request) -> Answer {
+        let name = destination(request)?;
+        if name.contains('/') {
+            return Err(error(400, "The new name must not contain a path"));
+        }
+        let destination = match tree_path.rsplit_once('/') {
+            Some((parent, _)) => format!("{}/{}", parent, name),
+            None => name,
+        };
+        self.move_to(tree_path, &destination, request)
+    }
+
+    fn move_entry(&self, tree_path: &str, request: &Request) -> Answer {
+        let destination = destination(request)?;
+        self.move_to(tree_path, &destination, request)
+    }
+
+    fn move_to(&self, tree_path: &str, destination: &str, request: &Request) -> Answer {
+        let user = self.authorize_change(request, tree_path, Permission::Delete)?;
+        self.authorize(request, destination, Permission::Upload)?;
+        let (source, target) = self.source_and_target(tree_path, destination)?;
+        self.check_tree(user.as_deref(), tree_path, Permission::Delete)?;
+
+        fs::rename(&source, &target).map_err(io_error)?;
+        self.info(destination)
+            .map(|info| Response::json(info.to_json()))
+    }
+
+    fn copy(&self, tree_path: &str, request: &Request) -> Answer {
+        let destination = destination(request)?;
+        let user = self.authorize_change(request, tree_path, Permission::Read)?;
+        self.authorize(request, &destination, Permission::Upload)?;
+        let (source, target) = self.source_and_target(tree_path, &destination)?;
+
+        let access = self.access.as_deref();
+        let readable = |path: &str| readable(access, user.as_deref(), path);
+        copy_tree(&source, &target, tree_path, &readable).map_err(io_error)?;
+        let info = self.info(&destination)?;
+        Ok(Response::json(info.to_json()).status(201))
+    }
+
+    fn delete(&self, tree_path: &str, request: &Request) -> Answer {
+        let user = self.authorize_change(request, tree_path, Permission::Delete)?;
+        let target = self.resolve_below_root(tree_path)?;
+        let recursive = matches!(
+            request.query_param("recursive").as_deref(),
+            Some("" | "1" | "true")
+        );
+
+        let result = match fs::symlink_metadata(&target).map_err(io_error)? {
+            metadata if metadata.is_dir() && recursive => {
+                self.check_tree(user.as_deref(), tree_path, Permission::Delete)?;
+                fs::remove_dir_all(&target)
+            }
+            metadata if metadata.is_dir() => fs::remove_dir(&target),
+            _ => fs::remove_file(&target),
+        };
+        match result {
+            Ok(()) => Ok(Response::no_content()),
+            Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => Err(error(
+                409,
+                "Directory is not empty; delete it with recursive=true",
+            )),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+
+    /// Like [`authorize`], with the denial as a JSON error.
+    fn authorize(
+        &self,
+        request: &Request,
+        tree_path: &str,
+        permission: Permission,
+    ) -> Result<Option<String>, Response> {
+        authorize(self.access.as_deref(), request, tree_path, permission).map_err(|denied| {
+            let message = match denied.status_code() {
+                401 => "Authentication required",
+                _ => "Forbidden",
+            };
+            let status = denied.status_code();
+            denied
+                .body(error_json(status, message))
+                .header("Content-Type", "application/json")
+        })
+    }
+
+    /// Authorizes a request that changes files, which is refused outright
+    /// unless the API is writable.
+    fn authorize_change(
+        &self,
+        request: &Request,
+        tree_path: &str,
+        permission: Permission,
+    ) -> Result<Option<String>, Response> {
+        let user = self.authorize(request, tree_path, permission)?;
+        if !self.writable {
+            return Err(error(403, "Uploads are disabled"));
+        }
+        Ok(user)
+    }
+
+    /// Fails unless `user` may use `permission` on everything below
+    /// `tree_path` too, since rules may be stricter deeper in the tree.
+    fn check_tree(
+        &self,
+        user: Option<&str>,
+        tree_path: &str,
+        permission: Permission,
+    ) -> Result<(), Response> {
+        let Some(access) = self.access.as_deref() else {
+            return Ok(());
+        };
+        let mut pending = vec![tree_path.to_string()];
+        while let Some(path) = pending.pop() {
+            if !access.allows(user, &path, permission) {
+                return Err(error(403, &format!("Forbidden: {}", path)));
+            }
+            let full_path = self.root.join(&path);
+            if !fs::symlink_metadata(&full_path).is_ok_and(|m| m.is_dir()) {
+                continue;
+            }
+            for entry in fs::read_dir(&full_path).map_err(io_error)?.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                pending.push(format!("{}/{}", path, name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a path that may not be the shared directory itself.
+    fn resolve_below_root(&self, tree_path: &str) -> Result<PathBuf, Response> {
+        match tree_path.is_empty() {
+            true => Err(error(400, "Invalid path")),
+            false => Ok(self.root.join(tree_path)),
+        }
+    }
+
+    /// Resolves the two sides of a move or copy: an existing source and a
+    /// target that is free, in an existing directory and not inside it.
+    fn source_and_target(
+        &self,
+        tree_path: &str,
+        destination: &str,
+    ) -> Result<(PathBuf, PathBuf), Response> {
+        let source = self.resolve_below_root(tree_path)?;
+        let target = self.resolve_below_root(destination)?;
+        fs::symlink_metadata(&source).map_err(io_error)?;
+        if fs::symlink_metadata(&target).is_ok() {
+            return Err(error(409, "The destination already exists"));
+        }
+        parent_exists(&target)?;
+        if target.starts_with(&source) {
+            return Err(error(400, "Cannot move or copy a directory into itself"));
+        }
+        Ok((source, target))
+    }
+
+    fn info(&self, tree_path: &str) -> Result<FileInfo, Response> {
+        FileInfo::read(&self.root, tree_path).map_err(io_error)
+    }
+}
+
+/// The entries of `sub_path` below `root` as a JSON array of
+/// [`FileInfo::to_json`] objects, sorted by name. Directories less than
+/// `depth` levels down carry their own listing as `entries`; linked ones
+/// are not descended into, so a link cannot make the listing loop.
+fn list_directory_json(
+    root: &Path,
+    sub_path: &str,
+    depth: usize,
+    visible: &dyn Fn(&str) -> bool,
+) -> io::Result<String> {
+    let mut names: Vec<String> = fs::read_dir(root.join(sub_path))?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    let mut files = Vec::new();
+
+    for name in names {
+        let path = match sub_path.is_empty() {
+            true => name,
+            false => format!("{}/{}", sub_path, name),
+        };
+        if !visible(&path) {
+            continue;
+        }
+        let Ok(info) = FileInfo::read(root, &path) else {
+            continue;
+        };
+        let mut json = info.to_json();
+        if info.is_dir && info.symlink_target.is_none() && depth > 1 {
+            // An unreadable directory is listed without its entries.
+            if let Ok(entries) = list_directory_json(root, &path, depth - 1, visible) {
+                json.pop();
+                json.push_str(&format!(r#","entries":{}}}"#, entries));
+            }
+        }
+        files.push(json);
+    }
+
+    Ok(format!("[{}]", files.join(",")))
+}
+
+/// Copies a file, or a directory with what `readable` allows of it, to a new
+/// `target`. Links are copied as what they point to, except links to
+/// directories, which are left out so a loop cannot be copied forever.
+fn copy_tree(
+    source: &Path,
+    target: &Path,
+    tree_path: &str,
+    readable: &dyn Fn(&str) -> bool,
+) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+    if !metadata.is_dir() {
+        return fs::copy(source, target).map(|_| ());
+    }
+
+    fs::create_dir(target)?;
+    for entry in fs::read_dir(source)?.flatten() {
+        let name = entry.file_name();
+        let path = format!("{}/{}", tree_path, name.to_string_lossy());
+        let linked_dir = entry.file_type()?.is_symlink() && entry.path().is_dir();
+        if readable(&path) && !linked_dir {
+            copy_tree(&entry.path(), &target.join(&name), &path, readable)?;
+        }
+    }
+    Ok(())
+}
+
+/// The `to` query parameter of a rename, move or copy, normalised like
+/// the path of the request.
+fn destination(request: &Request) -> Result<String, Response> {
+    let to = request
+        .query_param("to")
+        .ok_or_else(|| error(400, "Missing destination: pass it as ?to="))?;
+    match normalize_path(&percent_encode_path(&to)) {
+        Some(destination) if !destination.is_empty() => Ok(destination),
+        Some(_) => Err(error(400, "Missing destination: pass it as ?to=")),
+        None => Err(error(400, "Invalid destination")),
+    }
+}
+
+fn parent_exists(path: &Path) -> Result<(), Response> {
+    match path.parent().is_some_and(Path::is_dir) {
+        true => Ok(()),
+        false => Err(error(409, "The parent directory does not exist")),
+    }
+}
+
+/// A `404` for a path that does not exist, or a `400` naming what it
+/// should have been.
+fn not_a(path: &Path, kind: &str) -> Response {
+    match path.exists() {
+        true => error(400, &format!("Not a {}", kind)),
+        false => error(404, "No such file or directory"),
+    }
+}
+
+fn io_error(e: io::Error) -> Response {
+    let status = match e.kind() {
+        io::ErrorKind::NotFound => 404,
+        io::ErrorKind::PermissionDenied => 403,
+        io::ErrorKind::AlreadyExists | io::ErrorKind::DirectoryNotEmpty => 409,
+        _ => 500,
+    };
+    let message = match e.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        _ => e.to_string(),
+    };
+    error(status, &message)
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(error_json(status, message)).status(status)
+}
+
+fn error_json(status: u16, message: &str) -> String {
+    format!(
+        r#"{{"error":{{"status":{},"message":"{}"}}}}"#,
+        status,
+        json_escape(message)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rustserve::http::Method;
+
+    use super::*;
+    use crate::auth::{AuthOptions, Rule, Users};
+
+    fn body(response: &Response) -> String {
+        match response.get_body() {
+            Some(rustserve::http::Body::Bytes(bytes)) => String::from_utf8_lossy(bytes).to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// A writable API over a fresh directory for `test`, holding
+    /// `docs/notes.txt` and `docs/deep/more.txt`.
+    fn api(test: &str) -> Api {
+        let name = format!("rustserve-api-{}-{}", test, std::process::id());
+        let root = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs/deep")).unwrap();
+        fs::write(root.join("docs/notes.txt"), "hello").unwrap();
+        fs::write(root.join("docs/deep/more.txt"), "more").unwrap();
+        Api {
+            root,
+            access: None,
+            writable: true,
+        }
+    }
+
+    fn request(method: Method, query: &str, body: Option<&str>) -> Request {
+        let path = format!("/api/v1/x?{}", query);
+        Request::new(method, &path, HashMap::new(), body.map(Vec::from))
+    }
+
+    fn to(destination: &str) -> Request {
+        request(Method::Post, &format!("to={}", destination), None)
+    }
+
+    #[test]
+    fn test_list() {
+        let api = api("list");
+        let get = request(Method::Get, "", None);
+
+        let listing = api.list("docs", &get).unwrap();
+        assert!(body(&listing).starts_with(r#"[{"name":"deep","path":"docs/deep""#));
+        assert!(!body(&listing).contains("more.txt"));
+        let deeper = api.list("docs", &request(Method::Get, "depth=2", None));
+        assert!(body(&deeper.unwrap()).contains(r#""path":"docs/deep/more.txt""#));
+        let recursive = api.list("", &request(Method::Get, "recursive=true", None));
+        assert!(body(&recursive.unwrap()).contains(r#""path":"docs/deep/more.txt""#));
+        let depth = api.list("docs", &request(Method::Get, "depth=0", None));
+        assert_eq!(depth.unwrap_err().status_code(), 400);
+        assert_eq!(
+            api.list("docs/notes.txt", &get).unwrap_err().status_code(),
+            400
+        );
+        assert_eq!(api.list("missing", &get).unwrap_err().status_code(), 404);
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_stat() {
+        let api = api("stat");
+        let get = request(Method::Get, "", None);
+
+        let stat = api.stat("docs/notes.txt", &get).unwrap();
+        assert!(body(&stat).contains(r#""size":5"#));
+        assert!(body(&stat).contains(r#""path":"docs/notes.txt""#));
+        assert_eq!(api.handle(Api::stat, "..", &get).status_code(), 400);
+        assert_eq!(api.handle(Api::stat, "./docs/", &get).status_code(), 200);
+        assert_eq!(api.stat("missing", &get).unwrap_err().status_code(), 404);
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_download() {
+        let api = api("download");
+        let get = request(Method::Get, "", None);
+
+        let download = api.download("docs/notes.txt", &get).unwrap();
+        assert_eq!(download.status_code(), 200);
+        assert_eq!(
+            download.get_header("Content-Disposition"),
+            Some(r#"attachment; filename="notes.txt""#)
+        );
+        assert_eq!(api.download("docs", &get).unwrap_err().status_code(), 400);
+        assert_eq!(
+            api.download("docs/gone.txt", &get)
+                .unwrap_err()
+                .status_code(),
+            404
+        );
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_upload() {
+        let api = api("upload");
+        let upload = request(Method::Put, "", Some("new"));
+
+        let created = api.upload("new.txt", &upload).unwrap();
+        assert_eq!(created.status_code(), 201);
+        assert!(body(&created).contains(r#""path":"new.txt""#));
+        // Replacing a file answers as PUT /upload does.
+        let replace = request(Method::Put, "", Some("newer"));
+        let replaced = api.upload("new.txt", &replace).unwrap();
+        assert_eq!(
+            (replaced.status_code(), body(&replaced)),
+            (204, String::new())
+        );
+        assert_eq!(
+            fs::read_to_string(api.root.join("new.txt")).unwrap(),
+            "newer"
+        );
+        assert_eq!(
+            api.upload("no/new.txt", &upload).unwrap_err().status_code(),
+            409
+        );
+        assert_eq!(api.upload("docs", &upload).unwrap_err().status_code(), 409);
+        assert_eq!(api.upload("", &upload).unwrap_err().status_code(), 400);
+
+        let root = api.root.clone();
+        let read_only = Api {
+            writable: false,
+            ..api
+        };
+        assert_eq!(
+            read_only
+                .upload("other.txt", &upload)
+                .unwrap_err()
+                .status_code(),
+            403
+        );
+        assert!(!root.join("other.txt").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mkdir() {
+        let api = api("mkdir");
+        let post = request(Method::Post, "", None);
+
+        let made = api.mkdir("made", &post).unwrap();
+        assert_eq!(made.status_code(), 201);
+        assert!(body(&made).contains(r#""isDir":true"#));
+        assert!(api.root.join("made").is_dir());
+        assert_eq!(api.mkdir("made", &post).unwrap_err().status_code(), 409);
+        assert_eq!(api.mkdir("no/made", &post).unwrap_err().status_code(), 409);
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_rename() {
+        let api = api("rename");
+
+        let renamed = api.rename("docs/notes.txt", &to("renamed.txt")).unwrap();
+        assert!(body(&renamed).contains(r#""path":"docs/renamed.txt""#));
+        assert!(api.root.join("docs/renamed.txt").is_file());
+        assert_eq!(
+            api.rename("docs/renamed.txt", &to("a/b"))
+                .unwrap_err()
+                .status_code(),
+            400
+        );
+        assert_eq!(
+            api.rename("docs/renamed.txt", &to(".."))
+                .unwrap_err()
+                .status_code(),
+            400
+        );
+        assert_eq!(
+            api.rename("docs/renamed.txt", &to("deep"))
+                .unwrap_err()
+                .status_code(),
+            409
+        );
+        let post = request(Method::Post, "", None);
+        assert_eq!(
+            api.rename("docs/renamed.txt", &post)
+                .unwrap_err()
+                .status_code(),
+            400
+        );
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_move() {
+        let api = api("move");
+
+        assert_eq!(
+            api.move_entry("docs/notes.txt", &to("docs"))
+                .unwrap_err()
+                .status_code(),
+            409
+        );
+        assert_eq!(
+            api.move_entry("docs", &to("docs/deep/docs"))
+                .unwrap_err()
+                .status_code(),
+            400
+        );
+        let moved = api
+            .move_entry("docs/notes.txt", &to("/notes.txt/"))
+            .unwrap();
+        assert!(body(&moved).contains(r#""path":"notes.txt""#));
+        assert!(api.root.join("notes.txt").is_file());
+        assert!(!api.root.join("docs/notes.txt").exists());
+        assert_eq!(
+            api.move_entry("docs/notes.txt", &to("again.txt"))
+                .unwrap_err()
+                .status_code(),
+            404
+        );
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_copy() {
+        let api = api("copy");
+
+        assert_eq!(
+            api.copy("docs", &to("docs/deep/copy"))
+                .unwrap_err()
+                .status_code(),
+            400
+        );
+        let copied = api.copy("docs", &to("copy")).unwrap();
+        assert_eq!(copied.status_code(), 201);
+        assert!(body(&copied).contains(r#""path":"copy""#));
+        assert_eq!(
+            fs::read_to_string(api.root.join("copy/deep/more.txt")).unwrap(),
+            "more"
+        );
+        assert!(api.root.join("docs/deep/more.txt").is_file());
+        assert_eq!(
+            api.copy("docs", &to("copy")).unwrap_err().status_code(),
+            409
+        );
+        let post = request(Method::Post, "", None);
+        assert_eq!(api.copy("docs", &post).unwrap_err().status_code(), 400);
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_delete() {
+        let api = api("delete");
+        let delete = request(Method::Delete, "", None);
+        let recursive = request(Method::Delete, "recursive=true", None);
+
+        assert_eq!(
+            api.delete("docs/notes.txt", &delete).unwrap().status_code(),
+            204
+        );
+        assert!(!api.root.join("docs/notes.txt").exists());
+        let error = api.delete("docs", &delete).unwrap_err();
+        assert_eq!(error.status_code(), 409);
+        assert!(body(&error).starts_with(r#"{"error":{"status":409,"message":"#));
+        assert_eq!(api.delete("docs", &recursive).unwrap().status_code(), 204);
+        assert!(!api.root.join("docs").exists());
+        assert_eq!(api.delete("docs", &delete).unwrap_err().status_code(), 404);
+        assert_eq!(api.delete("", &recursive).unwrap_err().status_code(), 400);
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+
+    #[test]
+    fn test_error_bodies() {
+        let api = Api {
+            access: Some(Arc::new(Access::new(AuthOptions {
+                users: Users::Single {
+                    user: "alice".to_string(),
+                    password: "secret".to_string(),
+                },
+                protect: Vec::new(),
+                rules: vec![Rule {
+                    users: Some(vec!["alice".to_string()]),
+                    path: "docs".to_string(),
+                    allow: Permission::parse_list("read, list").unwrap(),
+                }],
+            }))),
+            ..api("errors")
+        };
+        let as_alice = |method| {
+            let credentials = rustserve::base64::encode(b"alice:secret");
+            let headers = HashMap::from([(
+                "Authorization".to_string(),
+                format!("Basic {}", credentials),
+            )]);
+            Request::new(method, "/api/v1/x", headers, None)
+        };
+        let json = |status, message| {
+            format!(
+                r#"{{"error":{{"status":{},"message":"{}"}}}}"#,
+                status, message
+            )
+        };
+
+        let missing = api
+            .stat("docs/gone.txt", &as_alice(Method::Get))
+            .unwrap_err();
+        assert_eq!(missing.status_code(), 404);
+        assert_eq!(body(&missing), json(404, "No such file or directory"));
+        assert_eq!(missing.get_header("Content-Type"), Some("application/json"));
+
+        let anonymous = request(Method::Get, "", None);
+        let unauthorized = api.stat("docs/notes.txt", &anonymous).unwrap_err();
+        assert_eq!(body(&unauthorized), json(401, "Authentication required"));
+        assert_eq!(
+            unauthorized.get_header("Content-Type"),
+            Some("application/json")
+        );
+
+        let forbidden = api
+            .upload("docs/new.txt", &as_alice(Method::Put))
+            .unwrap_err();
+        assert_eq!(body(&forbidden), json(403, "Forbidden"));
+        let outside = api.download("elsewhere.txt", &as_alice(Method::Get));
+        assert_eq!(body(&outside.unwrap_err()), json(403, "Forbidden"));
+        let invalid = api.handle(Api::stat, "docs/%2e%2e/..", &as_alice(Method::Get));
+        assert_eq!(body(&invalid), json(400, "Invalid path"));
+
+        fs::remove_dir_all(&api.root).unwrap();
+    }
+}
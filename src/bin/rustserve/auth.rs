@@ -0,0 +1,645 @@
+//! Who may access which parts of the shared directory.
+//!
+//! Credentials come either from a single `--auth USER:PASSWORD` pair or from
+//! an htpasswd file whose hashes use SHA-256-crypt (`$5$`, as produced by
+//! `htpasswd -2`, `openssl passwd -5` or `rustserve --hash-password`).
+//! Access control rules from the configuration file then decide what each
+//! user may do below which path.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+
+use rustserve::crypto::{Sha256, constant_time_eq, sha256};
+use rustserve::http::{Request, Response};
+
+use crate::config::{ConfigError, split_list};
+
+const REALM: &str = "rustserve";
+const DEFAULT_ROUNDS: u32 = 5000;
+/// Verified credentials remembered so browsers re-sending them with every
+/// request do not pay for thousands of hash rounds each time.
+const CACHE_LIMIT: usize = 1024;
+/// Checked in place of an unknown user's hash, so the time taken does not
+/// reveal which names exist; no password matches it.
+const DUMMY_HASH: &str = "$5$unknownuserxxx$AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+const CRYPT_ALPHABET: &[u8; 64] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Users {
+    Single { user: String, password: String },
+    Htpasswd(Vec<(String, String)>),
+}
+
+impl Users {
+    /// Reads an htpasswd file of `user:hash` lines.
+    pub fn load_htpasswd(path: &Path) -> Result<Users, Vec<ConfigError>> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            vec![ConfigError {
+                line: 0,
+                message: format!("cannot read {}: {}", path.display(), e),
+            }]
+        })?;
+
+        let mut users = Vec::new();
+        let mut errors = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let message = match line.split_once(':') {
+                Some((user, hash)) if hash.starts_with("$5$") && !user.is_empty() => {
+                    users.push((user.to_string(), hash.to_string()));
+                    continue;
+                }
+                Some((_, _)) => "unsupported hash; use SHA-256-crypt ($5$), e.g. `htpasswd -2`",
+                None => "expected `user:hash`",
+            };
+            errors.push(ConfigError {
+                line: line_number,
+                message: message.to_string(),
+            });
+        }
+
+        match errors.is_empty() {
+            true => Ok(Users::Htpasswd(users)),
+            false => Err(errors),
+        }
+    }
+
+    fn verify(&self, user: &str, password: &str) -> bool {
+        match self {
+            Users::Single {
+                user: expected_user,
+                password: expected_password,
+            } => {
+                // Evaluate both so the timing does not reveal which was wrong.
+                let user_ok = constant_time_eq(user.as_bytes(), expected_user.as_bytes());
+                let password_ok =
+                    constant_time_eq(password.as_bytes(), expected_password.as_bytes());
+                user_ok & password_ok
+            }
+            Users::Htpasswd(users) => match users.iter().find(|(name, _)| name == user) {
+                Some((_, hash)) => verify_password(password, hash),
+                None => {
+                    verify_password(password, DUMMY_HASH);
+                    false
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    /// Download files.
+    Read,
+    /// See a directory's entries.
+    List,
+    Upload,
+    Delete,
+    /// See the `/_admin` statistics; only meaningful on the root.
+    Admin,
+}
+
+impl Permission {
+    /// Parses a comma-separated list such as `"read, list"`.
+    pub fn parse_list(list: &str) -> Result<Vec<Permission>, String> {
+        let permissions = split_list(list)
+            .iter()
+            .map(|name| match name.as_str() {
+                "read" => Ok(Permission::Read),
+                "list" => Ok(Permission::List),
+                "upload" => Ok(Permission::Upload),
+                "delete" => Ok(Permission::Delete),
+                "admin" => Ok(Permission::Admin),
+                other => Err(format!(
+                    "unknown permission `{}`; expected read, list, upload, delete or admin",
+                    other
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match permissions.is_empty() {
+            true => Err("`allow` lists no permissions".to_string()),
+            false => Ok(permissions),
+        }
+    }
+}
+
+/// Grants `allow` below `path` to `users`, or to everyone when `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub users: Option<Vec<String>>,
+    pub path: String,
+    pub allow: Vec<Permission>,
+}
+
+impl Rule {
+    fn applies_to(&self, user: Option<&str>) -> bool {
+        match (&self.users, user) {
+            (None, _) => true,
+            (Some(users), Some(user)) => users.iter().any(|u| u == user),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Credentials plus the parts of the tree that require them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthOptions {
+    pub users: Users,
+    /// Paths below the root that need credentials; empty protects everything.
+    pub protect: Vec<String>,
+    /// Access control rules; empty lets every authenticated user do anything.
+    pub rules: Vec<Rule>,
+}
+
+pub struct Access {
+    options: AuthOptions,
+    verified: Mutex<HashSet<[u8; 32]>>,
+}
+
+impl Access {
+    pub fn new(options: AuthOptions) -> Self {
+        Access {
+            options,
+            verified: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Checks a request to use `permission` on `tree_path`, a path below
+    /// the shared directory as [`normalize_path`] gives it, which must be
+    /// the path the file is then found at. Returns the authenticated user,
+    /// if any, or the `401` or `403` to send.
+    ///
+    /// [`normalize_path`]: rustserve::http::normalize_path
+    pub fn check(
+        &self,
+        request: &Request,
+        tree_path: &str,
+        permission: Permission,
+    ) -> Result<Option<String>, Response> {
+        let user = match request.basic_auth() {
+            Some((user, password)) if self.verify(&user, &password) => {
+                request.set_user(&user);
+                Some(user)
+            }
+            Some(_) => return Err(unauthorized()),
+            None if self.is_protected(tree_path) => return Err(unauthorized()),
+            None => None,
+        };
+
+        match (self.allows(user.as_deref(), tree_path, permission), &user) {
+            (true, _) => Ok(user),
+            (false, None) => Err(unauthorized()),
+            (false, Some(_)) => Err(Response::new(403).body("Forbidden")),
+        }
+    }
+
+    /// Checks a request for the admin pages, which always need a signed-in
+    /// user with [`Permission::Admin`] on the root, whatever is protected.
+    pub fn check_admin(&self, request: &Request) -> Result<String, Response> {
+        match request.basic_auth() {
+            Some((user, password)) if self.verify(&user, &password) => {
+                request.set_user(&user);
+                match self.allows(Some(&user), "", Permission::Admin) {
+                    true => Ok(user),
+                    false => Err(Response::new(403).body("Forbidden")),
+                }
+            }
+            _ => Err(unauthorized()),
+        }
+    }
+
+    /// Whether `user` may use `permission` on `tree_path`. Listing is also
+    /// allowed on the directories leading to a path the user has rights
+    /// below, so they can navigate there.
+    pub fn allows(&self, user: Option<&str>, tree_path: &str, permission: Permission) -> bool {
+        if self.options.rules.is_empty() {
+            return true;
+        }
+        let path = segments(tree_path);
+        self.rules_for(user).any(|(rule_path, rule)| {
+            (path.starts_with(&rule_path) && rule.allow.contains(&permission))
+                || (permission == Permission::List && rule_path.starts_with(&path))
+        })
+    }
+
+    /// Whether `user` may see the entry at `tree_path` in a listing: they
+    /// need some right on it, below it or above it.
+    pub fn visible(&self, user: Option<&str>, tree_path: &str) -> bool {
+        if self.options.rules.is_empty() {
+            return true;
+        }
+        let path = segments(tree_path);
+        self.rules_for(user)
+            .any(|(rule_path, _)| path.starts_with(&rule_path) || rule_path.starts_with(&path))
+    }
+
+    /// Whether a listing, search or archive should include the entry at
+    /// `tree_path` for `user`: it must be [`visible`](Self::visible) to
+    /// them and, if they are anonymous, not protected.
+    pub fn shows(&self, user: Option<&str>, tree_path: &str) -> bool {
+        (user.is_some() || !self.is_protected(tree_path)) && self.visible(user, tree_path)
+    }
+
+    /// Whether `user` could download the file at `tree_path` on its own,
+    /// so it may be bundled into an archive or copied for them.
+    pub fn readable(&self, user: Option<&str>, tree_path: &str) -> bool {
+        self.shows(user, tree_path) && self.allows(user, tree_path, Permission::Read)
+    }
+
+    fn rules_for(&self, user: Option<&str>) -> impl Iterator<Item = (Vec<String>, &Rule)> {
+        self.options
+            .rules
+            .iter()
+            .filter(move |rule| rule.applies_to(user))
+            .map(|rule| (segments(&rule.path), rule))
+    }
+
+    fn verify(&self, user: &str, password: &str) -> bool {
+        let key = sha256(format!("{}:{}", user, password).as_bytes());
+        if self.verified.lock().unwrap().contains(&key) {
+            return true;
+        }
+        if !self.options.users.verify(user, password) {
+            return false;
+        }
+
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= CACHE_LIMIT {
+            verified.clear();
+        }
+        verified.insert(key);
+        true
+    }
+
+    fn is_protected(&self, tree_path: &str) -> bool {
+        if self.options.protect.is_empty() {
+            return true;
+        }
+        let path = segments(tree_path);
+        self.options
+            .protect
+            .iter()
+            .any(|prefix| path.starts_with(&segments(prefix)))
+    }
+}
+
+fn unauthorized() -> Response {
+    Response::unauthorized(&format!("Basic realm=\"{}\", charset=\"UTF-8\"", REALM))
+}
+
+/// The segments of a decoded path without empty or `.` ones, so `a//b/`
+/// and `./a/b` compare as `a/b`.
+fn segments(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .map(str::to_string)
+        .collect()
+}
+
+/// Fills `buf` from the operating system's random number generator.
+pub fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    File::open("/dev/urandom")?.read_exact(buf)
+}
+
+/// Hashes `password` with SHA-256-crypt and a fresh random salt.
+pub fn hash_password(password: &str) -> io::Result<String> {
+    let mut random = [0u8; 12];
+    random_bytes(&mut random)?;
+    let salt: String = random
+        .iter()
+        .flat_map(|b| [b >> 2, b & 0x3f])
+        .take(16)
+        .map(|i| CRYPT_ALPHABET[i as usize] as char)
+        .collect();
+    Ok(sha256_crypt(password.as_bytes(), &salt, None))
+}
+
+/// Checks `password` against a `$5$[rounds=N$]salt$hash` string.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let Some(rest) = hash.strip_prefix("$5$") else {
+        return false;
+    };
+    let (rounds, rest) = match rest.strip_prefix("rounds=") {
+        Some(rest) => match rest.split_once('$') {
+            Some((rounds, rest)) => match rounds.parse::<u32>() {
+                Ok(rounds) => (Some(rounds), rest),
+                Err(_) => return false,
+            },
+            None => return false,
+        },
+        None => (None, rest),
+    };
+    let Some((salt, _)) = rest.split_once('$') else {
+        return false;
+    };
+
+    let computed = sha256_crypt(password.as_bytes(), salt, rounds);
+    constant_time_eq(computed.as_bytes(), hash.as_bytes())
+}
+
+/// SHA-256-crypt as specified by Ulrich Drepper.
+fn sha256_crypt(password: &[u8], salt: &str, rounds: Option<u32>) -> String {
+    let salt = &salt.as_bytes()[..salt.len().min(16)];
+    let rounds_used = rounds.map_or(DEFAULT_ROUNDS, |r| r.clamp(1000, 999_999_999));
+
+    let digest_b = {
+        let mut ctx = Sha256::new();
+        ctx.update(password);
+        ctx.update(salt);
+        ctx.update(password);
+        ctx.finish()
+    };
+
+    let digest_a = {
+        let mut ctx = Sha256::new();
+        ctx.update(password);
+        ctx.update(salt);
+        ctx.update(&repeat(&digest_b, password.len()));
+        let mut n = password.len();
+        while n > 0 {
+            if n & 1 == 1 {
+                ctx.update(&digest_b);
+            } else {
+                ctx.update(password);
+            }
+            n >>= 1;
+        }
+        ctx.finish()
+    };
+
+    let p_bytes = {
+        let mut ctx = Sha256::new();
+        for _ in 0..password.len() {
+            ctx.update(password);
+        }
+        repeat(&ctx.finish(), password.len())
+    };
+
+    let s_bytes = {
+        let mut ctx = Sha256::new();
+        for _ in 0..16 + digest_a[0] as usize {
+            ctx.update(salt);
+        }
+        repeat(&ctx.finish(), salt.len())
+    };
+
+    let mut c = digest_a;
+    for i in 0..rounds_used {
+        let mut ctx = Sha256::new();
+        if i % 2 == 1 {
+            ctx.update(&p_bytes);
+        } else {
+            ctx.update(&c);
+        }
+        if i % 3 != 0 {
+            ctx.update(&s_bytes);
+        }
+        if i % 7 != 0 {
+            ctx.update(&p_bytes);
+        }
+        if i % 2 == 1 {
+            ctx.update(&c);
+        } else {
+            ctx.update(&p_bytes);
+        }
+        c = ctx.finish();
+    }
+
+    let mut out = String::from("$5$");
+    if rounds.is_some() {
+        out.push_str(&format!("rounds={}$", rounds_used));
+    }
+    out.push_str(&String::from_utf8_lossy(salt));
+    out.push('$');
+
+    const ORDER: [(usize, usize, usize); 10] = [
+        (0, 10, 20),
+        (21, 1, 11),
+        (12, 22, 2),
+        (3, 13, 23),
+        (24, 4, 14),
+        (15, 25, 5),
+        (6, 16, 26),
+        (27, 7, 17),
+        (18, 28, 8),
+        (9, 19, 29),
+    ];
+    let mut push = |b2: u8, b1: u8, b0: u8, n: usize| {
+        let mut w = (b2 as u32) << 16 | (b1 as u32) << 8 | b0 as u32;
+        for _ in 0..n {
+            out.push(CRYPT_ALPHABET[(w & 0x3f) as usize] as char);
+            w >>= 6;
+        }
+    };
+    for (x, y, z) in ORDER {
+        push(c[x], c[y], c[z], 4);
+    }
+    push(0, c[31], c[30], 3);
+    out
+}
+
+/// `digest` repeated and truncated to `len` bytes.
+fn repeat(digest: &[u8; 32], len: usize) -> Vec<u8> {
+    digest.iter().copied().cycle().take(len).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustserve::http::normalize_path;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_sha256_crypt() {
+        // Reference values from `openssl passwd -5`.
+        assert_eq!(
+            sha256_crypt(b"Hello world!", "saltstring", None),
+            "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5"
+        );
+        assert_eq!(
+            sha256_crypt(b"Hello world!", "saltstringsaltstring", Some(10000)),
+            "$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA"
+        );
+        assert_eq!(
+            sha256_crypt(&[b'a'; 70], "x", None),
+            "$5$x$axDQZkhK0ZRj65Q2wYwDv2MrERYVRjAfx2UzCKdz0YB"
+        );
+
+        let hash = hash_password("open sesame").unwrap();
+        assert!(verify_password("open sesame", &hash));
+        assert!(!verify_password("open sesame!", &hash));
+    }
+
+    #[test]
+    fn test_protected_paths() {
+        let access = Access::new(AuthOptions {
+            users: Users::Single {
+                user: "alice".to_string(),
+                password: "secret".to_string(),
+            },
+            protect: vec!["private/".to_string()],
+            rules: Vec::new(),
+        });
+        let request = |credentials: Option<&str>| {
+            let mut headers = HashMap::new();
+            if let Some(credentials) = credentials {
+                let encoded = rustserve::base64::encode(credentials.as_bytes());
+                headers.insert("Authorization".to_string(), format!("Basic {}", encoded));
+            }
+            Request::new(rustserve::http::Method::Get, "/", headers, None)
+        };
+
+        // Paths are checked as the routes see them, normalised from the URL.
+        let read = |credentials, url_path| {
+            let tree_path = normalize_path(url_path).unwrap();
+            access.check(&request(credentials), &tree_path, Permission::Read)
+        };
+        assert_eq!(read(None, "public/a.txt").ok(), Some(None));
+        assert_eq!(read(None, "privateer").ok(), Some(None));
+        let denied = read(None, "private//a%20b.txt").unwrap_err();
+        assert_eq!(denied.status_code(), 401);
+        assert!(read(Some("alice:nope"), "private").is_err());
+        for sneaky in ["./private/s.txt", "%2e/private/s.txt", "%2E//private/"] {
+            let denied = read(None, sneaky).unwrap_err();
+            assert_eq!(denied.status_code(), 401, "{}", sneaky);
+        }
+        let check = |path| access.check(&request(None), path, Permission::Read);
+        assert_eq!(check("./private").unwrap_err().status_code(), 401);
+        assert_eq!(
+            read(Some("alice:secret"), "private/x").ok(),
+            Some(Some("alice".to_string()))
+        );
+        let forged = request(Some("admin:guess"));
+        assert!(access.check(&forged, "public", Permission::Read).is_err());
+        assert_eq!(forged.user(), None);
+        let signed_in = request(Some("alice:secret"));
+        access
+            .check(&signed_in, "public", Permission::Read)
+            .unwrap();
+        assert_eq!(signed_in.user(), Some("alice"));
+
+        // Listings and archives leave out what an anonymous user cannot open.
+        assert!(access.shows(None, "public/a.txt"));
+        assert!(!access.shows(None, "private"));
+        assert!(!access.readable(None, "private/s.txt"));
+        assert!(access.readable(None, "privateer"));
+        assert!(access.shows(Some("alice"), "private"));
+        assert!(access.readable(Some("alice"), "private/s.txt"));
+    }
+
+    #[test]
+    fn test_acl() {
+        let rule = |users: Option<&[&str]>, path: &str, allow: &str| Rule {
+            users: users.map(|u| u.iter().map(|s| s.to_string()).collect()),
+            path: path.to_string(),
+            allow: Permission::parse_list(allow).unwrap(),
+        };
+        let access = Access::new(AuthOptions {
+            users: Users::Htpasswd(Vec::new()),
+            protect: Vec::new(),
+            rules: vec![
+                rule(
+                    Some(&["alice", "bob"]),
+                    "/teams/design",
+                    "read, list, upload",
+                ),
+                rule(Some(&["carol"]), "teams/ops", "read"),
+                rule(None, "public", "read,list"),
+            ],
+        });
+
+        let alice = Some("alice");
+        assert!(access.allows(alice, "teams/design/logo.svg", Permission::Upload));
+        assert!(!access.allows(alice, "teams/design/logo.svg", Permission::Delete));
+        assert!(!access.allows(alice, "teams/ops/runbook.md", Permission::Read));
+        assert!(access.allows(alice, "teams", Permission::List));
+        assert!(access.allows(alice, "", Permission::List));
+        assert!(!access.allows(alice, "teams", Permission::Read));
+        assert!(access.allows(None, "public/a", Permission::Read));
+        assert!(!access.allows(None, "teams", Permission::List));
+
+        assert!(access.visible(alice, "teams/design"));
+        assert!(!access.visible(alice, "teams/ops"));
+        assert!(access.visible(Some("carol"), "teams/ops/x"));
+        assert!(access.visible(None, "public"));
+        assert!(!access.visible(None, "teams"));
+
+        assert!(Permission::parse_list("read, write").is_err());
+        assert!(Permission::parse_list(" ,").is_err());
+    }
+
+    #[test]
+    fn test_check_admin() {
+        let request = |credentials: &str| {
+            let encoded = rustserve::base64::encode(credentials.as_bytes());
+            let headers =
+                HashMap::from([("Authorization".to_string(), format!("Basic {}", encoded))]);
+            Request::new(rustserve::http::Method::Get, "/_admin", headers, None)
+        };
+        let users = Users::Htpasswd(vec![
+            ("alice".to_string(), hash_password("a").unwrap()),
+            ("bob".to_string(), hash_password("b").unwrap()),
+        ]);
+        let open = Access::new(AuthOptions {
+            users: users.clone(),
+            protect: vec!["private".to_string()],
+            rules: Vec::new(),
+        });
+        let anonymous = Request::new(
+            rustserve::http::Method::Get,
+            "/_admin",
+            HashMap::new(),
+            None,
+        );
+        assert_eq!(open.check_admin(&anonymous).unwrap_err().status_code(), 401);
+        assert_eq!(
+            open.check_admin(&request("bob:a"))
+                .unwrap_err()
+                .status_code(),
+            401
+        );
+        assert_eq!(
+            open.check_admin(&request("bob:b")).ok(),
+            Some("bob".to_string())
+        );
+        assert!(users.verify("alice", "a"));
+        assert!(!users.verify("carol", "a"));
+
+        let ruled = Access::new(AuthOptions {
+            users,
+            protect: Vec::new(),
+            rules: vec![
+                Rule {
+                    users: Some(vec!["alice".to_string()]),
+                    path: "/".to_string(),
+                    allow: vec![Permission::Admin],
+                },
+                Rule {
+                    users: None,
+                    path: "/".to_string(),
+                    allow: vec![Permission::Read, Permission::List],
+                },
+            ],
+        });
+        assert_eq!(
+            ruled.check_admin(&request("alice:a")).ok(),
+            Some("alice".to_string())
+        );
+        assert_eq!(
+            ruled
+                .check_admin(&request("bob:b"))
+                .unwrap_err()
+                .status_code(),
+            403
+        );
+    }
+}
@@ -0,0 +1,1411 @@
+//! Command-line options for the `rustserve` binary.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rustserve::http::{Backend, Compression, LogFormat, ServerConfig};
+
+use crate::auth::{AuthOptions, Permission, Rule, Users};
+use crate::config::{CertificateEntry, Config, Mount, Subject, split_list};
+use crate::share::ShareRequest;
+
+pub const USAGE: &str = "\
+Usage: rustserve [OPTIONS] [DIRECTORY] [PORT]
+
+Share DIRECTORY (default: the current directory) over HTTP or HTTPS.
+
+Options:
+  -b, --bind <ADDR>       Address to listen on [env: RUSTSERVE_BIND] [default: 0.0.0.0]
+  -p, --port <PORT>       Port to listen on [env: RUSTSERVE_PORT] [default: 8080]
+  -t, --threads <N>       Worker threads [env: RUSTSERVE_THREADS] [default: 20]
+      --backend <NAME>    Connection handling: threaded or epoll [env: RUSTSERVE_BACKEND]
+                          [default: epoll if built in, otherwise threaded]
+      --read-only         Refuse uploads (the default)
+      --upload            Accept uploads with PUT /upload/<path>, deletions
+                          with DELETE /upload/<path> and changes through the
+                          file API under /api/v1
+      --max-body <SIZE>   Largest accepted request body, e.g. 512K or 2G
+                          [env: RUSTSERVE_MAX_BODY] [default: 100M]
+      --no-compression    Send every response uncompressed
+  -q, --quiet, --no-dashboard
+                          Print no live statistics: neither the dashboard drawn
+                          on a terminal nor the summary logged every minute
+                          when output goes elsewhere
+      --metrics           Serve Prometheus metrics at /metrics
+      --admin             Serve live statistics at /_admin to users with the
+                          admin permission; needs --auth or --htpasswd
+      --site              Serve files at their own paths instead of a listing
+      --fallback <FILE>   With --site, serve FILE for unknown paths (single-page apps)
+      --404 <FILE>        With --site, send FILE with 404 Not Found for unknown paths
+      --auth <USER:PASS>  Require this user name and password [env: RUSTSERVE_AUTH]
+      --htpasswd <FILE>   Require a user from an htpasswd file of SHA-256-crypt
+                          hashes [env: RUSTSERVE_HTPASSWD]
+      --protect <PATH>    Only require credentials below PATH; repeatable
+                          [env: RUSTSERVE_PROTECT, comma-separated]
+      --hash-password     Read a password from stdin and print an htpasswd hash
+      --share-keys <FILE> Sign share links with the keys in FILE, which is
+                          created when first needed [env: RUSTSERVE_SHARE_KEYS]
+      --share <PATH>      Print a signed link to the file at PATH and exit
+      --expires <TIME>    How long a --share link works, e.g. 30m, 12h or 7d
+                          [default: 1d]
+      --downloads <N>     How many times a --share link may be used
+      --allow-ip <ADDR>   Only accept a --share link from ADDR
+      --base-url <URL>    Start a --share link with URL [default: this machine]
+      --revoke-key <ID>   Delete a share key, disabling every link it signed
+      --thumbnail-cache <DIR>
+                          Keep the gallery's image thumbnails in DIR
+                          [env: RUSTSERVE_THUMBNAIL_CACHE]
+                          [default: rustserve-thumbnails in the temp directory]
+      --tls-cert <FILE>   Serve HTTPS with the PEM certificate chain in FILE
+                          [env: RUSTSERVE_TLS_CERT]
+      --tls-key <FILE>    The PEM private key for --tls-cert [env: RUSTSERVE_TLS_KEY]
+      --self-signed       Serve HTTPS with a throwaway self-signed certificate
+      --redirect-http <PORT>
+                          With HTTPS, redirect plain HTTP on PORT to it
+                          [env: RUSTSERVE_REDIRECT_HTTP]
+      --access-log <FILE> Log every request to FILE, or to stdout if FILE is -
+                          [env: RUSTSERVE_ACCESS_LOG]
+      --log-format <NAME> Access log lines: common, combined or json
+                          [env: RUSTSERVE_LOG_FORMAT] [default: combined]
+      --log-max-size <SIZE>
+                          Rotate the access log before it grows past SIZE
+                          [env: RUSTSERVE_LOG_MAX_SIZE]
+      --log-max-age <TIME>
+                          Rotate the access log after TIME, e.g. 1d
+                          [env: RUSTSERVE_LOG_MAX_AGE]
+  -c, --config <FILE>     Read settings from FILE [env: RUSTSERVE_CONFIG]
+      --check-config      Report problems in the configuration file and exit
+  -h, --help              Print this help
+  -V, --version           Print the version
+
+Options not given on the command line are read from the environment, then
+from the configuration file. Flags are set in the environment with 1, true or
+yes, e.g. RUSTSERVE_UPLOAD=1 or RUSTSERVE_NO_DASHBOARD=true. The file's keys are the long option names, with
+`compression`, `dashboard`, `metrics`, `admin`, `upload` and `self-signed`
+taking true or false, `not-found` standing in for --404, `protect` taking a
+comma-separated list, `share-keys`, `thumbnail-cache`, `[[mount]]` tables
+(`url`, `directory`) serving further directories below a URL prefix,
+`[[group]]` (`name`, `members`) and `[[acl]]` tables (`user` or `group`,
+`path`, `allow` from read, list, upload, delete and admin) limiting what each
+user may do below a path,
+and `[[certificate]]` tables (`name`, `cert`, `key`) presenting another
+certificate to HTTPS clients asking for `name`, which may start with `*.`.";
+
+const DEFAULT_MAX_BODY: usize = 100 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Serve(Box<Options>),
+    /// Report on a configuration file instead of serving.
+    CheckConfig {
+        path: PathBuf,
+        problems: Vec<String>,
+    },
+    /// Hash a password read from stdin for an htpasswd file.
+    HashPassword,
+    /// Print a link to `link.path` below `directory`, signed with a key
+    /// from `keys`, starting with `base_url` or else this machine's address,
+    /// with `https` if the server uses TLS.
+    Share {
+        keys: PathBuf,
+        directory: PathBuf,
+        port: u16,
+        https: bool,
+        link: ShareRequest,
+        base_url: Option<String>,
+    },
+    /// Delete share key `id` from the `keys` file.
+    RevokeKey {
+        keys: PathBuf,
+        id: String,
+    },
+    Help,
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub directory: PathBuf,
+    pub bind: String,
+    pub port: u16,
+    pub threads: usize,
+    pub backend: Backend,
+    pub upload: bool,
+    pub max_body_size: usize,
+    pub compression: bool,
+    pub dashboard: bool,
+    pub metrics: bool,
+    pub admin: bool,
+    pub site: Option<SiteOptions>,
+    pub mounts: Vec<Mount>,
+    pub auth: Option<AuthOptions>,
+    pub share_keys: Option<PathBuf>,
+    /// Where thumbnails are kept; `None` for the temp directory.
+    pub thumbnail_cache: Option<PathBuf>,
+    pub tls: Option<TlsOptions>,
+    pub access_log: Option<AccessLog>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLog {
+    /// `None` for standard output.
+    pub file: Option<PathBuf>,
+    pub format: LogFormat,
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsOptions {
+    pub certificate: CertificateSource,
+    /// Certificates chosen by the name a client asks for, with their files
+    /// resolved.
+    pub sni: Vec<CertificateEntry>,
+    pub redirect_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CertificateSource {
+    Files { cert: PathBuf, key: PathBuf },
+    SelfSigned,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SiteOptions {
+    pub fallback: Option<PathBuf>,
+    pub not_found: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn server_config(&self) -> ServerConfig {
+        let mut config = ServerConfig::new(self.bind.clone(), self.port)
+            .threads(self.threads)
+            .backend(self.backend)
+            .max_body_size(self.max_body_size);
+        if self.compression {
+            config = config.compression(Compression::default());
+        }
+        config
+    }
+}
+
+/// A problem with the command line, reported before anything starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+fn error<T>(message: impl Into<String>) -> Result<T, CliError> {
+    Err(CliError(message.into()))
+}
+
+/// Parses the arguments after the program name. Options not given there are
+/// taken from `env`, then from the configuration file, then from defaults.
+pub fn parse(
+    args: impl IntoIterator<Item = String>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Command, CliError> {
+    let mut args = args.into_iter();
+    let mut values: Vec<(&'static str, String)> = Vec::new();
+    let mut flags: Vec<&'static str> = Vec::new();
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg);
+            continue;
+        }
+
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        match name {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--hash-password" => return Ok(Command::HashPassword),
+            "--read-only" | "--upload" | "--no-compression" | "-q" | "--quiet"
+            | "--no-dashboard" | "--site" | "--check-config" | "--self-signed" | "--metrics"
+            | "--admin" => {
+                if inline.is_some() {
+                    return error(format!("{} does not take a value", name));
+                }
+                flags.push(canonical(name));
+            }
+            "-b" | "--bind" | "-p" | "--port" | "-t" | "--threads" | "--backend" | "--max-body"
+            | "--fallback" | "--404" | "-c" | "--config" | "--auth" | "--htpasswd"
+            | "--protect" | "--share-keys" | "--share" | "--expires" | "--downloads"
+            | "--allow-ip" | "--base-url" | "--revoke-key" | "--thumbnail-cache" | "--tls-cert"
+            | "--tls-key" | "--redirect-http" | "--access-log" | "--log-format"
+            | "--log-max-size" | "--log-max-age" => {
+                let value = match inline {
+                    Some(value) => value,
+                    None => match args.next() {
+                        Some(value) => value,
+                        None => return error(format!("{} requires a value", name)),
+                    },
+                };
+                values.push((canonical(name), value));
+            }
+            _ => return error(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    if positional.len() > 2 {
+        return error(format!("unexpected argument '{}'", positional[2]));
+    }
+
+    let mut sources = Sources {
+        values,
+        flags,
+        positional,
+        env: &env,
+        config: None,
+    };
+    let check = sources.flag("--check-config");
+
+    if let Some(path) = sources.value("--config") {
+        let path = PathBuf::from(path.value);
+        match Config::load(&path) {
+            Ok(config) => sources.config = Some(config),
+            Err(errors) => {
+                let problems = errors
+                    .iter()
+                    .map(|e| format!("{}:{}: {}", path.display(), e.line, e.message))
+                    .collect();
+                return match check {
+                    true => Ok(Command::CheckConfig { path, problems }),
+                    false => Err(CliError(problems.join("\n"))),
+                };
+            }
+        }
+    }
+
+    if check {
+        let Some(config) = &sources.config else {
+            return error("--check-config needs a file from --config or RUSTSERVE_CONFIG");
+        };
+        let path = config.path.clone();
+        let problems = match sources.resolve() {
+            Ok(_) => Vec::new(),
+            Err(e) => vec![e.0],
+        };
+        return Ok(Command::CheckConfig { path, problems });
+    }
+
+    if let Some(id) = sources.given("--revoke-key") {
+        let Some(keys) = sources.resolve()?.share_keys else {
+            return error("--revoke-key needs --share-keys");
+        };
+        return Ok(Command::RevokeKey { keys, id: id.value });
+    }
+
+    if let Some(path) = sources.given("--share") {
+        let link = sources.share_request(path.value)?;
+        let options = sources.resolve()?;
+        let Some(keys) = options.share_keys else {
+            return error("--share needs --share-keys");
+        };
+        let base_url = sources
+            .given("--base-url")
+            .map(|url| url.value.trim_end_matches('/').to_string());
+        return Ok(Command::Share {
+            keys,
+            directory: options.directory,
+            port: options.port,
+            https: options.tls.is_some(),
+            link,
+            base_url,
+        });
+    }
+
+    for option in ["--expires", "--downloads", "--allow-ip", "--base-url"] {
+        if sources.given(option).is_some() {
+            return error(format!("{} only applies to --share", option));
+        }
+    }
+
+    sources
+        .resolve()
+        .map(|options| Command::Serve(Box::new(options)))
+}
+
+/// A setting's value and where it came from, so errors can point at it.
+struct Setting {
+    value: String,
+    origin: String,
+}
+
+impl Setting {
+    fn error<T>(&self, message: impl fmt::Display) -> Result<T, CliError> {
+        error(format!("{}{}", self.origin, message))
+    }
+}
+
+/// Everything options are resolved from, in order of precedence.
+struct Sources<'a> {
+    values: Vec<(&'static str, String)>,
+    flags: Vec<&'static str>,
+    positional: Vec<String>,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    config: Option<Config>,
+}
+
+impl Sources<'_> {
+    /// The last occurrence of an option wins, then the environment, then
+    /// the configuration file.
+    fn value(&self, option: &str) -> Option<Setting> {
+        self.lookup(option, false)
+    }
+
+    /// Like [`Sources::value`] for a path, which the configuration file
+    /// gives relative to itself.
+    fn path_value(&self, option: &str) -> Option<Setting> {
+        self.lookup(option, true)
+    }
+
+    /// Every occurrence of a repeatable option, or else the comma-separated
+    /// items of its environment variable or configuration key.
+    fn values(&self, option: &str) -> Vec<Setting> {
+        let given: Vec<Setting> = self
+            .values
+            .iter()
+            .filter(|(n, _)| *n == option)
+            .map(|(_, value)| Setting {
+                value: value.clone(),
+                origin: String::new(),
+            })
+            .collect();
+        if !given.is_empty() {
+            return given;
+        }
+
+        let Some(list) = self.value(option) else {
+            return Vec::new();
+        };
+        split_list(&list.value)
+            .into_iter()
+            .map(|value| Setting {
+                value,
+                origin: list.origin.clone(),
+            })
+            .collect()
+    }
+
+    /// An option from the command line alone, for those that make no sense
+    /// as lasting settings.
+    fn given(&self, option: &str) -> Option<Setting> {
+        let (_, value) = self.values.iter().rev().find(|(n, _)| *n == option)?;
+        Some(Setting {
+            value: value.clone(),
+            origin: String::new(),
+        })
+    }
+
+    fn share_request(&self, path: String) -> Result<ShareRequest, CliError> {
+        let expires_in = match self.given("--expires") {
+            Some(expires) => match parse_duration(&expires.value) {
+                Some(seconds) if seconds > 0 => seconds,
+                _ => return error(format!("invalid duration '{}'", expires.value)),
+            },
+            None => 24 * 60 * 60,
+        };
+        let downloads = match self.given("--downloads") {
+            Some(downloads) => match downloads.value.parse::<u32>() {
+                Ok(count) if count > 0 => Some(count),
+                _ => return error(format!("invalid download count '{}'", downloads.value)),
+            },
+            None => None,
+        };
+        let ip = match self.given("--allow-ip") {
+            Some(ip) => match ip.value.parse::<IpAddr>() {
+                Ok(ip) => Some(ip),
+                Err(_) => return error(format!("invalid IP address '{}'", ip.value)),
+            },
+            None => None,
+        };
+        Ok(ShareRequest {
+            path,
+            expires_in,
+            downloads,
+            ip,
+        })
+    }
+
+    /// The configuration file's `[[acl]]` rules, with groups expanded to
+    /// their members and `user = "*"` standing for everyone.
+    fn acl_rules(&self) -> Result<Vec<Rule>, CliError> {
+        let Some(config) = &self.config else {
+            return Ok(Vec::new());
+        };
+
+        let mut rules = Vec::new();
+        for entry in &config.acl {
+            let origin = format!("{}:{}: ", config.path.display(), entry.line);
+            let users = match &entry.subject {
+                Subject::User(user) if user == "*" => None,
+                Subject::User(user) => Some(vec![user.clone()]),
+                Subject::Group(name) => match config.groups.iter().find(|g| &g.name == name) {
+                    Some(group) => Some(group.members.clone()),
+                    None => return error(format!("{}unknown group `{}`", origin, name)),
+                },
+            };
+            let allow = Permission::parse_list(&entry.allow)
+                .or_else(|message| error(format!("{}{}", origin, message)))?;
+            rules.push(Rule {
+                users,
+                path: entry.path.clone(),
+                allow,
+            });
+        }
+        Ok(rules)
+    }
+
+    fn lookup(&self, option: &str, is_path: bool) -> Option<Setting> {
+        if let Some((_, value)) = self.values.iter().rev().find(|(n, _)| *n == option) {
+            return Some(Setting {
+                value: value.clone(),
+                origin: String::new(),
+            });
+        }
+
+        let var = env_name(option);
+        if let Some(value) = (self.env)(&var) {
+            return Some(Setting {
+                value,
+                origin: format!("{}: ", var),
+            });
+        }
+
+        let key = match option {
+            "--404" => "not-found",
+            option => option.trim_start_matches('-'),
+        };
+        match is_path {
+            true => self.config_path(key),
+            false => self.config_value(key),
+        }
+    }
+
+    fn config_value(&self, key: &str) -> Option<Setting> {
+        let config = self.config.as_ref()?;
+        config.get(key).map(|entry| Setting {
+            value: entry.value.clone(),
+            origin: format!("{}:{}: ", config.path.display(), entry.line),
+        })
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(&name)
+    }
+
+    /// Whether a flag is given on the command line or else set in the
+    /// environment, where `RUSTSERVE_UPLOAD=1`, `true` or `yes` stands for
+    /// `--upload`.
+    fn flag_or_env(&self, name: &str) -> Result<bool, CliError> {
+        if self.flag(name) {
+            return Ok(true);
+        }
+        let var = env_name(name);
+        match (self.env)(&var) {
+            None => Ok(false),
+            Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" => Ok(true),
+                _ => error(format!(
+                    "{}: expected 1, true or yes, found '{}'",
+                    var, value
+                )),
+            },
+        }
+    }
+
+    /// A boolean that a flag or its environment variable turns on, and the
+    /// configuration file may set.
+    fn switch(&self, flag: &str, key: &str, default: bool) -> Result<bool, CliError> {
+        if self.flag_or_env(flag)? {
+            return Ok(!default);
+        }
+        Ok(match self.config_value(key) {
+            Some(setting) => setting.value == "true",
+            None => default,
+        })
+    }
+
+    /// Like [`Sources::config_value`] for a path, which is taken relative
+    /// to the configuration file.
+    fn config_path(&self, key: &str) -> Option<Setting> {
+        let setting = self.config_value(key)?;
+        Some(Setting {
+            value: self.relative_to_config(&setting.value),
+            ..setting
+        })
+    }
+
+    fn relative_to_config(&self, path: &str) -> String {
+        match self.config.as_ref().and_then(|c| c.path.parent()) {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        }
+    }
+
+    fn resolve(&self) -> Result<Options, CliError> {
+        let directory = match self.positional.first() {
+            Some(directory) => Setting {
+                value: directory.clone(),
+                origin: String::new(),
+            },
+            None => match (self.env)("RUSTSERVE_DIR") {
+                Some(value) => Setting {
+                    value,
+                    origin: "RUSTSERVE_DIR: ".to_string(),
+                },
+                None => self.config_path("directory").unwrap_or(Setting {
+                    value: ".".to_string(),
+                    origin: String::new(),
+                }),
+            },
+        };
+        let directory = existing_dir(&directory)?;
+
+        let port = match self.positional.get(1) {
+            Some(port) => Some(Setting {
+                value: port.clone(),
+                origin: String::new(),
+            }),
+            None => self.value("--port"),
+        };
+        let port = match port {
+            Some(port) => match port.value.parse() {
+                Ok(number) => number,
+                Err(_) => return port.error(format_args!("invalid port '{}'", port.value)),
+            },
+            None => 8080,
+        };
+
+        let threads = match self.value("--threads") {
+            Some(threads) => match threads.value.parse() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    return threads.error(format_args!("invalid thread count '{}'", threads.value));
+                }
+            },
+            None => 20,
+        };
+
+        let backend = self.value("--backend");
+        let backend = match backend.as_ref().map(|b| b.value.as_str()) {
+            Some("threaded") => Backend::Threaded,
+            #[cfg(all(feature = "epoll", target_os = "linux"))]
+            None | Some("epoll") => Backend::Epoll,
+            #[cfg(not(all(feature = "epoll", target_os = "linux")))]
+            None => Backend::Threaded,
+            #[cfg(not(all(feature = "epoll", target_os = "linux")))]
+            Some("epoll") => return backend.unwrap().error("this build has no epoll backend"),
+            Some(other) => {
+                return backend
+                    .as_ref()
+                    .unwrap()
+                    .error(format_args!("unknown backend '{}'", other));
+            }
+        };
+
+        let max_body_size = match self.value("--max-body") {
+            Some(size) => match parse_size(&size.value) {
+                Some(bytes) => bytes,
+                None => return size.error(format_args!("invalid size '{}'", size.value)),
+            },
+            None => DEFAULT_MAX_BODY,
+        };
+
+        let read_only = self.flag_or_env("--read-only")?;
+        if read_only && self.flag_or_env("--upload")? {
+            return error("--read-only and --upload cannot be used together");
+        }
+        let upload = !read_only && self.switch("--upload", "upload", false)?;
+
+        let fallback = self.value("--fallback");
+        let not_found = self.value("--404");
+        // Naming a fallback or 404 page only makes sense for a site.
+        let site =
+            self.switch("--site", "site", false)? || fallback.is_some() || not_found.is_some();
+        let site = match site {
+            true => {
+                for file in fallback.iter().chain(&not_found) {
+                    if !directory.join(&file.value).is_file() {
+                        return file
+                            .error(format_args!("'{}' not found in the site root", file.value));
+                    }
+                }
+                if upload {
+                    return error("uploads cannot be enabled for a site");
+                }
+                Some(SiteOptions {
+                    fallback: fallback.map(|f| PathBuf::from(f.value)),
+                    not_found: not_found.map(|f| PathBuf::from(f.value)),
+                })
+            }
+            false => None,
+        };
+
+        let mut mounts = Vec::new();
+        if let Some(config) = &self.config {
+            for mount in &config.mounts {
+                let origin = format!("{}:{}: ", config.path.display(), mount.line);
+                if !mount.url.starts_with('/') || mount.url == "/" {
+                    return error(format!(
+                        "{}mount url '{}' must start with / and name a path",
+                        origin, mount.url
+                    ));
+                }
+                let directory = existing_dir(&Setting {
+                    value: self.relative_to_config(&mount.directory.to_string_lossy()),
+                    origin,
+                })?;
+                mounts.push(Mount {
+                    url: mount.url.trim_end_matches('/').to_string(),
+                    directory,
+                    line: mount.line,
+                });
+            }
+        }
+
+        let users = match (self.value("--auth"), self.path_value("--htpasswd")) {
+            (Some(_), Some(file)) => {
+                return file.error("--auth and --htpasswd cannot be used together");
+            }
+            (Some(auth), None) => match auth.value.split_once(':') {
+                Some((user, password)) if !user.is_empty() => Some(Users::Single {
+                    user: user.to_string(),
+                    password: password.to_string(),
+                }),
+                _ => return auth.error("--auth expects USER:PASSWORD"),
+            },
+            (None, Some(file)) => match Users::load_htpasswd(Path::new(&file.value)) {
+                Ok(users) => Some(users),
+                Err(errors) => {
+                    let problems: Vec<String> = errors
+                        .iter()
+                        .map(|e| format!("{}:{}: {}", file.value, e.line, e.message))
+                        .collect();
+                    return file.error(problems.join("\n"));
+                }
+            },
+            (None, None) => None,
+        };
+
+        let protect = self.values("--protect");
+        let rules = self.acl_rules()?;
+        let auth = match users {
+            Some(users) => Some(AuthOptions {
+                users,
+                protect: protect.into_iter().map(|p| p.value).collect(),
+                rules,
+            }),
+            None if !protect.is_empty() => {
+                return protect[0].error("--protect needs --auth or --htpasswd");
+            }
+            None if !rules.is_empty() => {
+                return error("[[acl]] rules need --auth or --htpasswd".to_string());
+            }
+            None => None,
+        };
+        let admin = self.switch("--admin", "admin", false)?;
+        if admin && auth.is_none() {
+            return error("--admin needs --auth or --htpasswd");
+        }
+
+        let share_keys = self
+            .path_value("--share-keys")
+            .map(|f| PathBuf::from(f.value));
+        let thumbnail_cache = self
+            .path_value("--thumbnail-cache")
+            .map(|d| PathBuf::from(d.value));
+        let tls = self.tls_options(port)?;
+        let dashboard = self.switch("--no-dashboard", "dashboard", true)?;
+        let compression = self.switch("--no-compression", "compression", true)?;
+        let metrics = self.switch("--metrics", "metrics", false)?;
+        let access_log = self.access_log(dashboard)?;
+
+        Ok(Options {
+            directory,
+            bind: self
+                .value("--bind")
+                .map(|b| b.value)
+                .unwrap_or_else(|| "0.0.0.0".to_string()),
+            port,
+            threads,
+            backend,
+            upload,
+            max_body_size,
+            compression,
+            dashboard,
+            metrics,
+            admin,
+            site,
+            mounts,
+            auth,
+            share_keys,
+            thumbnail_cache,
+            tls,
+            access_log,
+        })
+    }
+
+    fn access_log(&self, dashboard: bool) -> Result<Option<AccessLog>, CliError> {
+        let format = self.value("--log-format");
+        let max_size = self.value("--log-max-size");
+        let max_age = self.value("--log-max-age");
+        let file = match self.value("--access-log") {
+            Some(file) if file.value == "-" => {
+                if dashboard {
+                    return file.error("--access-log - needs --no-dashboard");
+                }
+                None
+            }
+            Some(_) => self
+                .path_value("--access-log")
+                .map(|f| PathBuf::from(f.value)),
+            None => {
+                if let Some(option) = [&format, &max_size, &max_age].into_iter().flatten().next() {
+                    return option.error("log settings need --access-log");
+                }
+                return Ok(None);
+            }
+        };
+
+        let format = match format.as_ref().map(|f| f.value.as_str()) {
+            None | Some("combined") => LogFormat::Combined,
+            Some("common") => LogFormat::Common,
+            Some("json") => LogFormat::Json,
+            Some(other) => {
+                return format
+                    .as_ref()
+                    .unwrap()
+                    .error(format_args!("unknown log format '{}'", other));
+            }
+        };
+        let max_size = match max_size {
+            Some(size) => match parse_size(&size.value) {
+                Some(bytes) if bytes > 0 => Some(bytes as u64),
+                _ => return size.error(format_args!("invalid size '{}'", size.value)),
+            },
+            None => None,
+        };
+        let max_age = match max_age {
+            Some(age) => match parse_duration(&age.value) {
+                Some(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+                _ => return age.error(format_args!("invalid duration '{}'", age.value)),
+            },
+            None => None,
+        };
+        if file.is_none() && (max_size.is_some() || max_age.is_some()) {
+            return error("standard output cannot be rotated");
+        }
+        Ok(Some(AccessLog {
+            file,
+            format,
+            max_size,
+            max_age,
+        }))
+    }
+
+    fn tls_options(&self, port: u16) -> Result<Option<TlsOptions>, CliError> {
+        let cert = self.path_value("--tls-cert");
+        let key = self.path_value("--tls-key");
+        let self_signed = self.switch("--self-signed", "self-signed", false)?;
+        let certificate = match (cert, key) {
+            (Some(cert), Some(key)) => {
+                if self_signed {
+                    return error("--self-signed and --tls-cert cannot be used together");
+                }
+                for file in [&cert, &key] {
+                    if !Path::new(&file.value).is_file() {
+                        return file.error(format_args!("file '{}' not found", file.value));
+                    }
+                }
+                Some(CertificateSource::Files {
+                    cert: PathBuf::from(cert.value),
+                    key: PathBuf::from(key.value),
+                })
+            }
+            (Some(cert), None) => return cert.error("--tls-cert needs --tls-key"),
+            (None, Some(key)) => return key.error("--tls-key needs --tls-cert"),
+            (None, None) if self_signed => Some(CertificateSource::SelfSigned),
+            (None, None) => None,
+        };
+
+        let redirect = self.value("--redirect-http");
+        let redirect_port = match &redirect {
+            Some(redirect) => match redirect.value.parse::<u16>() {
+                Ok(number) if number == port => {
+                    return redirect.error("--redirect-http needs a port of its own");
+                }
+                Ok(number) => Some(number),
+                Err(_) => {
+                    return redirect.error(format_args!("invalid port '{}'", redirect.value));
+                }
+            },
+            None => None,
+        };
+
+        let mut sni = Vec::new();
+        if let Some(config) = &self.config {
+            for entry in &config.certificates {
+                let origin = format!("{}:{}: ", config.path.display(), entry.line);
+                let cert = self.relative_to_config(&entry.cert.to_string_lossy());
+                let key = self.relative_to_config(&entry.key.to_string_lossy());
+                for file in [&cert, &key] {
+                    if !Path::new(file).is_file() {
+                        return error(format!("{}file '{}' not found", origin, file));
+                    }
+                }
+                sni.push(CertificateEntry {
+                    name: entry.name.clone(),
+                    cert: PathBuf::from(cert),
+                    key: PathBuf::from(key),
+                    line: entry.line,
+                });
+            }
+        }
+
+        let Some(certificate) = certificate else {
+            if let Some(redirect) = redirect {
+                return redirect.error("--redirect-http needs --tls-cert or --self-signed");
+            }
+            if !sni.is_empty() {
+                return error("[[certificate]] tables need --tls-cert or --self-signed");
+            }
+            return Ok(None);
+        };
+        if !cfg!(feature = "tls") {
+            return error("HTTPS needs rustserve built with `--features tls`");
+        }
+        Ok(Some(TlsOptions {
+            certificate,
+            sni,
+            redirect_port,
+        }))
+    }
+}
+
+fn existing_dir(setting: &Setting) -> Result<PathBuf, CliError> {
+    match PathBuf::from(&setting.value).canonicalize() {
+        Ok(path) if path.is_dir() => Ok(path),
+        Ok(_) => setting.error(format_args!("'{}' is not a directory", setting.value)),
+        Err(_) => setting.error(format_args!("directory '{}' not found", setting.value)),
+    }
+}
+
+fn canonical(name: &str) -> &'static str {
+    match name {
+        "-b" | "--bind" => "--bind",
+        "-p" | "--port" => "--port",
+        "-t" | "--threads" => "--threads",
+        "--backend" => "--backend",
+        "--max-body" => "--max-body",
+        "--fallback" => "--fallback",
+        "--404" => "--404",
+        "--read-only" => "--read-only",
+        "--upload" => "--upload",
+        "--no-compression" => "--no-compression",
+        "-q" | "--quiet" | "--no-dashboard" => "--no-dashboard",
+        "--site" => "--site",
+        "-c" | "--config" => "--config",
+        "--auth" => "--auth",
+        "--htpasswd" => "--htpasswd",
+        "--protect" => "--protect",
+        "--share-keys" => "--share-keys",
+        "--share" => "--share",
+        "--expires" => "--expires",
+        "--downloads" => "--downloads",
+        "--allow-ip" => "--allow-ip",
+        "--base-url" => "--base-url",
+        "--revoke-key" => "--revoke-key",
+        "--thumbnail-cache" => "--thumbnail-cache",
+        "--tls-cert" => "--tls-cert",
+        "--tls-key" => "--tls-key",
+        "--self-signed" => "--self-signed",
+        "--metrics" => "--metrics",
+        "--admin" => "--admin",
+        "--redirect-http" => "--redirect-http",
+        "--access-log" => "--access-log",
+        "--log-format" => "--log-format",
+        "--log-max-size" => "--log-max-size",
+        "--log-max-age" => "--log-max-age",
+        "--check-config" => "--check-config",
+        _ => unreachable!("not a known option: {}", name),
+    }
+}
+
+/// `--max-body` becomes `RUSTSERVE_MAX_BODY`.
+fn env_name(option: &str) -> String {
+    format!(
+        "RUSTSERVE_{}",
+        option
+            .trim_start_matches('-')
+            .replace('-', "_")
+            .to_uppercase()
+    )
+}
+
+/// Parses a byte count with an optional binary suffix: `512`, `64K`, `2G`.
+fn parse_size(s: &str) -> Option<usize> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last()? {
+        (i, 'k' | 'K') => (&s[..i], 10),
+        (i, 'm' | 'M') => (&s[..i], 20),
+        (i, 'g' | 'G') => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+/// Parses a duration in seconds with an optional unit: `90`, `30m`, `12h`,
+/// `7d`, `2w`.
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (digits, unit) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1),
+        (i, 'm') => (&s[..i], 60),
+        (i, 'h') => (&s[..i], 60 * 60),
+        (i, 'd') => (&s[..i], 24 * 60 * 60),
+        (i, 'w') => (&s[..i], 7 * 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str], env: &[(&str, &str)]) -> Result<Command, CliError> {
+        let env: Vec<(String, String)> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        parse(args.iter().map(|s| s.to_string()), move |name| {
+            env.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+        })
+    }
+
+    fn options(args: &[&str], env: &[(&str, &str)]) -> Options {
+        match parse_args(args, env) {
+            Ok(Command::Serve(options)) => *options,
+            other => panic!("expected options, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_defaults_and_positionals() {
+        let o = options(&[], &[]);
+        assert_eq!(o.bind, "0.0.0.0");
+        assert_eq!((o.port, o.threads), (8080, 20));
+        assert!(!o.upload && o.compression && o.dashboard && !o.metrics && o.site.is_none());
+        assert!(!o.admin);
+
+        let o = options(&["/", "9000"], &[]);
+        assert_eq!(o.directory, PathBuf::from("/"));
+        assert_eq!(o.port, 9000);
+    }
+
+    #[test]
+    fn test_flags_and_env() {
+        let o = options(
+            &[
+                "-p",
+                "81",
+                "--threads=3",
+                "--max-body",
+                "64K",
+                "--upload",
+                "--metrics",
+            ],
+            &[
+                ("RUSTSERVE_PORT", "82"),
+                ("RUSTSERVE_BIND", "127.0.0.1"),
+                ("RUSTSERVE_THUMBNAIL_CACHE", "/tmp/thumbs"),
+            ],
+        );
+        assert_eq!(o.port, 81);
+        assert_eq!(o.bind, "127.0.0.1");
+        assert_eq!(o.threads, 3);
+        assert_eq!(o.max_body_size, 64 * 1024);
+        assert!(o.upload && o.metrics);
+        assert_eq!(o.thumbnail_cache, Some(PathBuf::from("/tmp/thumbs")));
+        assert!(options(&["--admin", "--auth", "a:b"], &[]).admin);
+        assert!(!options(&["-q"], &[]).dashboard);
+        assert!(!options(&["--quiet"], &[]).dashboard);
+
+        assert_eq!(
+            parse_args(&["--version", "--bogus"], &[]),
+            Ok(Command::Version)
+        );
+        assert_eq!(parse_args(&["-h"], &[]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_flags_from_env() {
+        let o = options(
+            &[],
+            &[
+                ("RUSTSERVE_UPLOAD", "1"),
+                ("RUSTSERVE_NO_DASHBOARD", "true"),
+                ("RUSTSERVE_NO_COMPRESSION", "YES"),
+            ],
+        );
+        assert!(o.upload && !o.dashboard && !o.compression && !o.metrics);
+        assert!(options(&["--upload"], &[("RUSTSERVE_METRICS", "yes")]).metrics);
+        let invalid = parse_args(&[], &[("RUSTSERVE_UPLOAD", "0")]).unwrap_err();
+        assert!(invalid.0.starts_with("RUSTSERVE_UPLOAD: "), "{}", invalid.0);
+        assert!(parse_args(&["--upload"], &[("RUSTSERVE_READ_ONLY", "1")]).is_err());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        for args in [
+            &["--port", "99999"][..],
+            &["--threads", "0"],
+            &["--max-body", "lots"],
+            &["--backend", "fibers"],
+            &["--read-only", "--upload"],
+            &["--bind"],
+            &["--bogus"],
+            &["--site=yes"],
+            &[".", "80", "extra"],
+            &["/definitely/not/here"],
+            &["--auth", "nopassword"],
+            &["--auth", "a:b", "--htpasswd", "users"],
+            &["--protect", "private"],
+            &["--htpasswd", "/definitely/not/here"],
+            &["--admin"],
+        ] {
+            assert!(parse_args(args, &[]).is_err(), "{:?} should fail", args);
+        }
+    }
+
+    #[test]
+    fn test_config_file() {
+        let dir = std::env::temp_dir().join(format!("rustserve-cli-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        let file = dir.join("rustserve.toml");
+        let file_arg = file.to_str().unwrap();
+        std::fs::write(
+            &file,
+            "directory = \".\"\nport = 81\nthreads = 2\ndashboard = false\n\n[[mount]]\nurl = \"/docs/\"\ndirectory = \"docs\"\n",
+        )
+        .unwrap();
+
+        // Command line, then environment, then the file.
+        let o = options(&["-c", file_arg, "-t", "5"], &[("RUSTSERVE_PORT", "82")]);
+        assert_eq!(o.directory, dir.canonicalize().unwrap());
+        assert_eq!((o.port, o.threads), (82, 5));
+        assert!(!o.dashboard);
+        assert_eq!(o.mounts[0].url, "/docs");
+        assert_eq!(
+            o.mounts[0].directory,
+            dir.join("docs").canonicalize().unwrap()
+        );
+
+        let check = parse_args(&["--check-config"], &[("RUSTSERVE_CONFIG", file_arg)]);
+        assert!(matches!(check, Ok(Command::CheckConfig { problems, .. }) if problems.is_empty()));
+
+        std::fs::write(&file, "port = 81\nthreads = 0\nbogus = 1\n").unwrap();
+        let Ok(Command::CheckConfig { problems, .. }) =
+            parse_args(&["-c", file_arg, "--check-config"], &[])
+        else {
+            panic!("expected a report");
+        };
+        assert_eq!(
+            problems,
+            vec![format!("{}:3: unknown key `bogus`", file.display())]
+        );
+
+        std::fs::write(&file, "threads = 0\n").unwrap();
+        let Ok(Command::CheckConfig { problems, .. }) =
+            parse_args(&["-c", file_arg, "--check-config"], &[])
+        else {
+            panic!("expected a report");
+        };
+        assert_eq!(
+            problems,
+            vec![format!("{}:1: invalid thread count '0'", file.display())]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_auth_options() {
+        let o = options(
+            &["--auth", "alice:pa:ss", "--protect", "a", "--protect=b"],
+            &[("RUSTSERVE_PROTECT", "ignored")],
+        );
+        let auth = o.auth.unwrap();
+        assert_eq!(
+            auth.users,
+            Users::Single {
+                user: "alice".to_string(),
+                password: "pa:ss".to_string(),
+            }
+        );
+        assert_eq!(auth.protect, vec!["a", "b"]);
+
+        let o = options(
+            &[],
+            &[
+                ("RUSTSERVE_AUTH", "bob:x"),
+                ("RUSTSERVE_PROTECT", "a, b/c,"),
+            ],
+        );
+        assert_eq!(o.auth.unwrap().protect, vec!["a", "b/c"]);
+        assert_eq!(options(&[], &[]).auth, None);
+        assert_eq!(
+            parse_args(&["--hash-password"], &[]),
+            Ok(Command::HashPassword)
+        );
+    }
+
+    #[test]
+    fn test_acl_rules() {
+        let dir = std::env::temp_dir().join(format!("rustserve-acl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("rustserve.toml");
+        let file_arg = file.to_str().unwrap();
+        let config = |acl: &str| {
+            let text = format!(
+                "auth = \"alice:x\"\n[[group]]\nname = \"design\"\nmembers = \"alice, bob\"\n{}",
+                acl
+            );
+            std::fs::write(&file, text).unwrap();
+        };
+
+        config(
+            "[[acl]]\ngroup = \"design\"\npath = \"/design\"\nallow = \"read, list\"\n[[acl]]\nuser = \"*\"\npath = \"pub\"\nallow = \"read\"\n",
+        );
+        let rules = options(&["-c", file_arg], &[]).auth.unwrap().rules;
+        assert_eq!(
+            rules,
+            vec![
+                Rule {
+                    users: Some(vec!["alice".to_string(), "bob".to_string()]),
+                    path: "/design".to_string(),
+                    allow: vec![Permission::Read, Permission::List],
+                },
+                Rule {
+                    users: None,
+                    path: "pub".to_string(),
+                    allow: vec![Permission::Read],
+                },
+            ]
+        );
+
+        config("[[acl]]\ngroup = \"ops\"\npath = \"/\"\nallow = \"read\"\n");
+        assert_eq!(
+            parse_args(&["-c", file_arg], &[]),
+            Err(CliError(format!(
+                "{}:5: unknown group `ops`",
+                file.display()
+            )))
+        );
+        config("[[acl]]\nuser = \"bob\"\npath = \"/\"\nallow = \"write\"\n");
+        assert!(parse_args(&["-c", file_arg], &[]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_access_log() {
+        let log = options(
+            &["--access-log", "access.log", "--log-format=json"],
+            &[
+                ("RUSTSERVE_LOG_MAX_SIZE", "10M"),
+                ("RUSTSERVE_LOG_MAX_AGE", "1d"),
+            ],
+        )
+        .access_log
+        .unwrap();
+        assert_eq!(log.file, Some(PathBuf::from("access.log")));
+        assert_eq!(log.format, LogFormat::Json);
+        assert_eq!(log.max_size, Some(10 << 20));
+        assert_eq!(log.max_age, Some(Duration::from_secs(86_400)));
+
+        let log = options(&["--access-log", "-", "--no-dashboard"], &[]);
+        assert_eq!(log.access_log.unwrap().file, None);
+        assert_eq!(options(&[], &[]).access_log, None);
+
+        for (args, message) in [
+            (
+                &["--access-log", "-"][..],
+                "--access-log - needs --no-dashboard",
+            ),
+            (&["--log-format", "json"], "log settings need --access-log"),
+            (
+                &["--access-log", "a", "--log-format", "xml"],
+                "unknown log format 'xml'",
+            ),
+            (
+                &["--access-log", "a", "--log-max-age", "0"],
+                "invalid duration '0'",
+            ),
+            (
+                &[
+                    "--access-log",
+                    "-",
+                    "--no-dashboard",
+                    "--log-max-size",
+                    "1M",
+                ],
+                "standard output cannot be rotated",
+            ),
+        ] {
+            assert_eq!(parse_args(args, &[]), error(message), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_share_commands() {
+        let Ok(Command::Share {
+            keys,
+            link,
+            base_url,
+            ..
+        }) = parse_args(
+            &[
+                "--share",
+                "docs/a.pdf",
+                "--expires=12h",
+                "--downloads",
+                "3",
+                "--base-url",
+                "https://files.example/",
+            ],
+            &[("RUSTSERVE_SHARE_KEYS", "/tmp/keys")],
+        )
+        else {
+            panic!("expected a share command");
+        };
+        assert_eq!(keys, PathBuf::from("/tmp/keys"));
+        assert_eq!(
+            link,
+            ShareRequest {
+                path: "docs/a.pdf".to_string(),
+                expires_in: 12 * 60 * 60,
+                downloads: Some(3),
+                ip: None,
+            }
+        );
+        assert_eq!(base_url.as_deref(), Some("https://files.example"));
+
+        assert_eq!(
+            parse_args(&["--revoke-key", "ab12", "--share-keys", "k"], &[]),
+            Ok(Command::RevokeKey {
+                keys: PathBuf::from("k"),
+                id: "ab12".to_string(),
+            })
+        );
+        for args in [
+            &["--share", "a"][..],
+            &["--share", "a", "--share-keys", "k", "--expires", "soon"],
+            &["--share", "a", "--share-keys", "k", "--allow-ip", "nowhere"],
+            &["--share-keys", "k", "--downloads", "2"],
+        ] {
+            assert!(parse_args(args, &[]).is_err(), "{:?} should fail", args);
+        }
+    }
+
+    #[test]
+    fn test_tls_options() {
+        let dir = std::env::temp_dir().join(format!("rustserve-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["cert.pem", "key.pem"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let file = dir.join("rustserve.toml");
+        std::fs::write(
+            &file,
+            "tls-cert = \"cert.pem\"\ntls-key = \"key.pem\"\n\n[[certificate]]\nname = \"*.example.com\"\ncert = \"cert.pem\"\nkey = \"key.pem\"\n",
+        )
+        .unwrap();
+        let file_arg = file.to_str().unwrap();
+
+        for (args, message) in [
+            (
+                &["--tls-cert", "cert.pem"][..],
+                "--tls-cert needs --tls-key",
+            ),
+            (
+                &["--redirect-http", "80"],
+                "--redirect-http needs --tls-cert or --self-signed",
+            ),
+            (
+                &["--self-signed", "-c", file_arg],
+                "--self-signed and --tls-cert cannot be used together",
+            ),
+            (
+                &["--self-signed", "--redirect-http", "8080"],
+                "--redirect-http needs a port of its own",
+            ),
+        ] {
+            assert_eq!(parse_args(args, &[]), error(message), "{:?}", args);
+        }
+        assert_eq!(
+            parse_args(&["--tls-cert", "none.pem", "--tls-key", "none.pem"], &[]),
+            error("file 'none.pem' not found")
+        );
+
+        let args = ["-c", file_arg, "--redirect-http=8000"];
+        if cfg!(feature = "tls") {
+            let tls = options(&args, &[]).tls.unwrap();
+            assert_eq!(
+                tls.certificate,
+                CertificateSource::Files {
+                    cert: dir.join("cert.pem"),
+                    key: dir.join("key.pem"),
+                }
+            );
+            assert_eq!(tls.sni[0].name, "*.example.com");
+            assert_eq!(tls.sni[0].key, dir.join("key.pem"));
+            assert_eq!(tls.redirect_port, Some(8000));
+            let o = options(&["--self-signed"], &[]);
+            assert_eq!(o.tls.unwrap().certificate, CertificateSource::SelfSigned);
+        } else {
+            assert_eq!(
+                parse_args(&args, &[]),
+                error("HTTPS needs rustserve built with `--features tls`")
+            );
+        }
+        assert_eq!(options(&[], &[]).tls, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("2m"), Some(2 << 20));
+        assert_eq!(parse_size("G"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("30m"), Some(1800));
+        assert_eq!(parse_duration("7d"), Some(7 * 86400));
+        assert_eq!(parse_duration("h"), None);
+    }
+}
@@ -0,0 +1,540 @@
+//! Configuration files for the `rustserve` binary.
+//!
+//! The format is the subset of TOML the settings need: `key = value` pairs
+//! with strings, integers and booleans, `#` comments, and `[[mount]]`,
+//! `[[group]]`, `[[acl]]` and `[[certificate]]` tables.
+//!
+//! ```toml
+//! directory = "/srv/share"
+//! port = 8080
+//! max-body = "1G"
+//! upload = true
+//!
+//! [[mount]]
+//! url = "/docs"
+//! directory = "/srv/docs"
+//!
+//! [[group]]
+//! name = "design"
+//! members = "alice, bob"
+//!
+//! [[acl]]
+//! group = "design"
+//! path = "/design"
+//! allow = "read, list, upload"
+//!
+//! [[certificate]]
+//! name = "*.example.com"
+//! cert = "example.pem"
+//! key = "example.key"
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+    /// A byte count, either an integer or a string such as `"64K"`.
+    Size,
+}
+
+/// Top-level keys and the value each one takes.
+const KEYS: &[(&str, Kind)] = &[
+    ("directory", Kind::String),
+    ("bind", Kind::String),
+    ("port", Kind::Integer),
+    ("threads", Kind::Integer),
+    ("backend", Kind::String),
+    ("upload", Kind::Boolean),
+    ("max-body", Kind::Size),
+    ("compression", Kind::Boolean),
+    ("dashboard", Kind::Boolean),
+    ("metrics", Kind::Boolean),
+    ("admin", Kind::Boolean),
+    ("site", Kind::Boolean),
+    ("fallback", Kind::String),
+    ("not-found", Kind::String),
+    ("auth", Kind::String),
+    ("htpasswd", Kind::String),
+    ("protect", Kind::String),
+    ("share-keys", Kind::String),
+    ("thumbnail-cache", Kind::String),
+    ("tls-cert", Kind::String),
+    ("tls-key", Kind::String),
+    ("self-signed", Kind::Boolean),
+    ("redirect-http", Kind::Integer),
+    ("access-log", Kind::String),
+    ("log-format", Kind::String),
+    ("log-max-size", Kind::Size),
+    ("log-max-age", Kind::String),
+];
+
+const MOUNT_KEYS: &[(&str, Kind)] = &[("url", Kind::String), ("directory", Kind::String)];
+const GROUP_KEYS: &[(&str, Kind)] = &[("name", Kind::String), ("members", Kind::String)];
+const ACL_KEYS: &[(&str, Kind)] = &[
+    ("user", Kind::String),
+    ("group", Kind::String),
+    ("path", Kind::String),
+    ("allow", Kind::String),
+];
+const CERTIFICATE_KEYS: &[(&str, Kind)] = &[
+    ("name", Kind::String),
+    ("cert", Kind::String),
+    ("key", Kind::String),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Table {
+    Mount,
+    Group,
+    Acl,
+    Certificate,
+}
+
+impl Table {
+    fn keys(self) -> &'static [(&'static str, Kind)] {
+        match self {
+            Table::Mount => MOUNT_KEYS,
+            Table::Group => GROUP_KEYS,
+            Table::Acl => ACL_KEYS,
+            Table::Certificate => CERTIFICATE_KEYS,
+        }
+    }
+}
+
+/// A value read from the file, kept as text so it goes through the same
+/// validation as the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: String,
+    pub line: usize,
+}
+
+/// Key/value pairs in the order they appear.
+type Entries = Vec<(String, Entry)>;
+
+/// Serves another directory's files below a URL prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub url: String,
+    pub directory: PathBuf,
+    pub line: usize,
+}
+
+/// Names a set of users for `[[acl]]` rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub members: Vec<String>,
+    pub line: usize,
+}
+
+/// Who may do what below a path, before the names in it are checked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    User(String),
+    Group(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AclEntry {
+    pub subject: Subject,
+    pub path: String,
+    pub allow: String,
+    pub line: usize,
+}
+
+/// A certificate presented to clients asking for `name` over TLS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateEntry {
+    pub name: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub path: PathBuf,
+    entries: Entries,
+    pub mounts: Vec<Mount>,
+    pub groups: Vec<Group>,
+    pub acl: Vec<AclEntry>,
+    pub certificates: Vec<CertificateEntry>,
+}
+
+/// A problem found in a configuration file, with the line it is on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Config {
+    /// Reads and parses `path`. An unreadable file is reported as line 0.
+    pub fn load(path: &Path) -> Result<Config, Vec<ConfigError>> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            vec![ConfigError {
+                line: 0,
+                message: format!("cannot read {}: {}", path.display(), e),
+            }]
+        })?;
+        let mut config = Config::parse(&text)?;
+        config.path = path.to_path_buf();
+        Ok(config)
+    }
+
+    /// Parses configuration text, collecting every problem rather than
+    /// stopping at the first.
+    pub fn parse(text: &str) -> Result<Config, Vec<ConfigError>> {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        // Key/value pairs of the table being read, if any.
+        let mut table: Option<(Table, usize, Entries)> = None;
+
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+
+            let content = strip_comment(raw).trim();
+            if content.is_empty() {
+                continue;
+            }
+
+            if content.starts_with('[') {
+                if let Some((kind, start, entries)) = table.take() {
+                    config.finish_table(kind, start, entries, &mut errors);
+                }
+                let kind = match content {
+                    "[[mount]]" => Table::Mount,
+                    "[[group]]" => Table::Group,
+                    "[[acl]]" => Table::Acl,
+                    "[[certificate]]" => Table::Certificate,
+                    _ => {
+                        errors.push(ConfigError {
+                            line,
+                            message: format!("unknown table {}", content),
+                        });
+                        continue;
+                    }
+                };
+                table = Some((kind, line, Vec::new()));
+                continue;
+            }
+
+            let Some((key, value)) = content.split_once('=') else {
+                errors.push(ConfigError {
+                    line,
+                    message: format!("expected `key = value`, found `{}`", content),
+                });
+                continue;
+            };
+            let key = key.trim();
+
+            let schema = table.as_ref().map_or(KEYS, |(kind, _, _)| kind.keys());
+            let Some(&(_, kind)) = schema.iter().find(|(name, _)| *name == key) else {
+                errors.push(ConfigError {
+                    line,
+                    message: format!("unknown key `{}`", key),
+                });
+                continue;
+            };
+
+            let value = match parse_value(value.trim(), kind) {
+                Ok(value) => value,
+                Err(message) => {
+                    errors.push(ConfigError {
+                        line,
+                        message: format!("`{}`: {}", key, message),
+                    });
+                    continue;
+                }
+            };
+
+            let entries = match table.as_mut() {
+                Some((_, _, entries)) => entries,
+                None => &mut config.entries,
+            };
+            if entries.iter().any(|(k, _)| k == key) {
+                errors.push(ConfigError {
+                    line,
+                    message: format!("`{}` is set more than once", key),
+                });
+                continue;
+            }
+            entries.push((key.to_string(), Entry { value, line }));
+        }
+
+        if let Some((kind, start, entries)) = table.take() {
+            config.finish_table(kind, start, entries, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Looks up a top-level key.
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, e)| e)
+    }
+
+    fn finish_table(
+        &mut self,
+        kind: Table,
+        start: usize,
+        entries: Entries,
+        errors: &mut Vec<ConfigError>,
+    ) {
+        let get = |key: &str| {
+            entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, e)| e.value.clone())
+        };
+        let message = match kind {
+            Table::Mount => match (get("url"), get("directory")) {
+                (Some(url), Some(directory)) => {
+                    self.mounts.push(Mount {
+                        url,
+                        directory: PathBuf::from(directory),
+                        line: start,
+                    });
+                    return;
+                }
+                _ => "[[mount]] needs both `url` and `directory`",
+            },
+            Table::Group => match (get("name"), get("members")) {
+                (Some(name), Some(members)) => {
+                    self.groups.push(Group {
+                        name,
+                        members: split_list(&members),
+                        line: start,
+                    });
+                    return;
+                }
+                _ => "[[group]] needs both `name` and `members`",
+            },
+            Table::Acl => {
+                let subject = match (get("user"), get("group")) {
+                    (Some(user), None) => Some(Subject::User(user)),
+                    (None, Some(group)) => Some(Subject::Group(group)),
+                    _ => None,
+                };
+                match (subject, get("path"), get("allow")) {
+                    (Some(subject), Some(path), Some(allow)) => {
+                        self.acl.push(AclEntry {
+                            subject,
+                            path,
+                            allow,
+                            line: start,
+                        });
+                        return;
+                    }
+                    (None, _, _) => "[[acl]] needs exactly one of `user` and `group`",
+                    _ => "[[acl]] needs both `path` and `allow`",
+                }
+            }
+            Table::Certificate => match (get("name"), get("cert"), get("key")) {
+                (Some(name), Some(cert), Some(key)) => {
+                    self.certificates.push(CertificateEntry {
+                        name,
+                        cert: PathBuf::from(cert),
+                        key: PathBuf::from(key),
+                        line: start,
+                    });
+                    return;
+                }
+                _ => "[[certificate]] needs `name`, `cert` and `key`",
+            },
+        };
+        errors.push(ConfigError {
+            line: start,
+            message: message.to_string(),
+        });
+    }
+}
+
+/// Splits a comma-separated list, dropping empty items.
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Cuts a trailing `#` comment, ignoring `#` inside quoted strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(text: &str, kind: Kind) -> Result<String, String> {
+    let string = parse_string(text);
+    let value = match (kind, string) {
+        (Kind::String | Kind::Size, Some(string)) => string?,
+        (Kind::Integer | Kind::Size, None) => {
+            let digits = text.replace('_', "");
+            digits
+                .parse::<i64>()
+                .map_err(|_| format!("expected an integer, found `{}`", text))?
+                .to_string()
+        }
+        (Kind::Boolean, None) if text == "true" || text == "false" => text.to_string(),
+        (Kind::String, None) => return Err(format!("expected a string, found `{}`", text)),
+        (Kind::Integer, Some(_)) => return Err(format!("expected an integer, found {}", text)),
+        (Kind::Boolean, _) => return Err(format!("expected true or false, found `{}`", text)),
+    };
+    Ok(value)
+}
+
+/// Parses a basic (`"..."`) or literal (`'...'`) string, or returns `None`
+/// if `text` is not quoted at all.
+fn parse_string(text: &str) -> Option<Result<String, String>> {
+    if let Some(rest) = text.strip_prefix('\'') {
+        return Some(match rest.strip_suffix('\'') {
+            Some(inner) if !inner.contains('\'') => Ok(inner.to_string()),
+            _ => Err("unterminated string".to_string()),
+        });
+    }
+
+    let rest = text.strip_prefix('"')?;
+    let mut out = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                return Some(match chars.as_str().trim() {
+                    "" => Ok(out),
+                    trailing => Err(format!("unexpected `{}` after string", trailing)),
+                });
+            }
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                            Some(c) => c,
+                            None => return Some(Err(format!("invalid escape \\u{}", hex))),
+                        }
+                    }
+                    other => {
+                        let other = other.map(String::from).unwrap_or_default();
+                        return Some(Err(format!("invalid escape \\{}", other)));
+                    }
+                };
+                out.push(escaped);
+            }
+            c => out.push(c),
+        }
+    }
+    Some(Err("unterminated string".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            # Shared drive
+            directory = "/srv/share"  # the root
+            port = 8_080
+            upload = true
+            max-body = "64K"
+            bind = '0.0.0.0'
+
+            [[mount]]
+            url = "/docs"
+            directory = "/srv/docs # not a comment"
+
+            [[group]]
+            name = "design"
+            members = "alice, bob,"
+
+            [[acl]]
+            group = "design"
+            path = "/design"
+            allow = "read, list"
+
+            [[certificate]]
+            name = "files.example.com"
+            cert = "files.pem"
+            key = "files.key"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.get("directory").unwrap().value, "/srv/share");
+        assert_eq!(config.get("port").unwrap().value, "8080");
+        assert_eq!(config.get("port").unwrap().line, 4);
+        assert_eq!(config.get("upload").unwrap().value, "true");
+        assert_eq!(config.get("bind").unwrap().value, "0.0.0.0");
+        assert_eq!(config.get("threads"), None);
+        assert_eq!(
+            config.mounts,
+            vec![Mount {
+                url: "/docs".to_string(),
+                directory: PathBuf::from("/srv/docs # not a comment"),
+                line: 9,
+            }]
+        );
+        assert_eq!(config.groups[0].members, vec!["alice", "bob"]);
+        assert_eq!(
+            config.acl,
+            vec![AclEntry {
+                subject: Subject::Group("design".to_string()),
+                path: "/design".to_string(),
+                allow: "read, list".to_string(),
+                line: 17,
+            }]
+        );
+        assert_eq!(config.certificates[0].name, "files.example.com");
+        assert_eq!(config.certificates[0].key, PathBuf::from("files.key"));
+        assert_eq!(config.certificates[0].line, 22);
+    }
+
+    #[test]
+    fn test_errors_have_line_numbers() {
+        let errors = Config::parse(
+            "port = \"eighty\"\nupload = yes\ncolour = 1\nport = 1\nport = 2\n[server]\njunk\n[[mount]]\nurl = \"/x\"\n[[acl]]\nuser = \"a\"\ngroup = \"b\"\n",
+        )
+        .unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 5, 6, 7, 8, 10]);
+        assert_eq!(errors[2].to_string(), "line 3: unknown key `colour`");
+    }
+}
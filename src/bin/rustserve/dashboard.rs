@@ -0,0 +1,314 @@
+//! Live statistics on the server's standard output: a dashboard redrawn in
+//! place when it is a terminal, otherwise a plain summary line now and then,
+//! so pipes and journald are not flooded with escape codes.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rustserve::stats::Stats;
+
+use crate::{get_local_ip, scheme};
+
+/// Time between two redraws of the dashboard.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+/// Time between two summary lines.
+pub const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+/// The size assumed when the terminal's own cannot be found out.
+const DEFAULT_SIZE: (usize, usize) = (80, 24);
+const MIN_WIDTH: usize = 40;
+const MAX_WIDTH: usize = 120;
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The dashboard for a server sharing `directory` on `port`.
+pub struct Dashboard {
+    pub stats: Arc<Stats>,
+    /// The name of the shared directory.
+    pub directory: String,
+    pub port: u16,
+    pub https: bool,
+    /// Shown below the box, e.g. a self-signed certificate's fingerprint.
+    pub notice: Option<String>,
+}
+
+impl Dashboard {
+    /// Redraws the dashboard forever, clearing the screen first and again
+    /// whenever the terminal is resized.
+    pub fn run(self) {
+        let mut drawn_size = None;
+        loop {
+            let size = terminal_size().unwrap_or(DEFAULT_SIZE);
+            let mut frame = String::new();
+            if drawn_size != Some(size) {
+                frame.push_str("\x1B[2J");
+                drawn_size = Some(size);
+            }
+            frame.push_str("\x1B[H");
+            for line in self.render(size) {
+                frame.push_str(&line);
+                frame.push_str("\x1B[K\n");
+            }
+            frame.push_str("\x1B[J");
+
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(frame.as_bytes());
+            let _ = stdout.flush();
+            drop(stdout);
+            thread::sleep(REDRAW_INTERVAL);
+        }
+    }
+
+    /// The lines of one frame for a terminal of `(columns, rows)`, dropping
+    /// the least important ones when it is short.
+    fn render(&self, (columns, rows): (usize, usize)) -> Vec<String> {
+        let stats = &self.stats;
+        let width = columns.clamp(MIN_WIDTH, MAX_WIDTH);
+        // Inside "║  " and " ║".
+        let inner = width - 5;
+
+        let ip = get_local_ip().unwrap_or_else(|| "unknown".to_string());
+        let https = self.https;
+        let [p50, p90, p99] = stats
+            .latency_percentiles()
+            .map(|d| d.as_secs_f64() * 1000.0);
+        let rates = [1, 5, 15]
+            .map(|minutes| format!("{:.1}", stats.request_rate(minutes_long(minutes))))
+            .join(" · ");
+        let throughput = stats.byte_rate(minutes_long(1)) as u64;
+
+        let rule = |left: char, right: char| format!("{}{}{}", left, "═".repeat(width - 2), right);
+        let row = |text: String| format!("║  {} ║", fit(&text, inner));
+
+        let mut lines = vec![
+            rule('╔', '╗'),
+            row("📁 rustserve - File Server".to_string()),
+            rule('╠', '╣'),
+            row(format!("📂 Serving: {}", self.directory)),
+            rule('╠', '╣'),
+            row(format!(
+                "Local:     {}://127.0.0.1:{}",
+                scheme(https),
+                self.port
+            )),
+            row(format!(
+                "Network:   {}://{}:{}",
+                scheme(https),
+                ip,
+                self.port
+            )),
+            rule('╠', '╣'),
+            row(format!(
+                "👥 Active connections: {}",
+                stats.get_active_connections()
+            )),
+            row(format!("📊 Total requests: {}", stats.get_total_requests())),
+            row(format!(
+                "📥 Files downloaded: {}",
+                stats.get_files_downloaded()
+            )),
+            row(format!(
+                "📤 Data sent: {}",
+                Stats::format_bytes(stats.get_total_bytes_sent())
+            )),
+            row(format!(
+                "🚫 Errors: {} client, {} server",
+                stats.get_responses(4),
+                stats.get_responses(5)
+            )),
+            row(format!(
+                "🕒 Response time p50/p90/p99: {:.1} / {:.1} / {:.1} ms",
+                p50, p90, p99
+            )),
+        ];
+
+        // The graphs take four lines plus a rule; they go first when space
+        // runs out, before the footer.
+        let footer = 3 + self.notice.is_some() as usize;
+        if lines.len() + 6 + footer <= rows {
+            let requests = stats.request_history(inner);
+            let bytes = stats.byte_history(inner);
+            lines.push(rule('╠', '╣'));
+            lines.push(row(format!("📈 Requests/s 1m · 5m · 15m: {}", rates)));
+            lines.push(row(sparkline(&requests)));
+            lines.push(row(format!(
+                "🚀 Throughput: {}/s, peak {}/s",
+                Stats::format_bytes(throughput),
+                Stats::format_bytes(bytes.iter().copied().max().unwrap_or(0))
+            )));
+            lines.push(row(sparkline(&bytes)));
+        } else {
+            lines.push(row(format!("📈 Requests/s 1m · 5m · 15m: {}", rates)));
+        }
+        lines.push(rule('╚', '╝'));
+
+        if lines.len() + footer <= rows {
+            lines.push(String::new());
+            lines.extend(self.notice.clone());
+            lines.push("Share the Network URL with others on your local network!".to_string());
+            lines.push("Press Ctrl+C to stop the server".to_string());
+        }
+        lines.truncate(rows.max(1));
+        lines
+    }
+}
+
+/// Prints [`summary`] every [`SUMMARY_INTERVAL`], forever.
+pub fn log_summaries(stats: Arc<Stats>) {
+    loop {
+        thread::sleep(SUMMARY_INTERVAL);
+        println!("{}", summary(&stats));
+    }
+}
+
+/// One line with the figures of the dashboard.
+pub fn summary(stats: &Stats) -> String {
+    let [p50, p90, p99] = stats
+        .latency_percentiles()
+        .map(|d| d.as_secs_f64() * 1000.0);
+    format!(
+        "stats: {} requests ({:.1}/s), {} downloads, {} sent ({}/s), \
+         {} client errors, {} server errors, p50/p90/p99 {:.1}/{:.1}/{:.1} ms, \
+         {} connections",
+        stats.get_total_requests(),
+        stats.request_rate(minutes_long(1)),
+        stats.get_files_downloaded(),
+        Stats::format_bytes(stats.get_total_bytes_sent()),
+        Stats::format_bytes(stats.byte_rate(minutes_long(1)) as u64),
+        stats.get_responses(4),
+        stats.get_responses(5),
+        p50,
+        p90,
+        p99,
+        stats.get_active_connections()
+    )
+}
+
+fn minutes_long(minutes: u64) -> Duration {
+    Duration::from_secs(minutes * 60)
+}
+
+/// One bar per value, scaled to the largest; zero is left blank.
+fn sparkline(values: &[u64]) -> String {
+    let max = values.iter().copied().max().unwrap_or(0);
+    values
+        .iter()
+        .map(|&value| match value {
+            0 => ' ',
+            _ => {
+                let level = value * (BARS.len() as u64 - 1) / max;
+                BARS[level as usize]
+            }
+        })
+        .collect()
+}
+
+/// Columns `s` takes on a terminal, counting emoji as two.
+fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+fn char_width(c: char) -> usize {
+    match c {
+        '\u{FE0F}' | '\u{200D}' => 0,
+        '\u{1F300}'..='\u{1FAFF}' => 2,
+        _ => 1,
+    }
+}
+
+/// Pads or cuts `s` to exactly `width` columns, ending a cut with `...`.
+fn fit(s: &str, width: usize) -> String {
+    let mut used = display_width(s);
+    if used <= width {
+        return format!("{}{}", s, " ".repeat(width - used));
+    }
+
+    let mut fitted = String::new();
+    used = 0;
+    for c in s.chars() {
+        if used + char_width(c) > width.saturating_sub(3) {
+            break;
+        }
+        used += char_width(c);
+        fitted.push(c);
+    }
+    fitted.push_str(&".".repeat(width.min(3)));
+    fitted.push_str(&" ".repeat(width - used - width.min(3)));
+    fitted
+}
+
+/// The terminal's `(columns, rows)`, asked of `stty` since the standard
+/// library has no way to query it. Falls back to `COLUMNS` and `LINES`.
+fn terminal_size() -> Option<(usize, usize)> {
+    let from_stty = || {
+        let output = Command::new("stty")
+            .arg("size")
+            .stdin(File::open("/dev/tty").ok()?)
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        let output = String::from_utf8(output.stdout).ok()?;
+        let (rows, columns) = output.trim().split_once(' ')?;
+        Some((columns.parse().ok()?, rows.parse().ok()?))
+    };
+    let from_env = || {
+        let columns = std::env::var("COLUMNS").ok()?.parse().ok()?;
+        let rows = std::env::var("LINES").ok()?.parse().ok()?;
+        Some((columns, rows))
+    };
+    from_stty()
+        .or_else(from_env)
+        .filter(|&(columns, rows)| columns > 0 && rows > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0, 1, 4, 8, 7]), " ▁▄█▇");
+        assert_eq!(sparkline(&[0, 0]), "  ");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("abcdefgh", 6), "abc...");
+        assert_eq!(display_width(&fit("📁 folder", 5)), 5);
+        assert_eq!(display_width(&fit("📁 folder", 12)), 12);
+    }
+
+    #[test]
+    fn test_render() {
+        let stats = Arc::new(Stats::new());
+        stats.request_completed(None, 404, Duration::from_millis(2), Some(10));
+        let dashboard = Dashboard {
+            stats: Arc::clone(&stats),
+            directory: "a directory with a rather long name that will not fit".to_string(),
+            port: 8080,
+            https: false,
+            notice: Some("notice".to_string()),
+        };
+
+        for (columns, rows) in [(80, 40), (41, 40), (200, 40), (80, 12)] {
+            let lines = dashboard.render((columns, rows));
+            assert!(lines.len() <= rows);
+            let width = columns.clamp(MIN_WIDTH, MAX_WIDTH);
+            for line in lines.iter().take_while(|line| !line.is_empty()) {
+                assert_eq!(display_width(line), width, "{:?}", line);
+            }
+        }
+        let tall = dashboard.render((80, 40));
+        assert!(tall.iter().any(|line| line.contains("Throughput")));
+        assert!(tall.contains(&"notice".to_string()));
+        let short = dashboard.render((80, 12));
+        assert!(!short.iter().any(|line| line.contains("Throughput")));
+
+        let summary = summary(&stats);
+        assert!(summary.contains("1 requests") && summary.contains("1 client errors"));
+    }
+}
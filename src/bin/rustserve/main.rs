@@ -0,0 +1,883 @@
+mod admin;
+mod api;
+mod auth;
+mod cli;
+mod config;
+mod dashboard;
+mod share;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::net::UdpSocket;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use rustserve::archive::{Archive, Format};
+use rustserve::html::{Listing, Sort, View, admin_html, search_results_html};
+use rustserve::http::FileLogger;
+use rustserve::http::Filter;
+use rustserve::http::IntoResponse;
+use rustserve::http::Method;
+use rustserve::http::Request;
+use rustserve::http::Response;
+use rustserve::http::Server;
+use rustserve::http::SignedLink;
+use rustserve::http::StdoutLogger;
+use rustserve::http::delete;
+use rustserve::http::fs_dir;
+use rustserve::http::get;
+use rustserve::http::guard;
+use rustserve::http::normalize_path;
+use rustserve::http::path;
+use rustserve::http::percent_encode_path;
+use rustserve::http::post;
+use rustserve::http::put;
+use rustserve::http::request;
+use rustserve::http::resolve_path;
+use rustserve::http::serve_file;
+use rustserve::http::signed;
+use rustserve::metrics::{self, Registry};
+use rustserve::preview::{self, Preview};
+use rustserve::search::Search;
+use rustserve::stats::Stats;
+use rustserve::thumbnail::Thumbnails;
+
+use crate::api::Api;
+use crate::auth::{Access, Permission};
+use crate::dashboard::Dashboard;
+use crate::share::{Downloads, ShareKeys, ShareRequest};
+
+fn main() {
+    let command = cli::parse(env::args().skip(1), |name| env::var(name).ok());
+    let options = match command {
+        Ok(cli::Command::Serve(options)) => *options,
+        Ok(cli::Command::CheckConfig { path, problems }) => {
+            if problems.is_empty() {
+                println!("{}: OK", path.display());
+                return;
+            }
+            for problem in problems {
+                eprintln!("{}", problem);
+            }
+            process::exit(1);
+        }
+        Ok(cli::Command::HashPassword) => {
+            let mut password = String::new();
+            if let Err(e) = io::stdin().lock().read_line(&mut password) {
+                eprintln!("Error: cannot read password: {}", e);
+                process::exit(1);
+            }
+            let password = password.trim_end_matches(['\r', '\n']);
+            match auth::hash_password(password) {
+                Ok(hash) => println!("{}", hash),
+                Err(e) => {
+                    eprintln!("Error: cannot generate salt: {}", e);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        Ok(cli::Command::Share {
+            keys,
+            directory,
+            port,
+            https,
+            link,
+            base_url,
+        }) => {
+            let is_file = resolve_path(&directory, &percent_encode_path(&link.path))
+                .is_some_and(|path| path.is_file());
+            if !is_file {
+                eprintln!(
+                    "Error: {} is not a file in {}",
+                    link.path,
+                    directory.display()
+                );
+                process::exit(1);
+            }
+            let base_url = base_url.unwrap_or_else(|| {
+                let ip = get_local_ip().unwrap_or_else(|| "127.0.0.1".to_string());
+                format!("{}://{}:{}", scheme(https), ip, port)
+            });
+            match ShareKeys::new(keys).mint(&link) {
+                Ok(url) => println!("{}/share/{}", base_url, url),
+                Err(e) => {
+                    eprintln!("Error: cannot use share keys: {}", e);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        Ok(cli::Command::RevokeKey { keys, id }) => {
+            match ShareKeys::new(&keys).revoke(&id) {
+                Ok(true) => println!("Revoked key {}", id),
+                Ok(false) => {
+                    eprintln!("Error: no key {} in {}", id, keys.display());
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Error: cannot update {}: {}", keys.display(), e);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        Ok(cli::Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(cli::Command::Version) => {
+            println!("rustserve {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Try 'rustserve --help' for more information.");
+            process::exit(2);
+        }
+    };
+
+    let root_path = options.directory.clone();
+    let port = options.port;
+    let https = options.tls.is_some();
+
+    let stats = Arc::new(Stats::new());
+    let registry = Registry::new();
+
+    #[cfg_attr(not(feature = "tls"), allow(unused_mut))]
+    let mut config = options.server_config();
+    #[cfg_attr(not(feature = "tls"), allow(unused_mut))]
+    let mut notice: Option<String> = None;
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &options.tls {
+        let (tls_config, fingerprint) = match tls_config(tls, &options.bind) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        };
+        config = config.tls(tls_config);
+        if let Some(port) = tls.redirect_port {
+            config = config.redirect_http(port);
+        }
+        notice = fingerprint.map(|fingerprint| format!("Self-signed SHA-256: {}", fingerprint));
+    }
+    let access = options.auth.clone().map(|auth| Arc::new(Access::new(auth)));
+
+    let mut server = match Server::new(config) {
+        Ok(s) => s.stats(Arc::clone(&stats)),
+        Err(e) => {
+            eprintln!("Failed to start server: {}", e);
+            process::exit(1);
+        }
+    };
+    if options.metrics {
+        server = server.metrics(registry.clone());
+    }
+    if let Some(log) = &options.access_log {
+        server = match &log.file {
+            None => server.logger(StdoutLogger::new(log.format)),
+            Some(file) => match FileLogger::open(file, log.format) {
+                Ok(mut logger) => {
+                    if let Some(bytes) = log.max_size {
+                        logger = logger.max_size(bytes);
+                    }
+                    if let Some(age) = log.max_age {
+                        logger = logger.max_age(age);
+                    }
+                    server.logger(logger)
+                }
+                Err(e) => {
+                    eprintln!("Error: cannot open {}: {}", file.display(), e);
+                    process::exit(1);
+                }
+            },
+        };
+    }
+
+    println!("Starting rustserve file server...\n");
+
+    // Live statistics: drawn on a terminal, otherwise logged now and then
+    let on_terminal = io::stdout().is_terminal();
+    if let (false, Some(notice)) = (options.dashboard && on_terminal, &notice) {
+        println!("{}", notice);
+    }
+    if options.dashboard && on_terminal {
+        let dashboard = Dashboard {
+            stats: Arc::clone(&stats),
+            directory: root_path
+                .file_name()
+                .unwrap_or(root_path.as_os_str())
+                .to_string_lossy()
+                .to_string(),
+            port,
+            https,
+            notice,
+        };
+        thread::spawn(move || dashboard.run());
+    } else if options.dashboard {
+        let stats = Arc::clone(&stats);
+        thread::spawn(move || dashboard::log_summaries(stats));
+    }
+
+    // Mounted directories take precedence over the built-in routes.
+    let mounts: Vec<_> = options
+        .mounts
+        .iter()
+        .map(|mount| path(&mount.url).and(fs_dir(&mount.directory)))
+        .collect();
+
+    // GET /metrics - Prometheus scraping, when enabled
+    let access_for_metrics = access.clone();
+    let metrics_route: Vec<_> = options
+        .metrics
+        .then(|| {
+            get("/metrics")
+                .and(request())
+                .map(move |(request,)| {
+                    let access = access_for_metrics.as_deref();
+                    match authorize(access, &request, "", Permission::List) {
+                        Ok(_) => Response::ok(registry.render())
+                            .header("Content-Type", metrics::CONTENT_TYPE),
+                        Err(denied) => denied,
+                    }
+                })
+                .label("metrics")
+        })
+        .into_iter()
+        .collect();
+
+    // GET /_admin - Live statistics for administrators, when enabled
+    let admin_route: Vec<_> = access
+        .clone()
+        .filter(|_| options.admin)
+        .map(|admin_access| {
+            let access_for_admin = admin_access.clone();
+            let access_for_events = admin_access.clone();
+            let stats_for_api = Arc::clone(&stats);
+            let admin_api = get("/_admin/api").and(request()).map(move |(request,)| {
+                admin_only(&admin_access, &request, || {
+                    Response::json(admin::snapshot(&stats_for_api))
+                })
+            });
+            let admin_events = get("/_admin/events").and(request()).map(move |(request,)| {
+                admin_only(&access_for_events, &request, || {
+                    Response::stream(admin::Events::new(Arc::clone(&stats)), None)
+                        .header("Content-Type", "text/event-stream")
+                        .header("Cache-Control", "no-cache")
+                        .uncompressed()
+                })
+            });
+            let admin_page = get("/_admin").and(request()).map(move |(request,)| {
+                admin_only(&access_for_admin, &request, || Response::html(admin_html()))
+            });
+            admin_api.or(admin_events).or(admin_page).label("admin")
+        })
+        .into_iter()
+        .collect();
+
+    if let Some(site) = options.site {
+        let mut files = fs_dir(&root_path).clean_urls();
+        if let Some(fallback) = site.fallback {
+            files = files.fallback(fallback);
+        }
+        if let Some(not_found) = site.not_found {
+            files = files.not_found_page(not_found);
+        }
+
+        // Checked before the files are looked at, so refused requests
+        // cannot tell which exist.
+        let allowed = guard(move |request| {
+            let path = tree_path(&request.path())?;
+            authorize(access.as_deref(), request, &path, Permission::Read).map(|_| ())
+        });
+        let files = mounts.or(files).map(|response| (response.into_response(),));
+        let routes = allowed.and(files);
+        server.run(metrics_route.or(admin_route).or(routes.label("site")));
+        return;
+    }
+
+    // Build routes
+    let root_for_index = root_path.clone();
+    let root_for_browse = root_path.clone();
+    let root_for_api = root_path;
+
+    let access_for_mounts = access.clone();
+    let access_for_index = access.clone();
+    let access_for_browse = access.clone();
+    let access_for_search = access.clone();
+    let access_for_files = access.clone();
+    let access_for_view = access.clone();
+    let access_for_thumbs = access.clone();
+    let access_for_archive = access.clone();
+    let access_for_upload = access.clone();
+    let access_for_delete = access.clone();
+    let access_for_share = access.clone();
+    let access_for_api = access;
+    let sharing = options.share_keys.is_some();
+
+    // Mounted directories, checked against their URL path
+    let mounts = request().and(mounts).map(move |(request, response)| {
+        let path = match tree_path(&request.path()) {
+            Ok(path) => path,
+            Err(invalid) => return invalid,
+        };
+        match authorize(
+            access_for_mounts.as_deref(),
+            &request,
+            &path,
+            Permission::Read,
+        ) {
+            Ok(_) => response,
+            Err(denied) => denied,
+        }
+    });
+
+    // GET / - Main UI
+    let index = get("/").and(request()).map(move |(request,)| {
+        let access = access_for_index.as_deref();
+        let user = match authorize(access, &request, "", Permission::List) {
+            Ok(user) => user,
+            Err(denied) => return denied,
+        };
+        let html = listing(&root_for_index, "", &request)
+            .visible(|path| visible(access, user.as_deref(), path))
+            .share_links(sharing)
+            .archive_links(true)
+            .render();
+        Response::html(html)
+    });
+
+    // GET /browse/* - Browse subdirectories
+    let value = root_for_browse.clone();
+    let browse =
+        get("/browse")
+            .param_slashes::<String>()
+            .and(request())
+            .map(move |(sub_path, request)| {
+                let access = access_for_browse.as_deref();
+                let sub_path = match tree_path(&sub_path) {
+                    Ok(sub_path) => sub_path,
+                    Err(invalid) => return invalid,
+                };
+                let user = match authorize(access, &request, &sub_path, Permission::List) {
+                    Ok(user) => user,
+                    Err(denied) => return denied,
+                };
+                let html = listing(&value, &sub_path, &request)
+                    .visible(|path| visible(access, user.as_deref(), path))
+                    .share_links(sharing)
+                    .archive_links(true)
+                    .render();
+                Response::html(html)
+            });
+
+    // GET /search/*?q= - Find files by name below a directory
+    let value = root_for_browse.clone();
+    let search =
+        get("/search")
+            .param_slashes::<String>()
+            .and(request())
+            .map(move |(sub_path, request)| {
+                let access = access_for_search.as_deref();
+                let sub_path = match tree_path(&sub_path) {
+                    Ok(sub_path) => sub_path,
+                    Err(invalid) => return invalid,
+                };
+                let user = match authorize(access, &request, &sub_path, Permission::List) {
+                    Ok(user) => user,
+                    Err(denied) => return denied,
+                };
+                if !value.join(&sub_path).is_dir() {
+                    return Response::not_found();
+                }
+                let query = request.query_param("q").unwrap_or_default();
+                if query.trim().is_empty() {
+                    let location = match sub_path.is_empty() {
+                        true => "/".to_string(),
+                        false => format!("/browse/{}", percent_encode_path(&sub_path)),
+                    };
+                    return Response::redirect(&location);
+                }
+                let (found, truncated) = Search::new(&value, &sub_path, &query)
+                    .visible(|path| visible(access, user.as_deref(), path))
+                    .run();
+                Response::html(search_results_html(
+                    &value, &sub_path, &query, &found, truncated, sharing,
+                ))
+            });
+
+    // GET /download/* - File downloads
+    let value = root_for_browse.clone();
+    let download = get("/download")
+        .param_slashes::<String>()
+        .and(request())
+        .map(move |(path, request)| {
+            let path = match tree_path(&path) {
+                Ok(path) => path,
+                Err(invalid) => return invalid,
+            };
+            if let Err(denied) = authorize(
+                access_for_files.as_deref(),
+                &request,
+                &path,
+                Permission::Read,
+            ) {
+                return denied;
+            }
+            let file_path = value.join(&path);
+            let file_name = file_path
+                .file_name()
+                .map(|s| s.to_string_lossy().replace('"', "'"))
+                .unwrap_or_default();
+            // ?inline lets the preview page embed what browsers show safely.
+            let disposition = match request.query_param("inline") {
+                Some(_) if preview::inline(&file_name) => "inline",
+                _ => "attachment",
+            };
+
+            serve_file(&request, &file_path).header(
+                "Content-Disposition",
+                &format!("{}; filename=\"{}\"", disposition, file_name),
+            )
+        });
+
+    // GET /view/* - A file shown in the page, with its neighbours linked
+    let value = root_for_browse.clone();
+    let view = get("/view")
+        .param_slashes::<String>()
+        .and(request())
+        .map(move |(path, request)| {
+            let access = access_for_view.as_deref();
+            let path = match tree_path(&path) {
+                Ok(path) => path,
+                Err(invalid) => return invalid,
+            };
+            let user = match authorize(access, &request, &path, Permission::Read) {
+                Ok(user) => user,
+                Err(denied) => return denied,
+            };
+            let file_path = value.join(&path);
+            if !file_path.exists() {
+                return Response::not_found();
+            }
+            if file_path.is_dir() {
+                return Response::redirect(&format!("/browse/{}", percent_encode_path(&path)));
+            }
+            let html = Preview::new(&value, &path)
+                .visible(|path| readable(access, user.as_deref(), path))
+                .source(request.query_param("source").is_some())
+                .render();
+            Response::html(html)
+        });
+
+    // GET /thumb/* - A small PNG of an image for the gallery, made once
+    let value = root_for_browse.clone();
+    let thumbnails = Thumbnails::new(
+        options
+            .thumbnail_cache
+            .clone()
+            .unwrap_or_else(|| env::temp_dir().join("rustserve-thumbnails")),
+    );
+    let thumb =
+        get("/thumb")
+            .param_slashes::<String>()
+            .and(request())
+            .map(move |(path, request)| {
+                let path = match tree_path(&path) {
+                    Ok(path) => path,
+                    Err(invalid) => return invalid,
+                };
+                if let Err(denied) = authorize(
+                    access_for_thumbs.as_deref(),
+                    &request,
+                    &path,
+                    Permission::Read,
+                ) {
+                    return denied;
+                }
+                let file_path = value.join(&path);
+                if !file_path.is_file() {
+                    return Response::not_found();
+                }
+                match thumbnails.get(&file_path) {
+                    Ok(thumbnail) => serve_file(&request, &thumbnail),
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::Unsupported | io::ErrorKind::InvalidData
+                        ) =>
+                    {
+                        Response::new(415).body(e.to_string())
+                    }
+                    Err(e) => Response::internal_error().body(format!("Error: {}", e)),
+                }
+            });
+
+    // GET /archive/*?format= - A directory, or the entries picked in it, as
+    // one ZIP or tar download
+    let value = root_for_browse.clone();
+    let archive = get("/archive")
+        .param_slashes::<String>()
+        .and(request())
+        .map(move |(path, request)| {
+            let access = access_for_archive.clone();
+            let path = match tree_path(&path) {
+                Ok(path) => path,
+                Err(invalid) => return invalid,
+            };
+            let user = match authorize(access.as_deref(), &request, &path, Permission::List) {
+                Ok(user) => user,
+                Err(denied) => return denied,
+            };
+            if !value.join(&path).exists() {
+                return Response::not_found();
+            }
+            let format = match request.query_param("format") {
+                None => Format::Zip,
+                Some(name) => match Format::from_name(&name) {
+                    Some(format) => format,
+                    None => return Response::bad_request().body("Unknown archive format"),
+                },
+            };
+
+            let name = match path.rsplit('/').next() {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => value.file_name().map_or("files".to_string(), |name| {
+                    name.to_string_lossy().to_string()
+                }),
+            };
+            // Only what the user could see and download one by one.
+            let mut archive = Archive::new(value.as_path(), &path, format)
+                .visible(move |path| readable(access.as_deref(), user.as_deref(), path));
+            let selected = request.query_params("files");
+            if !selected.is_empty() {
+                archive = archive.select(selected);
+            }
+            Response::stream(archive.reader(), None)
+                .header("Content-Type", format.content_type())
+                .header(
+                    "Content-Disposition",
+                    &format!(
+                        "attachment; filename=\"{}.{}\"",
+                        name.replace('"', "'"),
+                        format.name()
+                    ),
+                )
+                .uncompressed()
+        });
+
+    // PUT /upload/* - File uploads, refused unless enabled
+    let value = root_for_browse.clone();
+    let upload_enabled = options.upload;
+    let upload =
+        put("/upload")
+            .param_slashes::<String>()
+            .and(request())
+            .map(move |(path, request)| {
+                let access = access_for_upload.as_deref();
+                let path = match tree_path(&path) {
+                    Ok(path) => path,
+                    Err(invalid) => return invalid,
+                };
+                if let Err(denied) = authorize(access, &request, &path, Permission::Upload) {
+                    return denied;
+                }
+                if !upload_enabled {
+                    return Response::new(403).body("Uploads are disabled");
+                }
+                save_upload(&value, &path, request.body().unwrap_or_default())
+            });
+
+    // DELETE /upload/* - Remove a file or empty directory, with uploads
+    let value = root_for_browse.clone();
+    let delete_route = delete("/upload")
+        .param_slashes::<String>()
+        .and(request())
+        .map(move |(path, request)| {
+            let access = access_for_delete.as_deref();
+            let path = match tree_path(&path) {
+                Ok(path) => path,
+                Err(invalid) => return invalid,
+            };
+            if let Err(denied) = authorize(access, &request, &path, Permission::Delete) {
+                return denied;
+            }
+            if !upload_enabled {
+                return Response::new(403).body("Uploads are disabled");
+            }
+            remove_entry(&value, &path)
+        });
+
+    // GET /share/* - Signed links, counted against their download limit
+    let share_keys = options
+        .share_keys
+        .map(|keys| Arc::new(ShareKeys::new(keys)));
+    let value = root_for_browse.clone();
+    let keys = share_keys.clone();
+    let downloads = Downloads::default();
+    let share = get("/share")
+        .and(signed(move |id| keys.as_ref()?.get(id)))
+        .and(request())
+        .map(move |(link, request): (SignedLink, Request)| {
+            let file_path = resolve_path(&value, &percent_encode_path(&link.path));
+            let Some(file_path) = file_path.filter(|path| path.is_file()) else {
+                return Response::not_found();
+            };
+            let signature = request.query_param("sig").unwrap_or_default();
+            // Only whole downloads count; HEAD, ranges and revalidation do not.
+            let response = serve_file(&request, &file_path);
+            let whole = request.method() == &Method::Get && response.status_code() == 200;
+            let allowed = match whole {
+                true => downloads.take(&link, &signature),
+                false => downloads.available(&link, &signature),
+            };
+            if !allowed {
+                return Response::new(410).body("This link has been used up");
+            }
+            response
+        });
+
+    // POST /api/share/* - Mint a share link for the UI
+    let value = root_for_browse.clone();
+    let api_share = post("/api/share")
+        .param_slashes::<String>()
+        .and(request())
+        .map(move |(path, request)| {
+            let Some(keys) = &share_keys else {
+                return Response::not_found();
+            };
+            let access = access_for_share.as_deref();
+            let path = match tree_path(&path) {
+                Ok(path) => path,
+                Err(invalid) => return invalid,
+            };
+            if let Err(denied) = authorize(access, &request, &path, Permission::Read) {
+                return denied;
+            }
+            if !value.join(&path).is_file() {
+                return Response::not_found();
+            }
+
+            let expires = request.query_param("expires");
+            let expires_in = match expires.as_deref().map(cli::parse_duration) {
+                None => 24 * 60 * 60,
+                Some(Some(seconds)) if seconds > 0 => seconds,
+                Some(_) => return Response::bad_request().body("Invalid expiry"),
+            };
+            let downloads = match request.query_param("downloads").map(|n| n.parse()) {
+                None => None,
+                Some(Ok(count)) if count > 0 => Some(count),
+                Some(_) => return Response::bad_request().body("Invalid download count"),
+            };
+            let ip = match request.query_param("ip").map(|ip| ip.parse()) {
+                None => None,
+                Some(Ok(ip)) => Some(ip),
+                Some(Err(_)) => return Response::bad_request().body("Invalid IP address"),
+            };
+
+            let link = ShareRequest {
+                path,
+                expires_in,
+                downloads,
+                ip,
+            };
+            match keys.mint(&link) {
+                Ok(url) => {
+                    let host = request.header("host").unwrap_or("localhost");
+                    let url = format!("{}://{}/share/{}", scheme(https), host, url);
+                    Response::json(format!(r#"{{"url":"{}"}}"#, json_escape(&url)))
+                }
+                Err(e) => Response::internal_error().body(format!("Error: {}", e)),
+            }
+        });
+
+    // /api/v1/* - The versioned file API, and its /api/files listing
+    let api = Api {
+        root: root_for_api,
+        access: access_for_api,
+        writable: upload_enabled,
+    };
+
+    // Combine routes
+    let routes = metrics_route
+        .or(admin_route)
+        .or(mounts.label("mount"))
+        .or(index.label("index"))
+        .or(browse.label("browse"))
+        .or(search.label("search"))
+        .or(download.label("download"))
+        .or(view.label("view"))
+        .or(thumb.label("thumb"))
+        .or(archive.label("archive"))
+        .or(upload.label("upload"))
+        .or(delete_route.label("delete"))
+        .or(share.label("share"))
+        .or(api_share.label("api_share"))
+        .or(api.routes().label("api"));
+
+    server.run(routes);
+}
+
+/// Loads the certificates for `tls`, or generates one for this machine's
+/// names, returning its fingerprint, when asked to be self-signed.
+#[cfg(feature = "tls")]
+fn tls_config(
+    tls: &cli::TlsOptions,
+    bind: &str,
+) -> io::Result<(rustserve::http::TlsConfig, Option<String>)> {
+    use rustserve::http::{Certificate, TlsConfig};
+
+    let (default, fingerprint) = match &tls.certificate {
+        cli::CertificateSource::Files { cert, key } => {
+            (Certificate::from_pem_files(cert, key)?, None)
+        }
+        cli::CertificateSource::SelfSigned => {
+            let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+            names.extend(get_local_ip());
+            if bind != "0.0.0.0" && bind != "::" {
+                names.push(bind.to_string());
+            }
+            names.dedup();
+            let certificate = Certificate::self_signed(&names)?;
+            let fingerprint = certificate
+                .fingerprint()
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(":");
+            (certificate, Some(fingerprint))
+        }
+    };
+    let mut config = TlsConfig::new(default);
+    for entry in &tls.sni {
+        config = config.sni(
+            &entry.name,
+            Certificate::from_pem_files(&entry.cert, &entry.key)?,
+        );
+    }
+    Ok((config, fingerprint))
+}
+
+fn scheme(https: bool) -> &'static str {
+    match https {
+        true => "https",
+        false => "http",
+    }
+}
+
+/// The request's path below the root, as both access rules and the file
+/// system see it, or the `400` refusing one that would leave the root.
+fn tree_path(url_path: &str) -> Result<String, Response> {
+    normalize_path(url_path).ok_or_else(|| Response::bad_request().body("Invalid path"))
+}
+
+/// Checks `permission` on `tree_path` when access control is configured,
+/// returning the authenticated user or the response refusing the request.
+fn authorize(
+    access: Option<&Access>,
+    request: &Request,
+    tree_path: &str,
+    permission: Permission,
+) -> Result<Option<String>, Response> {
+    match access {
+        Some(access) => access.check(request, tree_path, permission),
+        None => Ok(None),
+    }
+}
+
+/// Answers a request for the admin pages with `respond` once the user is
+/// known to be an administrator.
+fn admin_only(access: &Access, request: &Request, respond: impl FnOnce() -> Response) -> Response {
+    match access.check_admin(request) {
+        Ok(_) => respond(),
+        Err(denied) => denied,
+    }
+}
+
+/// A listing of `sub_path` ordered and paged as the request's query asks.
+fn listing<'a>(root: &'a Path, sub_path: &'a str, request: &Request) -> Listing<'a> {
+    let sort = request.query_param("sort");
+    let order = request.query_param("order");
+    let page = request
+        .query_param("page")
+        .and_then(|page| page.parse().ok());
+    let view = request.query_param("view");
+    Listing::new(root, sub_path)
+        .sort(Sort::from_query(sort.as_deref(), order.as_deref()))
+        .view(View::from_query(view.as_deref()))
+        .page(page.unwrap_or(1))
+}
+
+/// Whether a listing should show the entry at `tree_path` to `user`.
+fn visible(access: Option<&Access>, user: Option<&str>, tree_path: &str) -> bool {
+    access.is_none_or(|access| access.shows(user, tree_path))
+}
+
+/// Whether `user` may have the file at `tree_path` as part of something
+/// else they download, such as an archive.
+fn readable(access: Option<&Access>, user: Option<&str>, tree_path: &str) -> bool {
+    access.is_none_or(|access| access.readable(user, tree_path))
+}
+
+/// Deletes a file or an empty directory at `tree_path` below `root`.
+fn remove_entry(root: &Path, tree_path: &str) -> Response {
+    if tree_path.is_empty() {
+        return Response::bad_request().body("Invalid path");
+    }
+    let target = root.join(tree_path);
+    let result = match fs::symlink_metadata(&target) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(&target),
+        Ok(_) => fs::remove_file(&target),
+        Err(_) => return Response::not_found(),
+    };
+    match result {
+        Ok(()) => Response::no_content(),
+        Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => {
+            Response::new(409).body("Directory is not empty")
+        }
+        Err(e) => Response::internal_error().body(format!("Error: {}", e)),
+    }
+}
+
+/// Writes an uploaded file, answering `201` for a new file and `204` for a
+/// replaced one. Directories are never created implicitly.
+fn save_upload(root: &Path, tree_path: &str, body: &[u8]) -> Response {
+    if tree_path.is_empty() {
+        return Response::bad_request().body("Invalid upload path");
+    }
+    let file_path = root.join(tree_path);
+    if file_path.is_dir() || !file_path.parent().is_some_and(Path::is_dir) {
+        return Response::new(409).body("Upload must name a file in an existing directory");
+    }
+
+    let existed = file_path.exists();
+    match fs::write(&file_path, body) {
+        Ok(()) if existed => Response::no_content(),
+        Ok(()) => Response::created(),
+        Err(e) => Response::internal_error().body(format!("Error: {}", e)),
+    }
+}
+
+/// Get the local IP address by connecting a UDP socket to an external address.
+/// This doesn't actually send any data, but allows the OS to choose the correct
+/// local IP for reaching external networks.
+fn get_local_ip() -> Option<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    // Connect to Google's DNS - doesn't actually send packets, just sets up routing
+    socket.connect("8.8.8.8:80").ok()?;
+    let addr = socket.local_addr().ok()?;
+    Some(addr.ip().to_string())
+}
+
+fn json_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+}
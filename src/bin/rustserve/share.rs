@@ -0,0 +1,204 @@
+//! Signed share links: the keys that sign them and the download counts of
+//! the links in use.
+//!
+//! Keys live in a file of `id:hex-key` lines that the server re-reads for
+//! every link, so deleting a line (or `--revoke-key ID`) disables the links
+//! that key signed straight away. Download counts are kept in memory and
+//! start over when the server restarts.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use rustserve::http::{SignedLink, decode_hex, encode_hex, unix_time};
+
+use crate::auth::random_bytes;
+
+/// What `--share` or the UI asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShareRequest {
+    /// The file's path below the shared directory.
+    pub path: String,
+    /// Seconds from now until the link expires.
+    pub expires_in: u64,
+    pub downloads: Option<u32>,
+    pub ip: Option<IpAddr>,
+}
+
+pub struct ShareKeys {
+    path: PathBuf,
+}
+
+impl ShareKeys {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ShareKeys { path: path.into() }
+    }
+
+    /// The key with id `id`, read afresh so revocations apply at once.
+    pub fn get(&self, id: &str) -> Option<Vec<u8>> {
+        self.load()
+            .ok()?
+            .into_iter()
+            .find(|(key_id, _)| key_id == id)
+            .map(|(_, key)| key)
+    }
+
+    /// Signs a link with the newest key, creating one if there is none, and
+    /// returns its path and query below `/share/`.
+    pub fn mint(&self, request: &ShareRequest) -> io::Result<String> {
+        let (id, key) = match self.load()?.pop() {
+            Some(newest) => newest,
+            None => self.create()?,
+        };
+
+        let mut link = SignedLink::new(&request.path, &id, unix_time() + request.expires_in);
+        if let Some(downloads) = request.downloads {
+            link = link.downloads(downloads);
+        }
+        if let Some(ip) = request.ip {
+            link = link.ip(ip);
+        }
+        Ok(link.to_url(&key))
+    }
+
+    /// Removes key `id`, returning whether it existed.
+    pub fn revoke(&self, id: &str) -> io::Result<bool> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let kept: Vec<&str> = text
+            .lines()
+            .filter(|line| line.split_once(':').is_none_or(|(key_id, _)| key_id != id))
+            .collect();
+        if kept.len() == text.lines().count() {
+            return Ok(false);
+        }
+        let mut rest = kept.join("\n");
+        if !rest.is_empty() {
+            rest.push('\n');
+        }
+        fs::write(&self.path, rest)?;
+        Ok(true)
+    }
+
+    /// Reads the keys, oldest first. A missing file holds no keys.
+    fn load(&self) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(text
+            .lines()
+            .filter_map(|line| {
+                let (id, key) = line.trim().split_once(':')?;
+                Some((id.to_string(), decode_hex(key)?))
+            })
+            .collect())
+    }
+
+    /// Appends a fresh random key, readable only by its owner.
+    fn create(&self) -> io::Result<(String, Vec<u8>)> {
+        let mut random = [0u8; 36];
+        random_bytes(&mut random)?;
+        let (id, key) = random.split_at(4);
+        let id = encode_hex(id);
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        writeln!(options.open(&self.path)?, "{}:{}", id, encode_hex(key))?;
+        Ok((id, key.to_vec()))
+    }
+}
+
+/// How often each limited link has been used, by its signature and with
+/// its expiry.
+#[derive(Default)]
+pub struct Downloads {
+    counts: Mutex<HashMap<String, (u64, u32)>>,
+}
+
+impl Downloads {
+    /// Counts a use of `link`, which came with `signature`, returning
+    /// `false` once it has run out. Links differing in any term have their
+    /// own count, even for the same file.
+    pub fn take(&self, link: &SignedLink, signature: &str) -> bool {
+        self.count(link, signature, true)
+    }
+
+    /// Whether `link` has uses left, without using one up.
+    pub fn available(&self, link: &SignedLink, signature: &str) -> bool {
+        self.count(link, signature, false)
+    }
+
+    fn count(&self, link: &SignedLink, signature: &str, take: bool) -> bool {
+        let Some(limit) = link.downloads else {
+            return true;
+        };
+        let mut counts = self.counts.lock().unwrap();
+        let now = unix_time();
+        counts.retain(|_, (expires, _)| *expires > now);
+
+        let (_, used) = counts
+            .entry(signature.to_string())
+            .or_insert((link.expires, 0));
+        if *used >= limit {
+            return false;
+        }
+        if take {
+            *used += 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_and_downloads() {
+        let path = std::env::temp_dir().join(format!("rustserve-keys-{}", std::process::id()));
+        let keys = ShareKeys::new(&path);
+        let request = ShareRequest {
+            path: "docs/a.pdf".to_string(),
+            expires_in: 60,
+            downloads: Some(2),
+            ip: None,
+        };
+
+        let url = keys.mint(&request).unwrap();
+        assert!(url.starts_with("docs/a.pdf?expires="));
+        let loaded = keys.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        let (id, key) = &loaded[0];
+        assert_eq!(keys.get(id).as_ref(), Some(key));
+        // Later links reuse the newest key.
+        keys.mint(&request).unwrap();
+        assert_eq!(keys.load().unwrap().len(), 1);
+
+        let link = SignedLink::new("docs/a.pdf", id, unix_time() + 60).downloads(2);
+        let downloads = Downloads::default();
+        assert!(downloads.available(&link, "a1") && downloads.available(&link, "a1"));
+        assert!(downloads.take(&link, "a1") && downloads.take(&link, "a1"));
+        assert!(!downloads.take(&link, "a1"));
+        assert!(!downloads.available(&link, "a1"));
+        // Another link to the file, expiring at the same time, is unaffected.
+        let other = link.clone().downloads(1);
+        assert!(downloads.take(&other, "b2"));
+        assert!(!downloads.take(&other, "b2"));
+
+        assert!(keys.revoke(id).unwrap());
+        assert!(!keys.revoke(id).unwrap());
+        assert_eq!(keys.get(id), None);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,809 @@
+//! Streaming DEFLATE encoder with gzip and zlib framing.
+//!
+//! Input is buffered into blocks of [`BLOCK_SIZE`] bytes, matched against a
+//! 32 KB sliding window with hash chains, and each block is written with
+//! whichever of stored, fixed or dynamic Huffman coding is smallest.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const BLOCK_SIZE: usize = 64 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const LAZY_LIMIT: usize = 32;
+const HASH_BITS: u32 = 15;
+const NONE: usize = usize::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// Incremental CRC-32 (IEEE), as used by gzip and zip.
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC_TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        !self.0
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
+
+/// Incremental Adler-32, as used by zlib.
+#[derive(Clone, Copy)]
+struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    fn new() -> Self {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        // 5552 is the largest run that cannot overflow `b` before reducing.
+        for chunk in data.chunks(5552) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= 65521;
+            self.b %= 65521;
+        }
+    }
+
+    fn value(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, bits: u32, count: u32) {
+        self.acc |= (bits as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+            self.acc = 0;
+            self.count = 0;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= length) - 1
+}
+
+fn dist_code(distance: u16) -> usize {
+    DIST_BASE.partition_point(|&base| base <= distance) - 1
+}
+
+/// Finds LZ77 matches in `data[start..]`, allowing references back into
+/// `data[..start]`.
+fn lz77(data: &[u8], start: usize) -> Vec<Token> {
+    let n = data.len();
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut prev = vec![NONE; n];
+    let mut next_insert = 0;
+    let mut tokens = Vec::with_capacity(n - start);
+
+    let hash = |p: usize| -> usize {
+        let v = (data[p] as u32) << 16 | (data[p + 1] as u32) << 8 | data[p + 2] as u32;
+        (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+
+    let mut insert_until = |end: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        while next_insert < end {
+            if next_insert + MIN_MATCH <= n {
+                let h = hash(next_insert);
+                prev[next_insert] = head[h];
+                head[h] = next_insert;
+            }
+            next_insert += 1;
+        }
+    };
+
+    let longest_match = |p: usize, head: &Vec<usize>, prev: &Vec<usize>| -> (usize, usize) {
+        if p + MIN_MATCH > n {
+            return (0, 0);
+        }
+
+        let max_len = MAX_MATCH.min(n - p);
+        let mut best = (0, 0);
+        let mut candidate = head[hash(p)];
+        let mut chain = MAX_CHAIN;
+
+        while candidate != NONE && chain > 0 {
+            let distance = p - candidate;
+            if distance > WINDOW_SIZE {
+                break;
+            }
+
+            if data[candidate + best.0] == data[p + best.0] {
+                let mut len = 0;
+                while len < max_len && data[candidate + len] == data[p + len] {
+                    len += 1;
+                }
+                if len > best.0 {
+                    best = (len, distance);
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+
+            candidate = prev[candidate];
+            chain -= 1;
+        }
+
+        if best.0 >= MIN_MATCH { best } else { (0, 0) }
+    };
+
+    insert_until(start, &mut head, &mut prev);
+
+    let mut i = start;
+    while i < n {
+        let (len, distance) = longest_match(i, &head, &prev);
+
+        if len == 0 {
+            tokens.push(Token::Literal(data[i]));
+            insert_until(i + 1, &mut head, &mut prev);
+            i += 1;
+            continue;
+        }
+
+        insert_until(i + 1, &mut head, &mut prev);
+
+        // One step of lazy evaluation: prefer a longer match starting at the
+        // next byte over the one found here.
+        if len < LAZY_LIMIT && i + 1 < n {
+            let (next_len, _) = longest_match(i + 1, &head, &prev);
+            if next_len > len {
+                tokens.push(Token::Literal(data[i]));
+                i += 1;
+                continue;
+            }
+        }
+
+        tokens.push(Token::Match {
+            length: len as u16,
+            distance: distance as u16,
+        });
+        insert_until(i + len, &mut head, &mut prev);
+        i += len;
+    }
+
+    tokens
+}
+
+/// Builds Huffman code lengths no longer than `limit` bits.
+fn code_lengths(freqs: &[u32], limit: usize) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let used: Vec<usize> = (0..freqs.len()).filter(|&s| freqs[s] > 0).collect();
+
+    if used.len() < 2 {
+        // A single symbol still needs a complete code, so pair it with a
+        // second, never used one.
+        let first = used.first().copied().unwrap_or(0);
+        let second = if first == 0 { 1 } else { 0 };
+        lengths[first] = 1;
+        lengths[second] = 1;
+        return lengths;
+    }
+
+    // Tree nodes: leaves first, internal nodes appended as they are built.
+    let mut parent = vec![0usize; used.len() * 2 - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = used
+        .iter()
+        .enumerate()
+        .map(|(node, &sym)| Reverse((freqs[sym] as u64, node)))
+        .collect();
+
+    let mut next = used.len();
+    while heap.len() > 1 {
+        let Reverse((fa, a)) = heap.pop().unwrap();
+        let Reverse((fb, b)) = heap.pop().unwrap();
+        parent[a] = next;
+        parent[b] = next;
+        heap.push(Reverse((fa + fb, next)));
+        next += 1;
+    }
+
+    let root = next - 1;
+    let mut depth = vec![0usize; next];
+    for node in (0..root).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+
+    let max_depth = (0..used.len()).map(|leaf| depth[leaf]).max().unwrap_or(0);
+    let mut bl_count = vec![0usize; max_depth.max(limit) + 1];
+    for leaf in 0..used.len() {
+        bl_count[depth[leaf]] += 1;
+    }
+
+    // Push overly deep leaves up while keeping the code complete.
+    for i in (limit + 1..=max_depth).rev() {
+        while bl_count[i] > 0 {
+            let mut j = i - 2;
+            while bl_count[j] == 0 {
+                j -= 1;
+            }
+            bl_count[i] -= 2;
+            bl_count[i - 1] += 1;
+            bl_count[j + 1] += 2;
+            bl_count[j] -= 1;
+        }
+    }
+
+    // Most frequent symbols get the shortest codes.
+    let mut by_freq = used;
+    by_freq.sort_by_key(|&s| (Reverse(freqs[s]), s));
+    let mut symbols = by_freq.into_iter();
+    for (len, &count) in bl_count.iter().enumerate().take(limit + 1) {
+        for _ in 0..count {
+            if let Some(sym) = symbols.next() {
+                lengths[sym] = len as u8;
+            }
+        }
+    }
+
+    lengths
+}
+
+/// Canonical Huffman codes, bit-reversed for LSB-first output.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let max = *lengths.iter().max().unwrap_or(&0) as usize;
+    let mut bl_count = vec![0u16; max + 1];
+    for &len in lengths {
+        if len > 0 {
+            bl_count[len as usize] += 1;
+        }
+    }
+
+    let mut next_code = vec![0u16; max + 2];
+    let mut code = 0u16;
+    for bits in 1..=max {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            code.reverse_bits() >> (16 - len)
+        })
+        .collect()
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut lit = vec![8u8; 288];
+    lit[144..256].fill(9);
+    lit[256..280].fill(7);
+    (lit, vec![5u8; 30])
+}
+
+/// Run-length encodes code lengths into (symbol, extra bits value) pairs.
+fn encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let len = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == len {
+            run += 1;
+        }
+
+        if len == 0 && run >= 3 {
+            let run = run.min(138);
+            if run <= 10 {
+                out.push((17, (run - 3) as u8));
+            } else {
+                out.push((18, (run - 11) as u8));
+            }
+            i += run;
+        } else if len != 0 && run >= 4 {
+            out.push((len, 0));
+            let repeat = (run - 1).min(6);
+            out.push((16, (repeat - 3) as u8));
+            i += 1 + repeat;
+        } else {
+            out.push((len, 0));
+            i += 1;
+        }
+    }
+
+    out
+}
+
+fn extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// A raw DEFLATE (RFC 1951) stream encoder.
+pub struct DeflateEncoder<W: Write> {
+    inner: W,
+    data: Vec<u8>,
+    history: usize,
+    bits: BitWriter,
+}
+
+impl<W: Write> DeflateEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        DeflateEncoder::with_prefix(inner, Vec::new())
+    }
+
+    /// Starts the output with `prefix`, used for gzip and zlib headers.
+    fn with_prefix(inner: W, prefix: Vec<u8>) -> Self {
+        DeflateEncoder {
+            inner,
+            data: Vec::new(),
+            history: 0,
+            bits: BitWriter {
+                out: prefix,
+                acc: 0,
+                count: 0,
+            },
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Compresses everything still buffered, ends the stream and returns the
+    /// underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.data.len() > self.history {
+            self.compress_block(true);
+        } else {
+            // Empty final block with fixed codes: just the end-of-block symbol.
+            self.bits.put(1, 1);
+            self.bits.put(1, 2);
+            self.bits.put(0, 7);
+        }
+
+        self.bits.align();
+        self.write_out()?;
+        Ok(self.inner)
+    }
+
+    fn write_out(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.bits.out)?;
+        self.bits.out.clear();
+        Ok(())
+    }
+
+    fn compress_block(&mut self, last: bool) {
+        let tokens = lz77(&self.data, self.history);
+        let pending = &self.data[self.history..];
+
+        let mut lit_freqs = vec![0u32; 286];
+        let mut dist_freqs = vec![0u32; 30];
+        for token in &tokens {
+            match *token {
+                Token::Literal(byte) => lit_freqs[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    lit_freqs[257 + length_code(length)] += 1;
+                    dist_freqs[dist_code(distance)] += 1;
+                }
+            }
+        }
+        lit_freqs[256] = 1;
+
+        let lit_lengths = code_lengths(&lit_freqs, 15);
+        let dist_lengths = code_lengths(&dist_freqs, 15);
+
+        let hlit = 257.max(lit_lengths.iter().rposition(|&l| l > 0).unwrap_or(0) + 1);
+        let hdist = 1.max(dist_lengths.iter().rposition(|&l| l > 0).unwrap_or(0) + 1);
+
+        let mut all_lengths = lit_lengths[..hlit].to_vec();
+        all_lengths.extend_from_slice(&dist_lengths[..hdist]);
+        let cl_symbols = encode_code_lengths(&all_lengths);
+
+        let mut cl_freqs = vec![0u32; 19];
+        for &(symbol, _) in &cl_symbols {
+            cl_freqs[symbol as usize] += 1;
+        }
+        let cl_lengths = code_lengths(&cl_freqs, 7);
+        let hclen = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&s| cl_lengths[s] > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+
+        let body_cost = |lit: &[u8], dist: &[u8]| -> u64 {
+            let mut bits = 0u64;
+            for (sym, &freq) in lit_freqs.iter().enumerate() {
+                bits += freq as u64 * lit[sym] as u64;
+                if sym >= 257 {
+                    bits += freq as u64 * LENGTH_EXTRA[sym - 257] as u64;
+                }
+            }
+            for (sym, &freq) in dist_freqs.iter().enumerate() {
+                bits += freq as u64 * (dist[sym] as u64 + DIST_EXTRA[sym] as u64);
+            }
+            bits
+        };
+
+        let mut dynamic_cost = 3 + 14 + 3 * hclen as u64 + body_cost(&lit_lengths, &dist_lengths);
+        for &(symbol, _) in &cl_symbols {
+            dynamic_cost += cl_lengths[symbol as usize] as u64 + extra_bits(symbol) as u64;
+        }
+
+        let (fixed_lit, fixed_dist) = fixed_lengths();
+        let fixed_cost = 3 + body_cost(&fixed_lit, &fixed_dist);
+        let stored_cost =
+            (pending.len() as u64 + 5 * pending.len().div_ceil(0xFFFF) as u64) * 8 + 7;
+
+        if stored_cost <= dynamic_cost && stored_cost <= fixed_cost {
+            self.write_stored(last);
+        } else if fixed_cost <= dynamic_cost {
+            self.bits.put(last as u32, 1);
+            self.bits.put(1, 2);
+            self.write_tokens(&tokens, &fixed_lit, &fixed_dist);
+        } else {
+            self.bits.put(last as u32, 1);
+            self.bits.put(2, 2);
+            self.bits.put((hlit - 257) as u32, 5);
+            self.bits.put((hdist - 1) as u32, 5);
+            self.bits.put((hclen - 4) as u32, 4);
+            for &sym in &CODE_LENGTH_ORDER[..hclen] {
+                self.bits.put(cl_lengths[sym] as u32, 3);
+            }
+
+            let cl_codes = canonical_codes(&cl_lengths);
+            for &(symbol, extra) in &cl_symbols {
+                let s = symbol as usize;
+                self.bits.put(cl_codes[s] as u32, cl_lengths[s] as u32);
+                self.bits.put(extra as u32, extra_bits(symbol));
+            }
+
+            self.write_tokens(&tokens, &lit_lengths, &dist_lengths);
+        }
+
+        // Keep the tail of the input as history for the next block.
+        let keep = WINDOW_SIZE.min(self.data.len());
+        self.data.drain(..self.data.len() - keep);
+        self.history = self.data.len();
+    }
+
+    fn write_tokens(&mut self, tokens: &[Token], lit_lengths: &[u8], dist_lengths: &[u8]) {
+        let lit_codes = canonical_codes(lit_lengths);
+        let dist_codes = canonical_codes(dist_lengths);
+
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => {
+                    let s = byte as usize;
+                    self.bits.put(lit_codes[s] as u32, lit_lengths[s] as u32);
+                }
+                Token::Match { length, distance } => {
+                    let code = length_code(length);
+                    let s = 257 + code;
+                    self.bits.put(lit_codes[s] as u32, lit_lengths[s] as u32);
+                    self.bits.put(
+                        (length - LENGTH_BASE[code]) as u32,
+                        LENGTH_EXTRA[code] as u32,
+                    );
+
+                    let code = dist_code(distance);
+                    self.bits
+                        .put(dist_codes[code] as u32, dist_lengths[code] as u32);
+                    self.bits
+                        .put((distance - DIST_BASE[code]) as u32, DIST_EXTRA[code] as u32);
+                }
+            }
+        }
+
+        self.bits
+            .put(lit_codes[256] as u32, lit_lengths[256] as u32);
+    }
+
+    fn write_stored(&mut self, last: bool) {
+        let pending = &self.data[self.history..];
+        let chunks: Vec<&[u8]> = pending.chunks(0xFFFF).collect();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let is_last = last && i + 1 == chunks.len();
+            self.bits.put(is_last as u32, 1);
+            self.bits.put(0, 2);
+            self.bits.align();
+
+            let len = chunk.len() as u16;
+            self.bits.out.extend_from_slice(&len.to_le_bytes());
+            self.bits.out.extend_from_slice(&(!len).to_le_bytes());
+            self.bits.out.extend_from_slice(chunk);
+        }
+    }
+}
+
+impl<W: Write> Write for DeflateEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        if self.data.len() - self.history >= BLOCK_SIZE {
+            self.compress_block(false);
+            self.write_out()?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Emits everything buffered so far followed by an empty stored block, so
+    /// the output up to this point can be decoded on its own.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.data.len() > self.history {
+            self.compress_block(false);
+        }
+        self.bits.put(0, 1);
+        self.bits.put(0, 2);
+        self.bits.align();
+        self.bits.out.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+        self.write_out()?;
+        self.inner.flush()
+    }
+}
+
+/// A gzip (RFC 1952) stream encoder.
+pub struct GzipEncoder<W: Write> {
+    deflate: DeflateEncoder<W>,
+    crc: Crc32,
+    size: u32,
+}
+
+impl<W: Write> GzipEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        // Magic, CM=deflate, no flags, no mtime, no extra flags, OS=unknown.
+        let header = vec![0x1F, 0x8B, 0x08, 0x00, 0, 0, 0, 0, 0x00, 0xFF];
+        GzipEncoder {
+            deflate: DeflateEncoder::with_prefix(inner, header),
+            crc: Crc32::new(),
+            size: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.deflate.get_mut()
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        let mut inner = self.deflate.finish()?;
+        inner.write_all(&self.crc.value().to_le_bytes())?;
+        inner.write_all(&self.size.to_le_bytes())?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for GzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.crc.update(buf);
+        self.size = self.size.wrapping_add(buf.len() as u32);
+        self.deflate.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate.flush()
+    }
+}
+
+/// A zlib (RFC 1950) stream encoder; this is what HTTP calls `deflate`.
+pub struct ZlibEncoder<W: Write> {
+    deflate: DeflateEncoder<W>,
+    adler: Adler32,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        // 32 KB window, default compression level.
+        let header = vec![0x78, 0x9C];
+        ZlibEncoder {
+            deflate: DeflateEncoder::with_prefix(inner, header),
+            adler: Adler32::new(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.deflate.get_mut()
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        let mut inner = self.deflate.finish()?;
+        inner.write_all(&self.adler.value().to_be_bytes())?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.adler.update(buf);
+        self.deflate.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn sample(len: usize) -> Vec<u8> {
+        // Text-like data with repetition plus a pseudo-random tail that
+        // defeats the matcher.
+        let mut data = Vec::with_capacity(len);
+        let mut x: u32 = 12345;
+        while data.len() < len {
+            if data.len() % 3000 < 2000 {
+                data.extend_from_slice(b"<a href=\"/browse/folder\" class=\"file-item\">");
+            } else {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                data.push((x >> 16) as u8);
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        for len in [0, 1, 100, BLOCK_SIZE + 17, 3 * BLOCK_SIZE] {
+            let data = sample(len);
+            let mut encoder = GzipEncoder::new(Vec::new());
+            for chunk in data.chunks(1000) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let compressed = encoder.finish().unwrap();
+
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(&compressed[..])
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, data, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_zlib_round_trip_with_flush() {
+        let data = sample(200_000);
+        let mut encoder = ZlibEncoder::new(Vec::new());
+        encoder.write_all(&data[..5000]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&data[5000..]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < data.len() / 2);
+
+        let mut decoded = Vec::new();
+        flate2::read::ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_incompressible_data_is_stored() {
+        let mut x: u32 = 1;
+        let data: Vec<u8> = (0..50_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+
+        let mut encoder = DeflateEncoder::new(Vec::new());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() <= data.len() + 16);
+
+        let mut decoded = Vec::new();
+        flate2::read::DeflateDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -0,0 +1,225 @@
+//! What the file browser shows about a file or directory.
+
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use crate::http::date::DateTime;
+use crate::http::json_escape;
+use crate::mime;
+
+/// Metadata of one entry below the served root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileInfo {
+    pub name: String,
+    /// `/`-separated, relative to the root.
+    pub path: String,
+    pub is_dir: bool,
+    /// In bytes; 0 for directories.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Not every file system records it.
+    pub created: Option<SystemTime>,
+    /// Unix permission bits, e.g. `0o644`.
+    pub mode: Option<u32>,
+    /// The owner's user name, or their numeric id if it has none.
+    pub owner: Option<String>,
+    /// Guessed from the extension; `None` for directories.
+    pub mime: Option<&'static str>,
+    /// Where the entry points, as written in the link, if it is a symbolic
+    /// link.
+    pub symlink_target: Option<String>,
+    /// The number of entries in a directory.
+    pub children: Option<u64>,
+}
+
+impl FileInfo {
+    /// Reads the entry at `path` below `root`. Symbolic links are followed
+    /// for everything but [`FileInfo::symlink_target`]; a dangling link is
+    /// described by the link itself.
+    pub fn read(root: &Path, path: &str) -> io::Result<FileInfo> {
+        let full_path = root.join(path);
+        let link = fs::symlink_metadata(&full_path)?;
+        let symlink_target = match link.file_type().is_symlink() {
+            true => Some(fs::read_link(&full_path)?.to_string_lossy().into_owned()),
+            false => None,
+        };
+        let metadata = fs::metadata(&full_path).unwrap_or(link);
+        let is_dir = metadata.is_dir();
+        let name = path.rsplit('/').next().unwrap_or_default().to_string();
+
+        Ok(FileInfo {
+            is_dir,
+            size: if is_dir { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+            created: metadata.created().ok(),
+            mode: mode(&metadata),
+            owner: owner(&metadata),
+            mime: (!is_dir).then(|| mime::from_path(&name)),
+            symlink_target,
+            children: match is_dir {
+                true => fs::read_dir(&full_path)
+                    .ok()
+                    .map(|entries| entries.count() as u64),
+                false => None,
+            },
+            name,
+            path: path.to_string(),
+        })
+    }
+
+    /// The permission bits as `ls` shows them, e.g. `rw-r--r--`.
+    pub fn permissions(&self) -> Option<String> {
+        let mode = self.mode?;
+        Some(
+            (0..9)
+                .map(|bit| match mode & (0o400 >> bit) {
+                    0 => '-',
+                    _ => ['r', 'w', 'x'][bit % 3],
+                })
+                .collect(),
+        )
+    }
+
+    /// A JSON object with every field that is known. Times are RFC 3339.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            r#"{{"name":"{}","path":"{}","isDir":{},"size":{}"#,
+            json_escape(&self.name),
+            json_escape(&self.path),
+            self.is_dir,
+            self.size
+        );
+        let time = |time: SystemTime| DateTime::from_system_time(time).to_rfc3339();
+        let strings = [
+            ("modified", self.modified.map(time)),
+            ("created", self.created.map(time)),
+            ("mode", self.mode.map(|mode| format!("{:04o}", mode))),
+            ("permissions", self.permissions()),
+            ("owner", self.owner.clone()),
+            ("mime", self.mime.map(String::from)),
+            ("symlinkTarget", self.symlink_target.clone()),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                json.push_str(&format!(r#","{}":"{}""#, key, json_escape(&value)));
+            }
+        }
+        if let Some(children) = self.children {
+            json.push_str(&format!(r#","childCount":{}"#, children));
+        }
+        json.push('}');
+        json
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn owner(metadata: &Metadata) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let uid = metadata.uid();
+    Some(match user_names().get(&uid) {
+        Some(name) => name.clone(),
+        None => uid.to_string(),
+    })
+}
+
+#[cfg(not(unix))]
+fn owner(_metadata: &Metadata) -> Option<String> {
+    None
+}
+
+/// User names by id from `/etc/passwd`, read once: users added while the
+/// server runs show up by number.
+#[cfg(unix)]
+fn user_names() -> &'static HashMap<u32, String> {
+    static NAMES: OnceLock<HashMap<u32, String>> = OnceLock::new();
+    NAMES.get_or_init(|| {
+        let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+        passwd
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let uid = fields.nth(1)?.parse().ok()?;
+                Some((uid, name.to_string()))
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let root = std::env::temp_dir().join(format!("rustserve-info-{}", std::process::id()));
+        fs::create_dir_all(root.join("dir/sub")).unwrap();
+        fs::write(root.join("dir/notes.txt"), "hello").unwrap();
+
+        let file = FileInfo::read(&root, "dir/notes.txt").unwrap();
+        assert_eq!((file.name.as_str(), file.size), ("notes.txt", 5));
+        assert!(!file.is_dir && file.modified.is_some());
+        assert_eq!(file.mime, Some("text/plain; charset=utf-8"));
+        assert_eq!(file.children, None);
+
+        let dir = FileInfo::read(&root, "dir").unwrap();
+        assert!(dir.is_dir);
+        assert_eq!((dir.size, dir.children, dir.mime), (0, Some(2), None));
+        assert!(FileInfo::read(&root, "missing").is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("dir/notes.txt", root.join("link")).unwrap();
+            std::os::unix::fs::symlink("nowhere", root.join("dangling")).unwrap();
+            let link = FileInfo::read(&root, "link").unwrap();
+            assert_eq!(link.symlink_target.as_deref(), Some("dir/notes.txt"));
+            assert_eq!(link.size, 5);
+            let dangling = FileInfo::read(&root, "dangling").unwrap();
+            assert_eq!(dangling.symlink_target.as_deref(), Some("nowhere"));
+            assert!(file.owner.is_some());
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_json() {
+        let info = FileInfo {
+            name: "a \"b\".txt".to_string(),
+            path: "x/a \"b\".txt".to_string(),
+            is_dir: false,
+            size: 3,
+            modified: Some(SystemTime::UNIX_EPOCH),
+            created: None,
+            mode: Some(0o754),
+            owner: Some("alice".to_string()),
+            mime: Some("text/plain; charset=utf-8"),
+            symlink_target: None,
+            children: None,
+        };
+        assert_eq!(info.permissions().as_deref(), Some("rwxr-xr--"));
+        assert_eq!(
+            info.to_json(),
+            concat!(
+                r#"{"name":"a \"b\".txt","path":"x/a \"b\".txt","isDir":false,"size":3,"#,
+                r#""modified":"1970-01-01T00:00:00Z","mode":"0754","permissions":"rwxr-xr--","#,
+                r#""owner":"alice","mime":"text/plain; charset=utf-8"}"#
+            )
+        );
+    }
+}
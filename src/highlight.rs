@@ -0,0 +1,1132 @@
+//! Syntax highlighting for source previews: a tokenizer per language
+//! family, described by its keywords, comments and quotes, that wraps
+//! tokens in `hl-*` classed spans.
+
+use crate::html::html_escape;
+
+/// A kind of string literal.
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    /// Whether it may span lines; one that may not ends with its line.
+    multiline: bool,
+    /// Whether a backslash escapes the next character.
+    escapes: bool,
+}
+
+const DOUBLE: Quote = Quote {
+    open: "\"",
+    close: "\"",
+    multiline: false,
+    escapes: true,
+};
+
+const SINGLE: Quote = Quote {
+    open: "'",
+    close: "'",
+    multiline: false,
+    escapes: true,
+};
+
+const TRIPLE_DOUBLE: Quote = Quote {
+    open: "\"\"\"",
+    close: "\"\"\"",
+    multiline: true,
+    escapes: true,
+};
+
+const TRIPLE_SINGLE: Quote = Quote {
+    open: "'''",
+    close: "'''",
+    multiline: true,
+    escapes: true,
+};
+
+const BACKTICK: Quote = Quote {
+    open: "`",
+    close: "`",
+    multiline: true,
+    escapes: true,
+};
+
+/// A language the highlighter knows.
+pub struct Language {
+    pub name: &'static str,
+    /// File extensions, file names and Markdown fence names, in lower case.
+    aliases: &'static [&'static str],
+    keywords: &'static [&'static str],
+    /// Built-in values such as `true` and `null`.
+    literals: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Checked in order, so longer openers come first.
+    strings: &'static [Quote],
+    /// Whether `'` only quotes a single character, as in Rust, where it
+    /// also starts lifetimes.
+    char_literals: bool,
+    /// Whether `-` may be part of a name, as in CSS.
+    dashed_names: bool,
+    ignore_case: bool,
+    /// Whether it is HTML or XML, highlighted by tags rather than words.
+    markup: bool,
+}
+
+const BASE: Language = Language {
+    name: "",
+    aliases: &[],
+    keywords: &[],
+    literals: &[],
+    line_comments: &[],
+    block_comment: None,
+    strings: &[],
+    char_literals: false,
+    dashed_names: false,
+    ignore_case: false,
+    markup: false,
+};
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while",
+];
+
+const JS_KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "finally",
+    "for",
+    "from",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "of",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+const JS_LITERALS: &[&str] = &["true", "false", "null", "undefined", "NaN", "Infinity"];
+
+static LANGUAGES: &[Language] = &[
+    Language {
+        name: "rust",
+        aliases: &["rs", "rust"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+            "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+            "type", "unsafe", "use", "where", "while",
+        ],
+        literals: &["true", "false"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[Quote {
+            multiline: true,
+            ..DOUBLE
+        }],
+        char_literals: true,
+        ..BASE
+    },
+    Language {
+        name: "python",
+        aliases: &["py", "pyw", "python"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "case", "class", "continue", "def",
+            "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+            "in", "is", "lambda", "match", "nonlocal", "not", "or", "pass", "raise", "return",
+            "try", "while", "with", "yield",
+        ],
+        literals: &["True", "False", "None"],
+        line_comments: &["#"],
+        strings: &[TRIPLE_DOUBLE, TRIPLE_SINGLE, DOUBLE, SINGLE],
+        ..BASE
+    },
+    Language {
+        name: "javascript",
+        aliases: &["js", "mjs", "cjs", "jsx", "javascript"],
+        keywords: JS_KEYWORDS,
+        literals: JS_LITERALS,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[DOUBLE, SINGLE, BACKTICK],
+        ..BASE
+    },
+    Language {
+        name: "typescript",
+        aliases: &["ts", "mts", "tsx", "typescript"],
+        keywords: &[
+            "abstract",
+            "any",
+            "as",
+            "async",
+            "await",
+            "boolean",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "declare",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "enum",
+            "export",
+            "extends",
+            "finally",
+            "for",
+            "from",
+            "function",
+            "if",
+            "implements",
+            "import",
+            "in",
+            "instanceof",
+            "interface",
+            "keyof",
+            "let",
+            "namespace",
+            "never",
+            "new",
+            "number",
+            "of",
+            "private",
+            "protected",
+            "public",
+            "readonly",
+            "return",
+            "static",
+            "string",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "try",
+            "type",
+            "typeof",
+            "unknown",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        literals: JS_LITERALS,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[DOUBLE, SINGLE, BACKTICK],
+        ..BASE
+    },
+    Language {
+        name: "go",
+        aliases: &["go", "golang"],
+        keywords: &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "fallthrough",
+            "for",
+            "func",
+            "go",
+            "goto",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "type",
+            "var",
+        ],
+        literals: &["true", "false", "nil", "iota"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[
+            DOUBLE,
+            SINGLE,
+            Quote {
+                escapes: false,
+                ..BACKTICK
+            },
+        ],
+        ..BASE
+    },
+    Language {
+        name: "c",
+        aliases: &["c", "h"],
+        keywords: C_KEYWORDS,
+        literals: &["NULL", "true", "false"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[DOUBLE, SINGLE],
+        ..BASE
+    },
+    Language {
+        name: "cpp",
+        aliases: &["cpp", "cc", "cxx", "hpp", "hh", "hxx", "c++"],
+        keywords: &[
+            "auto",
+            "bool",
+            "break",
+            "case",
+            "catch",
+            "char",
+            "class",
+            "const",
+            "constexpr",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "double",
+            "else",
+            "enum",
+            "explicit",
+            "extern",
+            "float",
+            "for",
+            "friend",
+            "goto",
+            "if",
+            "inline",
+            "int",
+            "long",
+            "namespace",
+            "new",
+            "noexcept",
+            "operator",
+            "override",
+            "private",
+            "protected",
+            "public",
+            "return",
+            "short",
+            "signed",
+            "sizeof",
+            "static",
+            "struct",
+            "switch",
+            "template",
+            "this",
+            "throw",
+            "try",
+            "typedef",
+            "typename",
+            "union",
+            "unsigned",
+            "using",
+            "virtual",
+            "void",
+            "volatile",
+            "while",
+        ],
+        literals: &["nullptr", "NULL", "true", "false"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[DOUBLE, SINGLE],
+        ..BASE
+    },
+    Language {
+        name: "java",
+        aliases: &["java", "kt", "kts", "kotlin", "cs", "csharp", "scala"],
+        keywords: &[
+            "abstract",
+            "assert",
+            "boolean",
+            "break",
+            "byte",
+            "case",
+            "catch",
+            "char",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "do",
+            "double",
+            "else",
+            "enum",
+            "extends",
+            "final",
+            "finally",
+            "float",
+            "for",
+            "fun",
+            "if",
+            "implements",
+            "import",
+            "instanceof",
+            "int",
+            "interface",
+            "long",
+            "namespace",
+            "native",
+            "new",
+            "override",
+            "package",
+            "private",
+            "protected",
+            "public",
+            "record",
+            "return",
+            "short",
+            "static",
+            "super",
+            "switch",
+            "synchronized",
+            "this",
+            "throw",
+            "throws",
+            "try",
+            "using",
+            "val",
+            "var",
+            "void",
+            "volatile",
+            "when",
+            "while",
+        ],
+        literals: &["true", "false", "null"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[TRIPLE_DOUBLE, DOUBLE, SINGLE],
+        ..BASE
+    },
+    Language {
+        name: "ruby",
+        aliases: &["rb", "ruby", "rake", "gemfile", "rakefile"],
+        keywords: &[
+            "alias", "and", "begin", "break", "case", "class", "def", "do", "else", "elsif", "end",
+            "ensure", "for", "if", "in", "module", "next", "not", "or", "raise", "redo", "require",
+            "rescue", "retry", "return", "self", "super", "then", "undef", "unless", "until",
+            "when", "while", "yield",
+        ],
+        literals: &["true", "false", "nil"],
+        line_comments: &["#"],
+        strings: &[
+            Quote {
+                multiline: true,
+                ..DOUBLE
+            },
+            Quote {
+                multiline: true,
+                ..SINGLE
+            },
+        ],
+        ..BASE
+    },
+    Language {
+        name: "shell",
+        aliases: &[
+            "sh",
+            "bash",
+            "zsh",
+            "fish",
+            "shell",
+            "console",
+            "makefile",
+            "dockerfile",
+            "env",
+        ],
+        keywords: &[
+            "case",
+            "do",
+            "done",
+            "elif",
+            "else",
+            "esac",
+            "exit",
+            "export",
+            "fi",
+            "for",
+            "function",
+            "if",
+            "in",
+            "local",
+            "readonly",
+            "return",
+            "select",
+            "shift",
+            "then",
+            "unset",
+            "until",
+            "while",
+            "FROM",
+            "RUN",
+            "COPY",
+            "ADD",
+            "CMD",
+            "ENTRYPOINT",
+            "ENV",
+            "WORKDIR",
+            "EXPOSE",
+        ],
+        literals: &["true", "false"],
+        line_comments: &["#"],
+        strings: &[
+            Quote {
+                multiline: true,
+                ..DOUBLE
+            },
+            Quote {
+                multiline: true,
+                escapes: false,
+                ..SINGLE
+            },
+        ],
+        ..BASE
+    },
+    Language {
+        name: "sql",
+        aliases: &["sql"],
+        keywords: &[
+            "add",
+            "all",
+            "alter",
+            "and",
+            "as",
+            "asc",
+            "begin",
+            "between",
+            "by",
+            "case",
+            "commit",
+            "create",
+            "default",
+            "delete",
+            "desc",
+            "distinct",
+            "drop",
+            "else",
+            "end",
+            "exists",
+            "foreign",
+            "from",
+            "group",
+            "having",
+            "in",
+            "index",
+            "inner",
+            "insert",
+            "into",
+            "is",
+            "join",
+            "key",
+            "left",
+            "like",
+            "limit",
+            "not",
+            "offset",
+            "on",
+            "or",
+            "order",
+            "outer",
+            "primary",
+            "references",
+            "right",
+            "rollback",
+            "select",
+            "set",
+            "table",
+            "then",
+            "union",
+            "unique",
+            "update",
+            "values",
+            "view",
+            "when",
+            "where",
+            "with",
+        ],
+        literals: &["true", "false", "null"],
+        line_comments: &["--"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[SINGLE, DOUBLE],
+        ignore_case: true,
+        ..BASE
+    },
+    Language {
+        name: "json",
+        aliases: &["json", "jsonc", "json5", "map", "lock"],
+        literals: &["true", "false", "null"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[DOUBLE],
+        ..BASE
+    },
+    Language {
+        name: "toml",
+        aliases: &["toml", "ini", "cfg", "conf"],
+        literals: &["true", "false"],
+        line_comments: &["#", ";"],
+        strings: &[TRIPLE_DOUBLE, TRIPLE_SINGLE, DOUBLE, SINGLE],
+        ..BASE
+    },
+    Language {
+        name: "yaml",
+        aliases: &["yaml", "yml"],
+        literals: &["true", "false", "null", "yes", "no", "on", "off"],
+        line_comments: &["#"],
+        strings: &[DOUBLE, SINGLE],
+        ..BASE
+    },
+    Language {
+        name: "css",
+        aliases: &["css", "scss", "sass", "less"],
+        keywords: &[
+            "important",
+            "media",
+            "import",
+            "keyframes",
+            "supports",
+            "font-face",
+        ],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        strings: &[DOUBLE, SINGLE],
+        dashed_names: true,
+        ..BASE
+    },
+    Language {
+        name: "html",
+        aliases: &["html", "htm", "xhtml", "xml", "svg", "vue", "plist"],
+        block_comment: Some(("<!--", "-->")),
+        markup: true,
+        ..BASE
+    },
+];
+
+impl Language {
+    /// The language of the file `name`, going by its name or extension.
+    pub fn for_file(name: &str) -> Option<&'static Language> {
+        let name = name.to_ascii_lowercase();
+        let extension = name.rsplit_once('.').map(|(_, extension)| extension);
+        LANGUAGES.iter().find(|language| {
+            language.aliases.contains(&name.as_str())
+                || extension.is_some_and(|extension| language.aliases.contains(&extension))
+        })
+    }
+
+    /// The language a Markdown code fence names, e.g. `rust` or `py`.
+    pub fn named(name: &str) -> Option<&'static Language> {
+        let name = name.to_ascii_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|language| language.aliases.contains(&name.as_str()))
+    }
+
+    /// `code` as HTML, one string per line as [`str::lines`] splits it.
+    /// Tokens that span lines, such as block comments, are closed at the
+    /// end of each line and reopened on the next.
+    pub fn highlight(&self, code: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for (class, text) in self.tokens(code) {
+            let mut parts = text.split('\n').peekable();
+            while let Some(part) = parts.next() {
+                let last = parts.peek().is_none();
+                let part = match last {
+                    true => part,
+                    false => part.strip_suffix('\r').unwrap_or(part),
+                };
+                if !part.is_empty() {
+                    match class {
+                        Some(class) => line.push_str(&format!(
+                            r#"<span class="hl-{}">{}</span>"#,
+                            class,
+                            html_escape(part)
+                        )),
+                        None => line.push_str(&html_escape(part)),
+                    }
+                }
+                if !last {
+                    lines.push(std::mem::take(&mut line));
+                }
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// `code` split into tokens, each with its class if it has one.
+    fn tokens<'c>(&self, code: &'c str) -> Vec<(Option<&'static str>, &'c str)> {
+        let mut tokens = Vec::new();
+        let mut plain = 0;
+        let mut in_tag = false;
+        let mut i = 0;
+        while i < code.len() {
+            let (class, len) = match self.markup {
+                true => self.markup_token(&code[i..], &mut in_tag),
+                false => self.token(&code[i..]),
+            };
+            if let Some(class) = class {
+                if plain < i {
+                    tokens.push((None, &code[plain..i]));
+                }
+                tokens.push((Some(class), &code[i..i + len]));
+                plain = i + len;
+            }
+            i += len;
+        }
+        if plain < code.len() {
+            tokens.push((None, &code[plain..]));
+        }
+        tokens
+    }
+
+    /// The class and length of the token at the start of `rest`.
+    fn token(&self, rest: &str) -> (Option<&'static str>, usize) {
+        let c = rest.chars().next().unwrap_or_default();
+        if self
+            .line_comments
+            .iter()
+            .any(|prefix| rest.starts_with(prefix))
+        {
+            return (Some("comment"), rest.find('\n').unwrap_or(rest.len()));
+        }
+        if let Some(len) = self.block_comment_len(rest) {
+            return (Some("comment"), len);
+        }
+        if let Some(quote) = self
+            .strings
+            .iter()
+            .find(|quote| rest.starts_with(quote.open))
+        {
+            return (Some("string"), quoted_len(quote, rest));
+        }
+        if c == '\'' && self.char_literals {
+            return match char_literal_len(rest) {
+                Some(len) => (Some("string"), len),
+                None => (None, 1),
+            };
+        }
+        let next_is_digit = rest[c.len_utf8()..].starts_with(|c: char| c.is_ascii_digit());
+        if c.is_ascii_digit() || (c == '.' && next_is_digit) {
+            return (Some("number"), number_len(rest));
+        }
+        if !(c.is_alphabetic() || c == '_' || c == '$') {
+            return (None, c.len_utf8());
+        }
+
+        let len = rest
+            .find(|c: char| {
+                !(c.is_alphanumeric() || c == '_' || c == '$' || (self.dashed_names && c == '-'))
+            })
+            .unwrap_or(rest.len());
+        let word = &rest[..len];
+        let after = rest[len..].trim_start_matches([' ', '\t']);
+        let class = if self.is(self.keywords, word) {
+            Some("keyword")
+        } else if self.is(self.literals, word) {
+            Some("literal")
+        } else if after.starts_with('(')
+            || (self.char_literals && after.starts_with('!') && !after.starts_with("!="))
+        {
+            Some("function")
+        } else if self.ignore_case {
+            None
+        } else if word.len() > 1
+            && word
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        {
+            Some("literal")
+        } else if c.is_uppercase() {
+            Some("type")
+        } else {
+            None
+        };
+        (class, len)
+    }
+
+    fn is(&self, words: &[&str], word: &str) -> bool {
+        match self.ignore_case {
+            true => words.iter().any(|w| w.eq_ignore_ascii_case(word)),
+            false => words.contains(&word),
+        }
+    }
+
+    fn block_comment_len(&self, rest: &str) -> Option<usize> {
+        let (open, close) = self.block_comment?;
+        let body = rest.strip_prefix(open)?;
+        Some(match body.find(close) {
+            Some(end) => open.len() + end + close.len(),
+            None => rest.len(),
+        })
+    }
+
+    /// Tags, their attributes and comments in HTML or XML. `in_tag` tells
+    /// whether the last tag has not been closed yet.
+    fn markup_token(&self, rest: &str, in_tag: &mut bool) -> (Option<&'static str>, usize) {
+        let c = rest.chars().next().unwrap_or_default();
+        if *in_tag {
+            return match c {
+                '>' => {
+                    *in_tag = false;
+                    (Some("keyword"), 1)
+                }
+                '/' if rest.starts_with("/>") => {
+                    *in_tag = false;
+                    (Some("keyword"), 2)
+                }
+                '"' | '\'' => {
+                    let len = rest[1..].find(c).map_or(rest.len(), |end| end + 2);
+                    (Some("string"), len)
+                }
+                c if c.is_whitespace() || c == '=' || c == '/' => (None, c.len_utf8()),
+                _ => {
+                    let len = rest
+                        .find(|c: char| c.is_whitespace() || "=>/\"'".contains(c))
+                        .unwrap_or(rest.len());
+                    (Some("attribute"), len)
+                }
+            };
+        }
+        if let Some(len) = self.block_comment_len(rest) {
+            return (Some("comment"), len);
+        }
+        if c == '<' {
+            let start = match rest[1..].starts_with(['/', '!', '?']) {
+                true => 2,
+                false => 1,
+            };
+            let name = rest[start..]
+                .find(|c: char| !(c.is_alphanumeric() || ":_.-".contains(c)))
+                .unwrap_or(rest.len() - start);
+            if name > 0 {
+                *in_tag = true;
+                return (Some("keyword"), start + name);
+            }
+        }
+        if c == '&'
+            && let Some(end) = rest.bytes().take(12).position(|b| b == b';')
+            && end > 1
+            && rest[1..end]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '#')
+        {
+            return (Some("literal"), end + 1);
+        }
+        (None, c.len_utf8())
+    }
+}
+
+/// The length of the string that `quote` opens at the start of `rest`, up
+/// to the end of the code if it is not closed.
+fn quoted_len(quote: &Quote, rest: &str) -> usize {
+    let mut i = quote.open.len();
+    while let Some(c) = rest[i..].chars().next() {
+        if rest[i..].starts_with(quote.close) {
+            return i + quote.close.len();
+        }
+        if c == '\n' && !quote.multiline {
+            return i;
+        }
+        i += c.len_utf8();
+        if c == '\\' && quote.escapes {
+            i += rest[i..].chars().next().map_or(0, char::len_utf8);
+        }
+    }
+    rest.len()
+}
+
+/// The length of a character literal such as `'a'` or `'\n'` at the start
+/// of `rest`; `None` for a lifetime or label.
+fn char_literal_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    match chars.next()? {
+        (_, '\\') => {
+            // Skip the escaped character, then find the end of `\u{…}`.
+            chars.next()?;
+            let (end, _) = chars.take(10).find(|&(_, c)| c == '\'')?;
+            Some(end + 1)
+        }
+        (_, '\n' | '\'') => None,
+        _ => match chars.next()? {
+            (end, '\'') => Some(end + 1),
+            _ => None,
+        },
+    }
+}
+
+/// The length of a number such as `42`, `0xff`, `1.5e3` or `10u8`.
+fn number_len(rest: &str) -> usize {
+    let mut len = 0;
+    let mut previous = '\0';
+    for c in rest.chars() {
+        let exponent_sign = (c == '-' || c == '+') && matches!(previous, 'e' | 'E');
+        let point = c == '.' && rest[len + 1..].starts_with(|c: char| c.is_ascii_digit());
+        if !(c.is_ascii_alphanumeric() || c == '_' || point || exponent_sign) {
+            break;
+        }
+        len += 1;
+        previous = c;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight(name: &str, code: &str) -> Vec<String> {
+        Language::for_file(name).unwrap().highlight(code)
+    }
+
+    #[test]
+    fn test_languages() {
+        assert_eq!(Language::for_file("main.RS").unwrap().name, "rust");
+        assert_eq!(Language::for_file("Dockerfile").unwrap().name, "shell");
+        assert_eq!(Language::named("py").unwrap().name, "python");
+        assert!(Language::for_file("notes.txt").is_none());
+        assert!(Language::named("").is_none());
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            highlight(
+                "a.rs",
+                "fn main() -> Option<&'static str> {\n    let c = '\\n'; // <done>\n    println!(\"{}\", 0x1F + 2.5e-3)\n}"
+            ),
+            [
+                r#"<span class="hl-keyword">fn</span> <span class="hl-function">main</span>() -&gt; <span class="hl-type">Option</span>&lt;&amp;'<span class="hl-keyword">static</span> str&gt; {"#,
+                r#"    <span class="hl-keyword">let</span> c = <span class="hl-string">'\n'</span>; <span class="hl-comment">// &lt;done&gt;</span>"#,
+                r#"    <span class="hl-function">println</span>!(<span class="hl-string">&quot;{}&quot;</span>, <span class="hl-number">0x1F</span> + <span class="hl-number">2.5e-3</span>)"#,
+                "}",
+            ]
+        );
+        assert_eq!(
+            highlight("a.py", "x = \"\"\"a\r\nb\"\"\"  # None\nreturn None\n"),
+            [
+                r#"x = <span class="hl-string">&quot;&quot;&quot;a</span>"#,
+                r#"<span class="hl-string">b&quot;&quot;&quot;</span>  <span class="hl-comment"># None</span>"#,
+                r#"<span class="hl-keyword">return</span> <span class="hl-literal">None</span>"#,
+            ]
+        );
+        assert_eq!(
+            highlight("q.sql", "SELECT name FROM t -- all\nwhere id = 'x"),
+            [
+                r#"<span class="hl-keyword">SELECT</span> name <span class="hl-keyword">FROM</span> t <span class="hl-comment">-- all</span>"#,
+                r#"<span class="hl-keyword">where</span> id = <span class="hl-string">'x</span>"#,
+            ]
+        );
+        assert_eq!(
+            highlight("a.c", "/* a\n\nb */ int MAX_SIZE;"),
+            [
+                r#"<span class="hl-comment">/* a</span>"#,
+                "",
+                r#"<span class="hl-comment">b */</span> <span class="hl-keyword">int</span> <span class="hl-literal">MAX_SIZE</span>;"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strings_comments_and_escapes() {
+        let string = |s: &str| format!(r#"<span class="hl-string">{}</span>"#, s);
+        let comment = |s: &str| format!(r#"<span class="hl-comment">{}</span>"#, s);
+        let cases = [
+            (
+                "a.rs",
+                r#"s = "a\"<b>"; // x & y"#,
+                format!(
+                    "s = {}; {}",
+                    string(r"&quot;a\&quot;&lt;b&gt;&quot;"),
+                    comment("// x &amp; y")
+                ),
+            ),
+            (
+                "a.rs",
+                r"c = '\''; /* <b> */",
+                format!("c = {}; {}", string(r"'\''"), comment("/* &lt;b&gt; */")),
+            ),
+            (
+                "a.py",
+                r"s = 'it\'s <ok>'  # a & b",
+                format!(
+                    "s = {}  {}",
+                    string(r"'it\'s &lt;ok&gt;'"),
+                    comment("# a &amp; b")
+                ),
+            ),
+            (
+                "a.js",
+                "t = `x ${y} <z>`; /* & */",
+                format!(
+                    "t = {}; {}",
+                    string("`x ${y} &lt;z&gt;`"),
+                    comment("/* &amp; */")
+                ),
+            ),
+            (
+                "a.ts",
+                r#"s = "a\\"; // <T>"#,
+                format!(
+                    r"s = {}; {}",
+                    string(r"&quot;a\\&quot;"),
+                    comment("// &lt;T&gt;")
+                ),
+            ),
+            (
+                "a.go",
+                r"s := `raw \n <x>` // c",
+                format!("s := {} {}", string(r"`raw \n &lt;x&gt;`"), comment("// c")),
+            ),
+            (
+                "a.c",
+                r#"s = "a\"b<"; /* <c> */"#,
+                format!(
+                    "s = {}; {}",
+                    string(r"&quot;a\&quot;b&lt;&quot;"),
+                    comment("/* &lt;c&gt; */")
+                ),
+            ),
+            (
+                "a.cpp",
+                r#"s = "<&>\\"; // a & b"#,
+                format!(
+                    "s = {}; {}",
+                    string(r"&quot;&lt;&amp;&gt;\\&quot;"),
+                    comment("// a &amp; b")
+                ),
+            ),
+            (
+                "A.java",
+                r#"s = "\"<&>"; // ok <"#,
+                format!(
+                    "s = {}; {}",
+                    string(r"&quot;\&quot;&lt;&amp;&gt;&quot;"),
+                    comment("// ok &lt;")
+                ),
+            ),
+            (
+                "a.rb",
+                r#"s = "a\"<" # c &"#,
+                format!(
+                    "s = {} {}",
+                    string(r"&quot;a\&quot;&lt;&quot;"),
+                    comment("# c &amp;")
+                ),
+            ),
+            (
+                "a.sh",
+                r#"echo "a \"$x\" <" '\' x # &"#,
+                format!(
+                    "echo {} {} x {}",
+                    string(r"&quot;a \&quot;$x\&quot; &lt;&quot;"),
+                    string(r"'\'"),
+                    comment("# &amp;")
+                ),
+            ),
+            (
+                "a.sql",
+                "s = 'it''s <' -- &",
+                format!(
+                    "s = {}{} {}",
+                    string("'it'"),
+                    string("'s &lt;'"),
+                    comment("-- &amp;")
+                ),
+            ),
+            (
+                "a.json",
+                r#"{"k": "a\"<&"}"#,
+                format!(
+                    "{{{}: {}}}",
+                    string("&quot;k&quot;"),
+                    string(r"&quot;a\&quot;&lt;&amp;&quot;")
+                ),
+            ),
+            (
+                "a.toml",
+                "m = '''<''' # &",
+                format!("m = {} {}", string("'''&lt;'''"), comment("# &amp;")),
+            ),
+            (
+                "a.yaml",
+                r#"v: "\"&" # <"#,
+                format!(
+                    "v: {} {}",
+                    string(r"&quot;\&quot;&amp;&quot;"),
+                    comment("# &lt;")
+                ),
+            ),
+            (
+                "a.css",
+                r#"a { content: "\"<&"; } /* <c> */"#,
+                format!(
+                    "a {{ content: {}; }} {}",
+                    string(r"&quot;\&quot;&lt;&amp;&quot;"),
+                    comment("/* &lt;c&gt; */")
+                ),
+            ),
+        ];
+        for (file, code, html) in cases {
+            assert_eq!(highlight(file, code), [html], "{}: {}", file, code);
+        }
+    }
+
+    #[test]
+    fn test_markup() {
+        assert_eq!(
+            highlight("a.html", "<!-- c -->\n<a href=\"x\" hidden>&amp; b</a>"),
+            [
+                r#"<span class="hl-comment">&lt;!-- c --&gt;</span>"#,
+                concat!(
+                    r#"<span class="hl-keyword">&lt;a</span> <span class="hl-attribute">href</span>="#,
+                    r#"<span class="hl-string">&quot;x&quot;</span> <span class="hl-attribute">hidden</span>"#,
+                    r#"<span class="hl-keyword">&gt;</span><span class="hl-literal">&amp;amp;</span> b"#,
+                    r#"<span class="hl-keyword">&lt;/a</span><span class="hl-keyword">&gt;</span>"#
+                ),
+            ]
+        );
+        assert_eq!(
+            highlight("a.xml", r#"<p title='a"<'>"#),
+            [concat!(
+                r#"<span class="hl-keyword">&lt;p</span> <span class="hl-attribute">title</span>="#,
+                r#"<span class="hl-string">'a&quot;&lt;'</span><span class="hl-keyword">&gt;</span>"#
+            )]
+        );
+    }
+}
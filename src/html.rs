@@ -1,75 +1,626 @@
-use std::fs;
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::Read;
 use std::path::Path;
+use std::time::SystemTime;
 
+use crate::archive::Format;
+use crate::fileinfo::FileInfo;
+use crate::http::date::DateTime;
+use crate::markdown::Markdown;
+use crate::preview::{self, Kind, TEXT_LIMIT};
 use crate::stats::Stats;
+use crate::thumbnail;
+
+/// Entries shown on one page of a listing unless
+/// [`Listing::page_size`] says otherwise.
+pub const PAGE_SIZE: usize = 1000;
 
 pub fn generate_index_html(root: &Path, subpath: &str) -> String {
-    let current_path = if subpath.is_empty() {
-        root.to_path_buf()
+    Listing::new(root, subpath).render()
+}
+
+/// What a listing is ordered by. Folders always come before files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    /// The extension, then the name.
+    Type,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 4] = [
+        SortKey::Name,
+        SortKey::Type,
+        SortKey::Modified,
+        SortKey::Size,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl Sort {
+    /// Reads the `sort` (`name`, `size`, `modified` or `type`) and `order`
+    /// (`asc` or `desc`) query parameters, ignoring values it does not know.
+    pub fn from_query(sort: Option<&str>, order: Option<&str>) -> Self {
+        let key = SortKey::ALL
+            .into_iter()
+            .find(|key| Some(key.name()) == sort)
+            .unwrap_or_default();
+        Sort {
+            key,
+            descending: order == Some("desc"),
+        }
+    }
+
+    fn query(self) -> String {
+        let order = if self.descending { "desc" } else { "asc" };
+        format!("sort={}&order={}", self.key.name(), order)
+    }
+
+    fn compare(self, a: &FileInfo, b: &FileInfo) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Type => extension(&a.name).cmp(&extension(&b.name)),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+        let ordering = if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        b.is_dir.cmp(&a.is_dir).then(ordering)
+    }
+}
+
+/// How a listing lays out its entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum View {
+    /// A row per entry with its details.
+    #[default]
+    List,
+    /// Tiles with a thumbnail of each image, opened in a lightbox. Only
+    /// offered for directories holding images.
+    Grid,
+}
+
+impl View {
+    /// Reads the `view` query parameter (`list` or `grid`).
+    pub fn from_query(view: Option<&str>) -> Self {
+        match view {
+            Some("grid") => View::Grid,
+            _ => View::List,
+        }
+    }
+}
+
+/// Whether a listing shows the file `name` below its entries.
+fn is_readme(name: &str) -> bool {
+    name.eq_ignore_ascii_case("readme.md") || name.eq_ignore_ascii_case("readme.markdown")
+}
+
+fn extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((_, ext)) => ext.to_lowercase(),
+        None => String::new(),
+    }
+}
+
+/// One row of a listing or of search results, labelled with the name or,
+/// for search results, the path. A `selectable` row has a box to pick it
+/// for an archive.
+fn entry_html(entry: &FileInfo, label: &str, share_links: bool, selectable: bool) -> String {
+    let (icon, size_str, file_class, href) = if entry.is_dir {
+        ("📁", "-".to_string(), "folder", "/browse/")
     } else {
-        root.join(subpath)
+        let icon = get_file_icon(&entry.name);
+        (icon, Stats::format_bytes(entry.size), "file", "/view/")
     };
 
-    let entries = match fs::read_dir(&current_path) {
-        Ok(entries) => entries,
-        Err(_) => return error_html("Cannot read directory"),
+    let share = if share_links && !entry.is_dir {
+        format!(
+            r#"<span class="file-share" title="Share link" data-path="{}">🔗</span>"#,
+            encode_path(&entry.path)
+        )
+    } else {
+        String::new()
+    };
+    let select = match selectable {
+        true => format!(
+            r#"<input type="checkbox" class="file-select" name="files" value="{}" form="selection" title="Select">"#,
+            html_escape(&entry.name)
+        ),
+        false => String::new(),
+    };
+    let created = match entry.created {
+        Some(created) => format!(r#" title="Created {}""#, format_date(created)),
+        None => String::new(),
     };
 
-    let mut files_html = String::new();
-    let mut items: Vec<_> = entries.filter_map(|e| e.ok()).collect();
-    items.sort_by(|a, b| {
-        let a_is_dir = a.path().is_dir();
-        let b_is_dir = b.path().is_dir();
-        match (a_is_dir, b_is_dir) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => a.file_name().cmp(&b.file_name()),
-        }
-    });
-
-    for entry in items {
-        let path = entry.path();
-        let name = entry.file_name().to_string_lossy().to_string();
-        let is_dir = path.is_dir();
-
-        let (icon, size_str, file_class) = if is_dir {
-            ("📁", "-".to_string(), "folder")
+    format!(
+        r#"<a href="{}{}" class="file-item {}">{}
+                <span class="file-icon">{}</span>
+                <span class="file-details">
+                    <span class="file-name">{}</span>
+                    <span class="file-meta">{}</span>
+                </span>
+                <span class="file-date"{}>{}</span>
+                <span class="file-size">{}</span>{}
+            </a>"#,
+        href,
+        encode_path(&entry.path),
+        file_class,
+        select,
+        icon,
+        html_escape(label),
+        html_escape(&meta(entry)),
+        created,
+        entry.modified.map(format_date).unwrap_or_default(),
+        size_str,
+        share
+    )
+}
+
+/// One tile of the grid: a thumbnail for an image, the icon for anything
+/// else. Images the browser can show open in the lightbox.
+fn tile_html(entry: &FileInfo, selectable: bool) -> String {
+    let path = encode_path(&entry.path);
+    let select = match selectable {
+        true => format!(
+            r#"<input type="checkbox" class="file-select" name="files" value="{}" form="selection" title="Select">"#,
+            html_escape(&entry.name)
+        ),
+        false => String::new(),
+    };
+    if entry.is_dir {
+        return format!(
+            r#"<a href="/browse/{}" class="file-item tile folder">{}
+                <span class="tile-thumb">📁</span>
+                <span class="file-name">{}</span>
+            </a>"#,
+            path,
+            select,
+            html_escape(&entry.name)
+        );
+    }
+
+    let image = Kind::of(&entry.name) == Kind::Image && preview::inline(&entry.name);
+    let thumbnail = if thumbnail::decodable(&entry.name) {
+        Some(format!("/thumb/{}", path))
+    } else if image {
+        Some(format!("/download/{}?inline", path))
+    } else {
+        None
+    };
+    let thumb = match thumbnail {
+        Some(src) => format!(r#"<img src="{}" alt="" loading="lazy">"#, src),
+        None => get_file_icon(&entry.name).to_string(),
+    };
+    let lightbox = match image {
+        true => format!(
+            r#" data-full="/download/{}?inline" data-name="{}""#,
+            path,
+            html_escape(&entry.name)
+        ),
+        false => String::new(),
+    };
+    format!(
+        r#"<a href="/view/{}" class="file-item tile file"{} title="{} · {}">{}
+                <span class="tile-thumb">{}</span>
+                <span class="file-name">{}</span>
+            </a>"#,
+        path,
+        lightbox,
+        html_escape(&entry.name),
+        Stats::format_bytes(entry.size),
+        select,
+        thumb,
+        html_escape(&entry.name)
+    )
+}
+
+/// The line below a name: the type or number of items, the permissions,
+/// the owner and where a link points.
+fn meta(entry: &FileInfo) -> String {
+    let kind = match entry.children {
+        Some(1) => Some("1 item".to_string()),
+        Some(children) => Some(format!("{} items", children)),
+        None => entry
+            .mime
+            .map(|mime| mime.split(';').next().unwrap_or(mime).to_string()),
+    };
+    let mut parts: Vec<String> = [kind, entry.permissions(), entry.owner.clone()]
+        .into_iter()
+        .flatten()
+        .collect();
+    if let Some(target) = &entry.symlink_target {
+        parts.push(format!("→ {}", target));
+    }
+    parts.join(" · ")
+}
+
+/// A modification time as `2024-05-01 13:45` UTC.
+pub(crate) fn format_date(time: SystemTime) -> String {
+    let date = DateTime::from_system_time(time);
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        date.year, date.month, date.day, date.hour, date.minute
+    )
+}
+
+/// A directory page with the optional parts of the file browser.
+pub struct Listing<'a> {
+    root: &'a Path,
+    subpath: &'a str,
+    visible: Box<dyn Fn(&str) -> bool + 'a>,
+    share_links: bool,
+    archive_links: bool,
+    sort: Sort,
+    view: View,
+    page: usize,
+    page_size: usize,
+}
+
+impl<'a> Listing<'a> {
+    /// The page for `subpath`, a decoded path below `root`.
+    pub fn new(root: &'a Path, subpath: &'a str) -> Self {
+        Listing {
+            root,
+            subpath,
+            visible: Box::new(|_| true),
+            share_links: false,
+            archive_links: false,
+            sort: Sort::default(),
+            view: View::default(),
+            page: 1,
+            page_size: PAGE_SIZE,
+        }
+    }
+
+    /// Lists only the entries whose path relative to the root passes
+    /// `visible`.
+    pub fn visible(mut self, visible: impl Fn(&str) -> bool + 'a) -> Self {
+        self.visible = Box::new(visible);
+        self
+    }
+
+    /// Adds a button to each file that mints a share link with
+    /// `POST /api/share/<path>`.
+    pub fn share_links(mut self, enabled: bool) -> Self {
+        self.share_links = enabled;
+        self
+    }
+
+    /// Adds a button that downloads the directory, or the entries ticked
+    /// in it, with `GET /archive/<path>`.
+    pub fn archive_links(mut self, enabled: bool) -> Self {
+        self.archive_links = enabled;
+        self
+    }
+
+    pub fn sort(mut self, sort: Sort) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Lays the entries out as `view` asks when the directory holds
+    /// images, and as a list otherwise.
+    pub fn view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+
+    /// Shows the `page`th page of entries, counting from 1.
+    pub fn page(mut self, page: usize) -> Self {
+        self.page = page.max(1);
+        self
+    }
+
+    pub fn page_size(mut self, entries: usize) -> Self {
+        self.page_size = entries.max(1);
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let (root, subpath) = (self.root, self.subpath);
+        let current_path = if subpath.is_empty() {
+            root.to_path_buf()
         } else {
-            let size = path.metadata().map(|m| m.len()).unwrap_or(0);
-            (get_file_icon(&name), Stats::format_bytes(size), "file")
+            root.join(subpath)
         };
 
-        let relative_path = if subpath.is_empty() {
-            name.clone()
-        } else {
-            format!("{}/{}", subpath, name)
+        let entries = match fs::read_dir(&current_path) {
+            Ok(entries) => entries,
+            Err(_) => return error_html("Cannot read directory"),
         };
 
-        let href = if is_dir {
-            format!("/browse/{}", encode_path(&relative_path))
+        let mut items: Vec<FileInfo> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let path = if subpath.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", subpath, name)
+                };
+                if !(self.visible)(&path) {
+                    return None;
+                }
+                FileInfo::read(root, &path).ok()
+            })
+            .collect();
+        items.sort_by(|a, b| self.sort.compare(a, b));
+        let images = items
+            .iter()
+            .any(|entry| !entry.is_dir && Kind::of(&entry.name) == Kind::Image);
+        let grid = images && self.view == View::Grid;
+
+        let pages = items.len().div_ceil(self.page_size).max(1);
+        let page = self.page.min(pages);
+        let files_html: String = items
+            .iter()
+            .skip((page - 1) * self.page_size)
+            .take(self.page_size)
+            .map(|entry| match grid {
+                true => tile_html(entry, self.archive_links),
+                false => entry_html(entry, &entry.name, self.share_links, self.archive_links),
+            })
+            .collect();
+
+        let files_html = if files_html.is_empty() {
+            r#"<div class="empty">📭 This directory is empty</div>"#.to_string()
         } else {
-            format!("/download/{}", encode_path(&relative_path))
+            files_html
         };
 
-        files_html.push_str(&format!(
-            r#"<a href="{}" class="file-item {}">
-                <span class="file-icon">{}</span>
-                <span class="file-name">{}</span>
-                <span class="file-size">{}</span>
-            </a>"#,
-            href,
-            file_class,
-            icon,
-            html_escape(&name),
-            size_str
-        ));
+        let toolbar = format!(
+            r#"<div class="toolbar">
+            <form class="search" action="/search/{}" method="get">
+                <input type="search" name="q" id="filter" autocomplete="off"
+                    placeholder="Filter this folder, or press Enter to search inside it">
+            </form>
+            <div class="sort">Sort by {}</div>{}{}
+        </div>"#,
+            encode_path(subpath),
+            self.sort_links(),
+            self.view_links(images),
+            self.archive_form()
+        );
+        let readme = items
+            .iter()
+            .find(|entry| !entry.is_dir && is_readme(&entry.name))
+            .map(|entry| self.readme_html(entry))
+            .unwrap_or_default();
+        let content = format!(
+            r#"{}
+        
+        <div class="file-list{}">
+            {}
+        </div>{}{}{}"#,
+            toolbar,
+            if grid { " grid" } else { "" },
+            files_html,
+            self.page_links(page, pages),
+            readme,
+            if grid { LIGHTBOX_HTML } else { "" }
+        );
+
+        let mut script = FILTER_SCRIPT.to_string();
+        if grid {
+            script.push_str(GALLERY_SCRIPT);
+        }
+        if self.share_links {
+            script.push_str(SHARE_SCRIPT);
+        }
+        if self.archive_links {
+            script.push_str(ARCHIVE_SCRIPT);
+        }
+        page_html(root, &generate_breadcrumb(subpath), &content, &script)
+    }
+
+    /// The README rendered below the entries.
+    fn readme_html(&self, entry: &FileInfo) -> String {
+        let mut source = Vec::new();
+        let read = File::open(self.root.join(&entry.path))
+            .and_then(|file| file.take(TEXT_LIMIT as u64).read_to_end(&mut source));
+        if read.is_err() {
+            return String::new();
+        }
+        format!(
+            r#"
+        
+        <div class="readme">
+            <div class="readme-title">📖 {}</div>
+            <div class="markdown">{}</div>
+        </div>"#,
+            html_escape(&entry.name),
+            Markdown::new(&String::from_utf8_lossy(&source))
+                .relative_to(self.subpath)
+                .render()
+        )
+    }
+
+    /// The query string for `sort` in the current view.
+    fn query(&self, sort: Sort) -> String {
+        match self.view {
+            View::List => sort.query(),
+            View::Grid => format!("{}&view=grid", sort.query()),
+        }
+    }
+
+    /// Links switching between the list and the grid, when there are
+    /// `images` to show in it.
+    fn view_links(&self, images: bool) -> String {
+        if !images {
+            return String::new();
+        }
+        let (list, grid) = match self.view {
+            View::List => (r#" class="active""#, ""),
+            View::Grid => ("", r#" class="active""#),
+        };
+        format!(
+            r#"
+            <div class="sort">View <a{} href="?{}">☰ List</a> <a{} href="?{}&view=grid">▦ Grid</a></div>"#,
+            list,
+            self.sort.query(),
+            grid,
+            self.sort.query()
+        )
+    }
+
+    /// The format picker and download button; the rows' boxes belong to
+    /// this form.
+    fn archive_form(&self) -> String {
+        if !self.archive_links {
+            return String::new();
+        }
+        let options: String = Format::ALL
+            .iter()
+            .map(|format| format!(r#"<option value="{0}">{0}</option>"#, format.name()))
+            .collect();
+        format!(
+            r#"
+            <form class="archive" id="selection" action="/archive/{}" method="get">
+                <select name="format" title="Archive format">{}</select>
+                <button type="submit" id="archive-button">⬇ Download all</button>
+            </form>"#,
+            encode_path(self.subpath),
+            options
+        )
+    }
+
+    /// A link per sort key; the current one shows its order and reverses
+    /// it when clicked.
+    fn sort_links(&self) -> String {
+        SortKey::ALL
+            .iter()
+            .map(|&key| {
+                let label = match key {
+                    SortKey::Name => "Name",
+                    SortKey::Size => "Size",
+                    SortKey::Modified => "Modified",
+                    SortKey::Type => "Type",
+                };
+                if key != self.sort.key {
+                    let sort = Sort {
+                        key,
+                        descending: false,
+                    };
+                    return format!(r#"<a href="?{}">{}</a>"#, self.query(sort), label);
+                }
+                let reversed = Sort {
+                    key,
+                    descending: !self.sort.descending,
+                };
+                let arrow = if self.sort.descending { "▼" } else { "▲" };
+                format!(
+                    r#"<a class="active" href="?{}">{} {}</a>"#,
+                    self.query(reversed),
+                    label,
+                    arrow
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
     }
 
-    if files_html.is_empty() {
-        files_html = r#"<div class="empty">📭 This directory is empty</div>"#.to_string();
+    fn page_links(&self, page: usize, pages: usize) -> String {
+        if pages == 1 {
+            return String::new();
+        }
+        let link = |page: usize, label: &str| {
+            format!(
+                r#"<a href="?{}&page={}">{}</a>"#,
+                self.query(self.sort),
+                page,
+                label
+            )
+        };
+        let previous = match page {
+            1 => String::new(),
+            _ => link(page - 1, "← Previous"),
+        };
+        let next = match page == pages {
+            true => String::new(),
+            false => link(page + 1, "Next →"),
+        };
+        format!(
+            r#"
+        
+        <div class="pages">{} <span>Page {} of {}</span> {}</div>"#,
+            previous, page, pages, next
+        )
     }
+}
 
-    let breadcrumb = generate_breadcrumb(subpath);
+/// The results of searching below `subpath` for `query`, stopped early if
+/// `truncated`.
+pub fn search_results_html(
+    root: &Path,
+    subpath: &str,
+    query: &str,
+    found: &[FileInfo],
+    truncated: bool,
+    share_links: bool,
+) -> String {
+    let files_html: String = found
+        .iter()
+        .map(|entry| entry_html(entry, &entry.path, share_links, false))
+        .collect();
+    let files_html = if files_html.is_empty() {
+        r#"<div class="empty">🔍 Nothing matches</div>"#.to_string()
+    } else {
+        files_html
+    };
+    let more = if truncated {
+        r#"
+        
+        <div class="pages"><span>Only the first results are shown; narrow the search to see the rest</span></div>"#
+    } else {
+        ""
+    };
+
+    let content = format!(
+        r#"<div class="toolbar">
+            <form class="search" action="/search/{}" method="get">
+                <input type="search" name="q" value="{}" autocomplete="off">
+            </form>
+            <div class="sort">{} found</div>
+        </div>
+        
+        <div class="file-list">
+            {}
+        </div>{}"#,
+        encode_path(subpath),
+        html_escape(query),
+        found.len(),
+        files_html,
+        more
+    );
+    let script = if share_links { SHARE_SCRIPT } else { "" };
+    page_html(root, &generate_breadcrumb(subpath), &content, script)
+}
+
+/// The file browser's page around `content`, for the directory `root`.
+pub(crate) fn page_html(root: &Path, breadcrumb: &str, content: &str, script: &str) -> String {
     let dir_name = root
         .file_name()
         .map(|s| s.to_string_lossy().to_string())
@@ -89,113 +640,597 @@ pub fn generate_index_html(root: &Path, subpath: &str) -> String {
             box-sizing: border-box;
         }}
         
-        body {{
-            font-family: 'Segoe UI', system-ui, -apple-system, sans-serif;
-            background: linear-gradient(135deg, #1a1a2e 0%, #16213e 50%, #0f3460 100%);
-            min-height: 100vh;
-            color: #e0e0e0;
+        body {{
+            font-family: 'Segoe UI', system-ui, -apple-system, sans-serif;
+            background: linear-gradient(135deg, #1a1a2e 0%, #16213e 50%, #0f3460 100%);
+            min-height: 100vh;
+            color: #e0e0e0;
+        }}
+        
+        .container {{
+            max-width: 900px;
+            margin: 0 auto;
+            padding: 40px 20px;
+        }}
+        
+        .header {{
+            text-align: center;
+            margin-bottom: 40px;
+        }}
+        
+        .header h1 {{
+            font-size: 2.5rem;
+            background: linear-gradient(135deg, #667eea 0%, #764ba2 100%);
+            -webkit-background-clip: text;
+            -webkit-text-fill-color: transparent;
+            background-clip: text;
+            margin-bottom: 10px;
+        }}
+        
+        .header .subtitle {{
+            color: #888;
+            font-size: 1rem;
+        }}
+        
+        .breadcrumb {{
+            background: rgba(255, 255, 255, 0.05);
+            border-radius: 12px;
+            padding: 15px 20px;
+            margin-bottom: 20px;
+            backdrop-filter: blur(10px);
+            border: 1px solid rgba(255, 255, 255, 0.1);
+        }}
+        
+        .breadcrumb a {{
+            color: #667eea;
+            text-decoration: none;
+            transition: color 0.2s;
+        }}
+        
+        .breadcrumb a:hover {{
+            color: #764ba2;
+        }}
+        
+        .breadcrumb span {{
+            color: #666;
+            margin: 0 8px;
+        }}
+        
+        .toolbar {{
+            display: flex;
+            flex-wrap: wrap;
+            align-items: center;
+            gap: 12px 20px;
+            margin-bottom: 20px;
+        }}
+        
+        .toolbar .search {{
+            flex: 1;
+            min-width: 240px;
+        }}
+        
+        .toolbar input {{
+            width: 100%;
+            padding: 10px 16px;
+            border-radius: 12px;
+            border: 1px solid rgba(255, 255, 255, 0.1);
+            background: rgba(255, 255, 255, 0.05);
+            color: #e0e0e0;
+            font-size: 0.95rem;
+        }}
+        
+        .toolbar input:focus {{
+            outline: none;
+            border-color: #667eea;
+        }}
+        
+        .archive {{
+            display: flex;
+            gap: 8px;
+        }}
+        
+        .archive select, .archive button {{
+            padding: 8px 12px;
+            border-radius: 12px;
+            border: 1px solid rgba(255, 255, 255, 0.1);
+            background: rgba(255, 255, 255, 0.05);
+            color: #e0e0e0;
+            font-size: 0.9rem;
+            cursor: pointer;
+        }}
+        
+        .archive button:hover {{
+            border-color: #667eea;
+        }}
+        
+        .file-select {{
+            margin-right: 12px;
+            accent-color: #667eea;
+            cursor: pointer;
+        }}
+        
+        .sort {{
+            color: #888;
+            font-size: 0.9rem;
+        }}
+        
+        .sort a, .pages a {{
+            color: #667eea;
+            text-decoration: none;
+            margin-left: 8px;
+        }}
+        
+        .sort a.active {{
+            color: #e0e0e0;
+            font-weight: 600;
+        }}
+        
+        .pages {{
+            text-align: center;
+            margin-top: 20px;
+            color: #888;
+        }}
+        
+        .pages span {{
+            margin: 0 12px;
+        }}
+        
+        .file-list {{
+            background: rgba(255, 255, 255, 0.03);
+            border-radius: 16px;
+            overflow: hidden;
+            backdrop-filter: blur(10px);
+            border: 1px solid rgba(255, 255, 255, 0.1);
+            box-shadow: 0 8px 32px rgba(0, 0, 0, 0.3);
+        }}
+        
+        .file-item {{
+            display: flex;
+            align-items: center;
+            padding: 16px 24px;
+            text-decoration: none;
+            color: #e0e0e0;
+            border-bottom: 1px solid rgba(255, 255, 255, 0.05);
+            transition: all 0.2s ease;
+        }}
+        
+        .file-item:last-child {{
+            border-bottom: none;
+        }}
+        
+        .file-item:hover {{
+            background: rgba(102, 126, 234, 0.1);
+            transform: translateX(5px);
+        }}
+        
+        .file-item.folder:hover {{
+            background: rgba(255, 193, 7, 0.1);
+        }}
+        
+        .file-icon {{
+            font-size: 1.5rem;
+            margin-right: 16px;
+            width: 32px;
+            text-align: center;
+        }}
+        
+        .file-details {{
+            flex: 1;
+            display: flex;
+            flex-direction: column;
+            min-width: 0;
+        }}
+        
+        .file-name {{
+            font-weight: 500;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            white-space: nowrap;
+        }}
+        
+        .file-meta {{
+            color: #666;
+            font-size: 0.75rem;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            white-space: nowrap;
+        }}
+        
+        .file-date {{
+            color: #666;
+            font-size: 0.85rem;
+            margin: 0 16px;
+            white-space: nowrap;
+        }}
+        
+        .file-size {{
+            color: #888;
+            font-size: 0.9rem;
+            font-family: 'Monaco', 'Consolas', monospace;
+        }}
+        
+        .file-share {{
+            margin-left: 16px;
+            cursor: pointer;
+            opacity: 0.5;
+        }}
+        
+        .file-share:hover {{
+            opacity: 1;
+        }}
+        
+        .file-list.grid {{
+            display: grid;
+            grid-template-columns: repeat(auto-fill, minmax(150px, 1fr));
+            gap: 12px;
+            padding: 12px;
+        }}
+        
+        .file-list.grid .file-item {{
+            position: relative;
+            flex-direction: column;
+            align-items: stretch;
+            padding: 8px;
+            border: 1px solid rgba(255, 255, 255, 0.05);
+            border-radius: 12px;
+        }}
+        
+        .file-list.grid .file-item:hover {{
+            transform: translateY(-3px);
+        }}
+        
+        .file-list.grid .file-name {{
+            margin-top: 8px;
+            text-align: center;
+            font-size: 0.85rem;
+        }}
+        
+        .file-list.grid .file-select {{
+            position: absolute;
+            top: 14px;
+            left: 14px;
+            margin: 0;
+        }}
+        
+        .tile-thumb {{
+            display: flex;
+            align-items: center;
+            justify-content: center;
+            aspect-ratio: 1;
+            overflow: hidden;
+            border-radius: 8px;
+            background: rgba(0, 0, 0, 0.2);
+            font-size: 3rem;
+        }}
+        
+        .tile-thumb img {{
+            width: 100%;
+            height: 100%;
+            object-fit: cover;
+        }}
+        
+        .lightbox {{
+            position: fixed;
+            inset: 0;
+            z-index: 10;
+            display: flex;
+            flex-direction: column;
+            align-items: center;
+            justify-content: center;
+            gap: 12px;
+            background: rgba(0, 0, 0, 0.92);
+        }}
+        
+        .lightbox[hidden] {{
+            display: none;
+        }}
+        
+        .lightbox img {{
+            max-width: 90vw;
+            max-height: 85vh;
+            object-fit: contain;
+        }}
+        
+        .lightbox-caption {{
+            color: #888;
+            font-size: 0.9rem;
+        }}
+        
+        .lightbox-caption a {{
+            color: #e0e0e0;
+            text-decoration: none;
+            margin-right: 12px;
+        }}
+        
+        .lightbox button {{
+            position: absolute;
+            padding: 8px 16px;
+            border: none;
+            background: none;
+            color: #e0e0e0;
+            font-size: 2rem;
+            cursor: pointer;
+            opacity: 0.6;
+        }}
+        
+        .lightbox button:hover {{
+            opacity: 1;
+        }}
+        
+        .lightbox-close {{
+            top: 12px;
+            right: 12px;
+        }}
+        
+        .lightbox-previous {{
+            left: 12px;
+        }}
+        
+        .lightbox-next {{
+            right: 12px;
+        }}
+        
+        .button {{
+            padding: 8px 16px;
+            border-radius: 12px;
+            border: 1px solid rgba(255, 255, 255, 0.1);
+            background: rgba(255, 255, 255, 0.05);
+            color: #e0e0e0;
+            text-decoration: none;
+            font-size: 0.9rem;
+            font-family: inherit;
+            cursor: pointer;
+        }}
+        
+        .button:hover {{
+            border-color: #667eea;
+        }}
+        
+        .preview-nav {{
+            flex: 1;
+            color: #888;
+            font-size: 0.9rem;
+        }}
+        
+        .preview-nav a {{
+            color: #667eea;
+            text-decoration: none;
+        }}
+        
+        .preview-nav span {{
+            margin: 0 12px;
+        }}
+        
+        .preview {{
+            background: rgba(255, 255, 255, 0.03);
+            border-radius: 16px;
+            overflow: hidden;
+            border: 1px solid rgba(255, 255, 255, 0.1);
+            box-shadow: 0 8px 32px rgba(0, 0, 0, 0.3);
+            text-align: center;
+        }}
+        
+        .preview img, .preview video {{
+            display: block;
+            max-width: 100%;
+            max-height: 80vh;
+            margin: 0 auto;
+        }}
+        
+        .preview audio {{
+            width: 100%;
+            padding: 20px;
+        }}
+        
+        .preview iframe {{
+            width: 100%;
+            height: 80vh;
+            border: none;
+            background: #fff;
+        }}
+        
+        .code {{
+            text-align: left;
+            overflow-x: auto;
+            padding: 16px 0;
+            font-family: 'Monaco', 'Consolas', monospace;
+            font-size: 0.85rem;
+            line-height: 1.5;
+            counter-reset: line;
+        }}
+        
+        .code .line {{
+            display: block;
+            padding-right: 16px;
+            white-space: pre;
+        }}
+        
+        .code .line::before {{
+            counter-increment: line;
+            content: counter(line);
+            display: inline-block;
+            width: 4em;
+            padding-right: 16px;
+            margin-right: 16px;
+            text-align: right;
+            color: #555;
+            border-right: 1px solid rgba(255, 255, 255, 0.1);
+            user-select: none;
+        }}
+        
+        .code .line:target {{
+            background: rgba(102, 126, 234, 0.15);
+        }}
+        
+        .readme {{
+            margin-top: 20px;
+            background: rgba(255, 255, 255, 0.03);
+            border-radius: 16px;
+            overflow: hidden;
+            border: 1px solid rgba(255, 255, 255, 0.1);
+            box-shadow: 0 8px 32px rgba(0, 0, 0, 0.3);
+        }}
+        
+        .readme-title {{
+            padding: 12px 24px;
+            color: #888;
+            font-size: 0.9rem;
+            border-bottom: 1px solid rgba(255, 255, 255, 0.1);
+        }}
+        
+        .markdown {{
+            text-align: left;
+            padding: 24px 32px;
+            line-height: 1.6;
+            overflow-wrap: break-word;
+        }}
+        
+        .markdown > :first-child {{
+            margin-top: 0;
+        }}
+        
+        .markdown h1, .markdown h2, .markdown h3,
+        .markdown h4, .markdown h5, .markdown h6 {{
+            margin: 1.4em 0 0.6em;
+            color: #fff;
+            line-height: 1.3;
+        }}
+        
+        .markdown h1, .markdown h2 {{
+            padding-bottom: 0.3em;
+            border-bottom: 1px solid rgba(255, 255, 255, 0.1);
+        }}
+        
+        .markdown p, .markdown ul, .markdown ol, .markdown pre,
+        .markdown blockquote, .markdown table, .markdown details {{
+            margin-bottom: 1em;
+        }}
+        
+        .markdown ul, .markdown ol {{
+            padding-left: 2em;
+        }}
+        
+        .markdown a {{
+            color: #667eea;
+        }}
+        
+        .markdown code {{
+            font-family: 'Monaco', 'Consolas', monospace;
+            font-size: 0.85em;
+            padding: 2px 6px;
+            border-radius: 6px;
+            background: rgba(255, 255, 255, 0.08);
+        }}
+        
+        .markdown pre {{
+            padding: 16px;
+            border-radius: 12px;
+            background: rgba(0, 0, 0, 0.3);
+            overflow-x: auto;
         }}
         
-        .container {{
-            max-width: 900px;
-            margin: 0 auto;
-            padding: 40px 20px;
+        .markdown pre code {{
+            padding: 0;
+            background: none;
         }}
         
-        .header {{
-            text-align: center;
-            margin-bottom: 40px;
+        .markdown blockquote {{
+            padding-left: 16px;
+            color: #aaa;
+            border-left: 4px solid #667eea;
         }}
         
-        .header h1 {{
-            font-size: 2.5rem;
-            background: linear-gradient(135deg, #667eea 0%, #764ba2 100%);
-            -webkit-background-clip: text;
-            -webkit-text-fill-color: transparent;
-            background-clip: text;
-            margin-bottom: 10px;
+        .markdown img {{
+            max-width: 100%;
         }}
         
-        .header .subtitle {{
-            color: #888;
-            font-size: 1rem;
+        .markdown hr {{
+            border: none;
+            border-top: 1px solid rgba(255, 255, 255, 0.1);
+            margin: 24px 0;
         }}
         
-        .breadcrumb {{
-            background: rgba(255, 255, 255, 0.05);
-            border-radius: 12px;
-            padding: 15px 20px;
-            margin-bottom: 20px;
-            backdrop-filter: blur(10px);
+        .markdown table {{
+            border-collapse: collapse;
+        }}
+        
+        .markdown th, .markdown td {{
+            padding: 6px 12px;
             border: 1px solid rgba(255, 255, 255, 0.1);
         }}
         
-        .breadcrumb a {{
-            color: #667eea;
-            text-decoration: none;
-            transition: color 0.2s;
+        .hl-keyword {{
+            color: #c792ea;
         }}
         
-        .breadcrumb a:hover {{
-            color: #764ba2;
+        .hl-type {{
+            color: #ffcb6b;
         }}
         
-        .breadcrumb span {{
-            color: #666;
-            margin: 0 8px;
+        .hl-function {{
+            color: #82aaff;
         }}
         
-        .file-list {{
-            background: rgba(255, 255, 255, 0.03);
-            border-radius: 16px;
-            overflow: hidden;
-            backdrop-filter: blur(10px);
-            border: 1px solid rgba(255, 255, 255, 0.1);
-            box-shadow: 0 8px 32px rgba(0, 0, 0, 0.3);
+        .hl-string {{
+            color: #c3e88d;
         }}
         
-        .file-item {{
-            display: flex;
-            align-items: center;
-            padding: 16px 24px;
-            text-decoration: none;
-            color: #e0e0e0;
-            border-bottom: 1px solid rgba(255, 255, 255, 0.05);
-            transition: all 0.2s ease;
+        .hl-number, .hl-literal {{
+            color: #f78c6c;
         }}
         
-        .file-item:last-child {{
-            border-bottom: none;
+        .hl-attribute {{
+            color: #89ddff;
         }}
         
-        .file-item:hover {{
-            background: rgba(102, 126, 234, 0.1);
-            transform: translateX(5px);
+        .hl-comment {{
+            color: #676e95;
+            font-style: italic;
         }}
         
-        .file-item.folder:hover {{
-            background: rgba(255, 193, 7, 0.1);
+        .theme-light .code, .theme-light .markdown pre {{
+            background: #fafafa;
+            color: #383a42;
         }}
         
-        .file-icon {{
-            font-size: 1.5rem;
-            margin-right: 16px;
-            width: 32px;
-            text-align: center;
+        .theme-light .code .line::before {{
+            color: #9d9d9f;
+            border-right-color: rgba(0, 0, 0, 0.1);
         }}
         
-        .file-name {{
-            flex: 1;
-            font-weight: 500;
-            overflow: hidden;
-            text-overflow: ellipsis;
-            white-space: nowrap;
+        .theme-light .hl-keyword {{
+            color: #a626a4;
         }}
         
-        .file-size {{
+        .theme-light .hl-type {{
+            color: #c18401;
+        }}
+        
+        .theme-light .hl-function {{
+            color: #4078f2;
+        }}
+        
+        .theme-light .hl-string {{
+            color: #50a14f;
+        }}
+        
+        .theme-light .hl-number, .theme-light .hl-literal {{
+            color: #986801;
+        }}
+        
+        .theme-light .hl-attribute {{
+            color: #e45649;
+        }}
+        
+        .theme-light .hl-comment {{
+            color: #a0a1a7;
+        }}
+        
+        .notice {{
+            padding: 12px;
             color: #888;
-            font-size: 0.9rem;
-            font-family: 'Monaco', 'Consolas', monospace;
+            font-size: 0.85rem;
+            border-top: 1px solid rgba(255, 255, 255, 0.1);
         }}
         
         .empty {{
@@ -229,8 +1264,23 @@ pub fn generate_index_html(root: &Path, subpath: &str) -> String {
             .file-item {{
                 padding: 12px 16px;
             }}
+            
+            .file-date {{
+                display: none;
+            }}
+            
+            .file-list.grid {{
+                grid-template-columns: repeat(auto-fill, minmax(110px, 1fr));
+            }}
         }}
     </style>
+    <script>
+        try {{
+            if (localStorage.getItem('code-theme') === 'light') {{
+                document.documentElement.classList.add('theme-light');
+            }}
+        }} catch (error) {{}}
+    </script>
 </head>
 <body>
     <div class="container">
@@ -243,24 +1293,144 @@ pub fn generate_index_html(root: &Path, subpath: &str) -> String {
             {}
         </div>
         
-        <div class="file-list">
-            {}
-        </div>
+        {}
         
         <div class="footer">
             Powered by <a href="https://github.com/rustserve">rustserve</a>
         </div>
-    </div>
+    </div>{}
 </body>
 </html>"#,
         html_escape(&dir_name),
         html_escape(&dir_name),
         breadcrumb,
-        files_html
+        content,
+        script
     )
 }
 
-fn generate_breadcrumb(subpath: &str) -> String {
+/// Hides the entries whose names do not contain what is typed in the
+/// filter box; Enter still submits it as a search of the subfolders.
+const FILTER_SCRIPT: &str = r#"
+    <script>
+        document.getElementById('filter').addEventListener('input', function (event) {
+            var query = event.target.value.toLowerCase();
+            document.querySelectorAll('.file-item').forEach(function (item) {
+                var name = item.querySelector('.file-name').textContent.toLowerCase();
+                item.style.display = name.includes(query) ? '' : 'none';
+            });
+        });
+    </script>"#;
+
+/// Asks how long a link should last, mints it and shows it for copying.
+const SHARE_SCRIPT: &str = r#"
+    <script>
+        document.querySelectorAll('.file-share').forEach(function (button) {
+            button.addEventListener('click', async function (event) {
+                event.preventDefault();
+                event.stopPropagation();
+                var hours = prompt('Keep the link working for how many hours?', '24');
+                if (hours === null) return;
+                var response = await fetch('/api/share/' + button.dataset.path +
+                    '?expires=' + encodeURIComponent(hours + 'h'), { method: 'POST' });
+                if (response.ok) {
+                    prompt('Share link', (await response.json()).url);
+                } else {
+                    alert(await response.text());
+                }
+            });
+        });
+    </script>"#;
+
+/// The full-size image over the grid, filled in by [`GALLERY_SCRIPT`].
+const LIGHTBOX_HTML: &str = r#"
+        
+        <div class="lightbox" id="lightbox" hidden>
+            <button class="lightbox-close" type="button" title="Close (Esc)">✕</button>
+            <button class="lightbox-previous" type="button" title="Previous (←)">‹</button>
+            <img alt="">
+            <button class="lightbox-next" type="button" title="Next (→)">›</button>
+            <div class="lightbox-caption"><a></a><span></span></div>
+        </div>"#;
+
+/// Opens an image tile in the lightbox, steps through the images still
+/// shown by the filter with the arrow keys and closes on Escape. A tile
+/// whose thumbnail fails to load falls back to an icon.
+const GALLERY_SCRIPT: &str = r#"
+    <script>
+        var lightbox = document.getElementById('lightbox');
+        var picture = lightbox.querySelector('img');
+        var caption = lightbox.querySelector('.lightbox-caption a');
+        var counter = lightbox.querySelector('.lightbox-caption span');
+        var current = -1;
+        function images() {
+            return Array.from(document.querySelectorAll('.tile[data-full]'))
+                .filter(function (tile) { return tile.style.display !== 'none'; });
+        }
+        function show(index) {
+            var tiles = images();
+            if (!tiles.length) return close();
+            current = (index + tiles.length) % tiles.length;
+            var tile = tiles[current];
+            picture.src = tile.dataset.full;
+            picture.alt = tile.dataset.name;
+            caption.textContent = tile.dataset.name;
+            caption.href = tile.getAttribute('href');
+            counter.textContent = (current + 1) + ' of ' + tiles.length;
+            lightbox.hidden = false;
+        }
+        function close() {
+            lightbox.hidden = true;
+            picture.removeAttribute('src');
+            current = -1;
+        }
+        document.querySelectorAll('.tile[data-full]').forEach(function (tile) {
+            tile.addEventListener('click', function (event) {
+                if (event.target.classList.contains('file-select') ||
+                    event.ctrlKey || event.metaKey || event.shiftKey) return;
+                event.preventDefault();
+                show(images().indexOf(tile));
+            });
+        });
+        document.querySelectorAll('.tile-thumb img').forEach(function (img) {
+            function fallback() { img.parentNode.textContent = '🖼️'; }
+            if (img.complete && !img.naturalWidth) fallback();
+            else img.addEventListener('error', fallback);
+        });
+        lightbox.querySelector('.lightbox-close').addEventListener('click', close);
+        lightbox.querySelector('.lightbox-previous').addEventListener('click', function () {
+            show(current - 1);
+        });
+        lightbox.querySelector('.lightbox-next').addEventListener('click', function () {
+            show(current + 1);
+        });
+        lightbox.addEventListener('click', function (event) {
+            if (event.target === lightbox) close();
+        });
+        document.addEventListener('keydown', function (event) {
+            if (lightbox.hidden) return;
+            if (event.key === 'ArrowLeft') show(current - 1);
+            else if (event.key === 'ArrowRight') show(current + 1);
+            else if (event.key === 'Escape') close();
+            else return;
+            event.preventDefault();
+        });
+    </script>"#;
+
+const ARCHIVE_SCRIPT: &str = r#"
+    <script>
+        var archiveButton = document.getElementById('archive-button');
+        document.querySelectorAll('.file-select').forEach(function (box) {
+            box.addEventListener('change', function () {
+                var selected = document.querySelectorAll('.file-select:checked').length;
+                archiveButton.textContent = selected
+                    ? '⬇ Download ' + selected + ' selected'
+                    : '⬇ Download all';
+            });
+        });
+    </script>"#;
+
+pub(crate) fn generate_breadcrumb(subpath: &str) -> String {
     let mut html = r#"<a href="/">📂 Home</a>"#.to_string();
 
     if !subpath.is_empty() {
@@ -321,11 +1491,18 @@ pub fn html_escape(s: &str) -> String {
         .replace('"', "&quot;")
 }
 
-fn encode_path(s: &str) -> String {
+/// Encodes a tree path for a link, including the characters that would
+/// end or break out of an HTML attribute, so the result is safe in one.
+pub(crate) fn encode_path(s: &str) -> String {
     s.replace('%', "%25")
         .replace(' ', "%20")
         .replace('#', "%23")
         .replace('?', "%3F")
+        .replace('"', "%22")
+        .replace('\'', "%27")
+        .replace('&', "%26")
+        .replace('<', "%3C")
+        .replace('>', "%3E")
 }
 
 pub fn error_html(message: &str) -> String {
@@ -366,3 +1543,410 @@ pub fn error_html(message: &str) -> String {
         html_escape(message)
     )
 }
+
+/// The `/_admin` page. It draws the JSON statistics from `/_admin/api`, then
+/// redraws them from each event on `/_admin/events`.
+pub fn admin_html() -> String {
+    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>rustserve - Admin</title>
+    <style>
+        * {
+            margin: 0;
+            padding: 0;
+            box-sizing: border-box;
+        }
+        
+        body {
+            font-family: 'Segoe UI', system-ui, -apple-system, sans-serif;
+            background: linear-gradient(135deg, #1a1a2e 0%, #16213e 50%, #0f3460 100%);
+            min-height: 100vh;
+            color: #e0e0e0;
+        }
+        
+        .container {
+            max-width: 1100px;
+            margin: 0 auto;
+            padding: 40px 20px;
+        }
+        
+        .header {
+            text-align: center;
+            margin-bottom: 40px;
+        }
+        
+        .header h1 {
+            font-size: 2.5rem;
+            background: linear-gradient(135deg, #667eea 0%, #764ba2 100%);
+            -webkit-background-clip: text;
+            -webkit-text-fill-color: transparent;
+            background-clip: text;
+            margin-bottom: 10px;
+        }
+        
+        .header .subtitle {
+            color: #888;
+            font-size: 1rem;
+        }
+        
+        .cards {
+            display: grid;
+            grid-template-columns: repeat(auto-fill, minmax(200px, 1fr));
+            gap: 16px;
+            margin-bottom: 30px;
+        }
+        
+        .card, .panel {
+            background: rgba(255, 255, 255, 0.03);
+            border-radius: 16px;
+            backdrop-filter: blur(10px);
+            border: 1px solid rgba(255, 255, 255, 0.1);
+            box-shadow: 0 8px 32px rgba(0, 0, 0, 0.3);
+        }
+        
+        .card {
+            padding: 20px 24px;
+        }
+        
+        .card .label {
+            color: #888;
+            font-size: 0.85rem;
+            margin-bottom: 6px;
+        }
+        
+        .card .value {
+            font-size: 1.5rem;
+            font-family: 'Monaco', 'Consolas', monospace;
+        }
+        
+        .panel {
+            margin-bottom: 30px;
+            overflow: hidden;
+        }
+        
+        .panel h2 {
+            font-size: 1.1rem;
+            padding: 16px 24px;
+            border-bottom: 1px solid rgba(255, 255, 255, 0.1);
+        }
+        
+        table {
+            width: 100%;
+            border-collapse: collapse;
+        }
+        
+        th, td {
+            text-align: left;
+            padding: 10px 24px;
+            border-bottom: 1px solid rgba(255, 255, 255, 0.05);
+            overflow-wrap: anywhere;
+        }
+        
+        th {
+            color: #888;
+            font-weight: 500;
+            font-size: 0.85rem;
+        }
+        
+        td.number {
+            font-family: 'Monaco', 'Consolas', monospace;
+        }
+        
+        .status-4 {
+            color: #ffc107;
+        }
+        
+        .status-5 {
+            color: #ff6b6b;
+        }
+        
+        .empty {
+            text-align: center;
+            padding: 30px 20px;
+            color: #666;
+        }
+        
+        .footer {
+            text-align: center;
+            margin-top: 40px;
+            color: #555;
+            font-size: 0.85rem;
+        }
+    </style>
+</head>
+<body>
+    <div class="container">
+        <div class="header">
+            <h1>📊 rustserve admin</h1>
+            <p class="subtitle" id="uptime">Connecting…</p>
+        </div>
+        
+        <div class="cards" id="cards"></div>
+        
+        <div class="panel">
+            <h2>👥 Active connections</h2>
+            <table id="connections"></table>
+        </div>
+        
+        <div class="panel">
+            <h2>📥 Top downloads</h2>
+            <table id="top-files"></table>
+        </div>
+        
+        <div class="panel">
+            <h2>⚠️ Recent errors</h2>
+            <table id="errors"></table>
+        </div>
+        
+        <div class="panel">
+            <h2>🧭 Routes</h2>
+            <table id="routes"></table>
+        </div>
+        
+        <div class="footer" id="status">Live updates paused</div>
+    </div>
+    <script>
+        function formatBytes(bytes) {
+            var units = ['B', 'KB', 'MB', 'GB', 'TB'];
+            var i = 0;
+            while (bytes >= 1024 && i < units.length - 1) {
+                bytes /= 1024;
+                i++;
+            }
+            return (i === 0 ? bytes : bytes.toFixed(2)) + ' ' + units[i];
+        }
+
+        function formatDuration(seconds) {
+            var d = Math.floor(seconds / 86400), h = Math.floor(seconds % 86400 / 3600),
+                m = Math.floor(seconds % 3600 / 60), s = Math.floor(seconds % 60);
+            return (d ? d + 'd ' : '') + (d || h ? h + 'h ' : '') + (d || h || m ? m + 'm ' : '') + s + 's';
+        }
+
+        // Built with textContent: paths and addresses come from clients.
+        function fill(id, headings, rows, empty) {
+            var table = document.getElementById(id);
+            table.textContent = '';
+            if (rows.length === 0) {
+                var cell = table.insertRow().insertCell();
+                cell.colSpan = headings.length;
+                cell.className = 'empty';
+                cell.textContent = empty;
+                return;
+            }
+            var head = table.insertRow();
+            headings.forEach(function (heading) {
+                var th = document.createElement('th');
+                th.textContent = heading;
+                head.appendChild(th);
+            });
+            rows.forEach(function (row) {
+                var tr = table.insertRow();
+                row.forEach(function (value) {
+                    var td = tr.insertCell();
+                    if (typeof value === 'object' && value !== null) {
+                        td.className = value.className;
+                        value = value.text;
+                    }
+                    td.textContent = value === null ? '-' : value;
+                });
+            });
+        }
+
+        function render(stats) {
+            document.getElementById('uptime').textContent = 'Up for ' + formatDuration(stats.uptime);
+            var cards = [
+                ['👥 Active connections', stats.active_connections],
+                ['📊 Total requests', stats.total_requests],
+                ['📥 Files downloaded', stats.files_downloaded],
+                ['📤 Data sent', formatBytes(stats.bytes_sent)],
+                ['⚠️ Client / server errors', stats.responses['4xx'] + ' / ' + stats.responses['5xx']],
+                ['⏱️ p50 / p90 / p99', stats.latency_ms.p50 + ' / ' + stats.latency_ms.p90 + ' / ' +
+                    stats.latency_ms.p99 + ' ms'],
+                ['📈 Requests/s 1m · 5m · 15m', stats.request_rate['1m'] + ' · ' +
+                    stats.request_rate['5m'] + ' · ' + stats.request_rate['15m']],
+                ['🚀 Throughput (1m)', formatBytes(stats.byte_rate['1m']) + '/s']
+            ];
+            var container = document.getElementById('cards');
+            container.textContent = '';
+            cards.forEach(function (card) {
+                var div = document.createElement('div');
+                div.className = 'card';
+                var label = document.createElement('div');
+                label.className = 'label';
+                label.textContent = card[0];
+                var value = document.createElement('div');
+                value.className = 'value';
+                value.textContent = card[1];
+                div.appendChild(label);
+                div.appendChild(value);
+                container.appendChild(div);
+            });
+
+            fill('connections', ['Client', 'Path', 'Open for'], stats.connections.map(function (c) {
+                return [c.remote_addr, c.path, formatDuration(c.seconds)];
+            }), 'No open connections');
+            fill('top-files', ['Path', 'Downloads'], stats.top_files.map(function (f) {
+                return [f.path, { text: f.downloads, className: 'number' }];
+            }), 'Nothing downloaded yet');
+            fill('errors', ['Time', 'Client', 'Request', 'Status'], stats.recent_errors.map(function (e) {
+                return [new Date(e.time * 1000).toLocaleString(), e.remote_addr, e.method + ' ' + e.path,
+                    { text: e.status, className: 'number status-' + Math.floor(e.status / 100) }];
+            }), 'No errors');
+            fill('routes', ['Route', 'Requests', 'Errors', 'p50 / p90 / p99 ms'], stats.routes.map(function (r) {
+                return [r.route, { text: r.requests, className: 'number' }, { text: r.errors, className: 'number' },
+                    { text: r.latency_ms.p50 + ' / ' + r.latency_ms.p90 + ' / ' + r.latency_ms.p99, className: 'number' }];
+            }), 'No requests yet');
+        }
+
+        fetch('/_admin/api').then(function (response) { return response.json(); }).then(render);
+        var events = new EventSource('/_admin/events');
+        events.onopen = function () {
+            document.getElementById('status').textContent = 'Updating live';
+        };
+        events.onmessage = function (event) {
+            render(JSON.parse(event.data));
+        };
+        events.onerror = function () {
+            document.getElementById('status').textContent = 'Reconnecting…';
+        };
+    </script>
+</body>
+</html>"#
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_pages() {
+        let root = std::env::temp_dir().join(format!("rustserve-html-{}", std::process::id()));
+        fs::create_dir_all(root.join("zdir")).unwrap();
+        for (name, size) in [("b.txt", 3), ("a.rs", 1), ("c.md", 2)] {
+            fs::write(root.join(name), "x".repeat(size)).unwrap();
+        }
+        let order = |html: &str| -> Vec<&str> {
+            ["zdir", "a.rs", "b.txt", "c.md"]
+                .into_iter()
+                .map(|name| (html.find(&format!(">{}<", name)), name))
+                .filter_map(|(index, name)| Some((index?, name)))
+                .collect::<std::collections::BTreeMap<_, _>>()
+                .into_values()
+                .collect()
+        };
+
+        let html = Listing::new(&root, "").render();
+        assert_eq!(order(&html), ["zdir", "a.rs", "b.txt", "c.md"]);
+        let by_size = Sort::from_query(Some("size"), Some("desc"));
+        let html = Listing::new(&root, "").sort(by_size).render();
+        assert_eq!(order(&html), ["zdir", "b.txt", "c.md", "a.rs"]);
+        assert!(html.contains(r#"href="?sort=size&order=asc">Size ▼"#));
+        let by_type = Sort::from_query(Some("type"), Some("bogus"));
+        let html = Listing::new(&root, "").sort(by_type).render();
+        assert_eq!(order(&html), ["zdir", "c.md", "a.rs", "b.txt"]);
+
+        let page = |n| Listing::new(&root, "").page_size(3).page(n).render();
+        assert_eq!(order(&page(1)), ["zdir", "a.rs", "b.txt"]);
+        assert_eq!(order(&page(2)), ["c.md"]);
+        assert_eq!(order(&page(9)), ["c.md"]);
+        assert!(page(2).contains("Page 2 of 2"));
+        assert!(!page(1).contains("📖"));
+
+        fs::write(root.join("README.md"), "# About\n\n![logo](img/logo.png)").unwrap();
+        let html = Listing::new(&root, "").render();
+        assert!(html.contains("📖 README.md"));
+        assert!(html.contains(r#"<h1>About</h1>"#));
+        assert!(html.contains(r#"<img src="/download/img/logo.png?inline" alt="logo">"#));
+        let hidden = Listing::new(&root, "").visible(|path| path != "README.md");
+        assert!(!hidden.render().contains("About"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_quoted_names() {
+        let root = std::env::temp_dir().join(format!("rustserve-quoted-{}", std::process::id()));
+        let name = r#"x"onmouseover="alert('1')&<b>"#;
+        fs::create_dir_all(root.join(name)).unwrap();
+        fs::write(root.join(name).join(name), "x").unwrap();
+
+        let encoded = "x%22onmouseover=%22alert(%271%27)%26%3Cb%3E";
+        let html = Listing::new(&root, "").render();
+        assert!(html.contains(&format!(
+            r#"<a href="/browse/{}" class="file-item folder">"#,
+            encoded
+        )));
+        assert!(!html.contains(r#"x"onmouseover"#));
+        let html = Listing::new(&root, name).render();
+        assert!(html.contains(&format!(
+            r#"<a href="/view/{0}/{0}" class="file-item file">"#,
+            encoded
+        )));
+        assert!(html.contains(&format!(r#"<a href="/browse/{}">"#, encoded)));
+        assert!(!html.contains(r#"x"onmouseover"#));
+        let html = Listing::new(&root, name).share_links(true).render();
+        assert!(html.contains(&format!(r#"data-path="{0}/{0}">"#, encoded)));
+        assert!(!html.contains(r#"x"onmouseover"#));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_gallery() {
+        let root = std::env::temp_dir().join(format!("rustserve-gallery-{}", std::process::id()));
+        fs::create_dir_all(root.join("album")).unwrap();
+        fs::write(root.join("notes.txt"), "x").unwrap();
+
+        // Only directories holding images offer the grid.
+        let html = Listing::new(&root, "").view(View::Grid).render();
+        assert!(!html.contains("▦ Grid") && !html.contains("file-list grid"));
+
+        for name in ["photo.png", "logo.svg"] {
+            fs::write(root.join(name), "x").unwrap();
+        }
+        let html = Listing::new(&root, "").render();
+        assert!(html.contains(r#"<a class="active" href="?sort=name&order=asc">☰ List</a>"#));
+        assert!(html.contains(r#"<a href="?sort=name&order=asc&view=grid">▦ Grid</a>"#));
+        assert!(!html.contains(r#"id="lightbox""#));
+
+        let html = Listing::new(&root, "")
+            .view(View::from_query(Some("grid")))
+            .render();
+        assert!(html.contains(r#"<div class="file-list grid">"#));
+        assert!(html.contains(r#"href="?sort=name&order=desc&view=grid">Name ▲"#));
+        assert!(html.contains(r#"<a href="/browse/album" class="file-item tile folder">"#));
+        let thumb = match thumbnail::decodable("photo.png") {
+            true => "/thumb/photo.png",
+            false => "/download/photo.png?inline",
+        };
+        assert!(html.contains(&format!(r#"<img src="{}" alt="" loading="lazy">"#, thumb)));
+        assert!(html.contains(r#"data-full="/download/photo.png?inline" data-name="photo.png""#));
+        // SVG can run scripts, so it is neither embedded nor opened.
+        assert!(!html.contains("logo.svg?inline") && !html.contains(r#"data-name="logo.svg""#));
+        assert!(html.contains(r#"<div class="lightbox" id="lightbox" hidden>"#));
+        assert!(html.contains("ArrowRight"));
+
+        // Names stay inside the tile's attributes.
+        fs::write(root.join(r#"x"onerror="alert(1).png"#), "x").unwrap();
+        let html = Listing::new(&root, "").view(View::Grid).render();
+        let path = "x%22onerror=%22alert(1).png";
+        assert!(html.contains(&format!(
+            r#"<a href="/view/{}" class="file-item tile file""#,
+            path
+        )));
+        assert!(html.contains(&format!(r#"data-full="/download/{}?inline""#, path)));
+        assert!(html.contains(r#"data-name="x&quot;onerror=&quot;alert(1).png""#));
+        let thumb = match thumbnail::decodable("photo.png") {
+            true => format!("/thumb/{}", path),
+            false => format!("/download/{}?inline", path),
+        };
+        assert!(html.contains(&format!(r#"<img src="{}" alt="" loading="lazy">"#, thumb)));
+        assert!(!html.contains(r#"x"onerror"#));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::encoding::Compression;
+use super::future::AsyncRequestHandler;
+use super::{Method, Request, Response, ServerConfig};
+
+/// A server whose handlers are futures driven by a tokio runtime.
+///
+/// It takes the same [`ServerConfig`] as [`Server`](super::Server); the
+/// thread count sizes the runtime's worker pool and the backend is ignored.
+/// Unlike `Server`, it keeps no statistics, metrics or access log.
+pub struct AsyncServer {
+    listener: std::net::TcpListener,
+    thread_count: usize,
+    compression: Option<Compression>,
+    max_body_size: Option<usize>,
+}
+
+impl AsyncServer {
+    pub fn new(config: ServerConfig) -> io::Result<Self> {
+        let addr = format!("{}:{}", config.address, config.port);
+        let listener = std::net::TcpListener::bind(&addr)?;
+
+        Ok(AsyncServer {
+            listener,
+            thread_count: config.thread_count,
+            compression: config.compression,
+            max_body_size: config.max_body_size,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Starts a multi-threaded runtime and serves on it until the listener fails.
+    pub fn run(self, handler: impl AsyncRequestHandler + 'static) -> io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(self.thread_count.max(1))
+            .enable_io()
+            .build()?;
+
+        runtime.block_on(self.serve(handler))
+    }
+
+    /// Serves on the runtime the caller is already running in.
+    pub async fn serve(self, handler: impl AsyncRequestHandler + 'static) -> io::Result<()> {
+        self.listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(self.listener)?;
+        let handler = Arc::new(handler);
+        let compression = Arc::new(self.compression);
+        let max_body_size = self.max_body_size;
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let handler = Arc::clone(&handler);
+                    let compression = Arc::clone(&compression);
+                    tokio::spawn(async move {
+                        let result = handle_connection(
+                            stream,
+                            addr,
+                            handler.as_ref(),
+                            compression.as_ref().as_ref(),
+                            max_body_size,
+                        )
+                        .await;
+                        if let Err(e) = result {
+                            eprintln!("Error handling connection: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    eprintln!("Error accepting connection: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    handler: &dyn AsyncRequestHandler,
+    compression: Option<&Compression>,
+    max_body_size: Option<usize>,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 16 * 1024];
+
+    loop {
+        let request = match Request::parse_bytes(&buf, max_body_size) {
+            Ok(Some((request, consumed))) => {
+                buf.drain(..consumed);
+                request.with_remote_addr(addr)
+            }
+            Ok(None) => {
+                let n = stream.read(&mut chunk).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                buf.extend_from_slice(&chunk[..n]);
+                continue;
+            }
+            Err(e) => {
+                let status = e.status_code().unwrap_or(400);
+                let response = Response::new(status).header("Connection", "close");
+                return write_response(&mut stream, response).await;
+            }
+        };
+
+        let keep_alive = request.keep_alive();
+        let mut response = handler.handle(&request).await;
+        if let Some(compression) = compression {
+            response = compression.apply(&request, response);
+        }
+        if request.method() == &Method::Head {
+            response.strip_body();
+        }
+        if !keep_alive {
+            response = response.header("Connection", "close");
+        }
+
+        write_response(&mut stream, response).await?;
+
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, response: Response) -> io::Result<()> {
+    let (head, body) = response.into_wire();
+    stream.write_all(&head).await?;
+
+    if let Some(mut body) = body {
+        loop {
+            // Body readers block, so each chunk is read off the runtime threads.
+            let (returned, chunk) = tokio::task::spawn_blocking(move || {
+                let chunk = body.next_chunk();
+                (body, chunk)
+            })
+            .await
+            .map_err(io::Error::other)?;
+            body = returned;
+
+            match chunk? {
+                Some(chunk) => stream.write_all(&chunk).await?,
+                None => break,
+            }
+        }
+    }
+
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    use crate::http::{AsyncServer, Filter, Response, ServerConfig, get, param};
+
+    #[test]
+    fn test_async_and_sync_routes() {
+        let server = AsyncServer::new(ServerConfig::new("127.0.0.1", 0).threads(1)).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let slow = get("/slow")
+            .and(param::<String>())
+            .then(|(name,)| async move {
+                tokio::task::yield_now().await;
+                Response::ok(format!("async {}", name))
+            });
+        let sync = get("/sync").map(|_| Response::ok("sync")).into_async();
+        thread::spawn(move || server.run(slow.or(sync)));
+
+        for (path, expected) in [("/slow/x", "async x"), ("/sync", "sync")] {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.ends_with(expected));
+        }
+    }
+}
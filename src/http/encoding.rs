@@ -0,0 +1,384 @@
+//! Response compression negotiated through `Accept-Encoding`.
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use super::response::Body;
+use super::{Method, Request, Response};
+use crate::compression::{GzipEncoder, ZlibEncoder};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+    /// Only served from precompressed files; never produced on the fly.
+    Brotli,
+    Identity,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Brotli => "br",
+            Encoding::Identity => "identity",
+        }
+    }
+
+    /// File name suffix conventionally used for precompressed siblings.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Gzip => Some("gz"),
+            Encoding::Brotli => Some("br"),
+            Encoding::Deflate | Encoding::Identity => None,
+        }
+    }
+
+    fn can_encode(&self) -> bool {
+        matches!(self, Encoding::Gzip | Encoding::Deflate)
+    }
+
+    /// Picks the encoding from `supported` with the highest q-value in an
+    /// `Accept-Encoding` header. Ties go to whichever comes first in
+    /// `supported`; if none is acceptable the response stays uncompressed.
+    pub fn negotiate(accept_encoding: Option<&str>, supported: &[Encoding]) -> Encoding {
+        let Some(header) = accept_encoding else {
+            return Encoding::Identity;
+        };
+
+        let mut entries = Vec::new();
+        for item in header.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or("").to_ascii_lowercase();
+            let q = parts
+                .find_map(|p| p.strip_prefix("q=").or_else(|| p.strip_prefix("Q=")))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if !name.is_empty() {
+                entries.push((name, q));
+            }
+        }
+
+        let quality = |encoding: Encoding| -> f32 {
+            let explicit = entries.iter().find(|(name, _)| {
+                name == encoding.name() || (encoding == Encoding::Gzip && name == "x-gzip")
+            });
+            let wildcard = entries.iter().find(|(name, _)| name == "*");
+            explicit.or(wildcard).map(|(_, q)| *q).unwrap_or(0.0)
+        };
+
+        let mut best = (Encoding::Identity, 0.0);
+        for &encoding in supported {
+            let q = quality(encoding);
+            if q > best.1 {
+                best = (encoding, q);
+            }
+        }
+
+        best.0
+    }
+}
+
+/// Server-wide response compression settings.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    pub min_size: u64,
+    pub content_types: Vec<String>,
+    pub encodings: Vec<Encoding>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/toml",
+                "application/yaml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            encodings: vec![Encoding::Gzip, Encoding::Deflate],
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression::default()
+    }
+
+    /// Bodies smaller than this many bytes are sent as they are.
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Adds a content type prefix eligible for compression.
+    pub fn content_type(mut self, prefix: impl Into<String>) -> Self {
+        self.content_types.push(prefix.into());
+        self
+    }
+
+    /// Sets the encodings to produce, most preferred first. Brotli is ignored
+    /// here since it can only be served from precompressed files.
+    pub fn encodings(mut self, encodings: &[Encoding]) -> Self {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    fn is_eligible_type(&self, content_type: &str) -> bool {
+        let content_type = content_type.to_ascii_lowercase();
+        self.content_types
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix.as_str()))
+    }
+
+    pub(crate) fn apply(&self, request: &Request, mut response: Response) -> Response {
+        if !response.is_compressible()
+            || request.method() == &Method::Head
+            || !(200..300).contains(&response.status_code())
+            || matches!(response.status_code(), 204 | 206)
+            || response.get_header("Content-Encoding").is_some()
+        {
+            return response;
+        }
+
+        match response.get_header("Content-Type") {
+            Some(content_type) if self.is_eligible_type(content_type) => {}
+            _ => return response,
+        }
+
+        let vary = match response.get_header("Vary") {
+            Some(vary) if !vary.to_ascii_lowercase().contains("accept-encoding") => {
+                format!("{}, Accept-Encoding", vary)
+            }
+            Some(vary) => vary.to_string(),
+            None => "Accept-Encoding".to_string(),
+        };
+        response = response.header("Vary", &vary);
+
+        let size = match response.get_body() {
+            Some(Body::Bytes(bytes)) => Some(bytes.len() as u64),
+            Some(Body::Stream { length, .. }) => *length,
+            None => return response,
+        };
+        if size.is_some_and(|size| size < self.min_size) {
+            return response;
+        }
+
+        let supported: Vec<Encoding> = self
+            .encodings
+            .iter()
+            .copied()
+            .filter(Encoding::can_encode)
+            .collect();
+        let encoding = Encoding::negotiate(request.header("accept-encoding"), &supported);
+        if encoding == Encoding::Identity {
+            return response;
+        }
+
+        let body = match response.take_body() {
+            Some(Body::Bytes(bytes)) => {
+                let mut encoder = Encoder::new(encoding);
+                encoder.write_all(&bytes);
+                Body::Bytes(encoder.finish())
+            }
+            Some(Body::Stream { reader, .. }) => Body::Stream {
+                reader: Arc::new(Mutex::new(Box::new(EncodingReader {
+                    source: reader,
+                    encoder: Some(Encoder::new(encoding)),
+                    out: Vec::new(),
+                    pos: 0,
+                }))),
+                length: None,
+            },
+            None => unreachable!("checked above"),
+        };
+
+        response.set_body(Some(body));
+        response.remove_header("Content-Length");
+
+        // Each representation needs its own validator.
+        if let Some(etag) = response.get_header("ETag") {
+            let etag = match etag.strip_suffix('"') {
+                Some(tag) => format!("{}-{}\"", tag, encoding.name()),
+                None => etag.to_string(),
+            };
+            response = response.header("ETag", &etag);
+        }
+
+        response.header("Content-Encoding", encoding.name())
+    }
+}
+
+enum Encoder {
+    Gzip(GzipEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new())),
+            _ => Encoder::Gzip(GzipEncoder::new(Vec::new())),
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) {
+        // The sink is a Vec, so encoding cannot fail.
+        match self {
+            Encoder::Gzip(e) => e.write_all(data).unwrap(),
+            Encoder::Deflate(e) => e.write_all(data).unwrap(),
+        }
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            Encoder::Gzip(e) => e.get_mut(),
+            Encoder::Deflate(e) => e.get_mut(),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(e) => e.finish().unwrap(),
+            Encoder::Deflate(e) => e.finish().unwrap(),
+        }
+    }
+}
+
+/// Compresses a streamed body on the fly as it is read.
+struct EncodingReader {
+    source: Arc<Mutex<Box<dyn Read + Send>>>,
+    encoder: Option<Encoder>,
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for EncodingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = vec![0u8; 32 * 1024];
+
+        while self.pos == self.out.len() {
+            let Some(encoder) = self.encoder.as_mut() else {
+                return Ok(0);
+            };
+
+            let n = self.source.lock().unwrap().read(&mut chunk)?;
+            self.pos = 0;
+            if n == 0 {
+                self.out = self.encoder.take().unwrap().finish();
+            } else {
+                encoder.write_all(&chunk[..n]);
+                self.out = std::mem::take(encoder.output());
+            }
+        }
+
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn request(accept_encoding: Option<&str>) -> Request {
+        let mut headers = HashMap::new();
+        if let Some(value) = accept_encoding {
+            headers.insert("Accept-Encoding".to_string(), value.to_string());
+        }
+        Request::new(Method::Get, "/", headers, None)
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let supported = [Encoding::Gzip, Encoding::Deflate];
+        assert_eq!(Encoding::negotiate(None, &supported), Encoding::Identity);
+        assert_eq!(
+            Encoding::negotiate(Some("gzip, deflate"), &supported),
+            Encoding::Gzip
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("gzip;q=0.5, deflate"), &supported),
+            Encoding::Deflate
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("br, *;q=0.1"), &supported),
+            Encoding::Gzip
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("gzip;q=0, deflate;q=0"), &supported),
+            Encoding::Identity
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("identity"), &supported),
+            Encoding::Identity
+        );
+    }
+
+    #[test]
+    fn test_apply_compresses_eligible_bodies() {
+        let compression = Compression::default();
+        let html = "<p>hello</p>".repeat(500);
+
+        let response = compression.apply(&request(Some("gzip")), Response::html(&html));
+        assert_eq!(response.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        let Some(Body::Bytes(body)) = response.get_body() else {
+            panic!("expected an in-memory body");
+        };
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, html);
+
+        let response = compression.apply(&request(None), Response::html(&html));
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+
+        let response = compression.apply(&request(Some("gzip")), Response::html("<p>tiny</p>"));
+        assert_eq!(response.get_header("Content-Encoding"), None);
+
+        let response = compression.apply(&request(Some("gzip")), Response::file(html.as_bytes()));
+        assert_eq!(response.get_header("Content-Encoding"), None);
+
+        let response =
+            compression.apply(&request(Some("gzip")), Response::html(&html).uncompressed());
+        assert_eq!(response.get_header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn test_apply_streams() {
+        let text = "line of text\n".repeat(20_000);
+        let response = Response::stream(io::Cursor::new(text.clone().into_bytes()), None)
+            .header("Content-Type", "text/plain");
+        let response = Compression::default().apply(&request(Some("deflate")), response);
+        assert_eq!(response.get_header("Content-Encoding"), Some("deflate"));
+
+        let Some(Body::Stream { reader, length }) = response.get_body() else {
+            panic!("expected a streamed body");
+        };
+        assert_eq!(*length, None);
+
+        let mut compressed = Vec::new();
+        reader.lock().unwrap().read_to_end(&mut compressed).unwrap();
+        let mut decoded = String::new();
+        flate2::read::ZlibDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+    }
+}
@@ -0,0 +1,711 @@
+//! Epoll-driven connection handling.
+//!
+//! A single thread owns every socket and only ever performs non-blocking
+//! reads and writes. Once a complete request has been buffered it is handed
+//! to the thread pool, and the finished response is passed back through a
+//! completion queue that wakes the loop via an `eventfd`. Streamed bodies are
+//! read one chunk at a time on the pool whenever the socket has drained the
+//! previous one, so a slow client never ties up a pool thread.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Request;
+use super::request::RequestHandler;
+use super::response::BodyStream;
+use super::server::{OpenConnection, Service};
+use crate::threads::ThreadPool;
+
+const LISTENER: u64 = u64::MAX;
+const WAKER: u64 = u64::MAX - 1;
+
+const READ_CHUNK: usize = 16 * 1024;
+const MAX_EVENTS: usize = 256;
+/// How long a connection may go without reading or writing anything; short
+/// in tests so that they can outlast it.
+const KEEP_ALIVE_TIMEOUT: Duration = match cfg!(test) {
+    true => Duration::from_secs(2),
+    false => Duration::from_secs(30),
+};
+
+/// Thin owner of an epoll file descriptor.
+struct Epoll {
+    fd: RawFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        Ok(Epoll { fd })
+    }
+
+    fn add(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, fd, token, events)
+    }
+
+    fn modify(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_MOD, fd, token, events)
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+        cvt(unsafe { libc::epoll_ctl(self.fd, op, fd, &mut event) })?;
+        Ok(())
+    }
+
+    fn wait(&self, events: &mut Vec<libc::epoll_event>, timeout: Duration) -> io::Result<()> {
+        events.clear();
+        let n = unsafe {
+            libc::epoll_wait(
+                self.fd,
+                events.as_mut_ptr(),
+                events.capacity() as libc::c_int,
+                timeout.as_millis() as libc::c_int,
+            )
+        };
+
+        match cvt(n) {
+            Ok(n) => {
+                // SAFETY: the kernel initialised the first `n` entries.
+                unsafe { events.set_len(n as usize) };
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// An `eventfd` that pool threads use to wake the event loop.
+struct Waker {
+    fd: RawFd,
+}
+
+impl Waker {
+    fn new() -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) })?;
+        Ok(Waker { fd })
+    }
+
+    fn wake(&self) {
+        let one: u64 = 1;
+        unsafe { libc::write(self.fd, &one as *const u64 as *const libc::c_void, 8) };
+    }
+
+    fn drain(&self) {
+        let mut value: u64 = 0;
+        unsafe { libc::read(self.fd, &mut value as *mut u64 as *mut libc::c_void, 8) };
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Output produced on the pool for one connection.
+struct Completion {
+    token: u64,
+    /// Bytes to write next, or `None` if producing them failed.
+    bytes: Option<Vec<u8>>,
+    /// The rest of a streamed body, to be read once `bytes` are written.
+    body: Option<BodyStream>,
+}
+
+/// Work finished by pool threads, waiting to be picked up by the loop.
+struct Completions {
+    queue: Mutex<Vec<Completion>>,
+    waker: Waker,
+}
+
+impl Completions {
+    fn push(&self, completion: Completion) {
+        self.queue.lock().unwrap().push(completion);
+        self.waker.wake();
+    }
+
+    fn take(&self) -> Vec<Completion> {
+        self.waker.drain();
+        std::mem::take(&mut *self.queue.lock().unwrap())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    Reading,
+    Handling,
+    Writing,
+}
+
+struct Connection {
+    stream: TcpStream,
+    open: OpenConnection,
+    remote_addr: SocketAddr,
+    state: State,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    written: usize,
+    body: Option<BodyStream>,
+    keep_alive: bool,
+    /// Whether the peer has closed its side; what it sent before is still
+    /// answered, and the connection closed once nothing is left to answer.
+    read_closed: bool,
+    last_active: Instant,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, remote_addr: SocketAddr, open: OpenConnection) -> Self {
+        Connection {
+            stream,
+            open,
+            remote_addr,
+            state: State::Reading,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            written: 0,
+            body: None,
+            keep_alive: true,
+            read_closed: false,
+            last_active: Instant::now(),
+        }
+    }
+
+    /// Reads everything currently available. Returns `false` if the socket
+    /// failed; a peer closing its side only sets `read_closed`.
+    fn fill(&mut self) -> bool {
+        let mut chunk = [0u8; READ_CHUNK];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.read_closed = true;
+                    return true;
+                }
+                Ok(n) => {
+                    self.read_buf.extend_from_slice(&chunk[..n]);
+                    self.last_active = Instant::now();
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Writes as much of the pending response as the socket accepts.
+    /// Returns `Ok(true)` once the whole response has been sent.
+    fn flush(&mut self) -> io::Result<bool> {
+        while self.written < self.write_buf.len() {
+            match self.stream.write(&self.write_buf[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+struct EventLoop<H> {
+    epoll: Epoll,
+    listener: TcpListener,
+    pool: ThreadPool,
+    service: Arc<Service<H>>,
+    completions: Arc<Completions>,
+    connections: HashMap<u64, Connection>,
+    next_token: u64,
+}
+
+pub(crate) fn run<H: RequestHandler + 'static>(
+    listener: TcpListener,
+    pool: ThreadPool,
+    service: Arc<Service<H>>,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+
+    let epoll = Epoll::new()?;
+    let completions = Arc::new(Completions {
+        queue: Mutex::new(Vec::new()),
+        waker: Waker::new()?,
+    });
+
+    epoll.add(listener.as_raw_fd(), LISTENER, libc::EPOLLIN as u32)?;
+    epoll.add(completions.waker.fd, WAKER, libc::EPOLLIN as u32)?;
+
+    let mut event_loop = EventLoop {
+        epoll,
+        listener,
+        pool,
+        service,
+        completions,
+        connections: HashMap::new(),
+        next_token: 0,
+    };
+
+    event_loop.run()
+}
+
+impl<H: RequestHandler + 'static> EventLoop<H> {
+    fn run(&mut self) -> io::Result<()> {
+        let mut events = Vec::with_capacity(MAX_EVENTS);
+        let mut last_sweep = Instant::now();
+
+        loop {
+            self.epoll.wait(&mut events, Duration::from_secs(1))?;
+
+            for event in &events {
+                let token = event.u64;
+                let flags = event.events;
+
+                match token {
+                    LISTENER => self.accept(),
+                    WAKER => self.complete(),
+                    token => self.ready(token, flags),
+                }
+            }
+
+            if last_sweep.elapsed() >= Duration::from_secs(1) {
+                self.close_idle();
+                last_sweep = Instant::now();
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if let Err(e) = self.register(stream, addr) {
+                        eprintln!("Error registering connection: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    eprintln!("Error accepting connection: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn register(&mut self, stream: TcpStream, addr: SocketAddr) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        let token = self.next_token;
+        self.next_token += 1;
+
+        self.epoll.add(
+            stream.as_raw_fd(),
+            token,
+            (libc::EPOLLIN | libc::EPOLLRDHUP) as u32,
+        )?;
+        let open = self.service.open_connection(Some(addr));
+        self.connections
+            .insert(token, Connection::new(stream, addr, open));
+        Ok(())
+    }
+
+    fn ready(&mut self, token: u64, flags: u32) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        if flags & (libc::EPOLLERR | libc::EPOLLHUP) as u32 != 0 {
+            self.close(token);
+            return;
+        }
+
+        let open = match conn.state {
+            State::Reading => conn.fill(),
+            // Nothing to do until the handler finishes; a hang-up is noticed
+            // when the response is written.
+            State::Handling => true,
+            State::Writing => true,
+        };
+
+        if !open {
+            self.close(token);
+            return;
+        }
+
+        match conn.state {
+            State::Reading => self.dispatch(token),
+            State::Writing => self.write(token),
+            State::Handling => {}
+        }
+    }
+
+    /// Hands a buffered request to the pool if one is complete, or closes a
+    /// connection whose peer has nothing more to send.
+    fn dispatch(&mut self, token: u64) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        let request = match Request::parse_bytes(&conn.read_buf, self.service.max_body_size) {
+            Ok(Some((request, consumed))) => {
+                conn.read_buf.drain(..consumed);
+                request.with_remote_addr(conn.remote_addr)
+            }
+            Ok(None) => {
+                if conn.read_closed {
+                    self.close(token);
+                }
+                return;
+            }
+            Err(e) => {
+                let status = e.status_code().unwrap_or(400);
+                let response = self
+                    .service
+                    .reject(status, Some(conn.remote_addr))
+                    .header("Connection", "close");
+                (conn.write_buf, _) = response.into_wire();
+                conn.written = 0;
+                conn.keep_alive = false;
+                conn.state = State::Writing;
+                self.write(token);
+                return;
+            }
+        };
+
+        conn.keep_alive = request.keep_alive();
+        conn.open.serving(&request);
+        if !self.suspend(token) {
+            return;
+        }
+
+        let service = Arc::clone(&self.service);
+        let completions = Arc::clone(&self.completions);
+
+        self.pool.execute(move || {
+            let mut response = service.respond(&request);
+            if !request.keep_alive() {
+                response = response.header("Connection", "close");
+            }
+
+            let (head, body) = response.into_wire();
+            completions.push(Completion {
+                token,
+                bytes: Some(head),
+                body,
+            });
+        });
+    }
+
+    /// Parks a connection while the pool works on it.
+    fn suspend(&mut self, token: u64) -> bool {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return false;
+        };
+
+        conn.state = State::Handling;
+        conn.last_active = Instant::now();
+
+        // Stop listening for input while the pool is busy so a pipelining or
+        // half-closing client cannot make the loop spin on a level-triggered
+        // socket. Hang-ups and errors are still reported.
+        if self
+            .epoll
+            .modify(conn.stream.as_raw_fd(), token, 0)
+            .is_err()
+        {
+            self.close(token);
+            return false;
+        }
+
+        true
+    }
+
+    /// Reads the next chunk of a streamed body on the pool.
+    fn next_chunk(&mut self, token: u64) {
+        let Some(mut body) = self
+            .connections
+            .get_mut(&token)
+            .and_then(|conn| conn.body.take())
+        else {
+            return;
+        };
+
+        if !self.suspend(token) {
+            return;
+        }
+
+        let completions = Arc::clone(&self.completions);
+        self.pool.execute(move || {
+            let completion = match body.next_chunk() {
+                Ok(Some(chunk)) => Completion {
+                    token,
+                    bytes: Some(chunk),
+                    body: Some(body),
+                },
+                Ok(None) => {
+                    // Reports the response as sent before the loop can
+                    // close the connection.
+                    drop(body);
+                    Completion {
+                        token,
+                        bytes: Some(Vec::new()),
+                        body: None,
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error reading response body: {}", e);
+                    Completion {
+                        token,
+                        bytes: None,
+                        body: None,
+                    }
+                }
+            };
+            completions.push(completion);
+        });
+    }
+
+    fn complete(&mut self) {
+        for completion in self.completions.take() {
+            let token = completion.token;
+            let Some(conn) = self.connections.get_mut(&token) else {
+                // The client went away while the pool was working for it.
+                continue;
+            };
+
+            let Some(bytes) = completion.bytes else {
+                self.close(token);
+                continue;
+            };
+
+            conn.write_buf = bytes;
+            conn.written = 0;
+            conn.body = completion.body;
+            conn.state = State::Writing;
+            self.write(token);
+        }
+    }
+
+    fn write(&mut self, token: u64) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        conn.last_active = Instant::now();
+
+        let before = conn.written;
+        let flushed = conn.flush();
+        self.service.bytes_written((conn.written - before) as u64);
+
+        match flushed {
+            Ok(false) => {
+                let fd = conn.stream.as_raw_fd();
+                if self.epoll.modify(fd, token, libc::EPOLLOUT as u32).is_err() {
+                    self.close(token);
+                }
+            }
+            Ok(true) if conn.body.is_some() => self.next_chunk(token),
+            Ok(true) if conn.keep_alive => {
+                conn.write_buf = Vec::new();
+                conn.written = 0;
+                conn.state = State::Reading;
+
+                let fd = conn.stream.as_raw_fd();
+                let interest = (libc::EPOLLIN | libc::EPOLLRDHUP) as u32;
+                if self.epoll.modify(fd, token, interest).is_err() {
+                    self.close(token);
+                    return;
+                }
+
+                // A pipelined request may already be sitting in the buffer.
+                self.dispatch(token);
+            }
+            Ok(true) | Err(_) => self.close(token),
+        }
+    }
+
+    fn close(&mut self, token: u64) {
+        // Dropping the stream closes the fd, which also removes it from epoll.
+        self.connections.remove(&token);
+    }
+
+    fn close_idle(&mut self) {
+        let idle: Vec<u64> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| {
+                conn.state != State::Handling && conn.last_active.elapsed() > KEEP_ALIVE_TIMEOUT
+            })
+            .map(|(token, _)| *token)
+            .collect();
+
+        for token in idle {
+            self.close(token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{Shutdown, TcpStream};
+    use std::thread;
+
+    use crate::http::{Backend, Filter, Response, Server, ServerConfig, get, param};
+
+    fn read_response(reader: &mut BufReader<TcpStream>) -> String {
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            assert!(
+                reader.read_line(&mut line).unwrap() > 0,
+                "connection closed"
+            );
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn test_keep_alive_and_pipelining() {
+        let config = ServerConfig::new("127.0.0.1", 0)
+            .threads(2)
+            .backend(Backend::Epoll);
+        let server = Server::new(config).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let routes = get("/echo")
+            .and(param::<String>())
+            .map(|(value,)| Response::ok(value));
+        thread::spawn(move || server.run(routes));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /echo/one HTTP/1.1\r\nHost: x\r\n\r\nGET /echo/two HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        assert_eq!(read_response(&mut reader), "one");
+        assert_eq!(read_response(&mut reader), "two");
+
+        stream
+            .write_all(b"GET /echo/three HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_response(&mut reader), "three");
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_streamed_body() {
+        let config = ServerConfig::new("127.0.0.1", 0)
+            .threads(2)
+            .backend(Backend::Epoll);
+        let server = Server::new(config).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let data: String = (0..300_000)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        let expected = data.clone();
+        let routes = get("/big").map(move |_| {
+            let bytes = data.clone().into_bytes();
+            let length = bytes.len() as u64;
+            Response::stream(std::io::Cursor::new(bytes), Some(length))
+        });
+        thread::spawn(move || server.run(routes));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /big HTTP/1.1\r\n\r\nGET /big HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        let mut reader = BufReader::new(stream);
+        assert_eq!(read_response(&mut reader), expected);
+        assert_eq!(read_response(&mut reader), expected);
+    }
+
+    #[test]
+    fn test_half_closed_requests_are_answered() {
+        let config = ServerConfig::new("127.0.0.1", 0)
+            .threads(2)
+            .backend(Backend::Epoll);
+        let server = Server::new(config).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let routes = get("/echo")
+            .and(param::<String>())
+            .map(|(value,)| Response::ok(value));
+        thread::spawn(move || server.run(routes));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /echo/one HTTP/1.1\r\n\r\nGET /echo/two HTTP/1.1\r\n\r\n")
+            .unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let mut reader = BufReader::new(stream);
+        assert_eq!(read_response(&mut reader), "one");
+        assert_eq!(read_response(&mut reader), "two");
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_slow_request_is_not_idle() {
+        let config = ServerConfig::new("127.0.0.1", 0)
+            .threads(2)
+            .backend(Backend::Epoll);
+        let server = Server::new(config).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let routes = get("/echo")
+            .and(param::<String>())
+            .map(|(value,)| Response::ok(value));
+        thread::spawn(move || server.run(routes));
+
+        // Trickled in over twice the idle timeout, one byte at a time.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let request = b"GET /echo/slow HTTP/1.1\r\n\r\n";
+        let pause = super::KEEP_ALIVE_TIMEOUT * 2 / request.len() as u32;
+        for byte in request {
+            stream.write_all(&[*byte]).unwrap();
+            thread::sleep(pause);
+        }
+
+        let mut reader = BufReader::new(stream);
+        assert_eq!(read_response(&mut reader), "slow");
+    }
+}
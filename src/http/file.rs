@@ -0,0 +1,343 @@
+//! Serving individual files from disk.
+
+use std::fs::{self, File, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::date::http_date;
+use super::url::percent_decode;
+use super::{Encoding, Request, Response};
+use crate::mime;
+
+/// Encodings that may exist as precompressed siblings, most preferred first.
+const PRECOMPRESSED: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+/// Responds with the file at `path`, streaming it from disk.
+///
+/// If `app.js.br` or `app.js.gz` sit next to `app.js` and the client accepts
+/// that encoding, the sibling is sent instead with `Content-Encoding` set and
+/// the original's MIME type. Every variant carries its own `ETag`, and a
+/// matching `If-None-Match` yields `304 Not Modified`.
+///
+/// A single byte range of the uncompressed file may be asked for with
+/// `Range`, as media players do to seek, and is sent as `206 Partial
+/// Content`.
+pub fn serve_file(request: &Request, path: &Path) -> Response {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Response::not_found(),
+    };
+
+    let siblings: Vec<(Encoding, PathBuf, Metadata)> = PRECOMPRESSED
+        .iter()
+        .filter_map(|&encoding| {
+            let mut name = path.file_name()?.to_os_string();
+            name.push(".");
+            name.push(encoding.extension()?);
+            let sibling = path.with_file_name(name);
+            let metadata = fs::metadata(&sibling).ok().filter(Metadata::is_file)?;
+            Some((encoding, sibling, metadata))
+        })
+        .collect();
+
+    let available: Vec<Encoding> = siblings.iter().map(|(e, _, _)| *e).collect();
+    let chosen = Encoding::negotiate(request.header("accept-encoding"), &available);
+    let (file_path, metadata, encoding) = siblings
+        .into_iter()
+        .find(|(e, _, _)| *e == chosen)
+        .map(|(e, p, m)| (p, m, Some(e)))
+        .unwrap_or((path.to_path_buf(), metadata, None));
+
+    let etag = etag(&metadata, encoding);
+
+    let length = metadata.len();
+    let range = match encoding {
+        None => requested_range(request, &etag, length),
+        Some(_) => None,
+    };
+    let mut response = if is_not_modified(request, &etag) {
+        Response::new(304)
+    } else {
+        let Ok(mut file) = File::open(&file_path) else {
+            return Response::not_found();
+        };
+        match range {
+            None => Response::stream(file, Some(length)),
+            Some(Ok((first, last))) => {
+                if file.seek(SeekFrom::Start(first)).is_err() {
+                    return Response::internal_error();
+                }
+                let content_range = format!("bytes {}-{}/{}", first, last, length);
+                let part = last - first + 1;
+                Response::stream(file.take(part), Some(part))
+                    .status(206)
+                    .header("Content-Range", &content_range)
+            }
+            Some(Err(())) => {
+                Response::new(416).header("Content-Range", &format!("bytes */{}", length))
+            }
+        }
+    };
+
+    response = response
+        .header("Content-Type", mime::from_path(path))
+        .header("ETag", &etag);
+
+    if let Ok(modified) = metadata.modified() {
+        response = response.header("Last-Modified", &http_date(modified));
+    }
+
+    if !available.is_empty() {
+        response = response.header("Vary", "Accept-Encoding");
+    }
+
+    if let Some(encoding) = encoding {
+        response = response.header("Content-Encoding", encoding.name());
+    } else {
+        response = response.header("Accept-Ranges", "bytes");
+    }
+
+    response
+}
+
+/// The inclusive byte range a `Range` header asks for, or `Err` if it starts
+/// past the end of a file of `length` bytes. Several ranges, other units,
+/// malformed headers and an `If-Range` naming another version are ignored,
+/// which sends the whole file.
+fn requested_range(request: &Request, etag: &str, length: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = request.header("range")?.trim().strip_prefix("bytes=")?;
+    if request
+        .header("if-range")
+        .is_some_and(|tag| tag.trim() != etag)
+    {
+        return None;
+    }
+    if spec.contains(',') || length == 0 {
+        return None;
+    }
+
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = match (first.trim(), last.trim()) {
+        ("", suffix) => match suffix.parse::<u64>().ok()? {
+            0 => return Some(Err(())),
+            suffix => (length.saturating_sub(suffix), length - 1),
+        },
+        (first, "") => (first.parse().ok()?, length - 1),
+        (first, last) => (
+            first.parse().ok()?,
+            last.parse::<u64>().ok()?.min(length - 1),
+        ),
+    };
+    if first >= length {
+        return Some(Err(()));
+    }
+    (first <= last).then_some(Ok((first, last)))
+}
+
+/// Joins the `/`-separated URL path `url_path` below `root`, percent-decoding
+/// each segment. Returns `None` if a segment could step outside `root`: `..`,
+/// or an encoded slash, backslash or NUL byte.
+pub fn resolve_path(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in normalize_path(url_path)?
+        .split('/')
+        .filter(|s| !s.is_empty())
+    {
+        path.push(segment);
+    }
+    Some(path)
+}
+
+/// The decoded path below the root that [`resolve_path`] would open for
+/// `url_path`, as `/`-separated segments without empty or `.` ones, so
+/// access checks see the same path as the file system.
+pub fn normalize_path(url_path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in url_path.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode(segment);
+        if segment == "." {
+            continue;
+        }
+        if segment == ".." || segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        segments.push(segment);
+    }
+    Some(segments.join("/"))
+}
+
+fn etag(metadata: &Metadata, encoding: Option<Encoding>) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    match encoding {
+        Some(encoding) => format!(
+            "\"{:x}-{:x}-{}\"",
+            modified,
+            metadata.len(),
+            encoding.name()
+        ),
+        None => format!("\"{:x}-{:x}\"", modified, metadata.len()),
+    }
+}
+
+/// Checks `If-None-Match` against `etag`, also accepting the tags that
+/// on-the-fly compression derives from it.
+fn is_not_modified(request: &Request, etag: &str) -> bool {
+    let Some(header) = request.header("if-none-match") else {
+        return false;
+    };
+
+    let base = etag.trim_end_matches('"');
+    header.split(',').map(str::trim).any(|tag| {
+        let tag = tag.strip_prefix("W/").unwrap_or(tag);
+        tag == "*"
+            || tag == etag
+            || [Encoding::Gzip, Encoding::Deflate]
+                .iter()
+                .any(|e| tag == format!("{}-{}\"", base, e.name()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Body, Method};
+    use std::collections::HashMap;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Request::new(Method::Get, "/", headers, None)
+    }
+
+    fn body_len(response: &Response) -> Option<u64> {
+        match response.get_body() {
+            Some(Body::Stream { length, .. }) => *length,
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_precompressed_siblings() {
+        let dir = std::env::temp_dir().join(format!("rustserve-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.js");
+        fs::write(&path, "console.log('hello');").unwrap();
+        fs::write(dir.join("app.js.gz"), "gz").unwrap();
+        fs::write(dir.join("app.js.br"), "b").unwrap();
+
+        let plain = serve_file(&request(&[]), &path);
+        assert_eq!(plain.get_header("Content-Encoding"), None);
+        assert_eq!(plain.get_header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(body_len(&plain), Some(21));
+
+        let gzip = serve_file(&request(&[("Accept-Encoding", "gzip, deflate")]), &path);
+        assert_eq!(gzip.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            gzip.get_header("Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(body_len(&gzip), Some(2));
+
+        let brotli = serve_file(&request(&[("Accept-Encoding", "gzip, br")]), &path);
+        assert_eq!(brotli.get_header("Content-Encoding"), Some("br"));
+        assert_eq!(body_len(&brotli), Some(1));
+        assert_ne!(brotli.get_header("ETag"), gzip.get_header("ETag"));
+
+        let etag = gzip.get_header("ETag").unwrap();
+        let cached = serve_file(
+            &request(&[("Accept-Encoding", "gzip"), ("If-None-Match", etag)]),
+            &path,
+        );
+        assert_eq!(cached.status_code(), 304);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ranges() {
+        let dir = std::env::temp_dir().join(format!("rustserve-range-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("video.mp4");
+        fs::write(&path, "0123456789").unwrap();
+        let range = |headers: &[(&str, &str)]| {
+            let response = serve_file(&request(headers), &path);
+            let content_range = response.get_header("Content-Range").map(String::from);
+            (response.status_code(), content_range, body_len(&response))
+        };
+
+        let whole = serve_file(&request(&[]), &path);
+        assert_eq!(whole.get_header("Accept-Ranges"), Some("bytes"));
+        assert_eq!(
+            range(&[("Range", "bytes=2-4")]),
+            (206, Some("bytes 2-4/10".to_string()), Some(3))
+        );
+        assert_eq!(
+            range(&[("Range", "bytes=7-")]),
+            (206, Some("bytes 7-9/10".to_string()), Some(3))
+        );
+        assert_eq!(
+            range(&[("Range", "bytes=-4")]),
+            (206, Some("bytes 6-9/10".to_string()), Some(4))
+        );
+        assert_eq!(
+            range(&[("Range", "bytes=5-99")]),
+            (206, Some("bytes 5-9/10".to_string()), Some(5))
+        );
+        assert_eq!(
+            range(&[("Range", "bytes=10-")]),
+            (416, Some("bytes */10".to_string()), None)
+        );
+        for ignored in ["bytes=4-2", "bytes=0-1,4-5", "lines=1-2", "bytes=x-"] {
+            assert_eq!(
+                range(&[("Range", ignored)]),
+                (200, None, Some(10)),
+                "{}",
+                ignored
+            );
+        }
+        let stale = [("Range", "bytes=2-4"), ("If-Range", "\"old\"")];
+        assert_eq!(range(&stale).0, 200);
+
+        let mut body = Vec::new();
+        let part = serve_file(&request(&[("Range", "bytes=3-5")]), &path);
+        match part.get_body() {
+            Some(Body::Stream { reader, .. }) => reader.lock().unwrap().read_to_end(&mut body),
+            _ => panic!("no stream"),
+        }
+        .unwrap();
+        assert_eq!(body, b"345");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let root = Path::new("/srv");
+        assert_eq!(
+            resolve_path(root, "docs/my%20file.txt"),
+            Some(PathBuf::from("/srv/docs/my file.txt"))
+        );
+        assert_eq!(
+            resolve_path(root, "/a//./b/"),
+            Some(PathBuf::from("/srv/a/b"))
+        );
+        assert_eq!(resolve_path(root, ""), Some(PathBuf::from("/srv")));
+        assert_eq!(resolve_path(root, "../etc/passwd"), None);
+        assert_eq!(resolve_path(root, "a/%2e%2e/b"), None);
+        assert_eq!(resolve_path(root, "a%2F..%2F..%2Fb"), None);
+        assert_eq!(resolve_path(root, "a%5Cb"), None);
+
+        assert_eq!(normalize_path("/./a//%2e/b%20c/").as_deref(), Some("a/b c"));
+        assert_eq!(normalize_path("%2E").as_deref(), Some(""));
+        assert_eq!(normalize_path("a/%2e%2E"), None);
+        assert_eq!(normalize_path("a%00"), None);
+    }
+}
@@ -1,9 +1,22 @@
-use crate::http::{response::IntoResponse, Method, Request, Response};
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::http::signed::{LinkError, SignedLink};
+use crate::http::{
+    Method, Request, Response, percent_decode, resolve_path, response::IntoResponse, serve_file,
+};
+use crate::metrics::{self, Registry};
+use crate::mime;
 
 #[derive(Clone)]
 pub struct Context<'a> {
     request: &'a Request,
     path_index: usize,
+    /// Shared by every clone, so a rejection survives backtracking in `or`.
+    rejection: Rc<RefCell<Option<Response>>>,
 }
 
 impl<'a> Context<'a> {
@@ -11,20 +24,29 @@ impl<'a> Context<'a> {
         Context {
             request,
             path_index: 0,
+            rejection: Rc::default(),
         }
     }
 
+    /// Records the response to send if no route ends up matching, such as a
+    /// `401` from a route whose path matched but whose credentials did not.
+    /// The first rejection is kept.
+    pub fn reject(&self, response: Response) {
+        self.rejection.borrow_mut().get_or_insert(response);
+    }
+
+    pub(crate) fn take_rejection(&self) -> Option<Response> {
+        self.rejection.borrow_mut().take()
+    }
+
     pub fn is_path_matched(&self) -> bool {
         self.path_index == self.request.path_segments().len()
     }
 
     pub(crate) fn next_segment(&mut self) -> Option<&str> {
-        let res = self.request.path_segment(self.path_index);
-        if res.is_none() {
-            return None;
-        }
+        let res = self.request.path_segment(self.path_index)?;
         self.path_index += 1;
-        res
+        Some(res)
     }
 }
 
@@ -65,6 +87,31 @@ pub trait Filter: Sized + Send + Sync {
     fn or<B: Filter>(self, other: B) -> Or<Self, B> {
         Or { a: self, b: other }
     }
+
+    /// Names the route in server statistics, unless its response already
+    /// carries a label.
+    fn label(self, label: &str) -> Label<Self>
+    where
+        Self::Extract: IntoResponse,
+    {
+        Label {
+            filter: self,
+            label: Arc::from(label),
+        }
+    }
+
+    /// Like [`Filter::map`], but the function returns a future that resolves
+    /// to the response.
+    #[cfg(feature = "async")]
+    fn then<F>(self, func: F) -> crate::http::future::Then<Self, F> {
+        crate::http::future::Then { filter: self, func }
+    }
+
+    /// Wraps a synchronous route so it can be combined with async ones.
+    #[cfg(feature = "async")]
+    fn into_async(self) -> crate::http::future::IntoAsync<Self> {
+        crate::http::future::IntoAsync { filter: self }
+    }
 }
 
 pub struct And<A: Filter, B: Filter> {
@@ -82,6 +129,11 @@ pub struct Map<A: Filter, B, F: Fn(A::Extract) -> B> {
     func: F,
 }
 
+pub struct Label<A: Filter> {
+    filter: A,
+    label: Arc<str>,
+}
+
 pub struct Maybe<A: Filter, B: Filter> {
     filter: A,
     other: B,
@@ -114,7 +166,7 @@ impl<T: From<String>> PathParam<T> {
     pub fn new(match_slashes: bool) -> Self {
         PathParam {
             _marker: std::marker::PhantomData,
-            match_slashes: match_slashes,
+            match_slashes,
         }
     }
 }
@@ -218,6 +270,21 @@ impl<A: IntoResponse, B: IntoResponse> IntoResponse for Either<A, B> {
     }
 }
 
+impl<A: Filter> Filter for Label<A>
+where
+    A::Extract: IntoResponse,
+{
+    type Extract = Response;
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        let mut response = self.filter.filter(ctx)?.into_response();
+        if response.get_label().is_none() {
+            response.set_label(Arc::clone(&self.label));
+        }
+        Some(response)
+    }
+}
+
 impl<A: Filter, B: Filter> Filter for Or<A, B> {
     type Extract = Either<A::Extract, B::Extract>;
 
@@ -228,15 +295,29 @@ impl<A: Filter, B: Filter> Filter for Or<A, B> {
             Some(Either::A(a))
         } else {
             let mut b_ctx = ctx.clone();
-            let res = self.b.filter(&mut b_ctx).map(|b| {
+            self.b.filter(&mut b_ctx).map(|b| {
                 *ctx = b_ctx;
                 Either::B(b)
-            });
-            res
+            })
         }
     }
 }
 
+/// Tries each filter in turn and yields the first match, for routes built
+/// at runtime where chaining [`Filter::or`] is not possible.
+impl<F: Filter> Filter for Vec<F> {
+    type Extract = F::Extract;
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        self.iter().find_map(|filter| {
+            let mut sub_ctx = ctx.clone();
+            let extract = filter.filter(&mut sub_ctx)?;
+            *ctx = sub_ctx;
+            Some(extract)
+        })
+    }
+}
+
 impl<A, B, F> Filter for Map<A, B, F>
 where
     A: Filter,
@@ -311,15 +392,328 @@ pub fn header(name: &'static str) -> Header {
     Header { name }
 }
 
-pub fn get(path: &str) -> impl Filter<Extract = ()> {
+pub struct RequestFilter;
+
+impl Filter for RequestFilter {
+    type Extract = (Request,);
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        Some((ctx.request.clone(),))
+    }
+}
+
+/// Extracts a copy of the whole request, for handlers that need more than
+/// individual headers.
+pub fn request() -> RequestFilter {
+    RequestFilter
+}
+
+pub struct BasicAuth<V> {
+    challenge: String,
+    verifier: V,
+}
+
+impl<V: Fn(&str, &str) -> bool + Send + Sync> Filter for BasicAuth<V> {
+    type Extract = (String,);
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        match ctx.request.basic_auth() {
+            Some((user, password)) if (self.verifier)(&user, &password) => Some((user,)),
+            _ => {
+                ctx.reject(Response::unauthorized(&self.challenge));
+                None
+            }
+        }
+    }
+}
+
+/// Requires HTTP Basic credentials accepted by `verifier` and extracts the
+/// user name. Otherwise the request is answered with `401` and a
+/// `WWW-Authenticate` challenge for `realm`, unless another route matches.
+///
+/// Put it after the path filters of the routes it protects, so only requests
+/// for those paths are challenged. Compare secrets in `verifier` with
+/// [`constant_time_eq`](crate::crypto::constant_time_eq).
+pub fn basic_auth<V>(realm: &str, verifier: V) -> BasicAuth<V>
+where
+    V: Fn(&str, &str) -> bool + Send + Sync,
+{
+    BasicAuth {
+        challenge: format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            realm.replace(['"', '\\'], "")
+        ),
+        verifier,
+    }
+}
+
+pub struct Bearer<V> {
+    verifier: V,
+}
+
+impl<V: Fn(&str) -> bool + Send + Sync> Filter for Bearer<V> {
+    type Extract = ();
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        match ctx.request.bearer_token() {
+            Some(token) if (self.verifier)(token) => Some(()),
+            Some(_) => {
+                ctx.reject(Response::unauthorized("Bearer error=\"invalid_token\""));
+                None
+            }
+            None => {
+                ctx.reject(Response::unauthorized("Bearer"));
+                None
+            }
+        }
+    }
+}
+
+/// Requires an `Authorization: Bearer` token accepted by `verifier`,
+/// rejecting the request with `401` like [`basic_auth`] otherwise.
+pub fn bearer<V: Fn(&str) -> bool + Send + Sync>(verifier: V) -> Bearer<V> {
+    Bearer { verifier }
+}
+
+pub struct Guard<C> {
+    check: C,
+}
+
+impl<C: Fn(&Request) -> Result<(), Response> + Send + Sync> Filter for Guard<C> {
+    type Extract = ();
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        match (self.check)(ctx.request) {
+            Ok(()) => Some(()),
+            Err(response) => {
+                ctx.reject(response);
+                None
+            }
+        }
+    }
+}
+
+/// Lets the request through when `check` accepts it, rejecting it with the
+/// returned response otherwise, like [`basic_auth`]. Put it before filters
+/// that do real work, such as [`fs_dir`], so refused requests never reach
+/// them.
+pub fn guard<C: Fn(&Request) -> Result<(), Response> + Send + Sync>(check: C) -> Guard<C> {
+    Guard { check }
+}
+
+pub struct Signed<K> {
+    keys: K,
+}
+
+impl<K: Fn(&str) -> Option<Vec<u8>> + Send + Sync> Filter for Signed<K> {
+    type Extract = (SignedLink,);
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        let mut segments = Vec::new();
+        while let Some(segment) = ctx.next_segment() {
+            segments.push(percent_decode(segment));
+        }
+
+        match SignedLink::verify(&segments.join("/"), ctx.request, &self.keys) {
+            Ok(link) => Some((link,)),
+            Err(LinkError::Expired) => {
+                ctx.reject(Response::new(410).body("This link has expired"));
+                None
+            }
+            Err(_) => {
+                ctx.reject(Response::new(403).body("This link is not valid"));
+                None
+            }
+        }
+    }
+}
+
+/// Matches the rest of the path when the query carries a valid
+/// [`SignedLink`] for it, signed by the key `keys` returns for the link's key
+/// id, and extracts the link. Bad signatures are rejected with `403` and
+/// expired links with `410`.
+///
+/// Returning `None` for a key id revokes every link it signed.
+pub fn signed<K: Fn(&str) -> Option<Vec<u8>> + Send + Sync>(keys: K) -> Signed<K> {
+    Signed { keys }
+}
+
+pub struct Metrics {
+    registry: Registry,
+}
+
+impl Filter for Metrics {
+    type Extract = (Response,);
+
+    fn filter(&self, _ctx: &mut Context) -> Option<Self::Extract> {
+        let response =
+            Response::ok(self.registry.render()).header("Content-Type", metrics::CONTENT_TYPE);
+        Some((response,))
+    }
+}
+
+/// Answers with every metric in `registry` in the Prometheus text format,
+/// as in `get("/metrics").and(metrics(&registry))`.
+pub fn metrics(registry: &Registry) -> Metrics {
+    Metrics {
+        registry: registry.clone(),
+    }
+}
+
+/// Serves a directory tree as a static site; see [`fs_dir`].
+pub struct FsDir {
+    root: PathBuf,
+    index: String,
+    fallback: Option<PathBuf>,
+    not_found: Option<PathBuf>,
+    clean_urls: bool,
+}
+
+impl FsDir {
+    /// File served for directory paths, `index.html` by default.
+    pub fn index(mut self, name: impl Into<String>) -> Self {
+        self.index = name.into();
+        self
+    }
+
+    /// Serves this file, relative to the root, for every path that matches
+    /// nothing else, so a single-page app can do its own routing.
+    pub fn fallback(mut self, path: impl Into<PathBuf>) -> Self {
+        self.fallback = Some(path.into());
+        self
+    }
+
+    /// Sends this file, relative to the root, with `404 Not Found` for paths
+    /// that match nothing else.
+    pub fn not_found_page(mut self, path: impl Into<PathBuf>) -> Self {
+        self.not_found = Some(path.into());
+        self
+    }
+
+    /// Also tries `<path>.html`, so `/about` serves `about.html`.
+    pub fn clean_urls(mut self) -> Self {
+        self.clean_urls = true;
+        self
+    }
+
+    fn find(&self, request: &Request, segments: &[String]) -> Option<Response> {
+        let trailing_slash = segments.last().is_none_or(|s| s.is_empty());
+        let path = resolve_path(&self.root, &segments.join("/"))?;
+
+        if path.is_file() {
+            return Some(serve_file(request, &path));
+        }
+
+        if path.is_dir() {
+            let index = path.join(&self.index);
+            if !index.is_file() {
+                return None;
+            }
+            if !trailing_slash {
+                return Some(redirect_to_directory(request));
+            }
+            return Some(serve_file(request, &index));
+        }
+
+        if self.clean_urls && !trailing_slash {
+            let mut html = path.into_os_string();
+            html.push(".html");
+            let html = PathBuf::from(html);
+            if html.is_file() {
+                return Some(serve_file(request, &html));
+            }
+        }
+
+        None
+    }
+}
+
+impl Filter for FsDir {
+    type Extract = (Response,);
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        if !matches!(ctx.request.method(), Method::Get | Method::Head) {
+            return None;
+        }
+
+        let request = ctx.request;
+        let mut segments = Vec::new();
+        while let Some(segment) = ctx.next_segment() {
+            segments.push(segment.to_string());
+        }
+
+        if let Some(response) = self.find(request, &segments) {
+            return Some((response,));
+        }
+
+        if let Some(fallback) = &self.fallback {
+            let path = self.root.join(fallback);
+            if path.is_file() {
+                return Some((serve_file(request, &path),));
+            }
+        }
+
+        // Read rather than served, so a conditional request cannot turn the
+        // error page into a 304.
+        let page = self.not_found.as_ref().map(|page| self.root.join(page))?;
+        let response = match fs::read(&page) {
+            Ok(body) => Response::ok(body)
+                .status(404)
+                .header("Content-Type", mime::from_path(&page)),
+            Err(_) => Response::not_found(),
+        };
+        Some((response,))
+    }
+}
+
+/// Sends `/docs` to `/docs/` so relative links inside its index resolve.
+fn redirect_to_directory(request: &Request) -> Response {
+    let mut location = String::from("/");
+    for segment in request.path_segments().iter().filter(|s| !s.is_empty()) {
+        location.push_str(segment);
+        location.push('/');
+    }
+    if let Some(query) = request.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    Response::new(301).header("Location", &location)
+}
+
+/// Maps the rest of the path onto files below `root` for `GET` and `HEAD`
+/// requests. Directories serve their `index.html`, and paths that escape
+/// `root` are refused.
+///
+/// When nothing matches and neither [`FsDir::fallback`] nor
+/// [`FsDir::not_found_page`] is set, the filter does not match either, so
+/// other routes can be tried.
+pub fn fs_dir(root: impl Into<PathBuf>) -> FsDir {
+    FsDir {
+        root: root.into(),
+        index: "index.html".to_string(),
+        fallback: None,
+        not_found: None,
+        clean_urls: false,
+    }
+}
+
+pub fn get(path: &str) -> impl Filter<Extract = ()> + use<> {
     Method::Get.path(path)
 }
 
-pub fn post(path: &str) -> impl Filter<Extract = ()> {
+pub fn post(path: &str) -> impl Filter<Extract = ()> + use<> {
     Method::Post.path(path)
 }
 
-pub fn path(path: &str) -> impl Filter<Extract = ()> {
+pub fn put(path: &str) -> impl Filter<Extract = ()> + use<> {
+    Method::Put.path(path)
+}
+
+pub fn delete(path: &str) -> impl Filter<Extract = ()> + use<> {
+    Method::Delete.path(path)
+}
+
+pub fn path(path: &str) -> impl Filter<Extract = ()> + use<> {
     Path {
         path: path.to_string(),
     }
@@ -348,6 +742,7 @@ impl Filter for Method {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::http::Body;
     use std::collections::HashMap;
 
     fn mock_req(method: Method, path: &str) -> Request {
@@ -426,6 +821,121 @@ mod tests {
         assert!(filter.filter(&mut ctx).is_none());
     }
 
+    #[test]
+    fn test_basic_auth() {
+        use crate::http::request::RequestHandler;
+
+        let routes = path("private")
+            .and(basic_auth("files", |user, password| {
+                user == "alice" && password == "secret"
+            }))
+            .map(|(user,)| Response::ok(user))
+            .or(path("public").map(|_| Response::ok("public")));
+
+        let with_auth = |path: &str, credentials: &str| {
+            let mut headers = HashMap::new();
+            let encoded = crate::base64::encode(credentials.as_bytes());
+            headers.insert("Authorization".to_string(), format!("Basic {}", encoded));
+            routes.handle(&Request::new(Method::Get, path, headers, None))
+        };
+
+        let response = with_auth("/private", "alice:secret");
+        assert_eq!(response.status_code(), 200);
+        assert!(matches!(response.get_body(), Some(Body::Bytes(b)) if b == b"alice"));
+
+        let response = with_auth("/private", "alice:wrong");
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(
+            response.get_header("WWW-Authenticate"),
+            Some("Basic realm=\"files\", charset=\"UTF-8\"")
+        );
+
+        assert_eq!(with_auth("/public", "alice:wrong").status_code(), 200);
+        assert_eq!(with_auth("/other", "alice:wrong").status_code(), 404);
+    }
+
+    #[test]
+    fn test_bearer() {
+        use crate::http::request::RequestHandler;
+
+        let routes = path("api")
+            .and(bearer(|token| token == "t0ken"))
+            .map(|_| Response::no_content());
+        let call = |authorization: Option<&str>| {
+            let mut headers = HashMap::new();
+            if let Some(value) = authorization {
+                headers.insert("Authorization".to_string(), value.to_string());
+            }
+            routes.handle(&Request::new(Method::Get, "/api", headers, None))
+        };
+
+        assert_eq!(call(Some("Bearer t0ken")).status_code(), 204);
+        let response = call(Some("Bearer nope"));
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(
+            response.get_header("WWW-Authenticate"),
+            Some("Bearer error=\"invalid_token\"")
+        );
+        assert_eq!(call(None).get_header("WWW-Authenticate"), Some("Bearer"));
+    }
+
+    #[test]
+    fn test_guard() {
+        use crate::http::request::RequestHandler;
+
+        let routes = guard(|request| match request.path().starts_with("private") {
+            true => Err(Response::new(403)),
+            false => Ok(()),
+        })
+        .param_slashes::<String>()
+        .map(|(path,)| Response::ok(path));
+        let call = |path| routes.handle(&Request::new(Method::Get, path, HashMap::new(), None));
+
+        assert_eq!(call("/public/a.txt").status_code(), 200);
+        assert_eq!(call("/private/a.txt").status_code(), 403);
+    }
+
+    #[test]
+    fn test_signed() {
+        use crate::http::request::RequestHandler;
+        use crate::http::signed::unix_time;
+
+        let routes = path("share")
+            .and(signed(|id| (id == "k").then(|| b"key".to_vec())))
+            .map(|(link,): (SignedLink,)| Response::ok(link.path));
+        let call = |link: SignedLink| {
+            let url = format!("/share/{}", link.to_url(b"key"));
+            routes.handle(&mock_req(Method::Get, &url))
+        };
+
+        let response = call(SignedLink::new("a b/c.txt", "k", unix_time() + 60));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            call(SignedLink::new("c.txt", "k", unix_time() - 60)).status_code(),
+            410
+        );
+        assert_eq!(
+            call(SignedLink::new("c.txt", "old", unix_time() + 60)).status_code(),
+            403
+        );
+        let unsigned = routes.handle(&mock_req(Method::Get, "/share/c.txt"));
+        assert_eq!(unsigned.status_code(), 403);
+    }
+
+    #[test]
+    fn test_vec_filter() {
+        let filter: Vec<_> = ["a", "b"].iter().map(|p| path(p).map(|_| *p)).collect();
+
+        let req = mock_req(Method::Get, "/b");
+        let mut ctx = Context::new(&req);
+        assert_eq!(filter.filter(&mut ctx), Some("b"));
+        assert!(ctx.is_path_matched());
+
+        let req = mock_req(Method::Get, "/c");
+        let mut ctx = Context::new(&req);
+        assert_eq!(filter.filter(&mut ctx), None);
+    }
+
     #[test]
     fn test_map_filter() {
         let filter = path("val").and(param::<String>()).map(|(s,)| s.len());
@@ -434,6 +944,27 @@ mod tests {
         assert_eq!(filter.filter(&mut ctx), Some(5));
     }
 
+    #[test]
+    fn test_label_filter() {
+        use crate::http::request::RequestHandler;
+
+        let routes = get("a")
+            .map(|_| Response::ok("a"))
+            .label("a")
+            .or(get("b")
+                .map(|_| Response::ok("b").label("inner"))
+                .label("b"))
+            .or(get("c").map(|_| Response::ok("c")));
+        let label = |path| {
+            let response = routes.handle(&mock_req(Method::Get, path));
+            response.get_label().map(String::from)
+        };
+
+        assert_eq!(label("/a").as_deref(), Some("a"));
+        assert_eq!(label("/b").as_deref(), Some("inner"));
+        assert_eq!(label("/c"), None);
+    }
+
     #[test]
     fn test_maybe_filter() {
         let filter = path("test").maybe(param::<String>());
@@ -484,4 +1015,55 @@ mod tests {
         assert!(matches!(res, Some(Either::A(_))));
         assert!(ctx.is_path_matched());
     }
+
+    fn fs_get(filter: &impl Filter<Extract = (Response,)>, target: &str) -> Option<Response> {
+        let head = format!("GET {} HTTP/1.1\r\n\r\n", target);
+        let (req, _) = Request::parse_bytes(head.as_bytes(), None)
+            .unwrap()
+            .unwrap();
+        filter
+            .filter(&mut Context::new(&req))
+            .map(|(response,)| response)
+    }
+
+    #[test]
+    fn test_fs_dir() {
+        let root = std::env::temp_dir().join(format!("rustserve-site-{}", std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("about.html"), "about").unwrap();
+        fs::write(root.join("docs/index.html"), "docs").unwrap();
+        fs::write(root.join("404.html"), "missing").unwrap();
+
+        let site = fs_dir(&root);
+        let status = |r: Option<Response>| r.map(|r| r.status_code());
+        assert_eq!(status(fs_get(&site, "/")), Some(200));
+        assert_eq!(status(fs_get(&site, "/docs/")), Some(200));
+        let redirect = fs_get(&site, "/docs?v=1").unwrap();
+        assert_eq!(redirect.status_code(), 301);
+        assert_eq!(redirect.get_header("Location"), Some("/docs/?v=1"));
+        assert_eq!(status(fs_get(&site, "/about.html?v=2")), Some(200));
+        assert_eq!(status(fs_get(&site, "/about")), None);
+        assert_eq!(status(fs_get(&site, "/../etc/passwd")), None);
+
+        let site = fs_dir(&root).clean_urls().not_found_page("404.html");
+        assert_eq!(status(fs_get(&site, "/about")), Some(200));
+        let missing = fs_get(&site, "/nope").unwrap();
+        assert_eq!(missing.status_code(), 404);
+        assert!(matches!(missing.get_body(), Some(Body::Bytes(b)) if b == b"missing"));
+
+        let spa = fs_dir(&root).fallback("index.html");
+        assert_eq!(status(fs_get(&spa, "/users/42")), Some(200));
+
+        let mounted = path("/static").and(fs_dir(&root));
+        assert_eq!(status(fs_get(&mounted, "/static/docs/")), Some(200));
+        assert_eq!(
+            fs_get(&mounted, "/static/docs")
+                .unwrap()
+                .get_header("Location"),
+            Some("/static/docs/")
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
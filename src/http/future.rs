@@ -0,0 +1,97 @@
+//! Async counterparts of the filter combinators.
+//!
+//! Routing stays synchronous: filters still match paths, methods and headers
+//! exactly as before. The difference is that a route ends in [`Filter::then`]
+//! instead of [`Filter::map`], producing a [`ResponseFuture`] that the
+//! [`AsyncServer`](super::AsyncServer) awaits.
+
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+
+use crate::http::filter::{Context, Either};
+use crate::http::response::IntoResponse;
+use crate::http::{Filter, Request, Response};
+
+pub type ResponseFuture = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+pub struct Then<A: Filter, F> {
+    pub(crate) filter: A,
+    pub(crate) func: F,
+}
+
+impl<A, F, Fut> Filter for Then<A, F>
+where
+    A: Filter,
+    F: Fn(A::Extract) -> Fut + Send + Sync,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    type Extract = ResponseFuture;
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        let a = self.filter.filter(ctx)?;
+        let fut = (self.func)(a);
+        Some(Box::pin(async move { fut.await.into_response() }))
+    }
+}
+
+/// Adapts a synchronous route so it can be combined with async ones.
+///
+/// The wrapped filter (including its `map` closures) still runs inline on
+/// the runtime thread, so long blocking work belongs in
+/// `tokio::task::spawn_blocking` instead.
+pub struct IntoAsync<A: Filter> {
+    pub(crate) filter: A,
+}
+
+impl<A: Filter> Filter for IntoAsync<A>
+where
+    A::Extract: IntoResponse,
+{
+    type Extract = ResponseFuture;
+
+    fn filter(&self, ctx: &mut Context) -> Option<Self::Extract> {
+        let response = self.filter.filter(ctx)?.into_response();
+        Some(Box::pin(future::ready(response)))
+    }
+}
+
+impl<A, B> Future for Either<A, B>
+where
+    A: Future + Unpin,
+    B: Future + Unpin,
+{
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        match self.get_mut() {
+            Either::A(a) => Pin::new(a).poll(cx).map(Either::A),
+            Either::B(b) => Pin::new(b).poll(cx).map(Either::B),
+        }
+    }
+}
+
+pub trait AsyncRequestHandler: Send + Sync {
+    fn handle(&self, req: &Request) -> ResponseFuture;
+}
+
+impl<A: Filter> AsyncRequestHandler for A
+where
+    A::Extract: Future + Send + 'static,
+    <A::Extract as Future>::Output: IntoResponse,
+{
+    fn handle(&self, req: &Request) -> ResponseFuture {
+        let mut ctx = Context::new(req);
+        let res = self.filter(&mut ctx);
+
+        match res {
+            Some(fut) if ctx.is_path_matched() => {
+                Box::pin(async move { fut.await.into_response() })
+            }
+            _ => Box::pin(future::ready(
+                ctx.take_rejection().unwrap_or_else(Response::not_found),
+            )),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Access logging: one record per request, written by a [`Logger`].
+//! Requests too malformed to parse are answered and counted in the
+//! statistics, but not logged.
+
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::date::DateTime;
+use super::{Method, Request, Response};
+
+/// What happened to one request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    /// When the request arrived.
+    pub time: SystemTime,
+    pub remote_addr: Option<SocketAddr>,
+    pub method: Method,
+    /// The path and query as the client sent them.
+    pub target: String,
+    pub version: String,
+    pub status: u16,
+    /// Body bytes sent, or known to be in the response.
+    pub bytes: Option<u64>,
+    /// How long it took to produce and send the response.
+    pub duration: Duration,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    /// The user the request was authenticated as, see
+    /// [`Request::set_user`].
+    pub user: Option<String>,
+}
+
+impl LogRecord {
+    pub(crate) fn new(request: &Request, response: &Response, started: Instant) -> Self {
+        let duration = started.elapsed();
+        let mut target = request.path();
+        if !target.starts_with('/') {
+            target.insert(0, '/');
+        }
+        if let Some(query) = request.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+        LogRecord {
+            time: SystemTime::now() - duration,
+            remote_addr: request.remote_addr(),
+            method: request.method().clone(),
+            target,
+            version: request.version().to_string(),
+            status: response.status_code(),
+            bytes: response.body_length(),
+            duration,
+            user_agent: request.header("user-agent").map(String::from),
+            referer: request.header("referer").map(String::from),
+            user: request.user().map(String::from),
+        }
+    }
+}
+
+/// Receives a record for every request the server answers.
+///
+/// Closures taking a `&LogRecord` are loggers too.
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &LogRecord);
+}
+
+impl<F: Fn(&LogRecord) + Send + Sync> Logger for F {
+    fn log(&self, record: &LogRecord) {
+        self(record)
+    }
+}
+
+/// How records are written out, one per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// `host ident user [time] "request" status bytes`
+    Common,
+    /// Common Log Format followed by the referer and user agent.
+    #[default]
+    Combined,
+    /// An object per line, including the duration in milliseconds.
+    Json,
+}
+
+impl LogFormat {
+    pub fn format(self, record: &LogRecord) -> String {
+        let time = DateTime::from_system_time(record.time);
+        let host = record
+            .remote_addr
+            .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+
+        if self == LogFormat::Json {
+            let mut line = format!(
+                r#"{{"time":"{}","remote_addr":"{}","method":"{}","path":"{}","version":"{}","status":{},"bytes":{},"duration_ms":{:.3}"#,
+                time.to_rfc3339(),
+                host,
+                record.method,
+                json_escape(&record.target),
+                json_escape(&record.version),
+                record.status,
+                record.bytes.map_or("null".to_string(), |b| b.to_string()),
+                record.duration.as_secs_f64() * 1000.0,
+            );
+            for (key, value) in [
+                ("user_agent", &record.user_agent),
+                ("referer", &record.referer),
+                ("user", &record.user),
+            ] {
+                match value {
+                    Some(value) => write!(line, r#","{}":"{}""#, key, json_escape(value)),
+                    None => write!(line, r#","{}":null"#, key),
+                }
+                .unwrap();
+            }
+            line.push('}');
+            return line;
+        }
+
+        let mut line = format!(
+            "{} - {} [{}] \"{} {} {}\" {} {}",
+            host,
+            record.user.as_deref().map_or("-".to_string(), clf_escape),
+            time.to_clf(),
+            record.method,
+            clf_escape(&record.target),
+            clf_escape(&record.version),
+            record.status,
+            record.bytes.map_or("-".to_string(), |b| b.to_string()),
+        );
+        if self == LogFormat::Combined {
+            for value in [&record.referer, &record.user_agent] {
+                let value = value.as_deref().map_or("-".to_string(), clf_escape);
+                write!(line, " \"{}\"", value).unwrap();
+            }
+        }
+        line
+    }
+}
+
+/// Escapes quotes, backslashes and control characters the way Apache does.
+fn clf_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => write!(out, "\\x{:02x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub(crate) fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Writes records to standard output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutLogger {
+    format: LogFormat,
+}
+
+impl StdoutLogger {
+    pub fn new(format: LogFormat) -> Self {
+        StdoutLogger { format }
+    }
+}
+
+impl Logger for StdoutLogger {
+    fn log(&self, record: &LogRecord) {
+        let line = self.format.format(record);
+        let _ = writeln!(io::stdout().lock(), "{}", line);
+    }
+}
+
+/// Appends records to a file, optionally rotating it once it grows too
+/// large or too old: `access.log` becomes `access.log.1`, which becomes
+/// `access.log.2`, and so on up to the number of files kept.
+#[derive(Debug)]
+pub struct FileLogger {
+    path: PathBuf,
+    format: LogFormat,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+    file: Mutex<OpenLog>,
+}
+
+#[derive(Debug)]
+struct OpenLog {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl FileLogger {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl Into<PathBuf>, format: LogFormat) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenLog::open(&path)?;
+        Ok(FileLogger {
+            path,
+            format,
+            max_size: None,
+            max_age: None,
+            keep: 5,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Rotates the file before it would grow past `bytes`.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotates the file once it has been written to for `age`.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// How many rotated files to keep besides the current one (default 5).
+    pub fn keep(mut self, count: usize) -> Self {
+        self.keep = count;
+        self
+    }
+
+    fn rotate(&self, log: &mut OpenLog) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        *log = OpenLog::open(&self.path)?;
+        Ok(())
+    }
+
+    fn write(&self, line: &str) -> io::Result<()> {
+        let mut log = self.file.lock().unwrap();
+        let len = line.len() as u64 + 1;
+        let too_large = self
+            .max_size
+            .is_some_and(|max| log.size > 0 && log.size + len > max);
+        let too_old = self.max_age.is_some_and(|age| log.opened.elapsed() >= age);
+        if too_large || too_old {
+            self.rotate(&mut log)?;
+        }
+        writeln!(log.file, "{}", line)?;
+        log.size += len;
+        Ok(())
+    }
+}
+
+impl OpenLog {
+    fn open(path: &PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(OpenLog {
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+}
+
+impl Logger for FileLogger {
+    fn log(&self, record: &LogRecord) {
+        if let Err(e) = self.write(&self.format.format(record)) {
+            eprintln!("Error writing {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn record() -> LogRecord {
+        let mut headers = HashMap::new();
+        headers.insert("User-Agent".to_string(), "curl/8.0 \"x\"".to_string());
+        let request = Request::new(Method::Get, "/a b?x=1", headers, None)
+            .with_remote_addr("10.0.0.7:5000".parse().unwrap());
+        request.set_user("alice");
+        let mut record = LogRecord::new(&request, &Response::ok("hello"), Instant::now());
+        record.time = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        record.duration = Duration::from_micros(1500);
+        record
+    }
+
+    #[test]
+    fn test_formats() {
+        let record = record();
+        assert_eq!(
+            LogFormat::Common.format(&record),
+            r#"10.0.0.7 - alice [06/Nov/1994:08:49:37 +0000] "GET /a b?x=1 HTTP/1.1" 200 5"#
+        );
+        assert_eq!(
+            LogFormat::Combined.format(&record),
+            r#"10.0.0.7 - alice [06/Nov/1994:08:49:37 +0000] "GET /a b?x=1 HTTP/1.1" 200 5 "-" "curl/8.0 \"x\"""#
+        );
+        assert_eq!(
+            LogFormat::Json.format(&record),
+            r#"{"time":"1994-11-06T08:49:37Z","remote_addr":"10.0.0.7","method":"GET","path":"/a b?x=1","version":"HTTP/1.1","status":200,"bytes":5,"duration_ms":1.500,"user_agent":"curl/8.0 \"x\"","referer":null,"user":"alice"}"#
+        );
+    }
+
+    #[test]
+    fn test_server_logs_requests() {
+        use crate::http::{Filter, Server, ServerConfig, get, request};
+        use std::io::Read;
+        use std::net::TcpStream;
+        use std::sync::mpsc;
+
+        let (sender, records) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let server = Server::new(ServerConfig::new("127.0.0.1", 0))
+            .unwrap()
+            .logger(move |record: &LogRecord| {
+                sender.lock().unwrap().send(record.clone()).unwrap();
+            });
+        let port = server.local_addr().unwrap().port();
+        let routes = get("/hello")
+            .map(|_| Response::ok("hi"))
+            .or(get("/me").and(request()).map(|(request,)| {
+                request.set_user("alice");
+                Response::ok("alice")
+            }));
+        std::thread::spawn(move || server.run(routes));
+
+        // Credentials nothing checked are not taken at their word.
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /hello?x=1 HTTP/1.1\r\nAuthorization: Basic YWRtaW46eA==\r\n\r\n")
+            .unwrap();
+        stream.read_to_end(&mut Vec::new()).unwrap();
+
+        let record = records.recv().unwrap();
+        assert_eq!(record.target, "/hello?x=1");
+        assert_eq!((record.status, record.bytes), (200, Some(2)));
+        assert_eq!(record.remote_addr.unwrap().ip().to_string(), "127.0.0.1");
+        assert_eq!(record.user, None);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(b"GET /me HTTP/1.1\r\n\r\n").unwrap();
+        stream.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(records.recv().unwrap().user.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_file_rotation() {
+        let dir = std::env::temp_dir().join(format!("rustserve-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let line_len = LogFormat::Common.format(&record()).len() as u64 + 1;
+        let logger = FileLogger::open(&path, LogFormat::Common)
+            .unwrap()
+            .max_size(line_len * 2)
+            .keep(2);
+
+        for _ in 0..7 {
+            logger.log(&record());
+        }
+        let lines = |n: &str| {
+            let name = format!("access.log{}", n);
+            fs::read_to_string(dir.join(name)).map_or(0, |s| s.lines().count())
+        };
+        assert_eq!((lines(""), lines(".1"), lines(".2")), (1, 2, 2));
+        assert!(!dir.join("access.log.3").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
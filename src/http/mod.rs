@@ -1,11 +1,40 @@
+#[cfg(feature = "async")]
+mod async_server;
+pub(crate) mod date;
+mod encoding;
+#[cfg(all(feature = "epoll", target_os = "linux"))]
+mod event_loop;
+mod file;
 mod filter;
+#[cfg(feature = "async")]
+mod future;
+mod logger;
 mod method;
 mod request;
 mod response;
 mod server;
+mod signed;
+#[cfg(feature = "tls")]
+mod tls;
+mod url;
 
-pub use filter::{Filter, end, get, header, param, path, post};
+#[cfg(feature = "async")]
+pub use async_server::AsyncServer;
+pub use encoding::{Compression, Encoding};
+pub use file::{normalize_path, resolve_path, serve_file};
+pub use filter::{
+    Filter, basic_auth, bearer, delete, end, fs_dir, get, guard, header, metrics, param, path,
+    post, put, request, signed,
+};
+#[cfg(feature = "async")]
+pub use future::{AsyncRequestHandler, ResponseFuture};
+pub(crate) use logger::json_escape;
+pub use logger::{FileLogger, LogFormat, LogRecord, Logger, StdoutLogger};
 pub use method::Method;
 pub use request::Request;
-pub use response::Response;
-pub use server::{Server, ServerConfig};
+pub use response::{Body, IntoResponse, Response};
+pub use server::{Backend, Server, ServerConfig};
+pub use signed::{LinkError, SignedLink, decode_hex, encode_hex, unix_time};
+#[cfg(feature = "tls")]
+pub use tls::{Certificate, TlsConfig};
+pub use url::{percent_decode, percent_encode_path};
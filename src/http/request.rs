@@ -1,19 +1,30 @@
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Read};
-use std::net::TcpStream;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
 
+use crate::base64;
 use crate::http::filter::Context;
 use crate::http::response::IntoResponse;
 use crate::http::{Filter, Response};
 
 use super::Method;
+use super::url::decode_query_component;
+
+/// Upper bound on the size of a request line plus headers.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct Request {
     method: Method,
     path_segments: Vec<String>,
+    query: Option<String>,
+    version: String,
     headers: HashMap<String, String>,
     body: Option<Vec<u8>>,
+    remote_addr: Option<SocketAddr>,
+    /// Shared with clones, so a handler given a copy can still set it.
+    user: Arc<OnceLock<String>>,
 }
 
 impl Request {
@@ -23,6 +34,7 @@ impl Request {
         headers: HashMap<String, String>,
         body: Option<Vec<u8>>,
     ) -> Self {
+        let (path, query) = split_query(path);
         let path_segments = path
             .trim_matches('/')
             .split('/')
@@ -38,8 +50,12 @@ impl Request {
         Request {
             method,
             path_segments,
+            query,
+            version: "HTTP/1.1".to_string(),
             headers,
             body,
+            remote_addr: None,
+            user: Arc::default(),
         }
     }
 
@@ -59,6 +75,34 @@ impl Request {
         self.path_segments.get(index).map(|s| s.as_str())
     }
 
+    /// The raw query string, without the leading `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// The first value of query parameter `name`, percent-decoded.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query.as_deref()?.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_query_component(key) == name).then(|| decode_query_component(value))
+        })
+    }
+
+    /// Every value of query parameter `name`, percent-decoded, in order; a
+    /// form sends one per checked box.
+    pub fn query_params(&self, name: &str) -> Vec<String> {
+        let Some(query) = self.query.as_deref() else {
+            return Vec::new();
+        };
+        query
+            .split('&')
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode_query_component(key) == name).then(|| decode_query_component(value))
+            })
+            .collect()
+    }
+
     pub fn headers(&self) -> &HashMap<String, String> {
         &self.headers
     }
@@ -67,28 +111,141 @@ impl Request {
         self.headers.get(&name.to_lowercase()).map(|s| s.as_str())
     }
 
+    /// The user name and password from an `Authorization: Basic` header.
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let (scheme, credentials) = self.header("authorization")?.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(base64::decode(credentials.trim())?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+
+    /// The token from an `Authorization: Bearer` header.
+    pub fn bearer_token(&self) -> Option<&str> {
+        let (scheme, token) = self.header("authorization")?.split_once(' ')?;
+        scheme
+            .eq_ignore_ascii_case("bearer")
+            .then(|| token.trim())
+            .filter(|token| !token.is_empty())
+    }
+
+    /// The address of the connected client, when the server knows it.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Sets the client address, as servers do for each connection.
+    pub fn with_remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.remote_addr = Some(addr);
+        self
+    }
+
+    /// Records `user` as whom the request was authenticated as, once their
+    /// credentials have been checked, e.g. for the access log. Only the
+    /// first call has an effect.
+    pub fn set_user(&self, user: &str) {
+        let _ = self.user.set(user.to_string());
+    }
+
+    /// The user set with [`Request::set_user`], if any.
+    pub fn user(&self) -> Option<&str> {
+        self.user.get().map(String::as_str)
+    }
+
     pub fn body(&self) -> Option<&[u8]> {
         self.body.as_deref()
     }
 
-    pub(crate) fn parse(stream: &TcpStream) -> Result<Self, ParseError> {
-        let mut buf_reader = BufReader::new(stream);
-        let mut lines: Vec<String> = Vec::new();
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Whether the client wants the connection kept open after the response.
+    pub fn keep_alive(&self) -> bool {
+        if let Some(connection) = self.header("connection") {
+            for token in connection.split(',').map(str::trim) {
+                if token.eq_ignore_ascii_case("close") {
+                    return false;
+                }
+                if token.eq_ignore_ascii_case("keep-alive") {
+                    return true;
+                }
+            }
+        }
+
+        self.version != "HTTP/1.0"
+    }
+
+    /// Reads one request from a blocking stream. The caller sets the
+    /// client address.
+    pub(crate) fn parse(
+        mut stream: impl Read,
+        max_body_size: Option<usize>,
+    ) -> Result<Self, ParseError> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+
+        loop {
+            if let Some((request, _)) = Self::parse_bytes(&buf, max_body_size)? {
+                return Ok(request);
+            }
+
+            let n = stream.read(&mut chunk).map_err(|_| ParseError::IoError)?;
+            if n == 0 {
+                return Err(ParseError::MalformedRequest);
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Parses a request from the start of `buf` without blocking.
+    ///
+    /// Returns `Ok(None)` while the request is still incomplete, otherwise the
+    /// request together with the number of bytes it occupied in `buf`. A body
+    /// announced to be longer than `max_body_size` is refused up front.
+    pub(crate) fn parse_bytes(
+        buf: &[u8],
+        max_body_size: Option<usize>,
+    ) -> Result<Option<(Self, usize)>, ParseError> {
+        let Some(head_end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            if buf.len() > MAX_HEAD_SIZE {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            return Ok(None);
+        };
+
+        let head =
+            std::str::from_utf8(&buf[..head_end]).map_err(|_| ParseError::MalformedRequest)?;
+        let mut request = Self::from_head(head.split("\r\n"))?;
+        let body_start = head_end + 4;
 
-        for line in buf_reader.by_ref().lines() {
-            let line = line.map_err(|_| ParseError::IoError)?;
-            if line.is_empty() {
-                break;
+        match request.content_length()? {
+            Some(length) if max_body_size.is_some_and(|max| length > max) => {
+                Err(ParseError::BodyTooLarge)
             }
-            lines.push(line);
+            Some(length) => {
+                let body_end = body_start + length;
+                if buf.len() < body_end {
+                    return Ok(None);
+                }
+                request.body = Some(buf[body_start..body_end].to_vec());
+                Ok(Some((request, body_end)))
+            }
+            None => Ok(Some((request, body_start))),
         }
+    }
 
-        let first_line = lines.first().ok_or(ParseError::MalformedRequest)?;
+    fn from_head<'l>(mut lines: impl Iterator<Item = &'l str>) -> Result<Self, ParseError> {
+        let first_line = lines.next().ok_or(ParseError::MalformedRequest)?;
         let parts: Vec<&str> = first_line.split_whitespace().collect();
 
-        let method_str = *parts.get(0).ok_or(ParseError::MalformedRequest)?;
+        let method_str = *parts.first().ok_or(ParseError::MalformedRequest)?;
         let path = *parts.get(1).ok_or(ParseError::MalformedRequest)?;
+        let version = parts.get(2).unwrap_or(&"HTTP/1.0").to_string();
 
+        let (path, query) = split_query(path);
         let path_segments = path.split('/').map(|s| s.to_string()).collect();
 
         let method: Method = method_str
@@ -96,34 +253,37 @@ impl Request {
             .map_err(|_| ParseError::UnrecognizedMethod)?;
 
         let mut headers: HashMap<String, String> = HashMap::new();
-        for line in lines.iter().skip(1) {
+        for line in lines {
             if let Some((key, value)) = line.split_once(':') {
                 headers.insert(key.trim().to_lowercase(), value.trim().to_string());
             }
         }
 
-        let body = if let Some(content_length) = headers.get("content-length") {
-            let length: usize = content_length
-                .parse()
-                .map_err(|_| ParseError::InvalidContentLength)?;
-
-            let mut buffer = vec![0u8; length];
-            buf_reader
-                .read_exact(&mut buffer)
-                .map_err(|_| ParseError::IoError)?;
-
-            Some(buffer)
-        } else {
-            None
-        };
-
         Ok(Request {
             method,
             path_segments,
+            query,
+            version,
             headers,
-            body,
+            body: None,
+            remote_addr: None,
+            user: Arc::default(),
         })
     }
+
+    fn content_length(&self) -> Result<Option<usize>, ParseError> {
+        self.headers
+            .get("content-length")
+            .map(|value| value.parse().map_err(|_| ParseError::InvalidContentLength))
+            .transpose()
+    }
+}
+
+fn split_query(target: &str) -> (&str, Option<String>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    }
 }
 
 #[derive(Debug)]
@@ -132,6 +292,20 @@ pub enum ParseError {
     MalformedRequest,
     UnrecognizedMethod,
     InvalidContentLength,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl ParseError {
+    /// The status to answer with, or `None` if the client is already gone.
+    pub(crate) fn status_code(&self) -> Option<u16> {
+        match self {
+            ParseError::IoError => None,
+            ParseError::HeadersTooLarge => Some(431),
+            ParseError::BodyTooLarge => Some(413),
+            _ => Some(400),
+        }
+    }
 }
 
 impl std::fmt::Display for ParseError {
@@ -141,6 +315,8 @@ impl std::fmt::Display for ParseError {
             ParseError::MalformedRequest => write!(f, "malformed request"),
             ParseError::UnrecognizedMethod => write!(f, "unrecognized method"),
             ParseError::InvalidContentLength => write!(f, "invalid content-length"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
         }
     }
 }
@@ -159,11 +335,77 @@ where
         let mut ctx = Context::new(req);
         let res = self.filter(&mut ctx);
 
-        if !ctx.is_path_matched() {
-            return Response::not_found();
+        match res {
+            Some(res) if ctx.is_path_matched() => res.into_response(),
+            _ => ctx.take_rejection().unwrap_or_else(Response::not_found),
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bytes_incomplete() {
+        assert!(matches!(
+            Request::parse_bytes(b"GET /a HTTP/1.1\r\nHost: x\r\n", None),
+            Ok(None)
+        ));
+        assert!(matches!(
+            Request::parse_bytes(b"POST /a HTTP/1.1\r\nContent-Length: 4\r\n\r\nab", None),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn test_parse_bytes_with_body() {
+        let buf = b"POST /a HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcdGET";
+        let (request, consumed) = Request::parse_bytes(buf, None).unwrap().unwrap();
+        assert_eq!(request.method(), &Method::Post);
+        assert_eq!(request.body(), Some(&b"abcd"[..]));
+        assert_eq!(consumed, buf.len() - 3);
+
+        assert!(matches!(
+            Request::parse_bytes(buf, Some(3)),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_query() {
+        let (request, _) =
+            Request::parse_bytes(b"GET /find?q=a+b%26c&empty HTTP/1.1\r\n\r\n", None)
+                .unwrap()
+                .unwrap();
+        assert_eq!(request.path_segments(), &["", "find"]);
+        assert_eq!(request.query(), Some("q=a+b%26c&empty"));
+        assert_eq!(request.query_param("q"), Some("a b&c".to_string()));
+        assert_eq!(request.query_param("empty"), Some(String::new()));
+        assert_eq!(request.query_param("missing"), None);
+        assert!(request.query_params("missing").is_empty());
+        let (request, _) = Request::parse_bytes(b"GET /?f=a&g=b&f=c%20d HTTP/1.1\r\n\r\n", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.query_params("f"), ["a", "c d"]);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let (request, _) = Request::parse_bytes(b"GET / HTTP/1.1\r\n\r\n", None)
+            .unwrap()
+            .unwrap();
+        assert!(request.keep_alive());
+
+        let (request, _) = Request::parse_bytes(b"GET / HTTP/1.0\r\n\r\n", None)
+            .unwrap()
+            .unwrap();
+        assert!(!request.keep_alive());
 
-        res.map(|r| r.into_response())
-            .unwrap_or(Response::not_found())
+        let (request, _) =
+            Request::parse_bytes(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n", None)
+                .unwrap()
+                .unwrap();
+        assert!(!request.keep_alive());
     }
 }
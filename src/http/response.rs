@@ -1,6 +1,9 @@
 use std::collections::HashMap;
-use std::io::Write;
-use std::net::TcpStream;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Bytes of a streamed body read per chunk while writing it out.
+const STREAM_CHUNK: usize = 64 * 1024;
 
 pub trait IntoResponse {
     fn into_response(self) -> Response;
@@ -18,17 +21,66 @@ impl IntoResponse for &Response {
     }
 }
 
+impl<T: IntoResponse> IntoResponse for (T,) {
+    fn into_response(self) -> Response {
+        self.0.into_response()
+    }
+}
+
 impl<S: AsRef<str>> IntoResponse for (u16, S) {
     fn into_response(self) -> Response {
         Response::new(self.0).body(self.1.as_ref().as_bytes().to_vec())
     }
 }
 
+/// A response body, either fully in memory or read while it is being sent.
+#[derive(Clone)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Read in chunks as the response is written. Clones share the reader.
+    /// Without a known length the body is sent with chunked encoding.
+    Stream {
+        reader: Arc<Mutex<Box<dyn Read + Send>>>,
+        length: Option<u64>,
+    },
+}
+
+impl std::fmt::Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Stream { length, .. } => {
+                f.debug_struct("Stream").field("length", length).finish()
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Response {
     status_code: u16,
     headers: HashMap<String, String>,
-    body: Option<Vec<u8>>,
+    body: Option<Body>,
+    compress: bool,
+    label: Option<Arc<str>>,
+    on_sent: OnSent,
+}
+
+/// Told how many body bytes went out once a response has been written, or
+/// abandoned part way. Clones of a response do not carry it.
+#[derive(Default)]
+struct OnSent(Option<Box<dyn FnOnce(u64) + Send>>);
+
+impl Clone for OnSent {
+    fn clone(&self) -> Self {
+        OnSent::default()
+    }
+}
+
+impl std::fmt::Debug for OnSent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.0.is_some() { "OnSent" } else { "None" })
+    }
 }
 
 impl Response {
@@ -37,6 +89,9 @@ impl Response {
             status_code,
             headers: HashMap::new(),
             body: None,
+            compress: true,
+            label: None,
+            on_sent: OnSent::default(),
         }
     }
 
@@ -58,6 +113,16 @@ impl Response {
         Response::ok(file_content.to_vec()).header("Content-Type", "application/octet-stream")
     }
 
+    /// A `200 OK` whose body is read from `reader` while it is being sent.
+    pub fn stream(reader: impl Read + Send + 'static, length: Option<u64>) -> Self {
+        let mut response = Response::new(200);
+        response.body = Some(Body::Stream {
+            reader: Arc::new(Mutex::new(Box::new(reader))),
+            length,
+        });
+        response
+    }
+
     pub fn created() -> Self {
         Response::new(201)
     }
@@ -70,6 +135,11 @@ impl Response {
         Response::new(400)
     }
 
+    /// A `401` asking for credentials, e.g. `Basic realm="files"`.
+    pub fn unauthorized(challenge: &str) -> Self {
+        Response::new(401).header("WWW-Authenticate", challenge)
+    }
+
     pub fn not_found() -> Self {
         Response::new(404)
     }
@@ -78,21 +148,122 @@ impl Response {
         Response::new(500)
     }
 
+    /// Redirects to `location` with `302 Found`.
+    pub fn redirect(location: &str) -> Self {
+        Response::new(302).header("Location", location)
+    }
+
+    /// Replaces the status code, e.g. to send a custom page with a 404.
+    pub fn status(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+
     pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case(key));
         self.headers.insert(key.to_string(), value.to_string());
         self
     }
 
     pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
-        self.body = Some(body.into());
+        self.body = Some(Body::Bytes(body.into()));
+        self
+    }
+
+    /// Opts this response out of the server's response compression.
+    pub fn uncompressed(mut self) -> Self {
+        self.compress = false;
+        self
+    }
+
+    /// Names the route that produced this response in server statistics.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(Arc::from(label));
+        self
+    }
+
+    pub fn get_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub(crate) fn set_label(&mut self, label: Arc<str>) {
+        self.label = Some(label);
+    }
+
+    /// Calls `notify` with the number of body bytes written once the whole
+    /// response has been sent, or the connection gave up on it.
+    pub(crate) fn on_sent(mut self, notify: impl FnOnce(u64) + Send + 'static) -> Self {
+        self.on_sent = OnSent(Some(Box::new(notify)));
         self
     }
 
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Looks up a header by name, ignoring case.
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_body(&self) -> Option<&Body> {
+        self.body.as_ref()
+    }
+
+    /// Body bytes, if known before the body is sent.
+    pub(crate) fn body_length(&self) -> Option<u64> {
+        match &self.body {
+            None => Some(0),
+            Some(Body::Bytes(bytes)) => Some(bytes.len() as u64),
+            Some(Body::Stream { length, .. }) => *length,
+        }
+    }
+
+    pub(crate) fn is_compressible(&self) -> bool {
+        self.compress
+    }
+
+    pub(crate) fn remove_header(&mut self, key: &str) {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case(key));
+    }
+
+    pub(crate) fn set_body(&mut self, body: Option<Body>) {
+        self.body = body;
+    }
+
+    pub(crate) fn take_body(&mut self) -> Option<Body> {
+        self.body.take()
+    }
+
+    /// Drops the body for a `HEAD` request, keeping the `Content-Length` the
+    /// matching `GET` would have had.
+    pub(crate) fn strip_body(&mut self) {
+        let length = match self.body.take() {
+            Some(Body::Bytes(bytes)) => Some(bytes.len() as u64),
+            Some(Body::Stream { length, .. }) => length,
+            None => Some(0),
+        };
+        if let Some(length) = length
+            && self.get_header("Content-Length").is_none()
+        {
+            self.headers
+                .insert("Content-Length".to_string(), length.to_string());
+        }
+    }
+
     fn status_text(&self) -> &'static str {
         match self.status_code {
             200 => "OK",
             201 => "Created",
             204 => "No Content",
+            206 => "Partial Content",
             301 => "Moved Permanently",
             302 => "Found",
             304 => "Not Modified",
@@ -101,6 +272,12 @@ impl Response {
             403 => "Forbidden",
             404 => "Not Found",
             405 => "Method Not Allowed",
+            409 => "Conflict",
+            410 => "Gone",
+            413 => "Content Too Large",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            431 => "Request Header Fields Too Large",
             500 => "Internal Server Error",
             502 => "Bad Gateway",
             503 => "Service Unavailable",
@@ -108,32 +285,181 @@ impl Response {
         }
     }
 
-    pub(crate) fn write_to_stream(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+    /// Writes the response and returns the number of bytes sent.
+    pub(crate) fn write_to_stream<W: Write>(self, stream: &mut W) -> io::Result<u64> {
+        let (head, mut body) = self.into_wire();
+        stream.write_all(&head)?;
+        let mut written = head.len() as u64;
+
+        if let Some(body) = body.as_mut() {
+            while let Some(chunk) = body.next_chunk()? {
+                stream.write_all(&chunk)?;
+                written += chunk.len() as u64;
+            }
+        }
+
+        stream.flush()?;
+        // Only now is the response sent, as far as `on_sent` is concerned.
+        drop(body);
+        Ok(written)
+    }
+
+    /// Splits the response into the bytes that can be sent straight away
+    /// (status line, headers and any in-memory body) and the streamed rest.
+    /// A response watched with [`on_sent`](Self::on_sent) always has a
+    /// streamed rest, if only an empty one, to report when it is done.
+    pub(crate) fn into_wire(mut self) -> (Vec<u8>, Option<BodyStream>) {
+        let on_sent = self.on_sent.0.take();
+        let mut head = Vec::new();
         write!(
-            stream,
+            head,
             "HTTP/1.1 {} {}\r\n",
             self.status_code,
             self.status_text()
-        )?;
+        )
+        .unwrap();
 
         for (key, value) in &self.headers {
-            write!(stream, "{}: {}\r\n", key, value)?;
+            write!(head, "{}: {}\r\n", key, value).unwrap();
         }
 
-        if let Some(ref body) = self.body {
-            if !self.headers.contains_key("Content-Length") {
-                write!(stream, "Content-Length: {}\r\n", body.len())?;
+        let has_length = self.get_header("Content-Length").is_some();
+
+        match self.body {
+            Some(Body::Bytes(body)) => {
+                if !has_length {
+                    write!(head, "Content-Length: {}\r\n\r\n", body.len()).unwrap();
+                } else {
+                    head.extend_from_slice(b"\r\n");
+                }
+                head.extend_from_slice(&body);
+                (head, BodyStream::reporting(on_sent, body.len() as u64))
             }
-        } else {
-            write!(stream, "Content-Length: 0\r\n")?;
+            Some(Body::Stream { reader, length }) => {
+                match length {
+                    Some(length) if !has_length => {
+                        write!(head, "Content-Length: {}\r\n", length).unwrap()
+                    }
+                    Some(_) => {}
+                    None => head.extend_from_slice(b"Transfer-Encoding: chunked\r\n"),
+                }
+                head.extend_from_slice(b"\r\n");
+
+                let stream = BodyStream {
+                    reader,
+                    remaining: length,
+                    chunked: length.is_none(),
+                    finished: false,
+                    on_sent,
+                    sent: 0,
+                    handed_out: 0,
+                };
+                (head, Some(stream))
+            }
+            None => {
+                if !has_length {
+                    head.extend_from_slice(b"Content-Length: 0\r\n");
+                }
+                head.extend_from_slice(b"\r\n");
+                (head, BodyStream::reporting(on_sent, 0))
+            }
+        }
+    }
+}
+
+/// The streamed part of a response body, already framed for the wire.
+pub(crate) struct BodyStream {
+    reader: Arc<Mutex<Box<dyn Read + Send>>>,
+    remaining: Option<u64>,
+    chunked: bool,
+    finished: bool,
+    /// Called with `sent` when the stream is dropped.
+    on_sent: Option<Box<dyn FnOnce(u64) + Send>>,
+    sent: u64,
+    /// Body bytes in the last chunk, counted as sent once the next one is
+    /// asked for.
+    handed_out: u64,
+}
+
+impl BodyStream {
+    /// An already finished body that is only there to call `on_sent`, if
+    /// given, once the `length` bytes sent along with the head are out.
+    fn reporting(on_sent: Option<Box<dyn FnOnce(u64) + Send>>, length: u64) -> Option<Self> {
+        on_sent.as_ref()?;
+        Some(BodyStream {
+            reader: Arc::new(Mutex::new(Box::new(io::empty()))),
+            remaining: Some(0),
+            chunked: false,
+            finished: true,
+            on_sent,
+            sent: 0,
+            handed_out: length,
+        })
+    }
+
+    /// Reads the next piece of the body, or `None` once it is complete.
+    pub(crate) fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.sent += std::mem::take(&mut self.handed_out);
+        if self.finished {
+            return Ok(None);
         }
 
-        write!(stream, "\r\n")?;
+        let limit = match self.remaining {
+            Some(remaining) => (remaining as usize).min(STREAM_CHUNK),
+            None => STREAM_CHUNK,
+        };
 
-        if let Some(ref body) = self.body {
-            stream.write_all(body)?;
+        let mut data = vec![0u8; limit];
+        let mut filled = 0;
+        {
+            let mut reader = self.reader.lock().unwrap();
+            while filled < limit {
+                match reader.read(&mut data[filled..]) {
+                    Ok(0) => break,
+                    // A chunk of unknown-length body goes out as soon as
+                    // there is something to send, so event streams are not
+                    // held back until a full chunk has been produced.
+                    Ok(n) if self.chunked => {
+                        filled = n;
+                        break;
+                    }
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            }
         }
+        data.truncate(filled);
+        self.handed_out = filled as u64;
 
-        stream.flush()
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= filled as u64;
+        }
+
+        if filled == 0 || self.remaining == Some(0) {
+            self.finished = true;
+        }
+
+        if !self.chunked {
+            return Ok(if filled == 0 { None } else { Some(data) });
+        }
+
+        let mut chunk = Vec::with_capacity(filled + 16);
+        if filled > 0 {
+            write!(chunk, "{:X}\r\n", filled).unwrap();
+            chunk.extend_from_slice(&data);
+            chunk.extend_from_slice(b"\r\n");
+        } else {
+            chunk.extend_from_slice(b"0\r\n\r\n");
+        }
+        Ok(Some(chunk))
+    }
+}
+
+impl Drop for BodyStream {
+    fn drop(&mut self) {
+        if let Some(on_sent) = self.on_sent.take() {
+            on_sent(self.sent);
+        }
     }
 }
@@ -1,13 +1,44 @@
-use std::net::TcpListener;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use super::Request;
+use super::encoding::Compression;
+use super::logger::{LogRecord, Logger};
+use super::response::Body;
+#[cfg(feature = "tls")]
+use super::tls::TlsConfig;
+use super::{Method, Request, Response};
+use crate::metrics::{Counter, DEFAULT_BUCKETS, Gauge, Registry};
+use crate::stats::{self, RecentError, Stats};
 use crate::{http::request::RequestHandler, threads::ThreadPool};
 
+/// How the server waits on client connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Every connection occupies a pool thread from accept until the
+    /// response has been written.
+    #[default]
+    Threaded,
+    /// Connections are multiplexed over a single epoll loop; pool threads are
+    /// only used to run the handler once a full request has arrived.
+    #[cfg(all(feature = "epoll", target_os = "linux"))]
+    Epoll,
+}
+
 pub struct ServerConfig {
     pub address: String,
     pub port: u16,
     pub thread_count: usize,
+    pub backend: Backend,
+    pub compression: Option<Compression>,
+    /// Requests announcing a larger body are answered with `413`.
+    pub max_body_size: Option<usize>,
+    /// Serve HTTPS instead of plain HTTP.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+    /// A port answering plain HTTP with redirects to HTTPS.
+    #[cfg(feature = "tls")]
+    pub redirect_port: Option<u16>,
 }
 
 impl Default for ServerConfig {
@@ -16,6 +47,13 @@ impl Default for ServerConfig {
             address: "127.0.0.1".to_string(),
             port: 8080,
             thread_count: 4,
+            backend: Backend::default(),
+            compression: None,
+            max_body_size: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "tls")]
+            redirect_port: None,
         }
     }
 }
@@ -25,7 +63,7 @@ impl ServerConfig {
         ServerConfig {
             address: address.into(),
             port,
-            thread_count: 4,
+            ..ServerConfig::default()
         }
     }
 
@@ -33,11 +71,277 @@ impl ServerConfig {
         self.thread_count = count;
         self
     }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Compresses eligible responses for clients that accept it.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Serves HTTPS with `tls`. Connections are then handled as with
+    /// [`Backend::Threaded`], whichever backend is set.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Also listens for plain HTTP on `port`, redirecting every request to
+    /// HTTPS. Only used together with [`ServerConfig::tls`].
+    #[cfg(feature = "tls")]
+    pub fn redirect_http(mut self, port: u16) -> Self {
+        self.redirect_port = Some(port);
+        self
+    }
+}
+
+/// The request handler plus the server-wide processing applied around it.
+pub(crate) struct Service<H> {
+    handler: H,
+    compression: Option<Compression>,
+    pub(crate) max_body_size: Option<usize>,
+    logger: Option<Arc<dyn Logger>>,
+    stats: Option<Arc<Stats>>,
+    metrics: Option<Arc<ServerMetrics>>,
+}
+
+impl<H: RequestHandler> Service<H> {
+    /// Answers `request`. It is recorded in the stats, metrics and log once
+    /// the response has been written, so that the time and bytes taken by
+    /// a streamed body count too.
+    pub(crate) fn respond(&self, request: &Request) -> Response {
+        let started = Instant::now();
+        let mut response = self.handler.handle(request);
+
+        let label = response.get_label().map(str::to_string);
+        let status = response.status_code();
+        // Files are the streamed bodies of known length, unlike event streams;
+        // ranges are parts of a download.
+        let is_file = matches!(
+            response.get_body(),
+            Some(Body::Stream {
+                length: Some(_),
+                ..
+            })
+        ) && request.method() == &Method::Get
+            && status == 200;
+        let path = absolute_path(request);
+        let error = (status >= 400).then(|| RecentError {
+            time: SystemTime::now(),
+            remote_addr: request.remote_addr(),
+            method: request.method().to_string(),
+            path: path.clone(),
+            status,
+        });
+
+        if let Some(compression) = &self.compression {
+            response = compression.apply(request, response);
+        }
+        if request.method() == &Method::Head {
+            response.strip_body();
+        }
+        if self.stats.is_none() && self.metrics.is_none() && self.logger.is_none() {
+            return response;
+        }
+
+        let stats = self.stats.clone();
+        let metrics = self.metrics.clone();
+        let logged = self.logger.as_ref().map(|logger| {
+            (
+                Arc::clone(logger),
+                LogRecord::new(request, &response, started),
+            )
+        });
+        response.on_sent(move |bytes| {
+            let latency = started.elapsed();
+            let label = label.as_deref();
+            if let Some(stats) = &stats {
+                stats.request_completed(label, status, latency, Some(bytes));
+                if is_file {
+                    stats.file_downloaded(&path);
+                }
+                if let Some(error) = error {
+                    stats.error_returned(error);
+                }
+            }
+            if let Some(metrics) = &metrics {
+                metrics.request_completed(label, status, latency);
+                if is_file {
+                    metrics.downloads.inc();
+                }
+            }
+            if let Some((logger, mut record)) = logged {
+                record.duration = latency;
+                record.bytes = Some(bytes);
+                logger.log(&record);
+            }
+        })
+    }
+
+    /// Answers a request that could not be parsed with `status`, counted in
+    /// the statistics and metrics like any other error once it is sent.
+    /// It is not logged, having no request line to log.
+    pub(crate) fn reject(&self, status: u16, remote_addr: Option<SocketAddr>) -> Response {
+        let started = Instant::now();
+        let response = Response::new(status);
+        if self.stats.is_none() && self.metrics.is_none() {
+            return response;
+        }
+
+        let stats = self.stats.clone();
+        let metrics = self.metrics.clone();
+        let error = RecentError {
+            time: SystemTime::now(),
+            remote_addr,
+            method: "-".to_string(),
+            path: "-".to_string(),
+            status,
+        };
+        response.on_sent(move |bytes| {
+            let latency = started.elapsed();
+            if let Some(stats) = &stats {
+                stats.request_completed(None, status, latency, Some(bytes));
+                stats.error_returned(error);
+            }
+            if let Some(metrics) = &metrics {
+                metrics.request_completed(None, status, latency);
+            }
+        })
+    }
+
+    /// Counts a connection from `remote_addr` as active until the returned
+    /// guard is dropped.
+    pub(crate) fn open_connection(&self, remote_addr: Option<SocketAddr>) -> OpenConnection {
+        let stats = self.stats.as_ref().map(|stats| {
+            let id = stats.connection_opened(remote_addr);
+            (Arc::clone(stats), id)
+        });
+        let gauge = self.metrics.as_ref().map(|m| m.connections.clone());
+        if let Some(gauge) = &gauge {
+            gauge.inc();
+        }
+        OpenConnection { stats, gauge }
+    }
+
+    /// Counts response bytes that reached the socket.
+    pub(crate) fn bytes_written(&self, bytes: u64) {
+        if let Some(stats) = &self.stats {
+            stats.bytes_sent(bytes);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.bytes.add(bytes);
+        }
+    }
+}
+
+pub(crate) struct OpenConnection {
+    stats: Option<(Arc<Stats>, u64)>,
+    gauge: Option<Gauge>,
+}
+
+impl OpenConnection {
+    /// Notes the request the connection is now answering.
+    pub(crate) fn serving(&self, request: &Request) {
+        if let Some((stats, id)) = &self.stats {
+            stats.connection_serving(*id, &absolute_path(request));
+        }
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        if let Some((stats, id)) = &self.stats {
+            stats.connection_closed(*id);
+        }
+        if let Some(gauge) = &self.gauge {
+            gauge.dec();
+        }
+    }
+}
+
+/// The request path with its leading `/`, without the query.
+fn absolute_path(request: &Request) -> String {
+    let mut path = request.path();
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+    path
+}
+
+/// The built-in metrics recorded in a registry given to [`Server::metrics`].
+struct ServerMetrics {
+    registry: Registry,
+    connections: Gauge,
+    bytes: Counter,
+    downloads: Counter,
+}
+
+impl ServerMetrics {
+    fn new(registry: Registry) -> Self {
+        ServerMetrics {
+            connections: registry.gauge(
+                "rustserve_connections_active",
+                "Connections currently open.",
+                &[],
+            ),
+            bytes: registry.counter(
+                "rustserve_response_bytes_total",
+                "Response bytes written to sockets.",
+                &[],
+            ),
+            downloads: registry.counter(
+                "rustserve_files_downloaded_total",
+                "Files sent in full.",
+                &[],
+            ),
+            registry,
+        }
+    }
+
+    fn request_completed(&self, label: Option<&str>, status: u16, latency: Duration) {
+        let route = label.unwrap_or(stats::UNLABELLED);
+        let class = format!("{}xx", status / 100);
+        self.registry
+            .counter(
+                "rustserve_requests_total",
+                "Requests answered, by route and status class.",
+                &[("route", route), ("status", &class)],
+            )
+            .inc();
+        self.registry
+            .histogram(
+                "rustserve_request_duration_seconds",
+                "Time taken to produce responses, by route.",
+                &[("route", route)],
+                DEFAULT_BUCKETS,
+            )
+            .observe(latency.as_secs_f64());
+    }
 }
 
 pub struct Server {
     listener: TcpListener,
     pool: ThreadPool,
+    backend: Backend,
+    compression: Option<Compression>,
+    max_body_size: Option<usize>,
+    logger: Option<Arc<dyn Logger>>,
+    stats: Option<Arc<Stats>>,
+    metrics: Option<Registry>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+    #[cfg(feature = "tls")]
+    redirect_listener: Option<TcpListener>,
 }
 
 impl Server {
@@ -46,32 +350,133 @@ impl Server {
         let listener = TcpListener::bind(&addr)?;
         let pool = ThreadPool::new(config.thread_count);
 
-        Ok(Server { listener, pool })
+        #[cfg(feature = "tls")]
+        let (tls, redirect_listener) = match &config.tls {
+            Some(tls) => {
+                let redirect = config
+                    .redirect_port
+                    .map(|port| TcpListener::bind((config.address.as_str(), port)))
+                    .transpose()?;
+                (Some(tls.server_config()?), redirect)
+            }
+            None => (None, None),
+        };
+
+        Ok(Server {
+            listener,
+            pool,
+            backend: config.backend,
+            compression: config.compression,
+            max_body_size: config.max_body_size,
+            logger: None,
+            stats: None,
+            metrics: None,
+            #[cfg(feature = "tls")]
+            tls,
+            #[cfg(feature = "tls")]
+            redirect_listener,
+        })
+    }
+
+    /// Passes a record of every request answered to `logger`.
+    pub fn logger(mut self, logger: impl Logger + 'static) -> Self {
+        self.logger = Some(Arc::new(logger));
+        self
+    }
+
+    /// Records connections, requests, status classes, handler latency and
+    /// bytes written in `stats`, per route where responses are labelled
+    /// with [`Filter::label`](super::Filter::label).
+    pub fn stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Records the server's built-in metrics in `registry`: open
+    /// connections, requests by route and status class, response time
+    /// histograms by route, bytes written and files downloaded.
+    pub fn metrics(mut self, registry: Registry) -> Self {
+        self.metrics = Some(registry);
+        self
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
     }
 
     pub fn run(self, handler: impl RequestHandler + 'static) {
-        let handler = Arc::new(handler);
-
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(mut stream) => {
-                    let handler = Arc::clone(&handler);
-
-                    self.pool.execute(move || match Request::parse(&stream) {
-                        Ok(request) => {
-                            let response = handler.handle(&request);
-                            if let Err(e) = response.write_to_stream(&mut stream) {
-                                eprintln!("Error writing response: {}", e);
+        let service = Arc::new(Service {
+            handler,
+            compression: self.compression,
+            max_body_size: self.max_body_size,
+            logger: self.logger,
+            stats: self.stats,
+            metrics: self
+                .metrics
+                .map(|registry| Arc::new(ServerMetrics::new(registry))),
+        });
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.tls {
+            if let Some(redirect) = self.redirect_listener {
+                let port = self.listener.local_addr().map_or(443, |addr| addr.port());
+                super::tls::spawn_redirect(redirect, port);
+            }
+            super::tls::run(self.listener, self.pool, service, tls);
+            return;
+        }
+
+        match self.backend {
+            Backend::Threaded => run_threaded(self.listener, self.pool, service),
+            #[cfg(all(feature = "epoll", target_os = "linux"))]
+            Backend::Epoll => {
+                if let Err(e) = super::event_loop::run(self.listener, self.pool, service) {
+                    eprintln!("Event loop failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+fn run_threaded<H: RequestHandler + 'static>(
+    listener: TcpListener,
+    pool: ThreadPool,
+    service: Arc<Service<H>>,
+) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let service = Arc::clone(&service);
+
+                pool.execute(move || {
+                    let peer = stream.peer_addr().ok();
+                    let connection = service.open_connection(peer);
+                    let response = match Request::parse(&stream, service.max_body_size) {
+                        Ok(mut request) => {
+                            if let Some(peer) = peer {
+                                request = request.with_remote_addr(peer);
                             }
+                            connection.serving(&request);
+                            service.respond(&request)
                         }
-                        Err(e) => {
-                            eprintln!("Error parsing request: {}", e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    eprintln!("Error accepting connection: {}", e);
-                }
+                        Err(e) => match e.status_code() {
+                            Some(status) => service.reject(status, peer),
+                            None => {
+                                eprintln!("Error parsing request: {}", e);
+                                return;
+                            }
+                        },
+                    };
+
+                    let response = response.header("Connection", "close");
+                    match response.write_to_stream(&mut stream) {
+                        Ok(written) => service.bytes_written(written),
+                        Err(e) => eprintln!("Error writing response: {}", e),
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("Error accepting connection: {}", e);
             }
         }
     }
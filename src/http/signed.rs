@@ -0,0 +1,208 @@
+//! Links that grant access to one path until they expire. They carry their
+//! terms in the query string, signed with HMAC-SHA-256, so the server can
+//! check them without remembering each link.
+
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto::{constant_time_eq, hmac_sha256};
+use crate::http::Request;
+use crate::http::url::percent_encode_path;
+
+/// The terms of a signed link.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedLink {
+    /// The decoded path the link grants, relative to where it is served.
+    pub path: String,
+    /// Names the key that signed the link, so keys can be rotated.
+    pub key_id: String,
+    /// Unix time after which the link stops working.
+    pub expires: u64,
+    /// How many times the link may be used; counting is up to the server.
+    pub downloads: Option<u32>,
+    /// The only client address allowed to use the link.
+    pub ip: Option<IpAddr>,
+}
+
+/// Why a request's signed link was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkError {
+    /// The signature is missing, malformed or from an unknown key.
+    BadSignature,
+    Expired,
+    WrongAddress,
+}
+
+impl SignedLink {
+    /// A link to `path` signed by `key_id`, valid until `expires`.
+    pub fn new(path: &str, key_id: &str, expires: u64) -> Self {
+        SignedLink {
+            path: path.trim_matches('/').to_string(),
+            key_id: key_id.to_string(),
+            expires,
+            downloads: None,
+            ip: None,
+        }
+    }
+
+    pub fn downloads(mut self, count: u32) -> Self {
+        self.downloads = Some(count);
+        self
+    }
+
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+
+    /// The path and query to append to the URL the links are served under,
+    /// such as `report.pdf?expires=...&sig=...`.
+    pub fn to_url(&self, key: &[u8]) -> String {
+        let mut url = format!(
+            "{}?expires={}&key={}",
+            percent_encode_path(&self.path),
+            self.expires,
+            percent_encode_path(&self.key_id)
+        );
+        if let Some(downloads) = self.downloads {
+            url.push_str(&format!("&downloads={}", downloads));
+        }
+        if let Some(ip) = self.ip {
+            url.push_str(&format!("&ip={}", ip));
+        }
+        url.push_str("&sig=");
+        url.push_str(&encode_hex(&self.signature(key)));
+        url
+    }
+
+    /// Reads the link for `path` from a request's query and checks it with
+    /// the key `keys` returns for its id.
+    pub fn verify(
+        path: &str,
+        request: &Request,
+        keys: impl Fn(&str) -> Option<Vec<u8>>,
+    ) -> Result<SignedLink, LinkError> {
+        let number = |name| request.query_param(name).and_then(|v| v.parse().ok());
+        let link = SignedLink {
+            path: path.trim_matches('/').to_string(),
+            key_id: request.query_param("key").ok_or(LinkError::BadSignature)?,
+            expires: number("expires").ok_or(LinkError::BadSignature)?,
+            downloads: match request.query_param("downloads") {
+                Some(value) => Some(value.parse().map_err(|_| LinkError::BadSignature)?),
+                None => None,
+            },
+            ip: match request.query_param("ip") {
+                Some(value) => Some(value.parse().map_err(|_| LinkError::BadSignature)?),
+                None => None,
+            },
+        };
+
+        let signature = request.query_param("sig").ok_or(LinkError::BadSignature)?;
+        let key = keys(&link.key_id).ok_or(LinkError::BadSignature)?;
+        let expected = encode_hex(&link.signature(&key));
+        if !constant_time_eq(expected.as_bytes(), signature.as_bytes()) {
+            return Err(LinkError::BadSignature);
+        }
+
+        if link.expires <= unix_time() {
+            return Err(LinkError::Expired);
+        }
+        if let Some(ip) = link.ip
+            && request.remote_addr().map(|addr| addr.ip()) != Some(ip)
+        {
+            return Err(LinkError::WrongAddress);
+        }
+        Ok(link)
+    }
+
+    /// Covers every term, each on its own line so none can bleed into the
+    /// next.
+    fn signature(&self, key: &[u8]) -> [u8; 32] {
+        let message = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.path,
+            self.key_id,
+            self.expires,
+            self.downloads.map(|d| d.to_string()).unwrap_or_default(),
+            self.ip.map(|ip| ip.to_string()).unwrap_or_default()
+        );
+        hmac_sha256(key, message.as_bytes())
+    }
+}
+
+/// Seconds since the Unix epoch, as links count their expiry.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Lowercase hex, as signatures are written in links.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The bytes `text` spells in hex, or `None` if it is not hex.
+pub fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Method;
+    use std::collections::HashMap;
+
+    fn request(url: &str) -> Request {
+        Request::new(Method::Get, url, HashMap::new(), None)
+            .with_remote_addr("10.0.0.7:50000".parse().unwrap())
+    }
+
+    #[test]
+    fn test_signed_link() {
+        let keys = |id: &str| (id == "k1").then(|| b"secret".to_vec());
+        let expires = unix_time() + 60;
+        let link = SignedLink::new("/docs/a b.pdf", "k1", expires).downloads(3);
+        let url = link.to_url(b"secret");
+        assert!(url.starts_with(&format!(
+            "docs/a%20b.pdf?expires={}&key=k1&downloads=3&sig=",
+            expires
+        )));
+
+        let verified = SignedLink::verify("docs/a b.pdf", &request(&format!("/{}", url)), keys);
+        assert_eq!(verified, Ok(link.clone()));
+
+        // Any change to the terms breaks the signature.
+        let tampered = url.replace("downloads=3", "downloads=30");
+        assert_eq!(
+            SignedLink::verify("docs/a b.pdf", &request(&tampered), keys),
+            Err(LinkError::BadSignature)
+        );
+        assert_eq!(
+            SignedLink::verify("docs/other.pdf", &request(&url), keys),
+            Err(LinkError::BadSignature)
+        );
+        assert_eq!(
+            SignedLink::verify("docs/a b.pdf", &request(&url), |_| None),
+            Err(LinkError::BadSignature)
+        );
+
+        let expired = SignedLink::new("x", "k1", unix_time() - 1).to_url(b"secret");
+        assert_eq!(
+            SignedLink::verify("x", &request(&expired), keys),
+            Err(LinkError::Expired)
+        );
+
+        let pinned = SignedLink::new("x", "k1", expires).ip("10.0.0.8".parse().unwrap());
+        assert_eq!(
+            SignedLink::verify("x", &request(&pinned.to_url(b"secret")), keys),
+            Err(LinkError::WrongAddress)
+        );
+    }
+}
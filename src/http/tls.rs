@@ -0,0 +1,381 @@
+//! HTTPS termination with rustls, behind the `tls` feature.
+//!
+//! TLS connections are served like [`Backend::Threaded`](super::Backend)
+//! ones whichever backend is configured: each connection is handshaken,
+//! answered and closed on a pool thread.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConnection, StreamOwned};
+
+use super::request::RequestHandler;
+use super::server::Service;
+use super::{Request, Response};
+use crate::crypto::sha256;
+use crate::threads::ThreadPool;
+
+/// How long the HTTP→HTTPS redirector waits for a request line.
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Threads answering redirects; requests beyond them wait their turn.
+const REDIRECT_THREADS: usize = 4;
+/// How long a TLS connection may block a pool thread on a single read or
+/// write, handshake included; short in tests so that they can outlast it.
+const IO_TIMEOUT: Duration = match cfg!(test) {
+    true => Duration::from_secs(2),
+    false => Duration::from_secs(30),
+};
+
+/// A certificate chain and the private key for its first certificate.
+#[derive(Clone)]
+pub struct Certificate {
+    key: Arc<CertifiedKey>,
+}
+
+impl fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Certificate")
+            .field("chain_length", &self.key.cert.len())
+            .finish()
+    }
+}
+
+impl Certificate {
+    /// Reads a PEM certificate chain, leaf first, and a PEM private key
+    /// (PKCS#8, PKCS#1 or SEC1).
+    pub fn from_pem(chain: &[u8], key: &[u8]) -> io::Result<Self> {
+        let chain = CertificateDer::pem_slice_iter(chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(format!("bad certificate PEM: {}", e)))?;
+        if chain.is_empty() {
+            return Err(invalid("no certificate in PEM".to_string()));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| invalid(format!("bad private key PEM: {}", e)))?;
+        Self::from_der(chain, key)
+    }
+
+    pub fn from_pem_files(chain: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Self> {
+        let read = |path: &Path| {
+            fs::read(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        };
+        Self::from_pem(&read(chain.as_ref())?, &read(key.as_ref())?)
+    }
+
+    /// Generates a throwaway certificate for `names`, which may be DNS names
+    /// or IP addresses. Clients will warn that nobody vouches for it.
+    pub fn self_signed(names: &[String]) -> io::Result<Self> {
+        let generated = rcgen::generate_simple_self_signed(names.to_vec())
+            .map_err(|e| io::Error::other(format!("cannot generate certificate: {}", e)))?;
+        let key = PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der());
+        Self::from_der(vec![generated.cert.der().clone()], key.into())
+    }
+
+    /// The SHA-256 of the leaf certificate, for checking it by hand.
+    pub fn fingerprint(&self) -> [u8; 32] {
+        sha256(self.key.cert[0].as_ref())
+    }
+
+    fn from_der(
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> io::Result<Self> {
+        let key = CertifiedKey::from_der(chain, key, &provider())
+            .map_err(|e| invalid(format!("unusable certificate or key: {}", e)))?;
+        Ok(Certificate { key: Arc::new(key) })
+    }
+}
+
+/// The certificates a server presents, chosen by the name the client asks
+/// for (SNI).
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    default: Certificate,
+    by_name: Vec<(String, Certificate)>,
+}
+
+impl TlsConfig {
+    /// Presents `default` unless a certificate added with
+    /// [`TlsConfig::sni`] matches.
+    pub fn new(default: Certificate) -> Self {
+        TlsConfig {
+            default,
+            by_name: Vec::new(),
+        }
+    }
+
+    /// Presents `certificate` to clients asking for `name`. A name such as
+    /// `*.example.com` matches exactly one label in its place.
+    pub fn sni(mut self, name: &str, certificate: Certificate) -> Self {
+        self.by_name.push((name.to_ascii_lowercase(), certificate));
+        self
+    }
+
+    fn select(&self, server_name: Option<&str>) -> &Certificate {
+        let Some(server_name) = server_name.map(str::to_ascii_lowercase) else {
+            return &self.default;
+        };
+        let exact = self.by_name.iter().find(|(name, _)| *name == server_name);
+        let wildcard = || {
+            let (_, parent) = server_name.split_once('.')?;
+            self.by_name
+                .iter()
+                .find(|(name, _)| name.strip_prefix("*.") == Some(parent))
+        };
+        exact
+            .or_else(wildcard)
+            .map_or(&self.default, |(_, certificate)| certificate)
+    }
+
+    pub(crate) fn server_config(&self) -> io::Result<Arc<rustls::ServerConfig>> {
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(e.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(Resolver(self.clone())));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+#[derive(Debug)]
+struct Resolver(TlsConfig);
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.0.select(client_hello.server_name()).key))
+    }
+}
+
+fn provider() -> CryptoProvider {
+    rustls::crypto::ring::default_provider()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn run<H: RequestHandler + 'static>(
+    listener: TcpListener,
+    pool: ThreadPool,
+    service: Arc<Service<H>>,
+    config: Arc<rustls::ServerConfig>,
+) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let service = Arc::clone(&service);
+                let config = Arc::clone(&config);
+                pool.execute(move || serve_connection(stream, &service, config));
+            }
+            Err(e) => {
+                eprintln!("Error accepting connection: {}", e);
+            }
+        }
+    }
+}
+
+fn serve_connection<H: RequestHandler>(
+    stream: TcpStream,
+    service: &Service<H>,
+    config: Arc<rustls::ServerConfig>,
+) {
+    let peer = stream.peer_addr().ok();
+    // Without these, an idle or stalled client would hold the thread forever.
+    if let Err(e) = stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)))
+    {
+        eprintln!("Error setting timeouts: {}", e);
+        return;
+    }
+    let open = service.open_connection(peer);
+    let connection = match ServerConnection::new(config) {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("Error starting TLS: {}", e);
+            return;
+        }
+    };
+    let mut tls = StreamOwned::new(connection, stream);
+
+    let response = match Request::parse(&mut tls, service.max_body_size) {
+        Ok(mut request) => {
+            if let Some(peer) = peer {
+                request = request.with_remote_addr(peer);
+            }
+            open.serving(&request);
+            service.respond(&request)
+        }
+        Err(e) => match e.status_code() {
+            Some(status) => service.reject(status, peer),
+            // Includes failed handshakes, such as a browser refusing an
+            // untrusted certificate.
+            None => return,
+        },
+    };
+
+    let response = response.header("Connection", "close");
+    match response.write_to_stream(&mut tls) {
+        Ok(written) => service.bytes_written(written),
+        Err(e) => {
+            eprintln!("Error writing response: {}", e);
+            return;
+        }
+    }
+    tls.conn.send_close_notify();
+    let _ = tls.flush();
+}
+
+/// Answers plain HTTP requests on `listener` with a permanent redirect to
+/// the same URL on HTTPS port `https_port`, on a few threads of its own.
+pub(crate) fn spawn_redirect(listener: TcpListener, https_port: u16) {
+    thread::spawn(move || {
+        let pool = ThreadPool::new(REDIRECT_THREADS);
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            pool.execute(move || {
+                let _ = stream.set_read_timeout(Some(REDIRECT_TIMEOUT));
+                let _ = stream.set_write_timeout(Some(REDIRECT_TIMEOUT));
+                // Bodies are not read, so requests carrying one are refused.
+                let response = match Request::parse(&stream, Some(0)) {
+                    Ok(request) => {
+                        Response::new(301).header("Location", &https_url(&request, https_port))
+                    }
+                    Err(e) => match e.status_code() {
+                        Some(status) => Response::new(status),
+                        None => return,
+                    },
+                };
+                let response = response.header("Connection", "close");
+                let _ = response.write_to_stream(&mut &stream);
+            });
+        }
+    });
+}
+
+fn https_url(request: &Request, https_port: u16) -> String {
+    let host = request.header("host").unwrap_or("localhost");
+    // Drop any port, keeping the brackets of an IPv6 literal.
+    let host = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    let mut url = match https_port {
+        443 => format!("https://{}", host),
+        port => format!("https://{}:{}", host, port),
+    };
+    let path = request.path();
+    if !path.starts_with('/') {
+        url.push('/');
+    }
+    url.push_str(&path);
+    if let Some(query) = request.query() {
+        url.push('?');
+        url.push_str(query);
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Filter, Server, ServerConfig, get};
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    fn certificate(name: &str) -> Certificate {
+        Certificate::self_signed(&[name.to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_sni_selection() {
+        let (default, exact, wildcard) = (certificate("a"), certificate("b"), certificate("c"));
+        let config = TlsConfig::new(default.clone())
+            .sni("files.example.com", exact.clone())
+            .sni("*.example.org", wildcard.clone());
+        let chosen = |name| config.select(name).fingerprint();
+
+        assert_eq!(chosen(Some("FILES.example.com")), exact.fingerprint());
+        assert_eq!(chosen(Some("www.example.org")), wildcard.fingerprint());
+        assert_eq!(chosen(Some("a.b.example.org")), default.fingerprint());
+        assert_eq!(chosen(Some("example.org")), default.fingerprint());
+        assert_eq!(chosen(None), default.fingerprint());
+    }
+
+    #[test]
+    fn test_https_url() {
+        let mut headers = HashMap::new();
+        headers.insert("Host".to_string(), "[::1]:8080".to_string());
+        let request = Request::new(crate::http::Method::Get, "/a/b?x=1", headers, None);
+        assert_eq!(https_url(&request, 8443), "https://[::1]:8443/a/b?x=1");
+        assert_eq!(https_url(&request, 443), "https://[::1]/a/b?x=1");
+    }
+
+    /// Serves `/hello` over HTTPS on `threads` pool threads.
+    fn serve(certificate: &Certificate, threads: usize) -> u16 {
+        let config = ServerConfig::new("127.0.0.1", 0)
+            .threads(threads)
+            .tls(TlsConfig::new(certificate.clone()));
+        let server = Server::new(config).unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.run(get("/hello").map(|_| Response::ok("secure"))));
+        port
+    }
+
+    fn fetch(certificate: &Certificate, port: u16) -> String {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certificate.key.cert[0].clone()).unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connection =
+            rustls::ClientConnection::new(Arc::new(client), "localhost".try_into().unwrap())
+                .unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut tls = StreamOwned::new(connection, stream);
+
+        tls.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        let _ = tls.read_to_string(&mut response);
+        response
+    }
+
+    #[test]
+    fn test_https_round_trip() {
+        let certificate = certificate("localhost");
+        let response = fetch(&certificate, serve(&certificate, 4));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("secure"));
+    }
+
+    #[test]
+    fn test_idle_clients_time_out() {
+        let certificate = certificate("localhost");
+        let port = serve(&certificate, 1);
+
+        // Holds the only thread without ever starting the handshake.
+        let _idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let started = std::time::Instant::now();
+        let response = fetch(&certificate, port);
+        assert!(response.ends_with("secure"), "{}", response);
+        assert!(started.elapsed() >= IO_TIMEOUT / 2);
+    }
+}
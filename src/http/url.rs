@@ -0,0 +1,64 @@
+//! Percent-encoding helpers for request targets.
+
+/// Decodes `%XX` escapes. Malformed escapes are kept as they are, and
+/// sequences that are not valid UTF-8 are replaced lossily.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Encodes everything but unreserved characters and `/`, for putting a
+/// file path into a URL.
+pub fn percent_encode_path(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for &byte in s.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Decodes a query string key or value, where `+` stands for a space.
+pub(crate) fn decode_query_component(s: &str) -> String {
+    percent_decode(&s.replace('+', " "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("my%20file.txt"), "my file.txt");
+        assert_eq!(percent_decode("%C5%BC%C3%B3%C5%82w"), "żółw");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+        assert_eq!(decode_query_component("a+b%2Bc"), "a b+c");
+        assert_eq!(
+            percent_encode_path("a b/żółw?.txt"),
+            "a%20b/%C5%BC%C3%B3%C5%82w%3F.txt"
+        );
+    }
+}
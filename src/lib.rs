@@ -1,4 +1,16 @@
+pub mod archive;
+pub mod base64;
+pub mod compression;
+pub mod crypto;
+pub mod fileinfo;
+pub mod highlight;
 pub mod html;
 pub mod http;
+pub mod markdown;
+pub mod metrics;
+pub mod mime;
+pub mod preview;
+pub mod search;
 pub mod stats;
 pub mod threads;
+pub mod thumbnail;
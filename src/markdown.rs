@@ -0,0 +1,1881 @@
+//! A CommonMark renderer for README files and Markdown previews.
+//!
+//! Raw HTML is kept only for harmless tags and attributes, and links only
+//! for web and mail addresses, so a shared file cannot run scripts in the
+//! browser.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::highlight::Language;
+use crate::html::{encode_path, html_escape};
+
+/// Tags raw HTML may use; any other tag is shown as text.
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "dd",
+    "del",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "s",
+    "samp",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "tt",
+    "u",
+    "ul",
+    "var",
+];
+
+/// Tags without content or a closing tag.
+const VOID_TAGS: &[&str] = &["br", "hr", "img"];
+
+/// How deeply quotes and lists may nest; deeper ones are read as text.
+const MAX_DEPTH: usize = 32;
+
+/// How far ahead the end of a link destination, title, tag or comment is
+/// looked for, so that unclosed ones cannot make a paragraph slow to
+/// render.
+const SCAN_LIMIT: usize = 4096;
+
+/// Tags that start an HTML block that runs to the next blank line.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "iframe",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "param",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// A Markdown document to render as HTML.
+pub struct Markdown<'a> {
+    source: &'a str,
+    base: Option<&'a str>,
+}
+
+impl<'a> Markdown<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Markdown { source, base: None }
+    }
+
+    /// Points relative links at `/view/` and relative images at
+    /// `/download/` below `dir`, the decoded path of the directory the
+    /// document is in.
+    pub fn relative_to(mut self, dir: &'a str) -> Self {
+        self.base = Some(dir);
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let lines: Vec<String> = self.source.lines().map(expand_tabs).collect();
+        let mut references = HashMap::new();
+        let (blocks, _) = parse_blocks(&lines, &mut references, 0);
+        let mut renderer = Renderer {
+            references,
+            base: self.base,
+            open: Vec::new(),
+        };
+        let mut html = renderer.blocks(&blocks, false);
+        html.push_str(&renderer.close_all());
+        html
+    }
+}
+
+#[derive(Debug)]
+enum Block {
+    Heading(u8, String),
+    Rule,
+    Code {
+        info: String,
+        text: String,
+    },
+    Html(String),
+    Quote(Vec<Block>),
+    List {
+        start: Option<u64>,
+        tight: bool,
+        items: Vec<Vec<Block>>,
+    },
+    Paragraph(String),
+}
+
+/// Link reference definitions by normalized label: the destination and
+/// title.
+type References = HashMap<String, (String, Option<String>)>;
+
+/// The blocks in `lines`, and whether a blank line separates any two of
+/// them, which makes a list item loose.
+fn parse_blocks(lines: &[String], references: &mut References, depth: usize) -> (Vec<Block>, bool) {
+    let mut blocks = Vec::new();
+    let mut gap = false;
+    let mut blank = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].as_str();
+        if is_blank(line) {
+            blank = true;
+            i += 1;
+            continue;
+        }
+        gap |= blank && !blocks.is_empty();
+        blank = false;
+
+        let (block, next) = if indent(line) >= 4 {
+            indented_code(lines, i)
+        } else if let Some(fence) = fence(line) {
+            fenced_code(lines, i, fence)
+        } else if let Some((level, text)) = atx_heading(line) {
+            (Some(Block::Heading(level, text.to_string())), i + 1)
+        } else if is_rule(line) {
+            (Some(Block::Rule), i + 1)
+        } else if depth < MAX_DEPTH && quote_content(line).is_some() {
+            quote(lines, i, references, depth + 1)
+        } else if let Some(end) = html_start(line, false) {
+            html_block(lines, i, end)
+        } else if depth < MAX_DEPTH && list_marker(line).is_some() {
+            list(lines, i, references, depth + 1)
+        } else {
+            paragraph(lines, i, references)
+        };
+        blocks.extend(block);
+        i = next;
+    }
+    (blocks, gap)
+}
+
+fn indented_code(lines: &[String], start: usize) -> (Option<Block>, usize) {
+    let mut end = start;
+    let mut i = start;
+    while i < lines.len() && (is_blank(&lines[i]) || indent(&lines[i]) >= 4) {
+        if !is_blank(&lines[i]) {
+            end = i + 1;
+        }
+        i += 1;
+    }
+    let text: String = lines[start..end]
+        .iter()
+        .map(|line| format!("{}\n", strip_indent(line, 4)))
+        .collect();
+    let info = String::new();
+    (Some(Block::Code { info, text }), end)
+}
+
+struct Fence {
+    ch: char,
+    len: usize,
+    indent: usize,
+    info: String,
+}
+
+fn fence(line: &str) -> Option<Fence> {
+    let indent = indent(line);
+    if indent >= 4 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let ch = rest.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = rest.chars().take_while(|&c| c == ch).count();
+    let info = rest[len..].trim();
+    if len < 3 || (ch == '`' && info.contains('`')) {
+        return None;
+    }
+    Some(Fence {
+        ch,
+        len,
+        indent,
+        info: unescape(info),
+    })
+}
+
+fn fenced_code(lines: &[String], start: usize, fence: Fence) -> (Option<Block>, usize) {
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < lines.len() {
+        let line = lines[i].as_str();
+        let closing = line.trim();
+        i += 1;
+        if indent(line) < 4 && closing.len() >= fence.len && closing.chars().all(|c| c == fence.ch)
+        {
+            break;
+        }
+        text.push_str(strip_indent(line, fence.indent));
+        text.push('\n');
+    }
+    let info = fence.info;
+    (Some(Block::Code { info, text }), i)
+}
+
+fn atx_heading(line: &str) -> Option<(u8, &str)> {
+    if indent(line) >= 4 {
+        return None;
+    }
+    let rest = line.trim_start();
+    let level = rest.bytes().take_while(|&b| b == b'#').count();
+    let text = &rest[level..];
+    if !(1..=6).contains(&level) || !(text.is_empty() || text.starts_with(' ')) {
+        return None;
+    }
+    let text = text.trim();
+    let unclosed = text.trim_end_matches('#');
+    let text = match unclosed.is_empty() || unclosed.ends_with(' ') {
+        true => unclosed.trim_end(),
+        false => text,
+    };
+    Some((level as u8, text))
+}
+
+fn is_rule(line: &str) -> bool {
+    if indent(line) >= 4 {
+        return false;
+    }
+    let mut chars = line.chars().filter(|c| !c.is_whitespace());
+    let Some(first) = chars.next().filter(|c| matches!(c, '-' | '*' | '_')) else {
+        return false;
+    };
+    let mut count = 1;
+    for c in chars {
+        if c != first {
+            return false;
+        }
+        count += 1;
+    }
+    count >= 3
+}
+
+fn setext_level(line: &str) -> Option<u8> {
+    let underline = line.trim();
+    if indent(line) >= 4 || underline.is_empty() {
+        return None;
+    }
+    if underline.chars().all(|c| c == '=') {
+        Some(1)
+    } else if underline.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn quote_content(line: &str) -> Option<&str> {
+    if indent(line) >= 4 {
+        return None;
+    }
+    let rest = line.trim_start().strip_prefix('>')?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+fn quote(
+    lines: &[String],
+    start: usize,
+    references: &mut References,
+    depth: usize,
+) -> (Option<Block>, usize) {
+    let mut content: Vec<String> = Vec::new();
+    let mut i = start;
+    while i < lines.len() {
+        let line = lines[i].as_str();
+        if let Some(rest) = quote_content(line) {
+            content.push(rest.to_string());
+        } else if is_lazy(&content, line) {
+            content.push(line.to_string());
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    let (blocks, _) = parse_blocks(&content, references, depth);
+    (Some(Block::Quote(blocks)), i)
+}
+
+/// Whether `line` continues the paragraph that `content` ends with,
+/// though it is not indented or quoted like the rest. Any list marker ends
+/// the container instead, even one that could not interrupt a paragraph.
+fn is_lazy(content: &[String], line: &str) -> bool {
+    content.last().is_some_and(|last| !is_blank(last))
+        && !is_blank(line)
+        && !is_block_start(line)
+        && list_marker(line).is_none()
+}
+
+/// How an HTML block ends.
+enum HtmlEnd {
+    /// With the line holding `-->`.
+    Comment,
+    /// With the line holding this closing tag.
+    Tag(&'static str),
+    /// Before the next blank line.
+    Blank,
+}
+
+/// How the HTML block `line` starts ends, if it starts one. Only blocks
+/// opened by a known block-level tag may interrupt a paragraph.
+fn html_start(line: &str, interrupting: bool) -> Option<HtmlEnd> {
+    if indent(line) >= 4 {
+        return None;
+    }
+    let rest = line.trim_start();
+    if rest.starts_with("<!--") {
+        return Some(HtmlEnd::Comment);
+    }
+    let lower = rest.to_ascii_lowercase();
+    let tag = lower.strip_prefix('<')?;
+    let name_end = |name: &str| {
+        tag[name.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| c == ' ' || c == '>')
+    };
+    for name in ["script", "pre", "style", "textarea"] {
+        if tag.starts_with(name) && name_end(name) {
+            return Some(HtmlEnd::Tag(name));
+        }
+    }
+    let tag = tag.strip_prefix('/').unwrap_or(tag);
+    let name: String = tag
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    let after = &tag[name.len()..];
+    if BLOCK_TAGS.contains(&name.as_str())
+        && (after.is_empty() || after.starts_with([' ', '>']) || after.starts_with("/>"))
+    {
+        return Some(HtmlEnd::Blank);
+    }
+    match parse_tag(rest) {
+        Some((_, len)) if !interrupting && is_blank(&rest[len..]) => Some(HtmlEnd::Blank),
+        _ => None,
+    }
+}
+
+fn html_block(lines: &[String], start: usize, end: HtmlEnd) -> (Option<Block>, usize) {
+    let mut html = String::new();
+    let mut i = start;
+    while i < lines.len() {
+        let line = lines[i].as_str();
+        if matches!(end, HtmlEnd::Blank) && is_blank(line) {
+            break;
+        }
+        html.push_str(line);
+        html.push('\n');
+        i += 1;
+        let ended = match end {
+            HtmlEnd::Comment => line.contains("-->"),
+            HtmlEnd::Tag(name) => line.to_ascii_lowercase().contains(&format!("</{}>", name)),
+            HtmlEnd::Blank => false,
+        };
+        if ended {
+            break;
+        }
+    }
+    (Some(Block::Html(html)), i)
+}
+
+struct Marker {
+    /// The bullet, or the `.` or `)` after the number.
+    ch: char,
+    start: Option<u64>,
+    /// Where the item's content starts.
+    width: usize,
+    empty: bool,
+}
+
+fn list_marker(line: &str) -> Option<Marker> {
+    let indent = indent(line);
+    if indent >= 4 || is_rule(line) {
+        return None;
+    }
+    let rest = &line[indent..];
+    let (ch, start, len) = match rest.bytes().next()? {
+        b'-' | b'+' | b'*' => (rest.chars().next()?, None, 1),
+        b'0'..=b'9' => {
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let ch = rest[digits..]
+                .chars()
+                .next()
+                .filter(|&c| c == '.' || c == ')')?;
+            if digits > 9 {
+                return None;
+            }
+            (ch, Some(rest[..digits].parse().ok()?), digits + 1)
+        }
+        _ => return None,
+    };
+    let after = &rest[len..];
+    let spaces = after.bytes().take_while(|&b| b == b' ').count();
+    let empty = is_blank(after);
+    if spaces == 0 && !empty {
+        return None;
+    }
+    let width = match empty || spaces >= 5 {
+        true => indent + len + 1,
+        false => indent + len + spaces,
+    };
+    Some(Marker {
+        ch,
+        start,
+        width,
+        empty,
+    })
+}
+
+fn list(
+    lines: &[String],
+    start: usize,
+    references: &mut References,
+    depth: usize,
+) -> (Option<Block>, usize) {
+    let first = list_marker(&lines[start]).expect("a list item");
+    let mut items = Vec::new();
+    let mut tight = true;
+    let mut i = start;
+    loop {
+        let marker = list_marker(&lines[i]).expect("a list item");
+        let mut content = vec![lines[i].get(marker.width..).unwrap_or_default().to_string()];
+        i += 1;
+        while i < lines.len() {
+            let line = lines[i].as_str();
+            if is_blank(line) {
+                content.push(String::new());
+            } else if indent(line) >= marker.width {
+                content.push(line[marker.width..].to_string());
+            } else if is_lazy(&content, line) {
+                content.push(line.to_string());
+            } else {
+                break;
+            }
+            i += 1;
+        }
+        // Blank lines after the item belong to the list, not to it.
+        while content.len() > 1 && content.last().is_some_and(|line| is_blank(line)) {
+            content.pop();
+            i -= 1;
+        }
+        let (blocks, gap) = parse_blocks(&content, references, depth);
+        tight &= !gap;
+        items.push(blocks);
+
+        let mut next = i;
+        while next < lines.len() && is_blank(&lines[next]) {
+            next += 1;
+        }
+        let same_list = |marker: &Marker| {
+            marker.ch == first.ch && marker.start.is_some() == first.start.is_some()
+        };
+        match lines.get(next).and_then(|line| list_marker(line)) {
+            Some(marker) if same_list(&marker) => {
+                tight &= next == i;
+                i = next;
+            }
+            _ => break,
+        }
+    }
+    let start = first.start;
+    (
+        Some(Block::List {
+            start,
+            tight,
+            items,
+        }),
+        i,
+    )
+}
+
+/// Whether `line` ends a paragraph by starting another block.
+fn is_block_start(line: &str) -> bool {
+    is_rule(line)
+        || atx_heading(line).is_some()
+        || fence(line).is_some()
+        || quote_content(line).is_some()
+        || html_start(line, true).is_some()
+        || list_marker(line)
+            .is_some_and(|marker| !marker.empty && marker.start.is_none_or(|start| start == 1))
+}
+
+fn paragraph(
+    lines: &[String],
+    start: usize,
+    references: &mut References,
+) -> (Option<Block>, usize) {
+    let mut text = lines[start].trim_start().to_string();
+    let mut i = start + 1;
+    let mut heading = None;
+    while i < lines.len() {
+        let line = lines[i].as_str();
+        if is_blank(line) {
+            break;
+        }
+        if let Some(level) = setext_level(line) {
+            heading = Some(level);
+            i += 1;
+            break;
+        }
+        if is_block_start(line) {
+            break;
+        }
+        text.push('\n');
+        text.push_str(line.trim_start());
+        i += 1;
+    }
+
+    let mut rest = text.as_str();
+    while let Some((label, destination, title, after)) = reference_definition(rest) {
+        references
+            .entry(normalize_label(&label))
+            .or_insert((destination, title));
+        rest = after;
+    }
+    let rest = rest.trim_end();
+    let block = match heading {
+        _ if rest.is_empty() => None,
+        Some(level) => Some(Block::Heading(level, rest.to_string())),
+        None => Some(Block::Paragraph(rest.to_string())),
+    };
+    (block, i)
+}
+
+/// `[label]: destination "title"` at the start of `text`, and what follows
+/// it.
+fn reference_definition(text: &str) -> Option<(String, String, Option<String>, &str)> {
+    let (label, used) = link_label(text)?;
+    if label.trim().is_empty() {
+        return None;
+    }
+    let rest = text[used..].strip_prefix(':')?;
+    let skipped = skip_whitespace(rest);
+    let (destination, len) = destination(&rest[skipped..])?;
+    let after = &rest[skipped + len..];
+
+    let space = skip_whitespace(after);
+    if space > 0
+        && let Some((title, len)) = title(&after[space..])
+        && let Some(rest) = line_end(&after[space + len..])
+    {
+        return Some((label.to_string(), destination, Some(title), rest));
+    }
+    Some((label.to_string(), destination, None, line_end(after)?))
+}
+
+/// What follows the end of the current line, if only spaces are left on it.
+fn line_end(s: &str) -> Option<&str> {
+    let rest = s.trim_start_matches([' ', '\t']);
+    match rest.is_empty() {
+        true => Some(rest),
+        false => rest.strip_prefix('\n'),
+    }
+}
+
+/// The length of the spaces and at most one line break at the start of `s`.
+fn skip_whitespace(s: &str) -> usize {
+    let spaces = |s: &str| s.len() - s.trim_start_matches([' ', '\t']).len();
+    let mut len = spaces(s);
+    if s[len..].starts_with('\n') {
+        len += 1;
+        len += spaces(&s[len..]);
+    }
+    len
+}
+
+/// The text between the brackets of `[label]` at the start of `s`, and
+/// the length of it all.
+fn link_label(s: &str) -> Option<(&str, usize)> {
+    s.strip_prefix('[')?;
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1).take(1000) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => return None,
+            ']' => return Some((&s[1..i], i + 1)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A link destination at the start of `s`, unescaped, and its length.
+fn destination(s: &str) -> Option<(String, usize)> {
+    let s = head(s, SCAN_LIMIT);
+    if let Some(rest) = s.strip_prefix('<') {
+        let mut escaped = false;
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '\n' | '<' => return None,
+                '>' => return Some((unescape(&rest[..i]), i + 2)),
+                _ => {}
+            }
+        }
+        return None;
+    }
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut end = s.len();
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                end = i;
+                break;
+            }
+            ')' => depth -= 1,
+            c if c.is_whitespace() || c.is_control() => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    (end > 0 && depth == 0).then(|| (unescape(&s[..end]), end))
+}
+
+/// A link title in quotes or parentheses at the start of `s`, unescaped,
+/// and its length.
+fn title(s: &str) -> Option<(String, usize)> {
+    let s = head(s, SCAN_LIMIT);
+    let close = match s.chars().next()? {
+        '"' => '"',
+        '\'' => '\'',
+        '(' => ')',
+        _ => return None,
+    };
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == close => return Some((unescape(&s[1..i]), i + 1)),
+            '(' if close == ')' => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The destination and title of `(destination "title")` at the start of
+/// `s`, and its length.
+fn inline_target(s: &str) -> Option<(String, Option<String>, usize)> {
+    s.strip_prefix('(')?;
+    let mut i = 1 + skip_whitespace(&s[1..]);
+    if s[i..].starts_with(')') {
+        return Some((String::new(), None, i + 1));
+    }
+    let (destination, len) = destination(&s[i..])?;
+    i += len;
+    let space = skip_whitespace(&s[i..]);
+    i += space;
+    let mut link_title = None;
+    if space > 0
+        && let Some((title, len)) = title(&s[i..])
+    {
+        link_title = Some(title);
+        i += len;
+        i += skip_whitespace(&s[i..]);
+    }
+    s[i..]
+        .starts_with(')')
+        .then_some((destination, link_title, i + 1))
+}
+
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// A piece of a paragraph while its emphasis and links are worked out.
+enum Node {
+    /// Finished HTML.
+    Html(String),
+    /// A run of `*` or `_` that may open or close emphasis. `before` and
+    /// `after` collect the tags it is turned into.
+    Delimiter {
+        ch: char,
+        count: usize,
+        length: usize,
+        open: bool,
+        close: bool,
+        before: String,
+        after: String,
+    },
+    /// A `[` or `![` that may start a link or image; `start` is where its
+    /// text begins.
+    Bracket {
+        image: bool,
+        active: bool,
+        start: usize,
+    },
+}
+
+impl Node {
+    fn into_html(self) -> String {
+        match self {
+            Node::Html(html) => html,
+            Node::Delimiter {
+                ch,
+                count,
+                before,
+                after,
+                ..
+            } => format!("{}{}{}", before, ch.to_string().repeat(count), after),
+            Node::Bracket { image: true, .. } => "![".to_string(),
+            Node::Bracket { image: false, .. } => "[".to_string(),
+        }
+    }
+}
+
+fn push_html(nodes: &mut Vec<Node>, html: &str) {
+    match nodes.last_mut() {
+        Some(Node::Html(last)) => last.push_str(html),
+        _ => nodes.push(Node::Html(html.to_string())),
+    }
+}
+
+/// Matches the emphasis delimiters in `nodes` from `bottom` on, as the
+/// CommonMark spec's "process emphasis" procedure does.
+fn process_emphasis(nodes: &mut [Node], bottom: usize) {
+    // Where the search for an opener can stop, by the closer's character,
+    // length modulo 3 and whether it can open too.
+    let mut openers_bottom = [[[bottom; 2]; 3]; 2];
+    let mut closer = bottom;
+    while closer < nodes.len() {
+        let (ch, length, can_open) = match &nodes[closer] {
+            Node::Delimiter {
+                ch,
+                count,
+                length,
+                open,
+                close: true,
+                ..
+            } if *count > 0 => (*ch, *length, *open),
+            _ => {
+                closer += 1;
+                continue;
+            }
+        };
+        let key = (usize::from(ch == '*'), length % 3, usize::from(can_open));
+        let floor = openers_bottom[key.0][key.1][key.2];
+        let opener = (floor..closer).rev().find(|&index| match &nodes[index] {
+            Node::Delimiter {
+                ch: other,
+                count,
+                length: other_length,
+                open: true,
+                close,
+                ..
+            } => {
+                let both = *close || can_open;
+                *other == ch
+                    && *count > 0
+                    && !(both
+                        && (other_length + length) % 3 == 0
+                        && !(other_length % 3 == 0 && length % 3 == 0))
+            }
+            _ => false,
+        });
+        let Some(opener) = opener else {
+            openers_bottom[key.0][key.1][key.2] = closer;
+            closer += 1;
+            continue;
+        };
+
+        let available = |node: &Node| match node {
+            Node::Delimiter { count, .. } => *count,
+            _ => 0,
+        };
+        let used = match available(&nodes[opener]) >= 2 && available(&nodes[closer]) >= 2 {
+            true => 2,
+            false => 1,
+        };
+        let tag = if used == 2 { "strong" } else { "em" };
+        if let Node::Delimiter { count, after, .. } = &mut nodes[opener] {
+            *count -= used;
+            after.insert_str(0, &format!("<{}>", tag));
+        }
+        for node in &mut nodes[opener + 1..closer] {
+            if let Node::Delimiter { open, close, .. } = node {
+                *open = false;
+                *close = false;
+            }
+        }
+        if let Node::Delimiter { count, before, .. } = &mut nodes[closer] {
+            *count -= used;
+            before.push_str(&format!("</{}>", tag));
+            if *count == 0 {
+                closer += 1;
+            }
+        }
+    }
+}
+
+struct Renderer<'a> {
+    references: References,
+    base: Option<&'a str>,
+    /// Tags opened by raw HTML and not closed yet.
+    open: Vec<String>,
+}
+
+impl Renderer<'_> {
+    /// `blocks` as HTML; the paragraphs of a `tight` list item are not
+    /// wrapped in `<p>`.
+    fn blocks(&mut self, blocks: &[Block], tight: bool) -> String {
+        let mut html = String::new();
+        for block in blocks {
+            match block {
+                Block::Heading(level, text) => {
+                    html.push_str(&format!("<h{0}>{1}</h{0}>\n", level, self.inline(text)))
+                }
+                Block::Rule => html.push_str("<hr>\n"),
+                Block::Code { info, text } => {
+                    let class = match info.split_whitespace().next() {
+                        Some(language) => format!(r#" class="language-{}""#, html_escape(language)),
+                        None => String::new(),
+                    };
+                    let code = match info.split_whitespace().next().and_then(Language::named) {
+                        Some(language) => language
+                            .highlight(text)
+                            .into_iter()
+                            .map(|line| line + "\n")
+                            .collect(),
+                        None => html_escape(text),
+                    };
+                    html.push_str(&format!("<pre><code{}>{}</code></pre>\n", class, code));
+                }
+                Block::Html(raw) => html.push_str(&self.sanitize(raw)),
+                Block::Quote(blocks) => {
+                    html.push_str("<blockquote>\n");
+                    html.push_str(&self.blocks(blocks, false));
+                    html.push_str("</blockquote>\n");
+                }
+                Block::List {
+                    start,
+                    tight: tight_list,
+                    items,
+                } => {
+                    let tag = match start {
+                        Some(1) => "<ol>".to_string(),
+                        Some(start) => format!(r#"<ol start="{}">"#, start),
+                        None => "<ul>".to_string(),
+                    };
+                    html.push_str(&tag);
+                    html.push('\n');
+                    for item in items {
+                        html.push_str("<li>");
+                        html.push_str(&self.blocks(item, *tight_list));
+                        html.push_str("</li>\n");
+                    }
+                    html.push_str(if start.is_some() {
+                        "</ol>\n"
+                    } else {
+                        "</ul>\n"
+                    });
+                }
+                Block::Paragraph(text) if tight => html.push_str(&self.inline(text)),
+                Block::Paragraph(text) => html.push_str(&format!("<p>{}</p>\n", self.inline(text))),
+            }
+        }
+        html
+    }
+
+    /// The emphasis, code, links, images and raw HTML in a paragraph.
+    fn inline(&mut self, text: &str) -> String {
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut brackets: Vec<usize> = Vec::new();
+        // Lengths of backtick runs known to have no closing run ahead.
+        let mut unclosed = HashSet::new();
+        let mut i = 0;
+        while let Some(c) = text[i..].chars().next() {
+            let rest = &text[i..];
+            match c {
+                '\\' => match rest[1..].chars().next() {
+                    Some('\n') => {
+                        push_html(&mut nodes, "<br>\n");
+                        i += 2;
+                    }
+                    Some(next) if next.is_ascii_punctuation() => {
+                        push_html(&mut nodes, &html_escape(&next.to_string()));
+                        i += 2;
+                    }
+                    _ => {
+                        push_html(&mut nodes, "\\");
+                        i += 1;
+                    }
+                },
+                '`' => {
+                    let run = rest.bytes().take_while(|&b| b == b'`').count();
+                    let span = match unclosed.contains(&run) {
+                        true => None,
+                        false => code_span(&rest[run..], run),
+                    };
+                    match span {
+                        Some((code, len)) => {
+                            push_html(&mut nodes, &format!("<code>{}</code>", html_escape(&code)));
+                            i += run + len;
+                        }
+                        None => {
+                            unclosed.insert(run);
+                            push_html(&mut nodes, &rest[..run]);
+                            i += run;
+                        }
+                    }
+                }
+                '*' | '_' => {
+                    let run = rest.bytes().take_while(|&b| b == c as u8).count();
+                    let before = text[..i].chars().next_back();
+                    let after = rest[run..].chars().next();
+                    let (open, close) = flanking(c, before, after);
+                    nodes.push(Node::Delimiter {
+                        ch: c,
+                        count: run,
+                        length: run,
+                        open,
+                        close,
+                        before: String::new(),
+                        after: String::new(),
+                    });
+                    i += run;
+                }
+                '!' if rest[1..].starts_with('[') => {
+                    brackets.push(nodes.len());
+                    nodes.push(Node::Bracket {
+                        image: true,
+                        active: true,
+                        start: i + 2,
+                    });
+                    i += 2;
+                }
+                '[' => {
+                    brackets.push(nodes.len());
+                    nodes.push(Node::Bracket {
+                        image: false,
+                        active: true,
+                        start: i + 1,
+                    });
+                    i += 1;
+                }
+                ']' => {
+                    let Some(opener) = brackets.pop() else {
+                        push_html(&mut nodes, "]");
+                        i += 1;
+                        continue;
+                    };
+                    let Node::Bracket {
+                        image,
+                        active: true,
+                        start,
+                    } = nodes[opener]
+                    else {
+                        push_html(&mut nodes, "]");
+                        i += 1;
+                        continue;
+                    };
+                    let Some((url, title, len)) = self.link_target(&text[start..i], &rest[1..])
+                    else {
+                        push_html(&mut nodes, "]");
+                        i += 1;
+                        continue;
+                    };
+
+                    process_emphasis(&mut nodes, opener + 1);
+                    let inner: String = nodes.drain(opener + 1..).map(Node::into_html).collect();
+                    nodes.pop();
+                    let html = match image {
+                        true => self.image(&url, title.as_deref(), &strip_tags(&inner)),
+                        false => {
+                            // Links may not contain other links.
+                            for &bracket in &brackets {
+                                if let Node::Bracket {
+                                    image: false,
+                                    active,
+                                    ..
+                                } = &mut nodes[bracket]
+                                {
+                                    *active = false;
+                                }
+                            }
+                            self.link(&url, title.as_deref(), &inner)
+                        }
+                    };
+                    push_html(&mut nodes, &html);
+                    i += 1 + len;
+                }
+                '<' => {
+                    if let Some((html, len)) = self.autolink(rest) {
+                        push_html(&mut nodes, &html);
+                        i += len;
+                    } else if let Some(len) = raw_html_len(rest) {
+                        let html = self.sanitize(&rest[..len]);
+                        push_html(&mut nodes, &html);
+                        i += len;
+                    } else {
+                        push_html(&mut nodes, "&lt;");
+                        i += 1;
+                    }
+                }
+                '&' => {
+                    let len = entity_len(rest).unwrap_or(0);
+                    push_html(&mut nodes, if len > 0 { &rest[..len] } else { "&amp;" });
+                    i += len.max(1);
+                }
+                '\n' => {
+                    let mut hard = false;
+                    if let Some(Node::Html(last)) = nodes.last_mut() {
+                        let trimmed = last.trim_end_matches(' ').len();
+                        hard = last.len() - trimmed >= 2;
+                        last.truncate(trimmed);
+                    }
+                    push_html(&mut nodes, if hard { "<br>\n" } else { "\n" });
+                    i += 1;
+                }
+                c => {
+                    push_html(&mut nodes, &html_escape(&c.to_string()));
+                    i += c.len_utf8();
+                }
+            }
+        }
+        process_emphasis(&mut nodes, 0);
+        nodes.into_iter().map(Node::into_html).collect()
+    }
+
+    /// Where the link whose text is `label` goes, from what `after` its
+    /// closing bracket: an inline destination, or a reference. The length
+    /// is that of what is used from `after`.
+    fn link_target(&self, label: &str, after: &str) -> Option<(String, Option<String>, usize)> {
+        if let Some(target) = inline_target(after) {
+            return Some(target);
+        }
+        let (reference, len) = match link_label(after) {
+            Some(("", len)) => (label, len),
+            Some((reference, len)) => (reference, len),
+            None => (label, 0),
+        };
+        let (url, title) = self.references.get(&normalize_label(reference))?;
+        Some((url.clone(), title.clone(), len))
+    }
+
+    fn link(&self, url: &str, title: Option<&str>, text: &str) -> String {
+        match self.url(url, false) {
+            Some(url) => format!(
+                r#"<a href="{}"{}>{}</a>"#,
+                html_escape(&url),
+                title_attribute(title),
+                text
+            ),
+            None => text.to_string(),
+        }
+    }
+
+    fn image(&self, url: &str, title: Option<&str>, alt: &str) -> String {
+        match self.url(url, true) {
+            Some(url) => format!(
+                r#"<img src="{}" alt="{}"{}>"#,
+                html_escape(&url),
+                alt,
+                title_attribute(title)
+            ),
+            None => alt.to_string(),
+        }
+    }
+
+    /// `<https://…>` or `<name@example.com>` at the start of `s`.
+    fn autolink(&self, s: &str) -> Option<(String, usize)> {
+        let end = 1 + s[1..].find(['>', '<', ' ', '\n'])?;
+        let address = &s[1..end];
+        if !s[end..].starts_with('>') {
+            return None;
+        }
+        let scheme = address.split_once(':').map(|(scheme, _)| scheme);
+        let url = if scheme.is_some_and(|scheme| {
+            (2..=32).contains(&scheme.len())
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+        }) {
+            address.to_string()
+        } else if address.contains('@') && !address.contains(['\\', '/']) {
+            format!("mailto:{}", address)
+        } else {
+            return None;
+        };
+        let text = html_escape(address);
+        let html = match self.url(&url, false) {
+            Some(url) => format!(r#"<a href="{}">{}</a>"#, html_escape(&url), text),
+            None => text,
+        };
+        Some((html, end + 1))
+    }
+
+    /// `url` if it is safe to link to, with a relative path turned into
+    /// one from the root of the site.
+    fn url(&self, url: &str, image: bool) -> Option<String> {
+        let url = url.trim();
+        if let Some(end) = url.find([':', '/', '?', '#'])
+            && url[end..].starts_with(':')
+        {
+            let scheme = url[..end].to_ascii_lowercase();
+            return ["http", "https", "mailto", "ftp"]
+                .contains(&scheme.as_str())
+                .then(|| url.to_string());
+        }
+        let Some(base) = self.base else {
+            return Some(url.to_string());
+        };
+        if url.is_empty() || url.starts_with(['/', '?', '#']) {
+            return Some(url.to_string());
+        }
+        let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+        let mut parts: Vec<String> = base
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(encode_path)
+            .collect();
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                part => parts.push(part.to_string()),
+            }
+        }
+        Some(match image {
+            true => format!("/download/{}?inline", parts.join("/")),
+            false => format!("/view/{}{}", parts.join("/"), suffix),
+        })
+    }
+
+    /// Raw HTML with only the allowed tags and attributes kept; other tags
+    /// are shown as text and comments are dropped.
+    fn sanitize(&mut self, raw: &str) -> String {
+        let mut html = String::new();
+        let mut i = 0;
+        while let Some(offset) = raw[i..].find('<') {
+            html.push_str(&raw[i..i + offset]);
+            i += offset;
+            let rest = &raw[i..];
+            if rest.starts_with("<!--") {
+                i += rest.find("-->").map_or(rest.len(), |end| end + 3);
+            } else if let Some((tag, len)) = parse_tag(rest) {
+                html.push_str(&self.tag(&tag, &rest[..len]));
+                i += len;
+            } else {
+                html.push_str("&lt;");
+                i += 1;
+            }
+        }
+        html.push_str(&raw[i..]);
+        html
+    }
+
+    fn tag(&mut self, tag: &Tag, source: &str) -> String {
+        let name = tag.name.to_ascii_lowercase();
+        if !ALLOWED_TAGS.contains(&name.as_str()) {
+            return html_escape(source);
+        }
+        if tag.closing {
+            // Only close what raw HTML opened, so the page around stays whole.
+            let Some(index) = self.open.iter().rposition(|open| *open == name) else {
+                return String::new();
+            };
+            return self
+                .open
+                .drain(index..)
+                .rev()
+                .map(|name| format!("</{}>", name))
+                .collect();
+        }
+
+        let mut html = format!("<{}", name);
+        for (attribute, value) in &tag.attributes {
+            let attribute = attribute.to_ascii_lowercase();
+            let allowed = matches!(
+                (name.as_str(), attribute.as_str()),
+                (_, "title" | "align")
+                    | ("a", "href")
+                    | ("img", "src" | "alt" | "width" | "height")
+                    | ("td" | "th", "colspan" | "rowspan")
+                    | ("ol", "start")
+                    | ("details", "open")
+            );
+            if !allowed {
+                continue;
+            }
+            let value = decode_entities(value.as_deref().unwrap_or_default());
+            let value = match attribute.as_str() {
+                "href" | "src" => match self.url(&value, attribute == "src") {
+                    Some(url) => url,
+                    None => continue,
+                },
+                _ => value,
+            };
+            html.push_str(&format!(r#" {}="{}""#, attribute, html_escape(&value)));
+        }
+        html.push('>');
+        if !VOID_TAGS.contains(&name.as_str()) {
+            self.open.push(name);
+        }
+        html
+    }
+
+    fn close_all(&mut self) -> String {
+        self.open
+            .drain(..)
+            .rev()
+            .map(|name| format!("</{}>", name))
+            .collect()
+    }
+}
+
+struct Tag {
+    name: String,
+    closing: bool,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+/// An opening or closing tag at the start of `s`, and its length.
+fn parse_tag(s: &str) -> Option<(Tag, usize)> {
+    let s = head(s, SCAN_LIMIT);
+    let mut rest = s.strip_prefix('<')?;
+    let closing = rest.starts_with('/');
+    if closing {
+        rest = &rest[1..];
+    }
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(rest.len());
+    let name = rest[..name_len].to_string();
+    rest = &rest[name_len..];
+
+    let mut attributes = Vec::new();
+    loop {
+        let trimmed = rest.trim_start();
+        let space = rest.len() - trimmed.len();
+        rest = trimmed;
+        if let Some(after) = rest
+            .strip_prefix('>')
+            .or_else(|| rest.strip_prefix("/>").filter(|_| !closing))
+        {
+            let tag = Tag {
+                name,
+                closing,
+                attributes,
+            };
+            return Some((tag, s.len() - after.len()));
+        }
+        if closing || space == 0 {
+            return None;
+        }
+        let attribute_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '_' | ':' | '.' | '-'))
+            .unwrap_or(rest.len());
+        if attribute_len == 0
+            || rest.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-')
+        {
+            return None;
+        }
+        let attribute = rest[..attribute_len].to_string();
+        rest = &rest[attribute_len..];
+        let Some(after_equals) = rest.trim_start().strip_prefix('=') else {
+            attributes.push((attribute, None));
+            continue;
+        };
+        let value_start = after_equals.trim_start();
+        let (value, len) = match value_start.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let end = value_start[1..].find(quote)?;
+                (&value_start[1..end + 1], end + 2)
+            }
+            _ => {
+                let end = value_start
+                    .find(|c: char| c.is_whitespace() || "\"'=<>`".contains(c))
+                    .unwrap_or(value_start.len());
+                if end == 0 {
+                    return None;
+                }
+                (&value_start[..end], end)
+            }
+        };
+        attributes.push((attribute, Some(value.to_string())));
+        rest = &value_start[len..];
+    }
+}
+
+/// The length of the tag or comment at the start of `s`.
+fn raw_html_len(s: &str) -> Option<usize> {
+    if let Some(comment) = s.strip_prefix("<!--") {
+        return head(comment, SCAN_LIMIT).find("-->").map(|end| end + 7);
+    }
+    parse_tag(s).map(|(_, len)| len)
+}
+
+/// The code in a span that `run` backticks opened, which `s` follows, and
+/// the length of it with the closing backticks.
+fn code_span(s: &str, run: usize) -> Option<(String, usize)> {
+    let mut from = 0;
+    while let Some(offset) = s[from..].find('`') {
+        let start = from + offset;
+        let len = s[start..].bytes().take_while(|&b| b == b'`').count();
+        if len == run {
+            let code = s[..start].replace('\n', " ");
+            let stripped = match code.len() > 1
+                && code.starts_with(' ')
+                && code.ends_with(' ')
+                && !code.trim().is_empty()
+            {
+                true => code[1..code.len() - 1].to_string(),
+                false => code,
+            };
+            return Some((stripped, start + len));
+        }
+        from = start + len;
+    }
+    None
+}
+
+/// Whether a run of `ch` between the characters `before` and `after` can
+/// open and close emphasis.
+fn flanking(ch: char, before: Option<char>, after: Option<char>) -> (bool, bool) {
+    let space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+    let punctuation = |c: Option<char>| {
+        c.is_some_and(|c| c.is_ascii_punctuation() || (!c.is_alphanumeric() && !c.is_whitespace()))
+    };
+    let left = !space(after) && (!punctuation(after) || space(before) || punctuation(before));
+    let right = !space(before) && (!punctuation(before) || space(after) || punctuation(after));
+    match ch {
+        '_' => (
+            left && (!right || punctuation(before)),
+            right && (!left || punctuation(after)),
+        ),
+        _ => (left, right),
+    }
+}
+
+/// The length of the entity or numeric character reference at the start
+/// of `s`.
+fn entity_len(s: &str) -> Option<usize> {
+    let end = s.bytes().take(40).position(|b| b == b';')?;
+    let body = &s[1..end];
+    let valid = match body.strip_prefix('#') {
+        Some(number) => {
+            let (digits, radix) = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => (hex, 16),
+                None => (number, 10),
+            };
+            (1..=7).contains(&digits.len()) && digits.chars().all(|c| c.is_digit(radix))
+        }
+        None => {
+            body.starts_with(|c: char| c.is_ascii_alphabetic())
+                && body.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+    };
+    valid.then_some(end + 1)
+}
+
+/// `s` with backslash escapes and the common entities resolved.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if next.is_ascii_punctuation() => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    decode_entities(&unescaped)
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = entity_len(rest).unwrap_or(1);
+        let body = &rest[1..len.max(2) - 1];
+        let c = match body {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => body.strip_prefix('#').and_then(|number| {
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => number.parse(),
+                };
+                code.ok().and_then(char::from_u32)
+            }),
+        };
+        match c {
+            Some(c) if len > 1 => decoded.push(c),
+            _ => decoded.push_str(&rest[..len]),
+        }
+        rest = &rest[len..];
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn title_attribute(title: Option<&str>) -> String {
+    match title {
+        Some(title) => format!(r#" title="{}""#, html_escape(title)),
+        None => String::new(),
+    }
+}
+
+/// The text of some HTML, for an `alt` attribute.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// The start of `s`, at most `max` bytes long.
+fn head(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn indent(line: &str) -> usize {
+    line.bytes().take_while(|&b| b == b' ').count()
+}
+
+fn strip_indent(line: &str, max: usize) -> &str {
+    &line[indent(line).min(max)..]
+}
+
+/// `line` with the tabs in its indentation turned into spaces, to the next
+/// multiple of 4 columns.
+fn expand_tabs(line: &str) -> String {
+    let indentation = line.len() - line.trim_start_matches([' ', '\t']).len();
+    if !line[..indentation].contains('\t') {
+        return line.to_string();
+    }
+    let mut expanded = String::new();
+    for c in line[..indentation].chars() {
+        match c {
+            '\t' => expanded.push_str(&" ".repeat(4 - expanded.len() % 4)),
+            c => expanded.push(c),
+        }
+    }
+    expanded.push_str(&line[indentation..]);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str) -> String {
+        Markdown::new(source).render()
+    }
+
+    #[test]
+    fn test_blocks() {
+        let cases = [
+            (
+                "# Title #\n\nSome *text*.",
+                "<h1>Title</h1>\n<p>Some <em>text</em>.</p>\n",
+            ),
+            ("Title\n=====\nSub\n---", "<h1>Title</h1>\n<h2>Sub</h2>\n"),
+            ("a\nb\n\n***", "<p>a\nb</p>\n<hr>\n"),
+            (
+                "```rust extra\nfn main() {}\n  <x>\n```",
+                concat!(
+                    r#"<pre><code class="language-rust"><span class="hl-keyword">fn</span> "#,
+                    r#"<span class="hl-function">main</span>() {}"#,
+                    "\n  &lt;x&gt;\n</code></pre>\n"
+                ),
+            ),
+            (
+                "    code\n\n    more\n",
+                "<pre><code>code\n\nmore\n</code></pre>\n",
+            ),
+            (
+                "> quoted\nlazy\n> > nested",
+                "<blockquote>\n<p>quoted\nlazy</p>\n<blockquote>\n<p>nested</p>\n</blockquote>\n</blockquote>\n",
+            ),
+            (
+                "- a\n- b\n  - c\n",
+                "<ul>\n<li>a</li>\n<li>b<ul>\n<li>c</li>\n</ul>\n</li>\n</ul>\n",
+            ),
+            (
+                "3. a\n\n4. b",
+                "<ol start=\"3\">\n<li><p>a</p>\n</li>\n<li><p>b</p>\n</li>\n</ol>\n",
+            ),
+            (
+                "- a\n+ b",
+                "<ul>\n<li>a</li>\n</ul>\n<ul>\n<li>b</li>\n</ul>\n",
+            ),
+            ("text\n2. not a list", "<p>text\n2. not a list</p>\n"),
+            (
+                "\tindented\tcode",
+                "<pre><code>indented\tcode\n</code></pre>\n",
+            ),
+        ];
+        for (source, html) in cases {
+            assert_eq!(render(source), html, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_inline() {
+        let cases = [
+            (
+                "**strong** and _em_ and ***both***",
+                "<strong>strong</strong> and <em>em</em> and <em><strong>both</strong></em>",
+            ),
+            (
+                "snake_case_name and *a **b** c*",
+                "snake_case_name and <em>a <strong>b</strong> c</em>",
+            ),
+            (
+                "`a <b>` and `` `tick` ``",
+                "<code>a &lt;b&gt;</code> and <code>`tick`</code>",
+            ),
+            (r"\*not\* &amp; &copy; & <", "*not* &amp; &copy; &amp; &lt;"),
+            ("line  \nbreak\\\nagain", "line<br>\nbreak<br>\nagain"),
+            (
+                "[link](http://a.b/c \"T\") <https://x.y>",
+                "<a href=\"http://a.b/c\" title=\"T\">link</a> <a href=\"https://x.y\">https://x.y</a>",
+            ),
+            (
+                "![an *image*](/i.png)",
+                "<img src=\"/i.png\" alt=\"an image\">",
+            ),
+            (
+                "[ref] and [text][Ref] and [not]\n\n[ref]: /r 'Title'",
+                "<a href=\"/r\" title=\"Title\">ref</a> and <a href=\"/r\" title=\"Title\">text</a> and [not]",
+            ),
+            ("[a [b](/b) c](/a)", "[a <a href=\"/b\">b</a> c](/a)"),
+            (
+                "<mail@example.com>",
+                "<a href=\"mailto:mail@example.com\">mail@example.com</a>",
+            ),
+        ];
+        for (source, html) in cases {
+            assert_eq!(render(source), format!("<p>{}</p>\n", html), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let cases = [
+            (
+                "<script>alert(1)</script>",
+                "&lt;script&gt;alert(1)&lt;/script&gt;\n",
+            ),
+            (
+                "<div onclick=\"x()\" align=center><b>ok</b>\n\ntext",
+                "<div align=\"center\"><b>ok</b>\n<p>text</p>\n</div>",
+            ),
+            (
+                "a <img src=x onerror=alert(1)> b",
+                "<p>a <img src=\"x\"> b</p>\n",
+            ),
+            (
+                "[x](javascript:alert(1)) <a href=\"JavaScript:x\">y</a>",
+                "<p>x <a>y</a></p>\n",
+            ),
+            ("![x](data:text/html,hi) </div></p>", "<p>x </p>\n"),
+            ("<!-- hidden -->\nshown", "\n<p>shown</p>\n"),
+            ("<svg onload=x>", "&lt;svg onload=x&gt;\n"),
+        ];
+        for (source, html) in cases {
+            assert_eq!(render(source), html, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_unsafe_urls() {
+        let sources = [
+            "[a](javascript:alert(1))",
+            "[a](JAVASCRIPT:alert(1))",
+            "[a]( javascript:alert(1))",
+            "[a](jav&#x61;script:alert(1))",
+            "[a](&#106;avascript:alert(1))",
+            "[a](vbscript:x)",
+            "[a](data:text/html;base64,PHNjcmlwdD4=)",
+            "[a](file:///etc/passwd)",
+            "[a][r]\n\n[r]: javascript:alert(1)",
+        ];
+        for source in sources {
+            assert_eq!(render(source), "<p>a</p>\n", "{:?}", source);
+        }
+
+        let sources = [
+            r#"<a href="jav&#x61;script:alert(1)">a</a>"#,
+            r#"<a href="&#106;&#97;vascript:alert(1)">a</a>"#,
+            r#"<a href="java&Tab;script:alert(1)">a</a>"#,
+            r#"<a href="java&#9;script:alert(1)">a</a>"#,
+            r#"<a href="  javascript:alert(1)">a</a>"#,
+        ];
+        for source in sources {
+            assert_eq!(render(source), "<p><a>a</a></p>\n", "{:?}", source);
+        }
+        assert_eq!(render(r#"<img src="javascript:alert(1)">"#), "<img>\n");
+        assert_eq!(
+            render("[a](https://x) [b](mailto:b@x) [c](ftp://x)"),
+            r#"<p><a href="https://x">a</a> <a href="mailto:b@x">b</a> <a href="ftp://x">c</a></p>"#
+                .to_string() + "\n"
+        );
+    }
+
+    #[test]
+    fn test_disallowed_html() {
+        let cases = [
+            (
+                r#"<a href="x" style="color:red" onmouseover="y()" target=_blank>a</a>"#,
+                "<p><a href=\"x\">a</a></p>\n",
+            ),
+            (
+                r#"<iframe src="https://x"></iframe>"#,
+                "&lt;iframe src=&quot;https://x&quot;&gt;&lt;/iframe&gt;\n",
+            ),
+            (
+                "<style>body{}</style>",
+                "&lt;style&gt;body{}&lt;/style&gt;\n",
+            ),
+            (
+                "<object data=x></object> <form action=x><input></form>",
+                concat!(
+                    "<p>&lt;object data=x&gt;&lt;/object&gt; ",
+                    "&lt;form action=x&gt;&lt;input&gt;&lt;/form&gt;</p>\n"
+                ),
+            ),
+            (
+                r#"<img src=a alt='q"uote'>"#,
+                "<img src=\"a\" alt=\"q&quot;uote\">\n",
+            ),
+            (
+                r#"<a title="&quot;><script>">a</a>"#,
+                "<p><a title=\"&quot;&gt;&lt;script&gt;\">a</a></p>\n",
+            ),
+        ];
+        for (source, html) in cases {
+            assert_eq!(render(source), html, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_nested_lists_and_fences() {
+        let cases = [
+            (
+                "- a\n  1. b\n     - c\n- d",
+                concat!(
+                    "<ul>\n<li>a<ol>\n<li>b<ul>\n<li>c</li>\n</ul>\n</li>\n</ol>\n</li>\n",
+                    "<li>d</li>\n</ul>\n"
+                ),
+            ),
+            (
+                "1. a\n   - b\n\n     para\n2. c",
+                concat!(
+                    "<ol>\n<li>a<ul>\n<li><p>b</p>\n<p>para</p>\n</li>\n</ul>\n</li>\n",
+                    "<li>c</li>\n</ol>\n"
+                ),
+            ),
+            (
+                "> a\n2. b",
+                "<blockquote>\n<p>a</p>\n</blockquote>\n<ol start=\"2\">\n<li>b</li>\n</ol>\n",
+            ),
+            ("~~~\n<b>\n~~~", "<pre><code>&lt;b&gt;\n</code></pre>\n"),
+            (
+                "````md\n```\ninner\n```\n````",
+                "<pre><code class=\"language-md\">```\ninner\n```\n</code></pre>\n",
+            ),
+            (
+                "```\nunclosed <x>",
+                "<pre><code>unclosed &lt;x&gt;\n</code></pre>\n",
+            ),
+            (
+                "  ```\n  indented\n    more\n  ```",
+                "<pre><code>indented\n  more\n</code></pre>\n",
+            ),
+            (
+                "- item\n\n  ```\n  code\n  ```",
+                "<ul>\n<li><p>item</p>\n<pre><code>code\n</code></pre>\n</li>\n</ul>\n",
+            ),
+        ];
+        for (source, html) in cases {
+            assert_eq!(render(source), html, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_tables() {
+        let source = concat!(
+            "<table>\n<tr><th colspan=2 style=x>H</th></tr>\n",
+            "<tr><td>a</td><td onclick=x>b</td></tr>\n</table>"
+        );
+        assert_eq!(
+            render(source),
+            concat!(
+                "<table>\n<tr><th colspan=\"2\">H</th></tr>\n",
+                "<tr><td>a</td><td>b</td></tr>\n</table>\n"
+            )
+        );
+        // Tags left open are closed at the end, so the page stays whole.
+        assert_eq!(
+            render("<table><tr><td>x"),
+            "<table><tr><td>x\n</td></tr></table>"
+        );
+        // Pipe tables are not CommonMark and stay text.
+        assert_eq!(
+            render("| a | b |\n|---|---|\n| 1 | 2 |"),
+            "<p>| a | b |\n|---|---|\n| 1 | 2 |</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_relative_links() {
+        let source =
+            "[doc](docs/a%20b.md#top) [up](../x) [abs](/y) [site](https://z)\n\n![img](pics/c.png)";
+        assert_eq!(
+            Markdown::new(source).relative_to("my dir").render(),
+            concat!(
+                r#"<p><a href="/view/my%20dir/docs/a%20b.md#top">doc</a> <a href="/view/x">up</a> "#,
+                r#"<a href="/y">abs</a> <a href="https://z">site</a></p>"#,
+                "\n",
+                r#"<p><img src="/download/my%20dir/pics/c.png?inline" alt="img"></p>"#,
+                "\n"
+            )
+        );
+
+        let link = |base, url: &str| {
+            Markdown::new(&format!("[l]({})", url))
+                .relative_to(base)
+                .render()
+        };
+        assert_eq!(
+            link("a/b", "../../../x"),
+            "<p><a href=\"/view/x\">l</a></p>\n"
+        );
+        assert_eq!(
+            link("a", "./b/../c.md"),
+            "<p><a href=\"/view/a/c.md\">l</a></p>\n"
+        );
+        assert_eq!(
+            link("", "x.md?raw#top"),
+            "<p><a href=\"/view/x.md?raw#top\">l</a></p>\n"
+        );
+        assert_eq!(
+            link("q\"x", "f.md"),
+            "<p><a href=\"/view/q%22x/f.md\">l</a></p>\n"
+        );
+        let raw = Markdown::new(r#"<a href="../up.md">u</a> <img src="i.png">"#).relative_to("a/b");
+        assert_eq!(
+            raw.render(),
+            "<p><a href=\"/view/a/up.md\">u</a> <img src=\"/download/a/b/i.png?inline\"></p>\n"
+        );
+    }
+}
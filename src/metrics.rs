@@ -0,0 +1,433 @@
+//! A metrics registry rendered in the Prometheus text exposition format.
+//!
+//! Metrics are created on first use and looked up by name and labels
+//! afterwards, so application code can count its own events next to the
+//! server's built-in metrics.
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Bucket upper bounds, in seconds, suited to request latencies.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The `Content-Type` of [`Registry::render`]'s output.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// A value that only goes up.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: f64) {
+        add_f64(&self.0, delta);
+    }
+
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1.0);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Counts observations into buckets by upper bound.
+#[derive(Debug, Clone)]
+pub struct Histogram(Arc<HistogramCore>);
+
+#[derive(Debug)]
+struct HistogramCore {
+    bounds: Vec<f64>,
+    /// One per bound, plus one for everything larger.
+    counts: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        let mut bounds: Vec<f64> = buckets.iter().copied().filter(|b| b.is_finite()).collect();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        let counts = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+        Histogram(Arc::new(HistogramCore {
+            bounds,
+            counts,
+            sum: AtomicU64::new(0f64.to_bits()),
+        }))
+    }
+
+    pub fn observe(&self, value: f64) {
+        let core = &self.0;
+        let bucket = core.bounds.partition_point(|&bound| bound < value);
+        core.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        add_f64(&core.sum, value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.0.sum.load(Ordering::Relaxed))
+    }
+}
+
+fn add_f64(cell: &AtomicU64, delta: f64) {
+    let _ = cell.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        Some((f64::from_bits(bits) + delta).to_bits())
+    });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Series {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug)]
+struct Family {
+    name: String,
+    help: String,
+    kind: Kind,
+    series: Vec<(Labels, Series)>,
+}
+
+/// Named metrics, each with any number of labelled series.
+///
+/// Clones share the same metrics, so one registry can be handed to the
+/// server, to application code and to the filter serving it.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    families: Arc<Mutex<Vec<Family>>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// The counter `name` with `labels`, created on first use.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is already registered as another kind of metric.
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+        match self.series(name, help, Kind::Counter, labels, || {
+            Series::Counter(Counter::default())
+        }) {
+            Series::Counter(counter) => counter,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The gauge `name` with `labels`, created on first use.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is already registered as another kind of metric.
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+        match self.series(name, help, Kind::Gauge, labels, || {
+            Series::Gauge(Gauge::default())
+        }) {
+            Series::Gauge(gauge) => gauge,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The histogram `name` with `labels`, created on first use with
+    /// `buckets` as its upper bounds. A `+Inf` bucket is always added.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is already registered as another kind of metric.
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        buckets: &[f64],
+    ) -> Histogram {
+        match self.series(name, help, Kind::Histogram, labels, || {
+            Series::Histogram(Histogram::new(buckets))
+        }) {
+            Series::Histogram(histogram) => histogram,
+            _ => unreachable!(),
+        }
+    }
+
+    fn series(
+        &self,
+        name: &str,
+        help: &str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        create: impl FnOnce() -> Series,
+    ) -> Series {
+        let mut families = self.families.lock().unwrap();
+        let index = match families.iter().position(|f| f.name == name) {
+            Some(index) => index,
+            None => {
+                families.push(Family {
+                    name: name.to_string(),
+                    help: help.to_string(),
+                    kind,
+                    series: Vec::new(),
+                });
+                families.len() - 1
+            }
+        };
+        let family = &mut families[index];
+        if family.kind != kind {
+            let existing = family.kind;
+            // Not while holding the lock, which would poison it.
+            drop(families);
+            panic!(
+                "metric {} is a {}, not a {}",
+                name,
+                existing.name(),
+                kind.name()
+            );
+        }
+
+        let matches = |existing: &Labels| {
+            existing.len() == labels.len()
+                && existing
+                    .iter()
+                    .zip(labels)
+                    .all(|((k, v), (key, value))| k == key && v == value)
+        };
+        if let Some((_, series)) = family.series.iter().find(|(l, _)| matches(l)) {
+            return series.clone();
+        }
+        let series = create();
+        let labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        family.series.push((labels, series.clone()));
+        series
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for family in families.iter() {
+            let name = &family.name;
+            let help = family.help.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, family.kind.name()).unwrap();
+
+            for (labels, series) in &family.series {
+                match series {
+                    Series::Counter(counter) => {
+                        writeln!(out, "{}{} {}", name, label_set(labels, None), counter.get())
+                    }
+                    Series::Gauge(gauge) => writeln!(
+                        out,
+                        "{}{} {}",
+                        name,
+                        label_set(labels, None),
+                        number(gauge.get())
+                    ),
+                    Series::Histogram(histogram) => {
+                        write_histogram(&mut out, name, labels, histogram)
+                    }
+                }
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    labels: &Labels,
+    histogram: &Histogram,
+) -> std::fmt::Result {
+    let core = &histogram.0;
+    let mut cumulative = 0;
+    let bounds = core
+        .bounds
+        .iter()
+        .map(|&b| number(b))
+        .chain(["+Inf".to_string()]);
+    for (bound, count) in bounds.zip(&core.counts) {
+        cumulative += count.load(Ordering::Relaxed);
+        let le = label_set(labels, Some(&bound));
+        writeln!(out, "{}_bucket{} {}", name, le, cumulative)?;
+    }
+    let labels = label_set(labels, None);
+    writeln!(out, "{}_sum{} {}", name, labels, number(histogram.sum()))?;
+    writeln!(out, "{}_count{} {}", name, labels, cumulative)
+}
+
+/// `{a="1",b="2"}`, with `le` appended for histogram buckets.
+fn label_set(labels: &Labels, le: Option<&str>) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect();
+    match pairs.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", pairs.join(",")),
+    }
+}
+
+fn number(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let registry = Registry::new();
+        registry
+            .counter(
+                "requests_total",
+                "Requests.\nAll of them.",
+                &[("route", "a\"b")],
+            )
+            .add(3);
+        registry
+            .counter("requests_total", "", &[("route", "a\"b")])
+            .inc();
+        let gauge = registry.gauge("temperature", "Heat.", &[]);
+        gauge.set(20.5);
+        gauge.dec();
+        let histogram = registry.histogram("latency_seconds", "Latency.", &[], &[1.0, 0.1]);
+        for value in [0.05, 0.1, 0.5, 3.0] {
+            histogram.observe(value);
+        }
+
+        assert_eq!(
+            registry.render(),
+            "# HELP requests_total Requests.\\nAll of them.\n\
+             # TYPE requests_total counter\n\
+             requests_total{route=\"a\\\"b\"} 4\n\
+             # HELP temperature Heat.\n\
+             # TYPE temperature gauge\n\
+             temperature 19.5\n\
+             # HELP latency_seconds Latency.\n\
+             # TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{le=\"0.1\"} 2\n\
+             latency_seconds_bucket{le=\"1\"} 3\n\
+             latency_seconds_bucket{le=\"+Inf\"} 4\n\
+             latency_seconds_sum 3.65\n\
+             latency_seconds_count 4\n"
+        );
+    }
+
+    #[test]
+    fn test_server_metrics() {
+        use crate::http::{Filter, Response, Server, ServerConfig, get, metrics};
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let registry = Registry::new();
+        let server = Server::new(ServerConfig::new("127.0.0.1", 0))
+            .unwrap()
+            .metrics(registry.clone());
+        let port = server.local_addr().unwrap().port();
+        let routes = get("/hello")
+            .map(|_| Response::ok("hi"))
+            .label("hello")
+            .or(get("/metrics").and(metrics(&registry)));
+        std::thread::spawn(move || server.run(routes));
+
+        let fetch = |path: &str| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: a\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        fetch("/hello");
+        fetch("/hello");
+        let response = fetch("/metrics");
+
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.contains("rustserve_requests_total{route=\"hello\",status=\"2xx\"} 2\n"));
+        assert!(response.contains("rustserve_request_duration_seconds_count{route=\"hello\"} 2\n"));
+        // The scrape itself is still being answered.
+        assert!(response.contains("rustserve_connections_active 1\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "metric up is a counter, not a gauge")]
+    fn test_kind_mismatch() {
+        let registry = Registry::new();
+        registry.counter("up", "", &[]);
+        registry.gauge("up", "", &[]);
+    }
+}
@@ -0,0 +1,388 @@
+//! The page showing one file in the browser, with the files next to it a
+//! key press away.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::fileinfo::FileInfo;
+use crate::highlight::Language;
+use crate::html::{
+    encode_path, error_html, format_date, generate_breadcrumb, html_escape, page_html,
+};
+use crate::markdown::Markdown;
+use crate::mime;
+use crate::stats::Stats;
+
+/// How much of a text file is shown, in bytes.
+pub const TEXT_LIMIT: usize = 512 * 1024;
+
+/// How a file is shown, going by its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Image,
+    Audio,
+    Video,
+    Pdf,
+    Text,
+    /// Anything else; files of unknown types that turn out to hold UTF-8
+    /// text are still shown as text.
+    Other,
+}
+
+impl Kind {
+    pub fn of(name: &str) -> Kind {
+        let mime = mime::from_path(name);
+        let essence = mime.split(';').next().unwrap_or(mime);
+        match essence.split_once('/') {
+            Some(("image", _)) => Kind::Image,
+            Some(("audio", _)) => Kind::Audio,
+            Some(("video", _)) => Kind::Video,
+            Some(("text", _)) => Kind::Text,
+            _ => match essence {
+                "application/pdf" => Kind::Pdf,
+                "application/json" | "application/xml" | "application/toml"
+                | "application/yaml" => Kind::Text,
+                _ => Kind::Other,
+            },
+        }
+    }
+}
+
+/// Whether the file `name` may be sent for the browser to show rather than
+/// save, so the preview can embed it. Never for types that can run
+/// scripts when opened on their own, such as HTML and SVG.
+pub fn inline(name: &str) -> bool {
+    match Kind::of(name) {
+        Kind::Image => mime::from_path(name) != "image/svg+xml",
+        Kind::Audio | Kind::Video | Kind::Pdf => true,
+        Kind::Text | Kind::Other => false,
+    }
+}
+
+/// The page for one file below the served root.
+pub struct Preview<'a> {
+    root: &'a Path,
+    path: &'a str,
+    visible: Box<dyn Fn(&str) -> bool + 'a>,
+    text_limit: usize,
+    source: bool,
+}
+
+impl<'a> Preview<'a> {
+    /// The page for `path`, a decoded path of a file below `root`.
+    pub fn new(root: &'a Path, path: &'a str) -> Self {
+        Preview {
+            root,
+            path,
+            visible: Box::new(|_| true),
+            text_limit: TEXT_LIMIT,
+            source: false,
+        }
+    }
+
+    /// Steps only to the files next to this one whose path relative to the
+    /// root passes `visible`.
+    pub fn visible(mut self, visible: impl Fn(&str) -> bool + 'a) -> Self {
+        self.visible = Box::new(visible);
+        self
+    }
+
+    /// Shows at most `bytes` of a text file.
+    pub fn text_limit(mut self, bytes: usize) -> Self {
+        self.text_limit = bytes;
+        self
+    }
+
+    /// Shows Markdown as text with line numbers rather than rendered.
+    pub fn source(mut self, enabled: bool) -> Self {
+        self.source = enabled;
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let info = match FileInfo::read(self.root, self.path) {
+            Ok(info) if !info.is_dir => info,
+            _ => return error_html("Cannot read file"),
+        };
+        let (parent, _) = self.path.rsplit_once('/').unwrap_or(("", self.path));
+        let download = format!("/download/{}", encode_path(self.path));
+        let markdown = mime::from_path(&info.name).starts_with("text/markdown");
+
+        let kind = Kind::of(&info.name);
+        let text = match kind {
+            Kind::Text | Kind::Other => self.read_text(kind),
+            _ => None,
+        };
+        let body = match (kind, &text) {
+            (Kind::Image, _) => format!(
+                r#"<img src="{}?inline" alt="{}">"#,
+                download,
+                html_escape(&info.name)
+            ),
+            (Kind::Audio, _) => format!(
+                r#"<audio controls preload="metadata" src="{}?inline"></audio>"#,
+                download
+            ),
+            (Kind::Video, _) => format!(
+                r#"<video controls preload="metadata" src="{}?inline"></video>"#,
+                download
+            ),
+            (Kind::Pdf, _) => format!(
+                r#"<iframe src="{}?inline" title="{}"></iframe>"#,
+                download,
+                html_escape(&info.name)
+            ),
+            (_, Some(text)) if markdown && !self.source => format!(
+                r#"<div class="markdown">{}</div>{}"#,
+                Markdown::new(&text.content).relative_to(parent).render(),
+                self.notice(&info, text)
+            ),
+            (_, Some(text)) => self.text_html(&info, text),
+            (_, None) => r#"<div class="empty">👀 There is no preview for this type of file</div>"#
+                .to_string(),
+        };
+
+        let theme = match text.is_some() {
+            true => {
+                r#"
+            <button class="button" id="theme" type="button" title="Switch between dark and light code colours">◐ Theme</button>"#
+            }
+            false => "",
+        };
+        let toggle = match (markdown, self.source) {
+            (false, _) => "",
+            (true, false) => {
+                r#"
+            <a class="button" href="?source">Source</a>"#
+            }
+            (true, true) => {
+                r#"
+            <a class="button" href="?">Rendered</a>"#
+            }
+        };
+        let content = format!(
+            r#"<div class="toolbar">
+            <div class="preview-nav">{}</div>
+            <div class="sort">{} · {}</div>{}{}
+            <a class="button" href="{}">⬇ Download</a>
+        </div>
+
+        <div class="preview">
+            {}
+        </div>"#,
+            self.neighbour_links(parent),
+            Stats::format_bytes(info.size),
+            info.modified.map(format_date).unwrap_or_default(),
+            theme,
+            toggle,
+            download,
+            body
+        );
+        let breadcrumb = format!(
+            "{} <span>/</span> {}",
+            generate_breadcrumb(parent),
+            html_escape(&info.name)
+        );
+        page_html(self.root, &breadcrumb, &content, PREVIEW_SCRIPT)
+    }
+
+    /// The start of the file if it holds text: there are no NUL bytes, and
+    /// for a type not known to be text, it is UTF-8.
+    fn read_text(&self, kind: Kind) -> Option<Text> {
+        let file = File::open(self.root.join(self.path)).ok()?;
+        let mut bytes = Vec::new();
+        file.take(self.text_limit as u64 + 1)
+            .read_to_end(&mut bytes)
+            .ok()?;
+        let truncated = bytes.len() > self.text_limit;
+        bytes.truncate(self.text_limit);
+        if bytes.contains(&0) {
+            return None;
+        }
+        // A character cut off at the limit does not make it binary.
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(_) => true,
+            Err(error) => truncated && error.error_len().is_none(),
+        };
+        if kind == Kind::Other && !valid {
+            return None;
+        }
+        Some(Text {
+            content: String::from_utf8_lossy(&bytes).into_owned(),
+            truncated,
+        })
+    }
+
+    fn text_html(&self, info: &FileInfo, text: &Text) -> String {
+        if text.content.is_empty() {
+            return r#"<div class="empty">📭 This file is empty</div>"#.to_string();
+        }
+        let lines = match Language::for_file(&info.name) {
+            Some(language) => language.highlight(&text.content),
+            None => text.content.lines().map(html_escape).collect(),
+        };
+        let lines: String = lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                format!(r#"<span class="line" id="L{}">{}</span>"#, index + 1, line)
+            })
+            .collect();
+        format!(
+            r#"<pre class="code"><code>{}</code></pre>{}"#,
+            lines,
+            self.notice(info, text)
+        )
+    }
+
+    /// Says that only the start of the file is shown, if so.
+    fn notice(&self, info: &FileInfo, text: &Text) -> String {
+        match text.truncated {
+            true => format!(
+                r#"
+            <div class="notice">Showing the first {} of {}; download the file to see the rest</div>"#,
+                Stats::format_bytes(self.text_limit as u64),
+                Stats::format_bytes(info.size)
+            ),
+            false => String::new(),
+        }
+    }
+
+    /// Links to the files before and after this one in `parent`, by name.
+    fn neighbour_links(&self, parent: &str) -> String {
+        let mut names: Vec<String> = fs::read_dir(self.root.join(parent))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| fs::metadata(entry.path()).is_ok_and(|m| m.is_file()))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| (self.visible)(&join(parent, name)))
+            .collect();
+        names.sort();
+
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        let Some(index) = names.iter().position(|n| n == name) else {
+            return String::new();
+        };
+        let link = |index: Option<usize>, id: &str, label: &str| match index
+            .and_then(|index| names.get(index))
+        {
+            Some(name) => format!(
+                r#"<a id="{}" href="/view/{}" title="{}">{}</a>"#,
+                id,
+                encode_path(&join(parent, name)),
+                html_escape(name),
+                label
+            ),
+            None => String::new(),
+        };
+        format!(
+            "{} <span>{} of {}</span> {}",
+            link(index.checked_sub(1), "previous", "← Previous"),
+            index + 1,
+            names.len(),
+            link(Some(index + 1), "next", "Next →")
+        )
+    }
+}
+
+struct Text {
+    content: String,
+    truncated: bool,
+}
+
+fn join(parent: &str, name: &str) -> String {
+    match parent {
+        "" => name.to_string(),
+        _ => format!("{}/{}", parent, name),
+    }
+}
+
+/// Steps to the previous or next file with the arrow keys, unless they are
+/// meant for a player or a text box, and switches the code colours,
+/// remembering the choice.
+const PREVIEW_SCRIPT: &str = r#"
+    <script>
+        document.addEventListener('keydown', function (event) {
+            if (event.altKey || event.ctrlKey || event.metaKey || event.shiftKey) return;
+            if (event.target.closest('audio, video, input, select, textarea')) return;
+            var link = document.getElementById({ ArrowLeft: 'previous', ArrowRight: 'next' }[event.key]);
+            if (link) location.href = link.href;
+        });
+        var theme = document.getElementById('theme');
+        if (theme) theme.addEventListener('click', function () {
+            var light = document.documentElement.classList.toggle('theme-light');
+            localStorage.setItem('code-theme', light ? 'light' : 'dark');
+        });
+    </script>"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds() {
+        assert_eq!(Kind::of("a.PNG"), Kind::Image);
+        assert_eq!(Kind::of("a.mp3"), Kind::Audio);
+        assert_eq!(Kind::of("a.webm"), Kind::Video);
+        assert_eq!(Kind::of("a.pdf"), Kind::Pdf);
+        assert_eq!(Kind::of("main.rs"), Kind::Text);
+        assert_eq!(Kind::of("Cargo.toml"), Kind::Text);
+        assert_eq!(Kind::of("Makefile"), Kind::Other);
+        assert!(inline("a.png") && inline("a.pdf") && inline("a.mp4"));
+        assert!(!inline("a.svg") && !inline("a.html") && !inline("a.txt"));
+    }
+
+    #[test]
+    fn test_render() {
+        let root = std::env::temp_dir().join(format!("rustserve-preview-{}", std::process::id()));
+        fs::create_dir_all(root.join("dir/sub")).unwrap();
+        fs::write(root.join("dir/a.txt"), "one\n<two>\n").unwrap();
+        fs::write(root.join("dir/b.png"), "").unwrap();
+        fs::write(root.join("dir/c.bin"), [1, 0, 2]).unwrap();
+        fs::write(root.join("dir/d"), "x".repeat(100)).unwrap();
+        fs::write(root.join("dir/e.md"), "# Notes\n\nSee [a](a.txt).").unwrap();
+        fs::write(root.join("dir/secret.txt"), "").unwrap();
+
+        let html = Preview::new(&root, "dir/a.txt").render();
+        assert!(html.contains(r#"<span class="line" id="L2">&lt;two&gt;</span>"#));
+        assert!(html.contains(r#"<a id="next" href="/view/dir/b.png""#));
+        assert!(!html.contains(r#"id="previous""#));
+        assert!(html.contains("1 of 6"));
+
+        let html = Preview::new(&root, "dir/b.png")
+            .visible(|path| !path.contains("secret"))
+            .render();
+        assert!(html.contains(r#"<img src="/download/dir/b.png?inline""#));
+        assert!(html.contains(r#"<a id="previous" href="/view/dir/a.txt""#));
+        assert!(html.contains("2 of 5"));
+
+        let html = Preview::new(&root, "dir/e.md").render();
+        assert!(html.contains(r#"<div class="markdown"><h1>Notes</h1>"#));
+        assert!(html.contains(r#"<a href="/view/dir/a.txt">a</a>"#));
+        assert!(html.contains(r#"href="?source">Source</a>"#));
+        let html = Preview::new(&root, "dir/e.md").source(true).render();
+        assert!(html.contains(r#"<span class="line" id="L1"># Notes</span>"#));
+
+        fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        let html = Preview::new(&root, "main.rs").render();
+        assert!(html.contains(r#"id="L1"><span class="hl-keyword">fn</span> "#));
+        assert!(html.contains(r#"id="theme""#) && html.contains("1 of 1"));
+
+        let html = Preview::new(&root, "dir/c.bin").render();
+        assert!(html.contains("no preview") && !html.contains(r#"id="theme""#));
+        let html = Preview::new(&root, "dir/d").text_limit(10).render();
+        assert!(html.contains(r#"id="L1">xxxxxxxxxx<"#));
+        assert!(html.contains("Showing the first 10 B of 100 B"));
+        let html = Preview::new(&root, "dir/secret.txt").render();
+        assert!(html.contains("This file is empty") && !html.contains(r#"id="next""#));
+        assert!(
+            Preview::new(&root, "dir/sub")
+                .render()
+                .contains("Cannot read file")
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
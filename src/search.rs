@@ -0,0 +1,223 @@
+//! Finding files by name below a directory.
+
+use std::fs;
+use std::path::Path;
+
+use crate::fileinfo::FileInfo;
+
+/// Results a search returns unless [`Search::limit`] says otherwise.
+pub const DEFAULT_LIMIT: usize = 500;
+
+/// What a search looks for. A query with `*` or `?` is a glob matched
+/// against whole names, or against whole paths if it contains `/`; any other
+/// query matches names containing it. Both ignore case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Substring(String),
+    Glob { glob: Vec<char>, whole_path: bool },
+}
+
+impl Pattern {
+    pub fn new(query: &str) -> Self {
+        let query = query.trim().to_lowercase();
+        if query.contains(['*', '?']) {
+            Pattern::Glob {
+                whole_path: query.contains('/'),
+                glob: query.trim_start_matches('/').chars().collect(),
+            }
+        } else {
+            Pattern::Substring(query)
+        }
+    }
+
+    /// Whether the entry at `relative_path`, `/`-separated below the
+    /// searched directory, matches.
+    pub fn matches(&self, relative_path: &str) -> bool {
+        let relative_path = relative_path.to_lowercase();
+        let name = relative_path.rsplit('/').next().unwrap_or_default();
+        match self {
+            Pattern::Substring(query) => name.contains(query.as_str()),
+            Pattern::Glob { glob, whole_path } => {
+                let text: Vec<char> = match whole_path {
+                    true => relative_path.chars().collect(),
+                    false => name.chars().collect(),
+                };
+                glob_match(glob, &text)
+            }
+        }
+    }
+}
+
+/// Matches `*` (any run of characters, including `/`) and `?` (one
+/// character), backtracking only to the last `*`.
+fn glob_match(glob: &[char], text: &[char]) -> bool {
+    let (mut g, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    g = star_g + 1;
+                    t = star_t + 1;
+                    star = Some((star_g, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// A recursive search by name below `subpath` in `root`.
+pub struct Search<'a> {
+    root: &'a Path,
+    subpath: &'a str,
+    pattern: Pattern,
+    visible: Box<dyn Fn(&str) -> bool + 'a>,
+    limit: usize,
+}
+
+impl<'a> Search<'a> {
+    /// Searches below `subpath`, a decoded path below `root`, for `query`
+    /// as described for [`Pattern`].
+    pub fn new(root: &'a Path, subpath: &'a str, query: &str) -> Self {
+        Search {
+            root,
+            subpath,
+            pattern: Pattern::new(query),
+            visible: Box::new(|_| true),
+            limit: DEFAULT_LIMIT,
+        }
+    }
+
+    /// Skips entries whose path relative to the root fails `visible`, and
+    /// everything below such directories.
+    pub fn visible(mut self, visible: impl Fn(&str) -> bool + 'a) -> Self {
+        self.visible = Box::new(visible);
+        self
+    }
+
+    /// Stops after `limit` results.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// The matches, sorted by path, and whether the search stopped at the
+    /// limit. Symbolic links to directories are not followed, so a link
+    /// cannot make the search loop or leave the root.
+    pub fn run(&self) -> (Vec<FileInfo>, bool) {
+        let mut found = Vec::new();
+        let mut pending = vec![self.subpath.trim_matches('/').to_string()];
+        let mut truncated = false;
+
+        'walk: while let Some(directory) = pending.pop() {
+            let Ok(entries) = fs::read_dir(self.root.join(&directory)) else {
+                continue;
+            };
+            let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
+            entries.sort_by_key(|entry| entry.file_name());
+            // Popped from the end, so pushed in reverse to be walked in order.
+            for entry in entries.iter().rev() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let path = match directory.is_empty() {
+                    true => name,
+                    false => format!("{}/{}", directory, name),
+                };
+                if !(self.visible)(&path) {
+                    continue;
+                }
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if file_type.is_dir() {
+                    pending.push(path.clone());
+                }
+
+                let below = path
+                    .strip_prefix(self.subpath.trim_matches('/'))
+                    .unwrap_or(&path)
+                    .trim_start_matches('/');
+                if !self.pattern.matches(below) {
+                    continue;
+                }
+                if found.len() == self.limit {
+                    truncated = true;
+                    break 'walk;
+                }
+                if let Ok(info) = FileInfo::read(self.root, &path) {
+                    found.push(info);
+                }
+            }
+        }
+
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        (found, truncated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let report = Pattern::new("Report");
+        assert!(report.matches("docs/2024-report.PDF"));
+        assert!(!report.matches("reports/notes.txt"));
+
+        let glob = Pattern::new("*.rs");
+        assert!(glob.matches("src/main.rs"));
+        assert!(!glob.matches("src/main.rs.bak"));
+        assert!(Pattern::new("?ain.*").matches("src/main.rs"));
+        assert!(Pattern::new("*a*b*c").matches("xaxxbxxbc"));
+        assert!(!Pattern::new("*a*b*c").matches("xaxxbxxb"));
+
+        let path_glob = Pattern::new("src/*/mod.rs");
+        assert!(path_glob.matches("src/http/mod.rs"));
+        assert!(!path_glob.matches("mod.rs"));
+    }
+
+    #[test]
+    fn test_search() {
+        let root = std::env::temp_dir().join(format!("rustserve-search-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/deep/er")).unwrap();
+        fs::create_dir_all(root.join("secret")).unwrap();
+        for file in [
+            "notes.txt",
+            "a/Notes.md",
+            "a/deep/er/notes.txt",
+            "secret/notes.txt",
+        ] {
+            fs::write(root.join(file), "x").unwrap();
+        }
+
+        let search = Search::new(&root, "", "notes").visible(|path| !path.starts_with("secret"));
+        let (found, truncated) = search.run();
+        let paths: Vec<_> = found.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["a/Notes.md", "a/deep/er/notes.txt", "notes.txt"]);
+        assert!(!truncated);
+        assert_eq!(found[0].size, 1);
+
+        let (found, _) = Search::new(&root, "a", "*.txt").run();
+        assert_eq!(found[0].path, "a/deep/er/notes.txt");
+        let (found, _) = Search::new(&root, "a", "deep/*").run();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].path, "a/deep/er");
+        assert!(found[0].is_dir);
+
+        let (found, truncated) = Search::new(&root, "", "e").limit(2).run();
+        assert_eq!(found.len(), 2);
+        assert!(truncated);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,37 +1,232 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// Thread-safe statistics for the file server
-#[derive(Default)]
+/// Thread-safe statistics for the file server.
+///
+/// A [`Server`](crate::http::Server) given these with
+/// [`Server::stats`](crate::http::Server::stats) keeps them up to date.
 pub struct Stats {
     pub active_connections: AtomicU64,
     pub total_requests: AtomicU64,
     pub total_bytes_sent: AtomicU64,
     pub files_downloaded: AtomicU64,
+    /// Responses by status class: `1xx` at index 0 through `5xx` at 4.
+    pub responses: [AtomicU64; 5],
+    /// Time spent producing and sending responses, in microseconds.
+    pub total_latency_micros: AtomicU64,
+    /// Time spent producing and sending each response, in microseconds.
+    pub latency: LogHistogram,
+    /// Body bytes actually sent for each response, counted once the body
+    /// has been written.
+    pub response_size: LogHistogram,
+    rates: RateWindow,
+    started: Instant,
+    routes: RwLock<HashMap<String, Arc<RouteCounters>>>,
+    next_connection: AtomicU64,
+    connections: Mutex<HashMap<u64, ActiveConnection>>,
+    downloads: Mutex<HashMap<String, u64>>,
+    errors: Mutex<VecDeque<RecentError>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            active_connections: AtomicU64::new(0),
+            total_requests: AtomicU64::new(0),
+            total_bytes_sent: AtomicU64::new(0),
+            files_downloaded: AtomicU64::new(0),
+            responses: Default::default(),
+            total_latency_micros: AtomicU64::new(0),
+            latency: LogHistogram::new(),
+            response_size: LogHistogram::new(),
+            rates: RateWindow::new(),
+            started: Instant::now(),
+            routes: RwLock::default(),
+            next_connection: AtomicU64::new(0),
+            connections: Mutex::default(),
+            downloads: Mutex::default(),
+            errors: Mutex::default(),
+        }
+    }
+}
+
+/// A client connection that is open right now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveConnection {
+    pub remote_addr: Option<SocketAddr>,
+    /// The path of the request being answered, or last answered.
+    pub path: Option<String>,
+    pub opened: Instant,
+}
+
+/// A request answered with a `4xx` or `5xx` status.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentError {
+    pub time: SystemTime,
+    pub remote_addr: Option<SocketAddr>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+/// How many of the latest errors [`Stats::recent_errors`] keeps.
+pub const RECENT_ERRORS: usize = 50;
+
+/// Counters for the requests answered by one labelled route.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteStats {
+    pub requests: u64,
+    /// Responses by status class, as in [`Stats::responses`].
+    pub responses: [u64; 5],
+    pub total_latency: Duration,
+    /// In microseconds.
+    pub latency: Percentiles,
+    /// In bytes.
+    pub response_size: Percentiles,
+}
+
+#[derive(Default)]
+struct RouteCounters {
+    requests: AtomicU64,
+    responses: [AtomicU64; 5],
+    total_latency_micros: AtomicU64,
+    latency: LogHistogram,
+    response_size: LogHistogram,
 }
 
+impl RouteCounters {
+    fn snapshot(&self) -> RouteStats {
+        RouteStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            responses: self
+                .responses
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            total_latency: Duration::from_micros(self.total_latency_micros.load(Ordering::Relaxed)),
+            latency: self.latency.percentiles(),
+            response_size: self.response_size.percentiles(),
+        }
+    }
+}
+
+impl RouteStats {
+    pub fn average_latency(&self) -> Duration {
+        average(self.total_latency, self.requests)
+    }
+}
+
+/// The label responses without one are counted under.
+pub const UNLABELLED: &str = "other";
+
 impl Stats {
     pub fn new() -> Self {
         Stats::default()
     }
 
-    pub fn connection_opened(&self) {
+    /// Counts a connection from `remote_addr` as open, returning the id to
+    /// pass to [`Stats::connection_closed`].
+    pub fn connection_opened(&self, remote_addr: Option<SocketAddr>) -> u64 {
         self.active_connections.fetch_add(1, Ordering::Relaxed);
+        let id = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        let connection = ActiveConnection {
+            remote_addr,
+            path: None,
+            opened: Instant::now(),
+        };
+        self.connections.lock().unwrap().insert(id, connection);
+        id
+    }
+
+    /// Notes that connection `id` is answering a request for `path`.
+    pub fn connection_serving(&self, id: u64, path: &str) {
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(&id) {
+            connection.path = Some(path.to_string());
+        }
     }
 
-    pub fn connection_closed(&self) {
+    pub fn connection_closed(&self, id: u64) {
         self.active_connections.fetch_sub(1, Ordering::Relaxed);
+        self.connections.lock().unwrap().remove(&id);
     }
 
     pub fn request_served(&self) {
         self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.rates.add(1, 0);
     }
 
     pub fn bytes_sent(&self, bytes: u64) {
         self.total_bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        self.rates.add(0, bytes);
     }
 
-    pub fn file_downloaded(&self) {
+    pub fn file_downloaded(&self, path: &str) {
         self.files_downloaded.fetch_add(1, Ordering::Relaxed);
+        *self
+            .downloads
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default() += 1;
+    }
+
+    /// Keeps `error` among the [`RECENT_ERRORS`] latest.
+    pub fn error_returned(&self, error: RecentError) {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    /// Counts a request answered with `status` by the route labelled `route`
+    /// after `latency`, having sent `size` body bytes if known.
+    pub fn request_completed(
+        &self,
+        route: Option<&str>,
+        status: u16,
+        latency: Duration,
+        size: Option<u64>,
+    ) {
+        self.request_served();
+        let class = status_class(status);
+        let micros = latency.as_micros().try_into().unwrap_or(u64::MAX);
+
+        let route = self.route(route.unwrap_or(UNLABELLED));
+        for (responses, latency, total_latency, response_size) in [
+            (
+                &self.responses,
+                &self.latency,
+                &self.total_latency_micros,
+                &self.response_size,
+            ),
+            (
+                &route.responses,
+                &route.latency,
+                &route.total_latency_micros,
+                &route.response_size,
+            ),
+        ] {
+            if let Some(class) = class {
+                responses[class].fetch_add(1, Ordering::Relaxed);
+            }
+            total_latency.fetch_add(micros, Ordering::Relaxed);
+            latency.record(micros);
+            if let Some(size) = size {
+                response_size.record(size);
+            }
+        }
+        route.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn route(&self, label: &str) -> Arc<RouteCounters> {
+        if let Some(route) = self.routes.read().unwrap().get(label) {
+            return Arc::clone(route);
+        }
+        let mut routes = self.routes.write().unwrap();
+        Arc::clone(routes.entry(label.to_string()).or_default())
     }
 
     pub fn get_active_connections(&self) -> u64 {
@@ -50,6 +245,99 @@ impl Stats {
         self.files_downloaded.load(Ordering::Relaxed)
     }
 
+    /// Responses whose status is in `class`, e.g. `4` for `4xx`.
+    pub fn get_responses(&self, class: u16) -> u64 {
+        match status_class(class * 100) {
+            Some(index) => self.responses[index].load(Ordering::Relaxed),
+            None => 0,
+        }
+    }
+
+    pub fn get_average_latency(&self) -> Duration {
+        let micros = self.total_latency_micros.load(Ordering::Relaxed);
+        average(Duration::from_micros(micros), self.get_total_requests())
+    }
+
+    /// The 50th, 90th and 99th percentile response times.
+    pub fn latency_percentiles(&self) -> [Duration; 3] {
+        let p = self.latency.percentiles();
+        [p.p50, p.p90, p.p99].map(Duration::from_micros)
+    }
+
+    /// Requests per second over the last `window`, at most
+    /// [`RATE_WINDOW`] long.
+    pub fn request_rate(&self, window: Duration) -> f64 {
+        let (requests, seconds) = self.rates.sum(window, self.started.elapsed());
+        requests.0 as f64 / seconds
+    }
+
+    /// Bytes written per second over the last `window`, at most
+    /// [`RATE_WINDOW`] long.
+    pub fn byte_rate(&self, window: Duration) -> f64 {
+        let (totals, seconds) = self.rates.sum(window, self.started.elapsed());
+        totals.1 as f64 / seconds
+    }
+
+    /// Requests answered in each of the last `seconds` whole seconds, oldest
+    /// first, going back at most [`RATE_WINDOW`].
+    pub fn request_history(&self, seconds: usize) -> Vec<u64> {
+        self.rates
+            .history(seconds)
+            .map(|(requests, _)| requests)
+            .collect()
+    }
+
+    /// Bytes written in each of the last `seconds` whole seconds, oldest
+    /// first, going back at most [`RATE_WINDOW`].
+    pub fn byte_history(&self, seconds: usize) -> Vec<u64> {
+        self.rates
+            .history(seconds)
+            .map(|(_, bytes)| bytes)
+            .collect()
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The connections open right now, oldest first.
+    pub fn connections(&self) -> Vec<ActiveConnection> {
+        let mut connections: Vec<_> = self.connections.lock().unwrap().values().cloned().collect();
+        connections.sort_by_key(|connection| connection.opened);
+        connections
+    }
+
+    /// The `limit` most downloaded paths with their download counts, most
+    /// downloaded first.
+    pub fn top_files(&self, limit: usize) -> Vec<(String, u64)> {
+        let mut files: Vec<_> = self
+            .downloads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, count)| (path.clone(), *count))
+            .collect();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        files.truncate(limit);
+        files
+    }
+
+    /// The latest error responses, newest first.
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.errors.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// The counters of every route seen so far, sorted by label.
+    pub fn routes(&self) -> Vec<(String, RouteStats)> {
+        let routes = self.routes.read().unwrap();
+        let mut routes: Vec<_> = routes
+            .iter()
+            .map(|(label, counters)| (label.clone(), counters.snapshot()))
+            .collect();
+        routes.sort_by(|a, b| a.0.cmp(&b.0));
+        routes
+    }
+
     /// Format bytes into human-readable string (e.g., "1.2 GB")
     pub fn format_bytes(bytes: u64) -> String {
         const KB: u64 = 1024;
@@ -67,3 +355,424 @@ impl Stats {
         }
     }
 }
+
+/// Values at the 50th, 90th and 99th percentiles of a [`LogHistogram`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+/// Sub-buckets per power of two, so values are kept to within 1/8.
+const SUB_BUCKETS: usize = 8;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+const BUCKETS: usize = SUB_BUCKETS * (64 - SUB_BITS as usize + 1);
+
+/// A lock-free histogram of `u64` values in logarithmic buckets: each power
+/// of two is split into eight, so a percentile is accurate to 12.5% however
+/// large the values get.
+pub struct LogHistogram {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+}
+
+impl Default for LogHistogram {
+    fn default() -> Self {
+        LogHistogram::new()
+    }
+}
+
+impl LogHistogram {
+    pub fn new() -> Self {
+        LogHistogram {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        self.buckets[bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// The largest value in the bucket holding the `p`th percentile, for
+    /// `p` from 0 to 100, or 0 if nothing has been recorded.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0 * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_max(index);
+            }
+        }
+        bucket_max(BUCKETS - 1)
+    }
+
+    pub fn percentiles(&self) -> Percentiles {
+        Percentiles {
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p99: self.percentile(99.0),
+        }
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let sub = (value >> (exponent - SUB_BITS)) as usize & (SUB_BUCKETS - 1);
+    SUB_BUCKETS * (exponent - SUB_BITS + 1) as usize + sub
+}
+
+fn bucket_max(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let exponent = (index / SUB_BUCKETS) as u32 + SUB_BITS - 1;
+    let sub = (index % SUB_BUCKETS) as u64;
+    let width = 1u64 << (exponent - SUB_BITS);
+    ((SUB_BUCKETS as u64 + sub) << (exponent - SUB_BITS)).saturating_add(width - 1)
+}
+
+/// The longest window [`Stats::request_rate`] and [`Stats::byte_rate`]
+/// cover.
+pub const RATE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+const RATE_SLOTS: usize = RATE_WINDOW.as_secs() as usize;
+
+/// Requests and bytes per second over the last [`RATE_WINDOW`], in a ring of
+/// one-second slots reused as time moves on.
+struct RateWindow {
+    slots: Box<[RateSlot]>,
+}
+
+#[derive(Default)]
+struct RateSlot {
+    /// The Unix second the counts belong to.
+    second: AtomicU64,
+    requests: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl RateWindow {
+    fn new() -> Self {
+        RateWindow {
+            slots: (0..RATE_SLOTS).map(|_| RateSlot::default()).collect(),
+        }
+    }
+
+    fn add(&self, requests: u64, bytes: u64) {
+        let now = unix_second();
+        let slot = &self.slots[now as usize % RATE_SLOTS];
+        let second = slot.second.load(Ordering::Relaxed);
+        // The first writer in a new second clears what the slot held a
+        // window ago. A count racing with that may be lost.
+        if second != now
+            && slot
+                .second
+                .compare_exchange(second, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            slot.requests.store(0, Ordering::Relaxed);
+            slot.bytes.store(0, Ordering::Relaxed);
+        }
+        slot.requests.fetch_add(requests, Ordering::Relaxed);
+        slot.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Totals over the last `window` and the seconds they were counted
+    /// over, which is less than `window` while `uptime` is shorter.
+    fn sum(&self, window: Duration, uptime: Duration) -> ((u64, u64), f64) {
+        let window = window.min(RATE_WINDOW).as_secs().max(1);
+        let now = unix_second();
+        let mut totals = (0, 0);
+        for slot in self.slots.iter() {
+            let second = slot.second.load(Ordering::Relaxed);
+            if second <= now && now - second < window {
+                totals.0 += slot.requests.load(Ordering::Relaxed);
+                totals.1 += slot.bytes.load(Ordering::Relaxed);
+            }
+        }
+        let seconds = (window as f64).min(uptime.as_secs_f64().max(1.0));
+        (totals, seconds)
+    }
+
+    /// Requests and bytes in each of the last `seconds` whole seconds, oldest
+    /// first.
+    fn history(&self, seconds: usize) -> impl Iterator<Item = (u64, u64)> {
+        let now = unix_second();
+        let seconds = seconds.min(RATE_SLOTS - 1) as u64;
+        (now.saturating_sub(seconds)..now).map(|second| {
+            let slot = &self.slots[second as usize % RATE_SLOTS];
+            match slot.second.load(Ordering::Relaxed) == second {
+                true => (
+                    slot.requests.load(Ordering::Relaxed),
+                    slot.bytes.load(Ordering::Relaxed),
+                ),
+                false => (0, 0),
+            }
+        })
+    }
+}
+
+fn unix_second() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn status_class(status: u16) -> Option<usize> {
+    match status / 100 {
+        class @ 1..=5 => Some(class as usize - 1),
+        _ => None,
+    }
+}
+
+fn average(total: Duration, count: u64) -> Duration {
+    match count {
+        0 => Duration::ZERO,
+        count => total / count.try_into().unwrap_or(u32::MAX),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_completed() {
+        let stats = Stats::new();
+        stats.request_completed(Some("download"), 200, Duration::from_millis(3), Some(4096));
+        stats.request_completed(Some("download"), 404, Duration::from_millis(1), Some(9));
+        stats.request_completed(None, 503, Duration::from_millis(2), None);
+
+        assert_eq!(stats.get_total_requests(), 3);
+        assert_eq!(
+            (
+                stats.get_responses(2),
+                stats.get_responses(4),
+                stats.get_responses(5)
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(stats.get_average_latency(), Duration::from_millis(2));
+
+        let routes = stats.routes();
+        assert_eq!(routes[0].0, "download");
+        assert_eq!(routes[0].1.requests, 2);
+        assert_eq!(routes[0].1.responses, [0, 1, 0, 1, 0]);
+        assert_eq!(routes[0].1.average_latency(), Duration::from_millis(2));
+        assert_eq!(routes[0].1.response_size.p99, 4096 + 511);
+        assert_eq!(routes[1].0, UNLABELLED);
+        assert_eq!(routes[1].1.response_size, Percentiles::default());
+
+        assert_eq!(stats.latency.count(), 3);
+        assert_eq!(stats.response_size.count(), 2);
+        assert_eq!(stats.request_rate(Duration::from_secs(60)), 3.0);
+    }
+
+    #[test]
+    fn test_buckets() {
+        let mut previous = None;
+        for value in (0..4096).chain([u64::MAX / 3, u64::MAX - 1, u64::MAX]) {
+            let index = bucket_index(value);
+            assert!(index < BUCKETS);
+            assert!(value <= bucket_max(index), "{}", value);
+            assert!(index == 0 || value > bucket_max(index - 1), "{}", value);
+            if let Some(previous) = previous {
+                assert!(index >= previous);
+            }
+            previous = Some(index);
+        }
+        assert_eq!(bucket_max(BUCKETS - 1), u64::MAX);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let histogram = LogHistogram::new();
+        assert_eq!(histogram.percentile(50.0), 0);
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+        let Percentiles { p50, p90, p99 } = histogram.percentiles();
+        for (value, expected) in [(p50, 500), (p90, 900), (p99, 990)] {
+            assert!(
+                value >= expected && value <= expected + expected / 8,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(histogram.percentile(100.0), 1023);
+    }
+
+    #[test]
+    fn test_server_records_stats() {
+        use crate::http::{Filter, Response, Server, ServerConfig, get};
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::sync::Arc;
+
+        let stats = Arc::new(Stats::new());
+        let server = Server::new(ServerConfig::new("127.0.0.1", 0))
+            .unwrap()
+            .stats(Arc::clone(&stats));
+        let port = server.local_addr().unwrap().port();
+        let routes = get("/hello").map(|_| Response::ok("hi")).label("hello");
+        std::thread::spawn(move || server.run(routes));
+
+        let mut written = 0;
+        for path in ["/hello", "/missing"] {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: a\r\n\r\n", path).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            written += response.len() as u64;
+        }
+
+        assert_eq!(stats.get_total_requests(), 2);
+        assert_eq!((stats.get_responses(2), stats.get_responses(4)), (1, 1));
+        assert_eq!(stats.get_total_bytes_sent(), written);
+        assert_eq!(stats.get_active_connections(), 0);
+        let labels: Vec<String> = stats.routes().into_iter().map(|(label, _)| label).collect();
+        assert_eq!(labels, vec!["hello", UNLABELLED]);
+        assert!(stats.connections().is_empty());
+        let errors = stats.recent_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            (errors[0].path.as_str(), errors[0].status),
+            ("/missing", 404)
+        );
+        assert!(
+            errors[0]
+                .remote_addr
+                .is_some_and(|addr| addr.ip().is_loopback())
+        );
+    }
+
+    #[test]
+    fn test_streamed_bodies_are_timed_until_sent() {
+        use crate::http::{Backend, Filter, Response, Server, ServerConfig, get};
+        use std::io::{self, Read, Write};
+        use std::net::TcpStream;
+        use std::sync::Arc;
+
+        /// A body of `.0` bytes, each taking 50 ms to read.
+        struct Slow(u8);
+        impl Read for Slow {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 == 0 {
+                    return Ok(0);
+                }
+                std::thread::sleep(Duration::from_millis(50));
+                self.0 -= 1;
+                buf[0] = b'x';
+                Ok(1)
+            }
+        }
+
+        let check = |backend| {
+            let stats = Arc::new(Stats::new());
+            let config = ServerConfig::new("127.0.0.1", 0).backend(backend);
+            let server = Server::new(config).unwrap().stats(Arc::clone(&stats));
+            let port = server.local_addr().unwrap().port();
+            let routes = get("/slow").map(|_| Response::stream(Slow(4), Some(4)));
+            std::thread::spawn(move || server.run(routes));
+
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(stream, "GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            stream.read_to_end(&mut Vec::new()).unwrap();
+
+            assert_eq!(stats.get_total_requests(), 1, "{:?}", backend);
+            assert!(stats.get_average_latency() >= Duration::from_millis(200));
+            assert_eq!(stats.get_files_downloaded(), 1);
+        };
+        check(Backend::Threaded);
+        #[cfg(all(feature = "epoll", target_os = "linux"))]
+        check(Backend::Epoll);
+    }
+
+    #[test]
+    fn test_malformed_requests_are_counted() {
+        use crate::http::{Backend, Filter, Response, Server, ServerConfig, get};
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::sync::Arc;
+
+        let check = |backend| {
+            let stats = Arc::new(Stats::new());
+            let config = ServerConfig::new("127.0.0.1", 0).backend(backend);
+            let server = Server::new(config).unwrap().stats(Arc::clone(&stats));
+            let port = server.local_addr().unwrap().port();
+            std::thread::spawn(move || server.run(get("/").map(|_| Response::ok("hi"))));
+
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(stream, "BREW /pot HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 400"), "{:?}", backend);
+
+            assert_eq!(stats.get_total_requests(), 1, "{:?}", backend);
+            assert_eq!(stats.get_responses(4), 1);
+            let errors = stats.recent_errors();
+            assert_eq!((errors[0].method.as_str(), errors[0].status), ("-", 400));
+        };
+        check(Backend::Threaded);
+        #[cfg(all(feature = "epoll", target_os = "linux"))]
+        check(Backend::Epoll);
+    }
+
+    #[test]
+    fn test_connections_files_and_errors() {
+        let stats = Stats::new();
+        let addr = "10.0.0.7:5000".parse().unwrap();
+        let first = stats.connection_opened(Some(addr));
+        let second = stats.connection_opened(None);
+        stats.connection_serving(first, "/download/a.txt");
+        stats.connection_closed(second);
+        let connections = stats.connections();
+        assert_eq!(stats.get_active_connections(), 1);
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].remote_addr, Some(addr));
+        assert_eq!(connections[0].path.as_deref(), Some("/download/a.txt"));
+
+        for path in ["/b", "/a", "/b", "/c"] {
+            stats.file_downloaded(path);
+        }
+        assert_eq!(
+            stats.top_files(2),
+            vec![("/b".to_string(), 2), ("/a".to_string(), 1)]
+        );
+        assert_eq!(stats.get_files_downloaded(), 4);
+
+        for status in 0..RECENT_ERRORS as u16 + 2 {
+            stats.error_returned(RecentError {
+                time: SystemTime::now(),
+                remote_addr: None,
+                method: "GET".to_string(),
+                path: "/".to_string(),
+                status,
+            });
+        }
+        let errors = stats.recent_errors();
+        assert_eq!(errors.len(), RECENT_ERRORS);
+        assert_eq!(errors[0].status, RECENT_ERRORS as u16 + 1);
+        assert_eq!(errors[RECENT_ERRORS - 1].status, 2);
+    }
+}
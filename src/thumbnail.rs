@@ -0,0 +1,808 @@
+//! Small previews of images for the gallery, made on first request and
+//! kept on disk.
+//!
+//! Each format is decoded only when its feature is enabled: `png`, `jpeg`
+//! and `gif` use the crates of the same name, `bmp` is read here. Every
+//! thumbnail is written as PNG.
+
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+use crate::compression::{Crc32, ZlibEncoder};
+use crate::crypto::sha256;
+
+/// The longest side of a thumbnail unless [`Thumbnails::size`] says
+/// otherwise, in pixels.
+pub const SIZE: u32 = 256;
+
+/// The largest image decoded, in pixels; bigger ones are refused rather
+/// than held in memory.
+pub const MAX_PIXELS: u64 = 40_000_000;
+
+/// Images larger than this are not read at all, in bytes.
+const MAX_FILE_SIZE: u64 = 64 << 20;
+
+/// Changes whenever thumbnails come out differently, so that the old ones
+/// are no longer used.
+const VERSION: &str = "1";
+
+/// The formats this build decodes, by extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+}
+
+impl Format {
+    fn of(name: &str) -> Option<Format> {
+        let (_, extension) = name.rsplit_once('.')?;
+        let format = match extension.to_ascii_lowercase().as_str() {
+            "png" => Format::Png,
+            "jpg" | "jpeg" | "jpe" | "jfif" => Format::Jpeg,
+            "gif" => Format::Gif,
+            "bmp" | "dib" => Format::Bmp,
+            _ => return None,
+        };
+        format.enabled().then_some(format)
+    }
+
+    fn enabled(self) -> bool {
+        match self {
+            Format::Png => cfg!(feature = "png"),
+            Format::Jpeg => cfg!(feature = "jpeg"),
+            Format::Gif => cfg!(feature = "gif"),
+            Format::Bmp => cfg!(feature = "bmp"),
+        }
+    }
+}
+
+/// Whether this build can make a thumbnail of the file `name`.
+pub fn decodable(name: &str) -> bool {
+    Format::of(name).is_some()
+}
+
+/// Eight-bit RGBA pixels, row by row from the top.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Decodes `bytes` as the format the file `name` has by its extension.
+    #[cfg_attr(
+        not(any(feature = "png", feature = "jpeg", feature = "gif", feature = "bmp")),
+        allow(unused_variables)
+    )]
+    pub fn decode(name: &str, bytes: &[u8]) -> io::Result<Image> {
+        match Format::of(name) {
+            #[cfg(feature = "png")]
+            Some(Format::Png) => decode_png(bytes),
+            #[cfg(feature = "jpeg")]
+            Some(Format::Jpeg) => decode_jpeg(bytes),
+            #[cfg(feature = "gif")]
+            Some(Format::Gif) => decode_gif(bytes),
+            #[cfg(feature = "bmp")]
+            Some(Format::Bmp) => decode_bmp(bytes),
+            _ => Err(unsupported()),
+        }
+    }
+
+    /// The image shrunk to fit in a `size` pixel square, averaging the
+    /// pixels each one covers. Smaller images are left as they are.
+    pub fn fit(&self, size: u32) -> Image {
+        let size = size.max(1);
+        if self.width <= size && self.height <= size {
+            return self.clone();
+        }
+        let (width, height) = if self.width >= self.height {
+            let height = (self.height as u64 * size as u64 / self.width as u64).max(1);
+            (size, height as u32)
+        } else {
+            let width = (self.width as u64 * size as u64 / self.height as u64).max(1);
+            (width as u32, size)
+        };
+
+        let spans = |from: u32, to: u32| -> Vec<(usize, usize)> {
+            (0..to as u64)
+                .map(|i| {
+                    let start = i * from as u64 / to as u64;
+                    let end = ((i + 1) * from as u64 / to as u64).max(start + 1);
+                    (start as usize, end as usize)
+                })
+                .collect()
+        };
+        let columns = spans(self.width, width);
+        let rows = spans(self.height, height);
+        let stride = self.width as usize * 4;
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        let mut sums = vec![[0u64; 4]; width as usize];
+        for &(top, bottom) in &rows {
+            sums.iter_mut().for_each(|sum| *sum = [0; 4]);
+            for row in self.pixels[top * stride..bottom * stride].chunks_exact(stride) {
+                for (sum, &(left, right)) in sums.iter_mut().zip(&columns) {
+                    for pixel in row[left * 4..right * 4].chunks_exact(4) {
+                        // Weighted by alpha so transparent pixels lend no colour.
+                        let alpha = pixel[3] as u64;
+                        sum[0] += pixel[0] as u64 * alpha;
+                        sum[1] += pixel[1] as u64 * alpha;
+                        sum[2] += pixel[2] as u64 * alpha;
+                        sum[3] += alpha;
+                    }
+                }
+            }
+            for (sum, &(left, right)) in sums.iter().zip(&columns) {
+                let count = ((bottom - top) * (right - left)) as u64;
+                let alpha = sum[3];
+                match alpha {
+                    0 => pixels.extend_from_slice(&[0; 4]),
+                    _ => pixels.extend_from_slice(&[
+                        ((sum[0] + alpha / 2) / alpha) as u8,
+                        ((sum[1] + alpha / 2) / alpha) as u8,
+                        ((sum[2] + alpha / 2) / alpha) as u8,
+                        ((alpha + count / 2) / count) as u8,
+                    ]),
+                }
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The image as a PNG file, without an alpha channel if every pixel is
+    /// opaque.
+    pub fn to_png(&self) -> io::Result<Vec<u8>> {
+        let opaque = self.pixels.chunks_exact(4).all(|pixel| pixel[3] == 255);
+        let channels = if opaque { 3 } else { 4 };
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // Eight bits per sample, truecolour with or without alpha, deflate,
+        // adaptive filtering, not interlaced.
+        header.extend_from_slice(&[8, if opaque { 2 } else { 6 }, 0, 0, 0]);
+
+        let mut data = ZlibEncoder::new(Vec::new());
+        let mut line = Vec::with_capacity(1 + self.width as usize * channels);
+        for row in self.pixels.chunks_exact(self.width as usize * 4) {
+            line.clear();
+            line.extend(
+                row.chunks_exact(4)
+                    .flat_map(|pixel| pixel[..channels].iter().copied()),
+            );
+            // The Sub filter: each byte less the one a pixel to its left.
+            for i in (channels..line.len()).rev() {
+                line[i] = line[i].wrapping_sub(line[i - channels]);
+            }
+            data.write_all(&[1])?;
+            data.write_all(&line)?;
+        }
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &data.finish()?);
+        write_chunk(&mut png, b"IEND", &[]);
+        Ok(png)
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.value().to_be_bytes());
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, "no decoder for this type of image")
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// Refuses images of more than [`MAX_PIXELS`] before they are decoded.
+#[cfg(any(feature = "png", feature = "jpeg", feature = "gif", feature = "bmp"))]
+fn check_size(width: u64, height: u64) -> io::Result<()> {
+    if width == 0 || height == 0 {
+        return Err(invalid("the image is empty"));
+    }
+    if width * height > MAX_PIXELS {
+        return Err(invalid(format!(
+            "the image is too large ({}×{})",
+            width, height
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "png")]
+fn decode_png(bytes: &[u8]) -> io::Result<Image> {
+    use png::{ColorType, Limits, Transformations};
+
+    let mut decoder = png::Decoder::new_with_limits(
+        bytes,
+        Limits {
+            bytes: MAX_PIXELS as usize * 4,
+        },
+    );
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid_png)?;
+    let info = reader.info();
+    check_size(info.width as u64, info.height as u64)?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer).map_err(invalid_png)?;
+    let mut pixels = Vec::with_capacity(frame.width as usize * frame.height as usize * 4);
+    for row in buffer[..frame.buffer_size()].chunks_exact(frame.line_size) {
+        let samples = &row[..frame.width as usize * frame.color_type.samples()];
+        match frame.color_type {
+            ColorType::Rgba => pixels.extend_from_slice(samples),
+            ColorType::Rgb => samples
+                .chunks_exact(3)
+                .for_each(|p| pixels.extend_from_slice(&[p[0], p[1], p[2], 255])),
+            ColorType::GrayscaleAlpha => samples
+                .chunks_exact(2)
+                .for_each(|p| pixels.extend_from_slice(&[p[0], p[0], p[0], p[1]])),
+            ColorType::Grayscale => samples
+                .iter()
+                .for_each(|&v| pixels.extend_from_slice(&[v, v, v, 255])),
+            ColorType::Indexed => return Err(invalid("the palette was not expanded")),
+        }
+    }
+    Ok(Image {
+        width: frame.width,
+        height: frame.height,
+        pixels,
+    })
+}
+
+#[cfg(feature = "png")]
+fn invalid_png(error: png::DecodingError) -> io::Error {
+    invalid(format!("bad PNG: {}", error))
+}
+
+#[cfg(feature = "jpeg")]
+fn decode_jpeg(bytes: &[u8]) -> io::Result<Image> {
+    use zune_jpeg::JpegDecoder;
+    use zune_jpeg::zune_core::colorspace::ColorSpace;
+    use zune_jpeg::zune_core::options::DecoderOptions;
+
+    let invalid_jpeg = |error| invalid(format!("bad JPEG: {:?}", error));
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
+    let mut decoder = JpegDecoder::new_with_options(bytes, options);
+    decoder.decode_headers().map_err(invalid_jpeg)?;
+    let Some(info) = decoder.info() else {
+        return Err(invalid("bad JPEG: no frame header"));
+    };
+    check_size(info.width as u64, info.height as u64)?;
+    let pixels = decoder.decode().map_err(invalid_jpeg)?;
+    Ok(Image {
+        width: info.width as u32,
+        height: info.height as u32,
+        pixels,
+    })
+}
+
+/// The first frame, drawn where it belongs on the logical screen.
+#[cfg(feature = "gif")]
+fn decode_gif(bytes: &[u8]) -> io::Result<Image> {
+    use gif::{ColorOutput, DecodeOptions};
+
+    let invalid_gif = |error: gif::DecodingError| invalid(format!("bad GIF: {}", error));
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::RGBA);
+    let mut decoder = options.read_info(bytes).map_err(invalid_gif)?;
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+    check_size(width as u64, height as u64)?;
+
+    let mut pixels = vec![0; width * height * 4];
+    let Some(frame) = decoder.read_next_frame().map_err(invalid_gif)? else {
+        return Err(invalid("bad GIF: no frames"));
+    };
+    let (left, top) = (frame.left as usize, frame.top as usize);
+    let frame_width = frame.width as usize;
+    if frame_width == 0 {
+        return Err(invalid("bad GIF: empty frame"));
+    }
+    for (y, row) in frame.buffer.chunks_exact(frame_width * 4).enumerate() {
+        if top + y >= height || left >= width {
+            break;
+        }
+        let visible = frame_width.min(width - left);
+        let start = ((top + y) * width + left) * 4;
+        pixels[start..start + visible * 4].copy_from_slice(&row[..visible * 4]);
+    }
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+/// Uncompressed Windows bitmaps: palettes of 1, 4 or 8 bits, and 16, 24
+/// or 32 bit pixels with the default or given channel masks.
+#[cfg(feature = "bmp")]
+fn decode_bmp(bytes: &[u8]) -> io::Result<Image> {
+    let u16_at = |offset: usize| -> io::Result<u32> {
+        match bytes.get(offset..offset + 2) {
+            Some(b) => Ok(u16::from_le_bytes([b[0], b[1]]) as u32),
+            None => Err(invalid("bad BMP: truncated header")),
+        }
+    };
+    let u32_at = |offset: usize| -> io::Result<u32> {
+        match bytes.get(offset..offset + 4) {
+            Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            None => Err(invalid("bad BMP: truncated header")),
+        }
+    };
+    if !bytes.starts_with(b"BM") {
+        return Err(invalid("bad BMP: no signature"));
+    }
+    let data_offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+    let core = header_size == 12;
+    let (width, height, bits, compression) = match core {
+        true => (u16_at(18)? as i64, u16_at(20)? as i64, u16_at(24)?, 0),
+        false if header_size >= 40 => (
+            u32_at(18)? as i32 as i64,
+            u32_at(22)? as i32 as i64,
+            u16_at(28)?,
+            u32_at(30)?,
+        ),
+        false => return Err(invalid("bad BMP: unknown header")),
+    };
+    let top_down = height < 0;
+    let (width, height) = (width, height.abs());
+    if width <= 0 {
+        return Err(invalid("bad BMP: no width"));
+    }
+    check_size(width as u64, height as u64)?;
+    let (width, height) = (width as usize, height as usize);
+
+    // BI_RGB, BI_BITFIELDS and BI_ALPHABITFIELDS; run-length encoded and
+    // embedded JPEG or PNG bitmaps are not read.
+    let masks = match (compression, bits) {
+        (0, 16) => [0x7C00, 0x03E0, 0x001F, 0],
+        (0, 24 | 32) => [0xFF_0000, 0x00_FF00, 0x00_00FF, 0],
+        (0, 1 | 4 | 8) => [0; 4],
+        (3 | 6, 16 | 32) => [
+            u32_at(54)?,
+            u32_at(58)?,
+            u32_at(62)?,
+            match header_size >= 56 || compression == 6 {
+                true => u32_at(66)?,
+                false => 0,
+            },
+        ],
+        _ => {
+            return Err(invalid(format!(
+                "bad BMP: cannot read {} bit pixels with compression {}",
+                bits, compression
+            )));
+        }
+    };
+
+    let palette: Vec<[u8; 4]> = match bits {
+        1 | 4 | 8 => {
+            let entry = if core { 3 } else { 4 };
+            let used = if core { 0 } else { u32_at(46)? as usize };
+            let count = match used {
+                0 => 1 << bits,
+                used => used.min(1 << bits),
+            };
+            let start = 14 + header_size;
+            let Some(table) = bytes.get(start..start + count * entry) else {
+                return Err(invalid("bad BMP: truncated palette"));
+            };
+            table
+                .chunks_exact(entry)
+                .map(|c| [c[2], c[1], c[0], 255])
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let stride = (width * bits as usize).div_ceil(32) * 4;
+    let Some(data) = bytes.get(data_offset..) else {
+        return Err(invalid("bad BMP: no pixels"));
+    };
+    if data.len() < stride * height {
+        return Err(invalid("bad BMP: truncated pixels"));
+    }
+
+    let channel = |value: u32, mask: u32, default: u8| -> u8 {
+        if mask == 0 {
+            return default;
+        }
+        let bits = mask.count_ones().min(32 - mask.trailing_zeros());
+        let max = (1u64 << bits) - 1;
+        let value = ((value & mask) >> mask.trailing_zeros()) as u64;
+        (value.min(max) * 255 / max) as u8
+    };
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row_index = if top_down { y } else { height - 1 - y };
+        let row = &data[row_index * stride..(row_index + 1) * stride];
+        for x in 0..width {
+            let pixel = match bits {
+                1 | 4 | 8 => {
+                    let bits = bits as usize;
+                    let bit = x * bits;
+                    let shift = 8 - bits - bit % 8;
+                    let index = (row[bit / 8] >> shift) as usize & ((1 << bits) - 1);
+                    *palette.get(index).unwrap_or(&[0, 0, 0, 255])
+                }
+                _ => {
+                    let start = x * bits as usize / 8;
+                    let value = match bits {
+                        16 => u16::from_le_bytes([row[start], row[start + 1]]) as u32,
+                        24 => u32::from_le_bytes([row[start], row[start + 1], row[start + 2], 0]),
+                        _ => u32::from_le_bytes([
+                            row[start],
+                            row[start + 1],
+                            row[start + 2],
+                            row[start + 3],
+                        ]),
+                    };
+                    [
+                        channel(value, masks[0], 0),
+                        channel(value, masks[1], 0),
+                        channel(value, masks[2], 0),
+                        channel(value, masks[3], 255),
+                    ]
+                }
+            };
+            pixels.extend_from_slice(&pixel);
+        }
+    }
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+/// Thumbnails kept as PNG files in a directory, named after the image's
+/// path, size and modification time so that a changed image gets a new
+/// one.
+pub struct Thumbnails {
+    dir: PathBuf,
+    size: u32,
+}
+
+impl Thumbnails {
+    /// Thumbnails kept in `dir`, which is created when first needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Thumbnails {
+            dir: dir.into(),
+            size: SIZE,
+        }
+    }
+
+    /// Makes thumbnails at most `pixels` wide and high.
+    pub fn size(mut self, pixels: u32) -> Self {
+        self.size = pixels.max(1);
+        self
+    }
+
+    /// The thumbnail of the image `file`, made now unless it already
+    /// exists. Fails with [`ErrorKind::Unsupported`] for types this build
+    /// does not decode and [`ErrorKind::InvalidData`] for images it cannot
+    /// read.
+    pub fn get(&self, file: &Path) -> io::Result<PathBuf> {
+        let name = file
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        if !decodable(&name) {
+            return Err(unsupported());
+        }
+        let metadata = fs::metadata(file)?;
+        if metadata.len() > MAX_FILE_SIZE {
+            return Err(invalid("the image file is too large"));
+        }
+        let thumbnail = self.dir.join(format!("{}.png", self.key(file, &metadata)));
+        if thumbnail.is_file() {
+            return Ok(thumbnail);
+        }
+
+        let png = Image::decode(&name, &fs::read(file)?)?
+            .fit(self.size)
+            .to_png()?;
+        // Written aside and renamed so no one is served half a file.
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        fs::create_dir_all(&self.dir)?;
+        let partial = self.dir.join(format!(
+            ".{}-{}.partial",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = fs::write(&partial, png).and_then(|_| fs::rename(&partial, &thumbnail));
+        if written.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        written.map(|_| thumbnail)
+    }
+
+    fn key(&self, file: &Path, metadata: &fs::Metadata) -> String {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_nanos())
+            .unwrap_or_default();
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let id = format!(
+            "{}\0{}\0{}\0{}\0{}",
+            VERSION,
+            file.display(),
+            metadata.len(),
+            modified,
+            self.size
+        );
+        sha256(id.as_bytes())[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn image(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> Image {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| pixel(x, y))
+            .collect();
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The header and the unfiltered pixels of a PNG written by
+    /// [`Image::to_png`], checking each chunk's CRC.
+    fn read_png(png: &[u8]) -> ([u8; 13], Vec<u8>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let (mut header, mut data, mut rest) = ([0; 13], Vec::new(), &png[8..]);
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, body) = (&rest[4..8], &rest[8..8 + length]);
+            let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap());
+            let mut expected = Crc32::new();
+            expected.update(kind);
+            expected.update(body);
+            assert_eq!(crc, expected.value());
+            match kind {
+                b"IHDR" => header.copy_from_slice(body),
+                b"IDAT" => data.extend_from_slice(body),
+                _ => {}
+            }
+            rest = &rest[12 + length..];
+        }
+
+        let mut raw = Vec::new();
+        flate2::read::ZlibDecoder::new(&data[..])
+            .read_to_end(&mut raw)
+            .unwrap();
+        let width = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let channels = if header[9] == 6 { 4 } else { 3 };
+        let mut pixels = Vec::new();
+        for line in raw.chunks_exact(1 + width * channels) {
+            assert_eq!(line[0], 1);
+            let start = pixels.len();
+            pixels.extend_from_slice(&line[1..]);
+            for i in start + channels..pixels.len() {
+                pixels[i] = pixels[i].wrapping_add(pixels[i - channels]);
+            }
+        }
+        (header, pixels)
+    }
+
+    #[test]
+    fn test_fit() {
+        let checks = image(8, 4, |x, y| match (x + y) % 2 {
+            0 => [0, 0, 0, 255],
+            _ => [200, 100, 50, 255],
+        });
+        let small = checks.fit(4);
+        assert_eq!((small.width, small.height), (4, 2));
+        assert!(
+            small
+                .pixels
+                .chunks_exact(4)
+                .all(|p| p == [100, 50, 25, 255])
+        );
+        assert_eq!(checks.fit(100), checks);
+        assert_eq!(image(1000, 1, |_, _| [0; 4]).fit(10).height, 1);
+
+        // Transparent pixels lend no colour.
+        let half = image(2, 1, |x, _| match x {
+            0 => [255, 0, 0, 255],
+            _ => [0, 255, 0, 0],
+        });
+        assert_eq!(half.fit(1).pixels, [255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn test_png() {
+        let opaque = image(5, 3, |x, y| [x as u8 * 50, y as u8 * 100, 7, 255]);
+        let (header, pixels) = read_png(&opaque.to_png().unwrap());
+        assert_eq!(header, [0, 0, 0, 5, 0, 0, 0, 3, 8, 2, 0, 0, 0]);
+        let rgb: Vec<u8> = opaque
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|p| p[..3].to_vec())
+            .collect();
+        assert_eq!(pixels, rgb);
+
+        let clear = image(3, 2, |x, _| [1, 2, 3, x as u8]);
+        let (header, pixels) = read_png(&clear.to_png().unwrap());
+        assert_eq!(header[9], 6);
+        assert_eq!(pixels, clear.pixels);
+    }
+
+    #[test]
+    fn test_formats() {
+        for (name, enabled) in [
+            ("a.png", cfg!(feature = "png")),
+            ("b.JPG", cfg!(feature = "jpeg")),
+            ("c.jpeg", cfg!(feature = "jpeg")),
+            ("d.gif", cfg!(feature = "gif")),
+            ("e.bmp", cfg!(feature = "bmp")),
+        ] {
+            assert_eq!(decodable(name), enabled, "{}", name);
+        }
+        assert!(!decodable("f.webp") && !decodable("g.svg") && !decodable("png"));
+        let error = Image::decode("f.webp", b"RIFF").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+
+    /// A bottom-up BMP of `rows` with `bits` per pixel and `extra` header
+    /// bytes (masks or a palette) before the pixels.
+    #[cfg(feature = "bmp")]
+    fn bmp(
+        width: i32,
+        height: i32,
+        bits: u16,
+        compression: u32,
+        extra: &[u8],
+        rows: &[&[u8]],
+    ) -> Vec<u8> {
+        let offset = 14 + 40 + extra.len() as u32;
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+        bmp.extend_from_slice(&offset.to_le_bytes());
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&width.to_le_bytes());
+        bmp.extend_from_slice(&height.to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&bits.to_le_bytes());
+        bmp.extend_from_slice(&compression.to_le_bytes());
+        bmp.extend_from_slice(&[0; 20]);
+        bmp.extend_from_slice(extra);
+        for row in rows {
+            bmp.extend_from_slice(row);
+        }
+        bmp
+    }
+
+    #[cfg(feature = "bmp")]
+    #[test]
+    fn test_bmp() {
+        // 24 bits, BGR, rows padded to four bytes and stored bottom up.
+        let bgr = bmp(
+            2,
+            2,
+            24,
+            0,
+            &[],
+            &[&[1, 2, 3, 4, 5, 6, 0, 0], &[7, 8, 9, 10, 11, 12, 0, 0]],
+        );
+        let decoded = Image::decode("a.bmp", &bgr).unwrap();
+        assert_eq!((decoded.width, decoded.height), (2, 2));
+        assert_eq!(
+            decoded.pixels,
+            [9, 8, 7, 255, 12, 11, 10, 255, 3, 2, 1, 255, 6, 5, 4, 255]
+        );
+
+        // A negative height is stored top down.
+        let top_down = bmp(1, -2, 24, 0, &[], &[&[1, 2, 3, 0], &[4, 5, 6, 0]]);
+        assert_eq!(
+            Image::decode("a.bmp", &top_down).unwrap().pixels,
+            [3, 2, 1, 255, 6, 5, 4, 255]
+        );
+
+        // A two colour palette.
+        let palette = [0, 0, 0, 0, 255, 255, 255, 0];
+        let mono = bmp(3, 1, 1, 0, &palette, &[&[0b1010_0000, 0, 0, 0]]);
+        assert_eq!(
+            Image::decode("a.bmp", &mono).unwrap().pixels,
+            [255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255]
+        );
+
+        // 16 bits in 5-6-5 from given masks.
+        let masks: Vec<u8> = [0xF800u32, 0x07E0, 0x001F]
+            .iter()
+            .flat_map(|m| m.to_le_bytes())
+            .collect();
+        let rgb565 = bmp(1, 1, 16, 3, &masks, &[&[0x1F, 0xF8, 0, 0]]);
+        assert_eq!(
+            Image::decode("a.bmp", &rgb565).unwrap().pixels,
+            [255, 0, 255, 255]
+        );
+
+        for bad in [
+            &b"BM"[..],
+            &bgr[..bgr.len() - 1],
+            &bmp(1, 1, 8, 1, &[], &[&[0, 0, 0, 0]]),
+            &bmp(0, 1, 24, 0, &[], &[]),
+            &bmp(100_000, 100_000, 24, 0, &[], &[]),
+        ] {
+            let error = Image::decode("a.bmp", bad).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png_decoding() {
+        let original = image(300, 20, |x, y| [x as u8, y as u8, 9, (x % 256) as u8]);
+        let decoded = Image::decode("a.png", &original.to_png().unwrap()).unwrap();
+        assert_eq!(decoded, original);
+        assert!(Image::decode("a.png", b"\x89PNG\r\n\x1a\nnot really").is_err());
+    }
+
+    #[cfg(feature = "bmp")]
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("rustserve-thumbs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let rows: Vec<Vec<u8>> = (0..40).map(|_| [0, 0, 255].repeat(80)).collect();
+        let rows: Vec<&[u8]> = rows.iter().map(|row| &row[..]).collect();
+        fs::write(dir.join("red.bmp"), bmp(80, 40, 24, 0, &[], &rows)).unwrap();
+        fs::write(dir.join("broken.bmp"), b"BM").unwrap();
+
+        let thumbnails = Thumbnails::new(dir.join("cache")).size(20);
+        let made = thumbnails.get(&dir.join("red.bmp")).unwrap();
+        let (header, pixels) = read_png(&fs::read(&made).unwrap());
+        assert_eq!(&header[..8], [0, 0, 0, 20, 0, 0, 0, 10]);
+        assert!(pixels.chunks_exact(3).all(|p| p == [255, 0, 0]));
+
+        // Kept, and made again only for a different size or a changed file.
+        assert_eq!(thumbnails.get(&dir.join("red.bmp")).unwrap(), made);
+        let larger = Thumbnails::new(dir.join("cache")).size(40);
+        assert_ne!(larger.get(&dir.join("red.bmp")).unwrap(), made);
+        fs::write(dir.join("red.bmp"), bmp(2, 1, 24, 0, &[], &[&[0; 8]])).unwrap();
+        assert_ne!(thumbnails.get(&dir.join("red.bmp")).unwrap(), made);
+
+        let broken = thumbnails.get(&dir.join("broken.bmp")).unwrap_err();
+        assert_eq!(broken.kind(), ErrorKind::InvalidData);
+        let missing = thumbnails.get(&dir.join("missing.bmp")).unwrap_err();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+        let unsupported = thumbnails.get(&dir.join("red.webp")).unwrap_err();
+        assert_eq!(unsupported.kind(), ErrorKind::Unsupported);
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}